@@ -1,8 +1,8 @@
 use once_cell::sync::OnceCell;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -17,20 +17,81 @@ pub struct Opt {
     )]
     input: Vec<PathBuf>,
 
-    /// Compile Target（llvm、wasm、cuda）
+    /// Compile Source（torchscript、graph、pt、mool）
     #[structopt(short, long, default_value = "mool", help = "Compile Source")]
     source: String,
 
-    /// Compile Target（torchscript、mool）
+    /// Compile Target（llvm、c、wasm）
     #[structopt(short, long, default_value = "llvm", help = "Compile Target")]
     target: String,
 
     /// Show Compilation Process
     #[structopt(short, long, help = "Show Compilation Process")]
     debug: bool,
+
+    /// Mool IR Passes（inline、fold、dce、cse、fuse、plan），按给出的顺序执行
+    #[structopt(
+        short,
+        long,
+        use_delimiter = true,
+        require_delimiter = true,
+        help = "Mool IR Passes Separated by Commas (inline, fold, dce, cse, fuse, plan), Run in the Given Order, e.g. inline,fold,fuse,cse,dce,plan"
+    )]
+    passes: Vec<String>,
+
+    /// Show Time Spent in Each Pass
+    #[structopt(long, help = "Show Time Spent in Each Pass")]
+    time_passes: bool,
+
+    /// Show Mool IR After Each Pass
+    #[structopt(long, help = "Show Mool IR After Each Pass")]
+    dump_passes: bool,
+
+    /// LLVM Optimization Level（0、1、2、3、s）
+    #[structopt(
+        short = "O",
+        default_value = "0",
+        help = "LLVM Optimization Level (0, 1, 2, 3, s)"
+    )]
+    opt_level: mool::codegen::OptLevel,
+
+    /// WebAssembly Linker（wasm-ld、rust-lld）
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "WebAssembly Linker, Output .wasm Besides .o When Set"
+    )]
+    linker: Option<PathBuf>,
+
+    /// LLVM Target Triple（x86_64-unknown-linux-gnu、aarch64-linux-gnu）
+    #[structopt(long, help = "LLVM Target Triple, Output .o Besides .ll When Set")]
+    target_triple: Option<String>,
+
+    /// Target CPU（skylake、cortex-a72）
+    #[structopt(long, requires = "target-triple", help = "Target CPU")]
+    cpu: Option<String>,
+
+    /// Target Features（+avx2,+fma）
+    #[structopt(
+        long,
+        requires = "target-triple",
+        allow_hyphen_values = true,
+        help = "Target Features, e.g. +avx2,+fma"
+    )]
+    features: Option<String>,
+}
+
+/// Mool IR 的 pass 配置
+struct PassOptions {
+    names: Vec<String>,
+    time: bool,
+    dump: bool,
 }
 
 static DEBUG: OnceCell<bool> = OnceCell::new();
+static PASSES: OnceCell<PassOptions> = OnceCell::new();
+static TARGET: OnceCell<String> = OnceCell::new();
+static TARGET_OPTIONS: OnceCell<mool::codegen::TargetOptions> = OnceCell::new();
 
 fn main() {
     // 获取配置
@@ -38,6 +99,34 @@ fn main() {
     let mut current_filename: String;
     // 全局保存配置信息
     DEBUG.set(opt.debug).unwrap();
+    // 提前检查 pass 的名称
+    for name in opt.passes.iter() {
+        if mool::ir::pass::create(name).is_none() {
+            panic!("没有名为{}的 pass", name);
+        }
+    }
+    PASSES
+        .set(PassOptions {
+            names: opt.passes.clone(),
+            time: opt.time_passes,
+            dump: opt.dump_passes,
+        })
+        .ok()
+        .unwrap();
+    // 提前检查目标的名称
+    let target_options = mool::codegen::TargetOptions {
+        opt_level: opt.opt_level,
+        linker: opt.linker.clone(),
+        triple: opt.target_triple.clone(),
+        cpu: opt.cpu.clone().unwrap_or_default(),
+        features: opt.features.clone().unwrap_or_default(),
+        module: String::new(),
+    };
+    if mool::codegen::create(&opt.target, &target_options).is_none() {
+        panic!("没有名为{}的目标", opt.target);
+    }
+    TARGET.set(opt.target.clone()).unwrap();
+    TARGET_OPTIONS.set(target_options).unwrap();
     // 编译每一个文件
     for file in opt.input.into_iter() {
         // 从参数列表获取文件名
@@ -46,38 +135,103 @@ fn main() {
             "\n\ncompiling {:?} from {:?} to {:?}\n",
             current_filename, opt.source, opt.target
         );
-        // 读取代码
-        let mut code = String::new();
-        let mut f = File::open(current_filename.clone()).unwrap();
-        f.read_to_string(&mut code).unwrap();
+        // 输出到同级的目标目录，例如 example/mool/a.mool 输出到 example/llvm/a.ll，
+        // example/graph/add 输出到 example/llvm/graph/add.ll
+        let output_stem = output_stem(&current_filename, &opt.source, &opt.target);
+        // 模块以输出的文件名命名
+        let name = Path::new(&output_stem)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
         // 编译
-        match &opt.source as &str {
-            "torchscript" => {
-                let llvm_code = compile_torchscript(&code);
-                let mut output_filename = current_filename.replace("torchscript", "llvm");
-                output_filename.push_str(".ll");
-                let mut f = File::create(output_filename).unwrap();
-                f.write_all(llvm_code.as_bytes()).unwrap();
-            }
-            "mool" => {
-                let llvm_code = compile_mool(&code);
-                let mut f = File::create(
-                    current_filename
-                        .replace("mool", "llvm")
-                        .replace(".llvm", ".ll"),
-                )
-                .unwrap();
-                f.write_all(llvm_code.as_bytes()).unwrap();
+        let artifacts = match &opt.source as &str {
+            "torchscript" => compile_torchscript(&read_code(&current_filename), None, &name),
+            "graph" => compile_graph(&read_code(&current_filename), &name),
+            "pt" => {
+                // 模型文件是 zip 压缩包，按二进制读取
+                let f = File::open(current_filename.clone()).unwrap();
+                compile_pt(f, &name)
             }
+            "mool" => compile_mool(&read_code(&current_filename), &name),
             _ => {
-                println!("暂不支持编译{}", opt.source)
+                println!("暂不支持编译{}", opt.source);
+                continue;
             }
+        };
+        if let Some(parent) = Path::new(&output_stem).parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        for artifact in artifacts {
+            let mut f = File::create(format!("{}.{}", output_stem, artifact.extension)).unwrap();
+            f.write_all(&artifact.content).unwrap();
         }
     }
 }
 
-fn compile_torchscript(code: &String) -> String {
-    let torchscript_ast = mool::torchscript::parse(&code).unwrap();
+/// 输出文件不带扩展名的路径：把来源目录换成目标目录，去掉来源的扩展名
+///
+/// 除 Mool 以外的来源输出到目标目录下以来源命名的子目录，
+/// 例如 example/graph/add 输出到 example/llvm/graph/add.ll，
+/// 避免和 example/torchscript/add 的输出互相覆盖
+fn output_stem(filename: &str, source: &str, target: &str) -> String {
+    let source_dir = format!("/{}/", source);
+    let target = if source == "mool" {
+        target.to_string()
+    } else {
+        format!("{}/{}", target, source)
+    };
+    // 相对路径前补上 /，这样 mool/a.mool 也能找到来源目录
+    let path = format!("/{}", filename);
+    let output_filename = match path.rfind(&source_dir) {
+        Some(0) => format!("{}/{}", target, &path[source_dir.len()..]),
+        Some(i) => format!(
+            "{}/{}/{}",
+            &filename[..i - 1],
+            target,
+            &path[i + source_dir.len()..]
+        ),
+        None => filename.to_string(),
+    };
+    output_filename
+        .trim_end_matches(&format!(".{}", source))
+        .to_string()
+}
+
+fn read_code(filename: &str) -> String {
+    let mut code = String::new();
+    let mut f = File::open(filename).unwrap();
+    f.read_to_string(&mut code).unwrap();
+    code
+}
+
+fn compile_pt(f: File, name: &str) -> Vec<mool::codegen::Artifact> {
+    let archive = mool::torchscript::archive::load(f).unwrap();
+    // 输出模型的参数
+    match DEBUG.get() {
+        Some(&debug) => {
+            if debug {
+                for (name, tensor) in archive.module.parameters() {
+                    println!(
+                        "Parameter {}: {:?} {}",
+                        name,
+                        tensor.shape,
+                        tensor.mool_type()
+                    );
+                }
+                println!();
+            }
+        }
+        None => panic!("未运行初始化"),
+    }
+    compile_torchscript(&archive.source(), Some(&archive.module), name)
+}
+
+fn compile_torchscript(
+    code: &str,
+    module: Option<&mool::torchscript::archive::Module>,
+    name: &str,
+) -> Vec<mool::codegen::Artifact> {
+    let torchscript_ast = mool::torchscript::parse(code).unwrap();
     // 输出抽象语法树
     match DEBUG.get() {
         Some(&debug) => {
@@ -91,7 +245,11 @@ fn compile_torchscript(code: &String) -> String {
         None => panic!("未运行初始化"),
     }
     unsafe {
-        let mool_code = mool::torchscript::codegen(torchscript_ast);
+        // 模型文件以模块的 forward 为入口
+        let mool_code = match module {
+            Some(module) => mool::torchscript::codegen_module(torchscript_ast, module),
+            None => mool::torchscript::codegen(torchscript_ast),
+        };
         match DEBUG.get() {
             Some(&debug) => {
                 if debug {
@@ -100,12 +258,36 @@ fn compile_torchscript(code: &String) -> String {
             }
             None => panic!("未运行初始化"),
         }
-        compile_mool(&mool_code)
+        compile_mool(&mool_code, name)
     }
 }
 
-fn compile_mool(code: &String) -> String {
-    let mool_ast = mool::ir::parse(&code).unwrap();
+fn compile_graph(code: &str, name: &str) -> Vec<mool::codegen::Artifact> {
+    let graph = mool::torchscript::graph::parse(code).unwrap();
+    // 输出抽象语法树
+    match DEBUG.get() {
+        Some(&debug) => {
+            if debug {
+                println!("AST:\n{}\n", serde_json::to_string(&graph).unwrap());
+            }
+        }
+        None => panic!("未运行初始化"),
+    }
+    let mool_code = mool::torchscript::graph::codegen(graph);
+    match DEBUG.get() {
+        Some(&debug) => {
+            if debug {
+                println!("Mool:\n{}\n", mool_code);
+            }
+        }
+        None => panic!("未运行初始化"),
+    }
+    compile_mool(&mool_code, name)
+}
+
+/// 编译 Mool IR，name 为模块名
+fn compile_mool(code: &str, name: &str) -> Vec<mool::codegen::Artifact> {
+    let mool_ast = mool::ir::parse(code).unwrap();
     // 输出抽象语法树
     match DEBUG.get() {
         Some(&debug) => {
@@ -115,16 +297,98 @@ fn compile_mool(code: &String) -> String {
         }
         None => panic!("未运行初始化"),
     }
-    unsafe {
-        let llvm_code = mool::codegen::llvm(mool_ast);
-        match DEBUG.get() {
-            Some(&debug) => {
-                if debug {
-                    println!("LLVM:\n{}\n", llvm_code);
+    // 类型不正确时不输出文件，以非零状态退出
+    if let Err(error) = mool::ir::pass::typecheck(&mool_ast) {
+        eprintln!("Mool IR 的类型不正确：{}", error);
+        std::process::exit(1);
+    }
+    let backend = match (TARGET.get(), TARGET_OPTIONS.get()) {
+        (Some(target), Some(options)) => {
+            let options = mool::codegen::TargetOptions {
+                module: name.to_string(),
+                ..options.clone()
+            };
+            mool::codegen::create(target, &options).unwrap()
+        }
+        _ => panic!("未运行初始化"),
+    };
+    // 按命令行指定的顺序执行 pass，死代码删除保留后端导出的函数
+    let roots = backend.roots(&mool_ast);
+    let mool_ast = run_passes(mool_ast, roots);
+    // 生成的代码不合法时不输出文件，以非零状态退出
+    let artifacts = match backend.codegen(mool_ast) {
+        Ok(artifacts) => artifacts,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    match DEBUG.get() {
+        Some(&debug) => {
+            if debug {
+                for artifact in artifacts.iter() {
+                    println!(
+                        "{}:\n{}\n",
+                        backend.name().to_uppercase(),
+                        String::from_utf8_lossy(&artifact.content)
+                    );
                 }
             }
-            None => panic!("未运行初始化"),
         }
-        llvm_code
+        None => panic!("未运行初始化"),
+    }
+    artifacts
+}
+
+fn run_passes(
+    mool_ast: Vec<mool::ir::ast::Program>,
+    roots: Vec<String>,
+) -> Vec<mool::ir::ast::Program> {
+    let options = match PASSES.get() {
+        Some(options) => options,
+        None => panic!("未运行初始化"),
+    };
+    let mut pass_manager = mool::ir::pass::PassManager::new();
+    for name in options.names.iter() {
+        match name.as_str() {
+            "dce" => pass_manager.add(Box::new(mool::ir::pass::DeadCode::with_roots(
+                roots.clone(),
+            ))),
+            name => pass_manager.add_by_name(name).unwrap(),
+        }
+    }
+    if options.dump {
+        pass_manager.dump(|name, programs| {
+            println!(
+                "AST after {}:\n{}\n",
+                name,
+                serde_json::to_string(programs).unwrap()
+            );
+        });
+    }
+    let mool_ast = match pass_manager.run(mool_ast) {
+        Ok(mool_ast) => mool_ast,
+        Err(error) => panic!("{}", error),
+    };
+    // 调试模式下输出每个 pass 的修改
+    match DEBUG.get() {
+        Some(&debug) => {
+            if debug {
+                for (name, report) in pass_manager.reports() {
+                    for line in report.iter() {
+                        println!("Pass {}: {}", name, line);
+                    }
+                }
+            }
+        }
+        None => panic!("未运行初始化"),
+    }
+    // 输出每个 pass 的耗时
+    if options.time {
+        for (name, duration) in pass_manager.timings() {
+            println!("Pass {}: {:?}", name, duration);
+        }
+        println!();
     }
+    mool_ast
 }
@@ -0,0 +1,81 @@
+use super::c::CBackend;
+use super::llvm::{LlvmBackend, OptLevel, WasmBackend};
+use mool_ir::ast::{Expr, Program};
+use std::error::Error;
+use std::path::PathBuf;
+
+/// 后端生成的一个文件，例如 LLVM IR 代码、C 源文件或头文件
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artifact {
+    /// 文件扩展名，例如 ll、c、h
+    pub extension: String,
+    pub content: Vec<u8>,
+}
+
+impl Artifact {
+    pub fn new(extension: &str, content: impl Into<Vec<u8>>) -> Self {
+        Self {
+            extension: extension.to_string(),
+            content: content.into(),
+        }
+    }
+}
+
+/// 所有后端共用的配置，后端只读取自己需要的部分
+#[derive(Debug, Clone, Default)]
+pub struct TargetOptions {
+    /// LLVM 优化级别
+    pub opt_level: OptLevel,
+    /// WebAssembly 后端使用的链接器（wasm-ld 或 rust-lld），没有时只输出目标文件
+    pub linker: Option<PathBuf>,
+    /// LLVM 后端交叉编译的目标平台，例如 x86_64-unknown-linux-gnu，没有时不设置 triple
+    pub triple: Option<String>,
+    /// 目标 CPU，例如 skylake、cortex-a72，为空时使用 generic
+    pub cpu: String,
+    /// LLVM 的目标特性，例如 +avx2,+fma
+    pub features: String,
+    /// 模块名，通常为输入文件名，C 后端据此命名入口函数和头文件的保护宏
+    pub module: String,
+}
+
+/// 代码生成后端，把经过验证的 Mool IR 翻译为目标平台的文件
+pub trait Backend {
+    /// 名称，命令行的 --target 按名称选择后端
+    fn name(&self) -> &'static str;
+    fn codegen(&self, programs: Vec<Program>) -> Result<Vec<Artifact>, Box<dyn Error>>;
+
+    /// 死代码删除除顶层代码以外的根，即后端导出、不能删除的顶层函数，默认没有
+    fn roots(&self, _programs: &[Program]) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// 顶层定义的函数名，C 和 WebAssembly 后端把它们全部导出
+pub(crate) fn top_level_functions(programs: &[Program]) -> Vec<String> {
+    programs
+        .iter()
+        .filter_map(|program| match program {
+            Program::Let(variable, Expr::Function(_)) => Some(variable.name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// 按名称创建后端
+pub fn create(name: &str, options: &TargetOptions) -> Option<Box<dyn Backend>> {
+    match name {
+        "llvm" => {
+            let backend = LlvmBackend::new(options.opt_level);
+            Some(Box::new(match &options.triple {
+                Some(triple) => backend.target(triple, &options.cpu, &options.features),
+                None => backend,
+            }))
+        }
+        "c" => Some(Box::new(CBackend::new(&options.module))),
+        "wasm" => Some(Box::new(WasmBackend::new(
+            options.opt_level,
+            options.linker.clone(),
+        ))),
+        _ => None,
+    }
+}
@@ -0,0 +1,10 @@
+mod backend;
+mod codegen;
+mod codegen_expr;
+mod codegen_operator;
+mod error;
+mod types;
+
+pub use backend::CBackend;
+pub use codegen::{codegen, CCode};
+pub use error::CError;
@@ -0,0 +1,38 @@
+use super::super::backend::{top_level_functions, Artifact, Backend};
+use super::codegen::codegen;
+use mool_ir::ast::Program;
+use std::error::Error;
+
+/// C 后端，输出不依赖 LLVM 的 C99 源文件（.c）和头文件（.h）
+///
+/// 入口函数和头文件的保护宏由模块名得到，不同模块生成的代码可以放在同一个工程中。
+#[derive(Debug, Clone, Default)]
+pub struct CBackend {
+    module: String,
+}
+
+impl CBackend {
+    pub fn new(module: &str) -> Self {
+        Self {
+            module: module.to_string(),
+        }
+    }
+}
+
+impl Backend for CBackend {
+    fn name(&self) -> &'static str {
+        "c"
+    }
+
+    fn codegen(&self, programs: Vec<Program>) -> Result<Vec<Artifact>, Box<dyn Error>> {
+        let code = codegen(programs, &self.module)?;
+        Ok(vec![
+            Artifact::new("c", code.source),
+            Artifact::new("h", code.header),
+        ])
+    }
+
+    fn roots(&self, programs: &[Program]) -> Vec<String> {
+        top_level_functions(programs)
+    }
+}
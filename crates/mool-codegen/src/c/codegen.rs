@@ -0,0 +1,349 @@
+use super::error::CError;
+use super::types::Types;
+use mool_ir::ast;
+use mool_ir::pass::verify;
+use std::collections::{HashMap, HashSet};
+
+/// 生成的 C 代码
+///
+/// 源文件不依赖头文件，可以单独编译；头文件声明结构体和导出的函数，供其他 C/C++ 代码调用。
+#[derive(Debug, Clone, PartialEq)]
+pub struct CCode {
+    pub source: String,
+    pub header: String,
+}
+
+/// 将 Mool 抽象语法树翻译为 C99 代码
+///
+/// 顶层代码生成入口函数 int64_t mool_<module>_main(void)（module 为空时为 mool_main），
+/// 顶层定义的函数 %f 生成 mool_fn_f 并在头文件中导出，函数中定义的函数只在源文件内可见。
+/// 头文件的保护宏同样由模块名得到，例如 MOOL_<MODULE>_H，声明包在 extern "C" 中，可以从 C++ 调用。
+/// 张量为包含定长数组的结构体，算子与 LLVM 后端一致。
+pub fn codegen(programs: Vec<ast::Program>, module: &str) -> Result<CCode, CError> {
+    verify(&programs).map_err(CError::new)?;
+    // 模块名中不能用于 C 标识符的字符换成下划线
+    let module: String = module
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let (entry, guard) = match module.as_str() {
+        "" => ("mool_main".to_string(), "MOOL_H".to_string()),
+        module => (
+            format!("mool_{}_main", module),
+            format!("MOOL_{}_H", module.to_ascii_uppercase()),
+        ),
+    };
+    let mut generator = Generator::default();
+    generator.function_names.insert(entry.clone());
+    generator.frames.push(Frame::default());
+    for program in programs {
+        generator.program(program)?;
+    }
+    generator.line("return 0;".to_string());
+    let frame = generator.frames.pop().unwrap();
+    let prototype = format!("int64_t {}(void)", entry);
+    generator
+        .definitions
+        .push(frame.definition(&prototype, true));
+    generator.exports.push(format!("{};", prototype));
+    Ok(generator.finish(&guard))
+}
+
+/// 表达式的值：C 表达式和它的 Mool 类型
+///
+/// 除字面量以外，值都先保存到临时变量中，保证子表达式按从左到右的顺序求值。
+pub(super) struct Value {
+    pub(super) code: String,
+    pub(super) ty: ast::Type,
+}
+
+impl Value {
+    /// 没有值的表达式（循环、break 等）与 LLVM 后端一样返回 int 0
+    pub(super) fn zero() -> Self {
+        Self {
+            code: "INT64_C(0)".to_string(),
+            ty: ast::Type::Int,
+        }
+    }
+}
+
+/// C 函数的签名
+#[derive(Clone)]
+pub(super) struct Signature {
+    pub(super) name: String,
+    pub(super) args: Vec<ast::Type>,
+    pub(super) rtn: ast::Type,
+}
+
+/// 正在生成的 C 函数
+#[derive(Default)]
+pub(super) struct Frame {
+    /// 在函数开头声明的变量，Mool 中块内定义的变量在块之后仍然可见
+    declarations: Vec<String>,
+    lines: Vec<String>,
+    pub(super) indent: usize,
+    /// Mool 变量对应的 C 变量和类型
+    variables: HashMap<String, (String, ast::Type)>,
+    names: HashSet<String>,
+    temps: usize,
+    pub(super) loops: usize,
+}
+
+impl Frame {
+    fn definition(self, prototype: &str, exported: bool) -> String {
+        let mut definition = String::new();
+        if !exported {
+            definition.push_str("static ");
+        }
+        definition.push_str(prototype);
+        definition.push_str(" {\n");
+        for line in self.declarations.iter().chain(self.lines.iter()) {
+            definition.push_str(line);
+            definition.push('\n');
+        }
+        definition.push('}');
+        definition
+    }
+}
+
+#[derive(Default)]
+pub(super) struct Generator {
+    pub(super) types: Types,
+    /// 张量算子的辅助函数，按名称去重
+    helpers: Vec<(String, String)>,
+    definitions: Vec<String>,
+    /// 头文件中导出的函数声明
+    exports: Vec<String>,
+    /// Mool 函数名对应的 C 函数，函数是全局的
+    pub(super) functions: HashMap<String, Signature>,
+    function_names: HashSet<String>,
+    /// 正在生成的函数，函数中定义的函数压在栈顶
+    frames: Vec<Frame>,
+}
+
+impl Generator {
+    pub(super) fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    /// 按当前的缩进添加一行代码
+    pub(super) fn line(&mut self, line: String) {
+        let frame = self.frame();
+        let line = format!("{}{}", "    ".repeat(frame.indent + 1), line);
+        frame.lines.push(line);
+    }
+
+    /// 当前函数已经生成的行数，配合 take_lines 取出一段代码
+    pub(super) fn line_count(&mut self) -> usize {
+        self.frame().lines.len()
+    }
+
+    pub(super) fn take_lines(&mut self, start: usize) -> Vec<String> {
+        self.frame().lines.split_off(start)
+    }
+
+    pub(super) fn extend_lines(&mut self, lines: Vec<String>) {
+        self.frame().lines.extend(lines);
+    }
+
+    /// 把值保存到新的临时变量中
+    pub(super) fn temp(&mut self, ty: &ast::Type, code: String) -> Result<Value, CError> {
+        let name = self.temp_name();
+        let type_name = self.types.name(ty)?;
+        self.line(format!("{} {} = {};", type_name, name, code));
+        Ok(Value {
+            code: name,
+            ty: ty.clone(),
+        })
+    }
+
+    pub(super) fn temp_name(&mut self) -> String {
+        let frame = self.frame();
+        frame.temps += 1;
+        format!("t_{}", frame.temps - 1)
+    }
+
+    /// 在函数开头声明变量，返回不与其他变量重名的 C 变量名
+    pub(super) fn declare(&mut self, ty: &ast::Type, name: &str) -> Result<String, CError> {
+        let type_name = self.types.name(ty)?;
+        let frame = self.frame();
+        let mut c_name = name.to_string();
+        let mut suffix = 0;
+        while frame.names.contains(&c_name) {
+            suffix += 1;
+            c_name = format!("{}_{}", name, suffix);
+        }
+        frame.names.insert(c_name.clone());
+        frame
+            .declarations
+            .push(format!("    {} {};", type_name, c_name));
+        Ok(c_name)
+    }
+
+    /// 记录 Mool 变量对应的 C 变量
+    pub(super) fn define(&mut self, name: &str, c_name: &str, ty: &ast::Type) {
+        self.frame()
+            .variables
+            .insert(name.to_string(), (c_name.to_string(), ty.clone()));
+    }
+
+    /// 当前函数中的变量
+    pub(super) fn lookup(&mut self, name: &str) -> Option<(String, ast::Type)> {
+        self.frame().variables.get(name).cloned()
+    }
+
+    pub(super) fn program(&mut self, program: ast::Program) -> Result<Value, CError> {
+        match program {
+            ast::Program::Let(variable, ast::Expr::Function(function)) => {
+                self.function(&variable.name, function)?;
+                Ok(Value::zero())
+            }
+            ast::Program::Let(variable, expr) => {
+                let value = self.expr(expr)?;
+                self.bind(&variable.name, value)
+            }
+            ast::Program::LetTuple(variables, expr) => {
+                let value = self.expr(expr)?;
+                let types = match &value.ty {
+                    ast::Type::Tuple(types) => types.clone(),
+                    _ => return Err(CError::new("只能解构元组")),
+                };
+                if types.len() != variables.len() {
+                    return Err(CError::new(format!(
+                        "元组有{}个元素，不能解构为{}个变量",
+                        types.len(),
+                        variables.len()
+                    )));
+                }
+                for (i, (variable, ty)) in variables.into_iter().zip(types).enumerate() {
+                    let element = Value {
+                        code: format!("{}.f{}", value.code, i),
+                        ty,
+                    };
+                    self.bind(&variable.name, element)?;
+                }
+                Ok(value)
+            }
+            ast::Program::Expr(expr) => self.expr(expr),
+        }
+    }
+
+    /// 把值绑定到变量，变量已经存在并且类型相同时更新值，否则声明新的变量
+    fn bind(&mut self, name: &str, value: Value) -> Result<Value, CError> {
+        let c_name = match self.lookup(name) {
+            Some((c_name, ty)) if ty == value.ty => c_name,
+            _ => {
+                let c_name = self.declare(&value.ty, &format!("v_{}", name))?;
+                self.define(name, &c_name, &value.ty);
+                c_name
+            }
+        };
+        self.line(format!("{} = {};", c_name, value.code));
+        Ok(Value {
+            code: c_name,
+            ty: value.ty,
+        })
+    }
+
+    /// 生成函数，顶层定义的函数导出到头文件
+    fn function(&mut self, name: &str, function: ast::Function) -> Result<(), CError> {
+        let mut c_name = format!("mool_fn_{}", name);
+        let mut suffix = 0;
+        while self.function_names.contains(&c_name) {
+            suffix += 1;
+            c_name = format!("mool_fn_{}_{}", name, suffix);
+        }
+        self.function_names.insert(c_name.clone());
+        // 形参直接作为 C 函数的参数
+        let mut frame = Frame::default();
+        let mut params = Vec::new();
+        for arg in function.args.iter() {
+            let param = format!("v_{}", arg.arg.name);
+            params.push(format!("{} {}", self.types.name(&arg.annotation)?, param));
+            frame.names.insert(param.clone());
+            frame
+                .variables
+                .insert(arg.arg.name.clone(), (param, arg.annotation.clone()));
+        }
+        let params = match params.is_empty() {
+            true => "void".to_string(),
+            false => params.join(", "),
+        };
+        let prototype = format!("{} {}({})", self.types.name(&function.rtn)?, c_name, params);
+        // 生成函数体，最后一个表达式的值为返回值
+        self.frames.push(frame);
+        let mut value = None;
+        for program in function.body {
+            value = Some(self.program(program)?);
+        }
+        let value = value.unwrap_or_else(Value::zero);
+        if value.ty != function.rtn {
+            return Err(CError::new(format!(
+                "函数%{}的返回值类型为{:?}，与声明的{:?}不一致",
+                name, value.ty, function.rtn
+            )));
+        }
+        self.line(format!("return {};", value.code));
+        let frame = self.frames.pop().unwrap();
+        // 只有顶层定义的函数导出
+        let exported = self.frames.len() == 1;
+        self.definitions
+            .push(frame.definition(&prototype, exported));
+        if exported {
+            self.exports.push(format!("{};", prototype));
+        }
+        self.functions.insert(
+            name.to_string(),
+            Signature {
+                name: c_name,
+                args: function
+                    .args
+                    .into_iter()
+                    .map(|arg| arg.annotation)
+                    .collect(),
+                rtn: function.rtn,
+            },
+        );
+        Ok(())
+    }
+
+    /// 添加张量算子的辅助函数，同名的函数只生成一次
+    pub(super) fn helper(&mut self, name: &str, definition: impl FnOnce() -> String) {
+        if !self.helpers.iter().any(|(known, _)| known == name) {
+            self.helpers.push((name.to_string(), definition()));
+        }
+    }
+
+    /// 拼接源文件和头文件，guard 为头文件的保护宏
+    fn finish(self, guard: &str) -> CCode {
+        let types = self.types.definitions().join("\n\n");
+        let mut source = String::from(
+            "/* 由 mool 生成 */\n\n#include <math.h>\n#include <stdbool.h>\n#include <stddef.h>\n#include <stdint.h>\n",
+        );
+        let helpers: Vec<String> = self.helpers.into_iter().map(|(_, helper)| helper).collect();
+        for section in [
+            types.clone(),
+            helpers.join("\n\n"),
+            self.definitions.join("\n\n"),
+        ] {
+            if !section.is_empty() {
+                source.push('\n');
+                source.push_str(&section);
+                source.push('\n');
+            }
+        }
+        let mut header = format!(
+            "/* 由 mool 生成 */\n\n#ifndef {0}\n#define {0}\n\n#include <stdbool.h>\n#include <stdint.h>\n\n#ifdef __cplusplus\nextern \"C\" {{\n#endif\n",
+            guard
+        );
+        if !types.is_empty() {
+            header.push('\n');
+            header.push_str(&types);
+            header.push('\n');
+        }
+        header.push('\n');
+        header.push_str(&self.exports.join("\n"));
+        header.push_str("\n\n#ifdef __cplusplus\n}\n#endif\n\n#endif\n");
+        CCode { source, header }
+    }
+}
@@ -0,0 +1,222 @@
+use super::codegen::{Generator, Value};
+use super::error::CError;
+use super::types::literal;
+use mool_ir::ast;
+
+impl Generator {
+    pub(super) fn expr(&mut self, expr: ast::Expr) -> Result<Value, CError> {
+        match expr {
+            ast::Expr::Literal(value) => {
+                let (code, ty) = literal(&value);
+                Ok(Value { code, ty })
+            }
+            ast::Expr::Variable(variable) => match self.lookup(&variable.name) {
+                // 先读到临时变量中，避免之后的赋值影响已经求值的表达式
+                Some((c_name, ty)) => self.temp(&ty, c_name),
+                None if self.functions.contains_key(&variable.name) => Err(CError::new(format!(
+                    "C 中不能把函数%{}作为值使用",
+                    variable.name
+                ))),
+                None => Err(CError::new(format!("没有找到变量%{}", variable.name))),
+            },
+            ast::Expr::Assign(variable, expr) => {
+                let value = self.expr(*expr)?;
+                match self.lookup(&variable.name) {
+                    Some((c_name, ty)) if ty == value.ty => {
+                        self.line(format!("{} = {};", c_name, value.code));
+                        Ok(value)
+                    }
+                    Some((_, ty)) => Err(CError::new(format!(
+                        "变量%{}的类型为{:?}，不能赋值为{:?}",
+                        variable.name, ty, value.ty
+                    ))),
+                    None => Err(CError::new(format!("变量%{}不存在", variable.name))),
+                }
+            }
+            ast::Expr::Function(_) => Err(CError::new("C 中函数只能用 let 绑定到变量")),
+            ast::Expr::Call(name, exprs) => {
+                let mut args = Vec::new();
+                for expr in exprs {
+                    args.push(self.expr(expr)?);
+                }
+                let signature = match self.functions.get(&name) {
+                    Some(signature) => signature.clone(),
+                    None => return Err(CError::new(format!("没有找到函数%{}", name))),
+                };
+                if args.len() != signature.args.len() {
+                    return Err(CError::new(format!(
+                        "函数%{}有{}个参数，调用时传入了{}个",
+                        name,
+                        signature.args.len(),
+                        args.len()
+                    )));
+                }
+                for (i, (arg, ty)) in args.iter().zip(signature.args.iter()).enumerate() {
+                    if arg.ty != *ty {
+                        return Err(CError::new(format!(
+                            "函数%{}的第{}个参数类型为{:?}，传入了{:?}",
+                            name,
+                            i + 1,
+                            ty,
+                            arg.ty
+                        )));
+                    }
+                }
+                let args: Vec<String> = args.into_iter().map(|arg| arg.code).collect();
+                self.temp(
+                    &signature.rtn,
+                    format!("{}({})", signature.name, args.join(", ")),
+                )
+            }
+            ast::Expr::Operator(operator) => self.operator(operator),
+            ast::Expr::Tuple(exprs) => {
+                let mut values = Vec::new();
+                for expr in exprs {
+                    values.push(self.expr(expr)?);
+                }
+                let ty = ast::Type::Tuple(values.iter().map(|value| value.ty.clone()).collect());
+                let fields: Vec<String> = values.into_iter().map(|value| value.code).collect();
+                self.temp(&ty, format!("{{{}}}", fields.join(", ")))
+            }
+            ast::Expr::Field(expr, index) => {
+                let tuple = self.expr(*expr)?;
+                let ty = match &tuple.ty {
+                    ast::Type::Tuple(types) if index < types.len() => types[index].clone(),
+                    ast::Type::Tuple(types) => {
+                        return Err(CError::new(format!(
+                            "元组只有{}个元素，下标{}越界",
+                            types.len(),
+                            index
+                        )))
+                    }
+                    _ => return Err(CError::new("只能取元组的元素")),
+                };
+                self.temp(&ty, format!("{}.f{}", tuple.code, index))
+            }
+            ast::Expr::If(if_else) => self.if_else(if_else),
+            ast::Expr::For(for_loop) => self.for_loop(for_loop),
+            ast::Expr::While(while_loop) => self.while_loop(while_loop),
+            ast::Expr::Break => self.jump("break"),
+            ast::Expr::Continue => self.jump("continue"),
+        }
+    }
+
+    /// 两个分支的值类型相同时，if 表达式的值保存在函数开头声明的变量中
+    fn if_else(&mut self, if_else: ast::If) -> Result<Value, CError> {
+        let cond = self.expr(*if_else.cond)?;
+        if cond.ty != ast::Type::Bool {
+            return Err(CError::new("if 的条件必须为 bool 类型"));
+        }
+        let (mut then_lines, then_value) = self.branch(if_else.then)?;
+        let (mut else_lines, else_value) = self.branch(if_else.otherwise)?;
+        let indent = "    ".repeat(self.frame().indent + 2);
+        let result = match (then_value, else_value) {
+            (Some(then_value), Some(else_value)) if then_value.ty == else_value.ty => {
+                let name = self.temp_name();
+                let name = self.declare(&then_value.ty, &name)?;
+                then_lines.push(format!("{}{} = {};", indent, name, then_value.code));
+                else_lines.push(format!("{}{} = {};", indent, name, else_value.code));
+                Some(Value {
+                    code: name,
+                    ty: then_value.ty,
+                })
+            }
+            _ => None,
+        };
+        self.line(format!("if ({}) {{", cond.code));
+        self.extend_lines(then_lines);
+        self.line("} else {".to_string());
+        self.extend_lines(else_lines);
+        self.line("}".to_string());
+        Ok(result.unwrap_or_else(Value::zero))
+    }
+
+    /// 生成分支内的代码并取出，分支为空时没有值
+    fn branch(
+        &mut self,
+        programs: Vec<ast::Program>,
+    ) -> Result<(Vec<String>, Option<Value>), CError> {
+        let start = self.line_count();
+        self.frame().indent += 1;
+        let mut value = None;
+        for program in programs {
+            value = Some(self.program(program)?);
+        }
+        self.frame().indent -= 1;
+        Ok((self.take_lines(start), value))
+    }
+
+    /// 步长为正时循环到 i < end，步长为负时循环到 i > end
+    fn for_loop(&mut self, for_loop: ast::For) -> Result<Value, CError> {
+        let start = self.expr(*for_loop.start)?;
+        let end = self.expr(*for_loop.end)?;
+        let step = self.expr(*for_loop.step)?;
+        if [&start, &end, &step]
+            .iter()
+            .any(|value| value.ty != ast::Type::Int)
+        {
+            return Err(CError::new("for 循环的范围必须为 int 类型"));
+        }
+        // 归纳变量和其他变量一样在函数开头声明，循环结束后仍然可见
+        let induction = match self.lookup(&for_loop.var.name) {
+            Some((c_name, ast::Type::Int)) => c_name,
+            Some(_) => {
+                return Err(CError::new(format!(
+                    "for 循环的变量%{}必须为 int 类型",
+                    for_loop.var.name
+                )))
+            }
+            None => {
+                let c_name = self.declare(&ast::Type::Int, &format!("v_{}", for_loop.var.name))?;
+                self.define(&for_loop.var.name, &c_name, &ast::Type::Int);
+                c_name
+            }
+        };
+        self.line(format!(
+            "for ({i} = {}; {step} > 0 ? {i} < {end} : {i} > {end}; {i} += {step}) {{",
+            start.code,
+            i = induction,
+            end = end.code,
+            step = step.code
+        ));
+        self.loop_body(for_loop.body)?;
+        self.line("}".to_string());
+        Ok(Value::zero())
+    }
+
+    /// 条件在循环体开头求值，continue 跳回开头重新判断条件
+    fn while_loop(&mut self, while_loop: ast::While) -> Result<Value, CError> {
+        self.line("while (true) {".to_string());
+        self.frame().indent += 1;
+        let cond = self.expr(*while_loop.cond)?;
+        if cond.ty != ast::Type::Bool {
+            return Err(CError::new("while 的条件必须为 bool 类型"));
+        }
+        self.line(format!("if (!{}) break;", cond.code));
+        self.frame().indent -= 1;
+        self.loop_body(while_loop.body)?;
+        self.line("}".to_string());
+        Ok(Value::zero())
+    }
+
+    fn loop_body(&mut self, programs: Vec<ast::Program>) -> Result<(), CError> {
+        let frame = self.frame();
+        frame.indent += 1;
+        frame.loops += 1;
+        for program in programs {
+            self.program(program)?;
+        }
+        let frame = self.frame();
+        frame.indent -= 1;
+        frame.loops -= 1;
+        Ok(())
+    }
+
+    fn jump(&mut self, keyword: &str) -> Result<Value, CError> {
+        if self.frame().loops == 0 {
+            return Err(CError::new(format!("{} 只能出现在循环中", keyword)));
+        }
+        self.line(format!("{};", keyword));
+        Ok(Value::zero())
+    }
+}
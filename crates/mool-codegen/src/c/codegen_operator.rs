@@ -0,0 +1,394 @@
+use super::codegen::{Generator, Value};
+use super::error::CError;
+use super::types::{literal, tensor_length};
+use mool_ir::ast;
+use mool_ir::pass::is_elementwise;
+
+/// 按元素计算的二元算子
+#[derive(Clone, Copy)]
+enum Binary {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Binary {
+    fn name(self) -> &'static str {
+        match self {
+            Binary::Add => "add",
+            Binary::Sub => "sub",
+            Binary::Mul => "mul",
+            Binary::Div => "div",
+            Binary::Lt => "lt",
+            Binary::Le => "le",
+            Binary::Gt => "gt",
+            Binary::Ge => "ge",
+            Binary::Eq => "eq",
+            Binary::Ne => "ne",
+        }
+    }
+
+    fn is_compare(self) -> bool {
+        !matches!(self, Binary::Add | Binary::Sub | Binary::Mul | Binary::Div)
+    }
+
+    /// 标量的计算，整数除法与 LLVM 后端一样按无符号数相除
+    fn apply(self, dtype: &ast::Type, x: &str, y: &str) -> String {
+        let symbol = match self {
+            Binary::Add => "+",
+            Binary::Sub => "-",
+            Binary::Mul => "*",
+            Binary::Div if *dtype == ast::Type::Int => {
+                return format!("(int64_t)((uint64_t){} / (uint64_t){})", x, y)
+            }
+            Binary::Div => "/",
+            Binary::Lt => "<",
+            Binary::Le => "<=",
+            Binary::Gt => ">",
+            Binary::Ge => ">=",
+            Binary::Eq => "==",
+            Binary::Ne => "!=",
+        };
+        format!("({} {} {})", x, symbol, y)
+    }
+
+    /// 结果的元素类型，比较的结果为 bool
+    fn result_type(self, dtype: &ast::Type) -> ast::Type {
+        match self.is_compare() {
+            true => ast::Type::Bool,
+            false => dtype.clone(),
+        }
+    }
+}
+
+/// relu(x) = x > 0 ? x : 0
+fn relu(x: &str) -> String {
+    format!("({x} > 0 ? {x} : 0)", x = x)
+}
+
+fn is_scalar(ty: &ast::Type) -> bool {
+    matches!(ty, ast::Type::Int | ast::Type::Float | ast::Type::Bool)
+}
+
+/// 张量的元素类型，其余类型为自身
+fn element_type(ty: &ast::Type) -> &ast::Type {
+    match ty {
+        ast::Type::Tensor(_, dtype) => dtype,
+        ty => ty,
+    }
+}
+
+impl Generator {
+    pub(super) fn operator(&mut self, operator: ast::Operator) -> Result<Value, CError> {
+        match operator {
+            ast::Operator::Tensor(literals) => self.tensor(literals),
+            ast::Operator::Add(x, y) => self.binary(Binary::Add, *x, *y),
+            ast::Operator::Sub(x, y) => self.binary(Binary::Sub, *x, *y),
+            ast::Operator::Mul(x, y) => self.binary(Binary::Mul, *x, *y),
+            ast::Operator::Div(x, y) => self.binary(Binary::Div, *x, *y),
+            ast::Operator::Lt(x, y) => self.binary(Binary::Lt, *x, *y),
+            ast::Operator::Le(x, y) => self.binary(Binary::Le, *x, *y),
+            ast::Operator::Gt(x, y) => self.binary(Binary::Gt, *x, *y),
+            ast::Operator::Ge(x, y) => self.binary(Binary::Ge, *x, *y),
+            ast::Operator::Eq(x, y) => self.binary(Binary::Eq, *x, *y),
+            ast::Operator::Ne(x, y) => self.binary(Binary::Ne, *x, *y),
+            ast::Operator::Matmul(x, y) => self.matmul(*x, *y),
+            ast::Operator::Relu(x) => self.relu(*x),
+            ast::Operator::Fused(x) => self.fused(*x),
+            // 张量是定长数组的结构体，不需要工作区
+            ast::Operator::Workspace(x, _, _) => self.expr(*x),
+        }
+    }
+
+    /// 张量字面量，元素类型必须相同
+    fn tensor(&mut self, literals: Vec<ast::Literal>) -> Result<Value, CError> {
+        let elements: Vec<(String, ast::Type)> = literals.iter().map(literal).collect();
+        let dtype = match elements.first() {
+            Some((_, dtype)) => dtype.clone(),
+            None => return Err(CError::new("张量不能为空")),
+        };
+        if elements.iter().any(|(_, ty)| *ty != dtype) {
+            return Err(CError::new("张量的元素类型必须相同"));
+        }
+        let ty = ast::Type::Tensor(vec![ast::Dim::Const(elements.len())], Box::new(dtype));
+        let codes: Vec<String> = elements.into_iter().map(|(code, _)| code).collect();
+        self.temp(&ty, format!("{{{{{}}}}}", codes.join(", ")))
+    }
+
+    /// 标量直接计算，张量调用逐个元素计算的辅助函数，两侧类型不同时广播计算
+    fn binary(&mut self, binary: Binary, x: ast::Expr, y: ast::Expr) -> Result<Value, CError> {
+        let x = self.expr(x)?;
+        let y = self.expr(y)?;
+        let is_tensor = |ty: &ast::Type| matches!(ty, ast::Type::Tensor(_, _));
+        if x.ty != y.ty
+            && (is_tensor(&x.ty) || is_tensor(&y.ty))
+            && element_type(&x.ty) == element_type(&y.ty)
+        {
+            return self.broadcast(&[x, y], |elements| {
+                let (x, dtype) = &elements[0];
+                Ok((
+                    binary.apply(dtype, x, &elements[1].0),
+                    binary.result_type(dtype),
+                ))
+            });
+        }
+        if x.ty != y.ty {
+            return Err(CError::new(format!(
+                "{} 算子两侧的类型必须相等，左侧为{:?}，右侧为{:?}",
+                binary.name(),
+                x.ty,
+                y.ty
+            )));
+        }
+        match &x.ty {
+            ty if is_scalar(ty) => {
+                self.temp(&binary.result_type(ty), binary.apply(ty, &x.code, &y.code))
+            }
+            ast::Type::Tensor(shape, dtype) => {
+                let result_type =
+                    ast::Type::Tensor(shape.clone(), Box::new(binary.result_type(dtype)));
+                let helper = self.tensor_helper(binary.name(), &x.ty, &result_type, |x, y| {
+                    binary.apply(dtype, x, y)
+                })?;
+                self.temp(&result_type, format!("{}({}, {})", helper, x.code, y.code))
+            }
+            ty => Err(CError::new(format!(
+                "{} 算子不支持{:?}类型",
+                binary.name(),
+                ty
+            ))),
+        }
+    }
+
+    /// 一维张量的矩阵乘法即为向量点积，标量直接相乘
+    fn matmul(&mut self, x: ast::Expr, y: ast::Expr) -> Result<Value, CError> {
+        let x = self.expr(x)?;
+        let y = self.expr(y)?;
+        if x.ty != y.ty {
+            return Err(CError::new("矩阵乘法中张量类型必须相等"));
+        }
+        match &x.ty {
+            ty if is_scalar(ty) => self.temp(ty, Binary::Mul.apply(ty, &x.code, &y.code)),
+            ast::Type::Tensor(shape, dtype) => {
+                let size = tensor_length(shape)?;
+                let operand = self.types.name(&x.ty)?;
+                let element = self.types.name(dtype)?;
+                let name = format!("mool_matmul_{}", operand.trim_start_matches("mool_"));
+                let product = Binary::Mul.apply(dtype, "x.data[i]", "y.data[i]");
+                self.helper(&name, || {
+                    format!(
+                        "static {element} {name}({operand} x, {operand} y) {{\n    {element} result = 0;\n    for (size_t i = 0; i < {size}; i++) {{\n        result += {product};\n    }}\n    return result;\n}}",
+                        element = element,
+                        name = name,
+                        operand = operand,
+                        size = size,
+                        product = product
+                    )
+                });
+                self.temp(dtype, format!("{}({}, {})", name, x.code, y.code))
+            }
+            ty => Err(CError::new(format!("matmul 算子不支持{:?}类型", ty))),
+        }
+    }
+
+    fn relu(&mut self, x: ast::Expr) -> Result<Value, CError> {
+        let x = self.expr(x)?;
+        match &x.ty {
+            ty if is_scalar(ty) => self.temp(ty, relu(&x.code)),
+            ast::Type::Tensor(_, _) => {
+                let ty = x.ty.clone();
+                let helper = self.tensor_helper("relu", &ty, &ty, |x, _| relu(x))?;
+                self.temp(&ty, format!("{}({})", helper, x.code))
+            }
+            ty => Err(CError::new(format!("relu 算子不支持{:?}类型", ty))),
+        }
+    }
+
+    /// 生成逐个元素计算的辅助函数，element 由两个操作数的元素生成结果的元素，返回函数名
+    fn tensor_helper(
+        &mut self,
+        operator: &str,
+        operand_type: &ast::Type,
+        result_type: &ast::Type,
+        element: impl Fn(&str, &str) -> String,
+    ) -> Result<String, CError> {
+        let size = match operand_type {
+            ast::Type::Tensor(shape, _) => tensor_length(shape)?,
+            _ => unreachable!(),
+        };
+        let operand = self.types.name(operand_type)?;
+        let result = self.types.name(result_type)?;
+        let name = format!("mool_{}_{}", operator, operand.trim_start_matches("mool_"));
+        // relu 只有一个操作数
+        let (params, body) = match operator {
+            "relu" => (format!("{} x", operand), element("x.data[i]", "")),
+            _ => (
+                format!("{operand} x, {operand} y", operand = operand),
+                element("x.data[i]", "y.data[i]"),
+            ),
+        };
+        self.helper(&name, || {
+            format!(
+                "static {result} {name}({params}) {{\n    {result} result;\n    for (size_t i = 0; i < {size}; i++) {{\n        result.data[i] = {body};\n    }}\n    return result;\n}}",
+                result = result,
+                name = name,
+                params = params,
+                size = size,
+                body = body
+            )
+        });
+        Ok(name)
+    }
+
+    /// 融合算子：先按计算顺序求值链的叶子，再用一个循环逐个元素计算整条链
+    fn fused(&mut self, chain: ast::Expr) -> Result<Value, CError> {
+        let mut leaves = Vec::new();
+        collect_leaves(&chain, &mut leaves);
+        let mut values = Vec::new();
+        for leaf in leaves {
+            values.push(self.expr(leaf)?);
+        }
+        self.broadcast(&values, |elements| chain_element(&chain, elements, &mut 0))
+    }
+
+    /// 用一个循环逐个元素计算，compute 由输入在第 i 个元素处的值和元素类型生成结果的元素和类型。
+    /// 标量和长度为 1 的张量广播到所有元素。
+    fn broadcast(
+        &mut self,
+        values: &[Value],
+        compute: impl FnOnce(&[(String, ast::Type)]) -> Result<(String, ast::Type), CError>,
+    ) -> Result<Value, CError> {
+        // 结果的形状和长度，全部为标量时长度为 0
+        let mut shape = Vec::new();
+        let mut length = 0;
+        for value in values.iter() {
+            match &value.ty {
+                ast::Type::Tensor(value_shape, _) => {
+                    let size = tensor_length(value_shape)?;
+                    match (length, size) {
+                        (0, _) | (1, _) => {
+                            shape = value_shape.clone();
+                            length = size;
+                        }
+                        (_, 1) => {}
+                        _ if size == length => {}
+                        _ => return Err(CError::new("逐个元素计算的张量长度必须相等或为 1")),
+                    }
+                }
+                ty if is_scalar(ty) => {}
+                ty => return Err(CError::new(format!("逐个元素计算不支持{:?}类型", ty))),
+            }
+        }
+        // 叶子在第 i 个元素处的值
+        let elements: Vec<(String, ast::Type)> = values
+            .iter()
+            .map(|value| match &value.ty {
+                ast::Type::Tensor(shape, dtype) if ast::numel(shape) == Some(1) => {
+                    (format!("{}.data[0]", value.code), dtype.as_ref().clone())
+                }
+                ast::Type::Tensor(_, dtype) => {
+                    (format!("{}.data[i]", value.code), dtype.as_ref().clone())
+                }
+                ty => (value.code.clone(), ty.clone()),
+            })
+            .collect();
+        let (body, element_type) = compute(&elements)?;
+        if length == 0 {
+            return self.temp(&element_type, body);
+        }
+        let ty = ast::Type::Tensor(shape, Box::new(element_type));
+        let type_name = self.types.name(&ty)?;
+        let name = self.temp_name();
+        self.line(format!("{} {};", type_name, name));
+        self.line(format!("for (size_t i = 0; i < {}; i++) {{", length));
+        self.line(format!("    {}.data[i] = {};", name, body));
+        self.line("}".to_string());
+        Ok(Value { code: name, ty })
+    }
+}
+
+/// 按计算顺序收集链的叶子
+fn collect_leaves(expr: &ast::Expr, leaves: &mut Vec<ast::Expr>) {
+    match expr {
+        ast::Expr::Operator(operator) if is_elementwise(operator) => {
+            for operand in operands(operator) {
+                collect_leaves(operand, leaves);
+            }
+        }
+        leaf => leaves.push(leaf.clone()),
+    }
+}
+
+/// 按元素计算的算子的操作数
+fn operands(operator: &ast::Operator) -> Vec<&ast::Expr> {
+    match operator {
+        ast::Operator::Add(x, y)
+        | ast::Operator::Sub(x, y)
+        | ast::Operator::Mul(x, y)
+        | ast::Operator::Div(x, y)
+        | ast::Operator::Lt(x, y)
+        | ast::Operator::Le(x, y)
+        | ast::Operator::Gt(x, y)
+        | ast::Operator::Ge(x, y)
+        | ast::Operator::Eq(x, y)
+        | ast::Operator::Ne(x, y) => vec![x, y],
+        ast::Operator::Relu(x) => vec![x],
+        _ => Vec::new(),
+    }
+}
+
+fn binary(operator: &ast::Operator) -> Option<Binary> {
+    match operator {
+        ast::Operator::Add(_, _) => Some(Binary::Add),
+        ast::Operator::Sub(_, _) => Some(Binary::Sub),
+        ast::Operator::Mul(_, _) => Some(Binary::Mul),
+        ast::Operator::Div(_, _) => Some(Binary::Div),
+        ast::Operator::Lt(_, _) => Some(Binary::Lt),
+        ast::Operator::Le(_, _) => Some(Binary::Le),
+        ast::Operator::Gt(_, _) => Some(Binary::Gt),
+        ast::Operator::Ge(_, _) => Some(Binary::Ge),
+        ast::Operator::Eq(_, _) => Some(Binary::Eq),
+        ast::Operator::Ne(_, _) => Some(Binary::Ne),
+        _ => None,
+    }
+}
+
+/// 用标量表达式计算整条链，返回表达式和元素类型。
+/// leaves 为按计算顺序排列的叶子在当前元素处的值和元素类型，比较的结果为 bool，其余算子与操作数相同
+fn chain_element(
+    expr: &ast::Expr,
+    leaves: &[(String, ast::Type)],
+    next: &mut usize,
+) -> Result<(String, ast::Type), CError> {
+    let operator = match expr {
+        ast::Expr::Operator(operator) if is_elementwise(operator) => operator,
+        _ => {
+            *next += 1;
+            return Ok(leaves[*next - 1].clone());
+        }
+    };
+    let mut codes = Vec::new();
+    let mut types = Vec::new();
+    for operand in operands(operator) {
+        let (code, ty) = chain_element(operand, leaves, next)?;
+        codes.push(code);
+        types.push(ty);
+    }
+    if types.iter().any(|ty| *ty != types[0]) {
+        return Err(CError::new("融合算子中两侧类型必须相等"));
+    }
+    Ok(match binary(operator) {
+        Some(binary) => (
+            binary.apply(&types[0], &codes[0], &codes[1]),
+            binary.result_type(&types[0]),
+        ),
+        None => (relu(&codes[0]), types[0].clone()),
+    })
+}
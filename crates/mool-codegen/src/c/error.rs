@@ -0,0 +1,23 @@
+use std::fmt;
+
+/// 生成 C 代码时的错误，例如类型不匹配或者 C 中无法表示的写法
+#[derive(Debug, Clone, PartialEq)]
+pub struct CError {
+    message: String,
+}
+
+impl CError {
+    pub(super) fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for CError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "生成 C 代码失败：{}", self.message)
+    }
+}
+
+impl std::error::Error for CError {}
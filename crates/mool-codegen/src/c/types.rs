@@ -0,0 +1,104 @@
+use super::error::CError;
+use mool_ir::ast::{Dim, Literal, Type};
+
+/// Mool 类型对应的 C 类型
+///
+/// 张量和元组都用结构体表示，这样可以像 LLVM 的向量和结构体一样按值传递和返回。
+#[derive(Default)]
+pub(super) struct Types {
+    names: Vec<(Type, String)>,
+    /// 按依赖顺序排列的结构体定义
+    definitions: Vec<String>,
+    tuples: usize,
+}
+
+impl Types {
+    /// 类型在 C 中的名称，结构体在第一次使用时定义
+    pub(super) fn name(&mut self, ty: &Type) -> Result<String, CError> {
+        match ty {
+            Type::Int => return Ok("int64_t".to_string()),
+            Type::Float => return Ok("double".to_string()),
+            Type::Bool => return Ok("bool".to_string()),
+            _ => {}
+        }
+        if let Some((_, name)) = self.names.iter().find(|(known, _)| known == ty) {
+            return Ok(name.clone());
+        }
+        let (name, fields) = match ty {
+            Type::Tensor(shape, dtype) => {
+                let size = tensor_length(shape)?;
+                if size == 0 {
+                    return Err(CError::new("张量的长度不能为 0"));
+                }
+                let dims: Vec<String> = shape.iter().map(Dim::to_string).collect();
+                let suffix = match dtype.as_ref() {
+                    Type::Int => "i64",
+                    Type::Float => "f64",
+                    Type::Bool => "bool",
+                    _ => return Err(CError::new(format!("张量的元素必须是标量：{:?}", dtype))),
+                };
+                (
+                    format!("mool_tensor_{}_{}", dims.join("x"), suffix),
+                    vec![format!("{} data[{}];", self.name(dtype)?, size)],
+                )
+            }
+            Type::Tuple(types) => {
+                if types.is_empty() {
+                    return Err(CError::new("C 中不能表示空元组"));
+                }
+                let mut fields = Vec::new();
+                for (i, ty) in types.iter().enumerate() {
+                    fields.push(format!("{} f{};", self.name(ty)?, i));
+                }
+                self.tuples += 1;
+                (format!("mool_tuple_{}", self.tuples - 1), fields)
+            }
+            Type::Int | Type::Float | Type::Bool => unreachable!(),
+        };
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|field| format!("    {}", field))
+            .collect();
+        self.definitions.push(format!(
+            "typedef struct {} {{\n{}\n}} {};",
+            name,
+            fields.join("\n"),
+            name
+        ));
+        self.names.push((ty.clone(), name.clone()));
+        Ok(name)
+    }
+
+    /// 所有用到的结构体定义
+    pub(super) fn definitions(&self) -> &[String] {
+        &self.definitions
+    }
+}
+
+/// 张量的元素个数，C 中张量为定长数组，不支持符号维度
+pub(super) fn tensor_length(shape: &[Dim]) -> Result<usize, CError> {
+    shape.iter().try_fold(1, |length, dim| match dim {
+        Dim::Const(size) => Ok(length * size),
+        Dim::Symbol(symbol) => Err(CError::new(format!(
+            "C 中张量的形状必须是常量，不支持符号维度{}",
+            symbol
+        ))),
+    })
+}
+
+/// 字面量的 C 代码和类型
+pub(super) fn literal(literal: &Literal) -> (String, Type) {
+    match *literal {
+        Literal::Int(i64::MIN) => ("INT64_MIN".to_string(), Type::Int),
+        Literal::Int(value) => (format!("INT64_C({})", value), Type::Int),
+        Literal::Float(value) if value.is_nan() => ("NAN".to_string(), Type::Float),
+        Literal::Float(value) if value.is_infinite() => {
+            let sign = if value > 0.0 { "" } else { "-" };
+            (format!("({}HUGE_VAL)", sign), Type::Float)
+        }
+        // Debug 格式保留完整精度，并且总是带有小数点或指数
+        Literal::Float(value) if value < 0.0 => (format!("({:?})", value), Type::Float),
+        Literal::Float(value) => (format!("{:?}", value), Type::Float),
+        Literal::Bool(value) => (value.to_string(), Type::Bool),
+    }
+}
@@ -1,2 +1,4 @@
+pub mod backend;
+pub mod c;
 pub mod llvm;
 mod scope;
@@ -1,7 +1,25 @@
+mod backend;
 mod codegen;
 mod codegen_expr;
+mod codegen_fused;
+mod codegen_if;
 mod codegen_literal;
+mod codegen_loop;
 mod codegen_operator;
 mod codegen_program;
+mod context;
+mod error;
+mod optimize;
+mod runtime;
+mod target;
+mod value;
+mod verify;
+mod wasm;
 
+pub use backend::LlvmBackend;
 pub use codegen::codegen;
+pub use context::CodegenContext;
+pub use error::CodegenError;
+pub use optimize::OptLevel;
+pub use value::{Type, Value};
+pub use wasm::WasmBackend;
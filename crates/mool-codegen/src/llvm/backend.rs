@@ -0,0 +1,60 @@
+use super::super::backend::{Artifact, Backend};
+use super::codegen::{codegen, lower};
+use super::context::CodegenContext;
+use super::optimize::OptLevel;
+use super::target::TargetMachine;
+use mool_ir::ast::Program;
+use std::error::Error;
+
+/// LLVM 后端，输出 LLVM IR 代码（.ll）
+///
+/// 指定 target triple 时按目标平台设置模块的 triple 和数据布局，同时输出目标文件（.o），
+/// 可以在同一台机器上为不同的平台交叉编译。
+#[derive(Debug, Clone, Default)]
+pub struct LlvmBackend {
+    level: OptLevel,
+    triple: Option<String>,
+    cpu: String,
+    features: String,
+}
+
+impl LlvmBackend {
+    pub fn new(level: OptLevel) -> Self {
+        Self {
+            level,
+            ..Self::default()
+        }
+    }
+
+    /// 设置目标平台，例如 x86_64-unknown-linux-gnu、aarch64-linux-gnu，
+    /// cpu 为空时使用 generic，features 为 LLVM 的目标特性，例如 +avx2
+    pub fn target(mut self, triple: &str, cpu: &str, features: &str) -> Self {
+        self.triple = Some(triple.to_string());
+        self.cpu = cpu.to_string();
+        self.features = features.to_string();
+        self
+    }
+}
+
+impl Backend for LlvmBackend {
+    fn name(&self) -> &'static str {
+        "llvm"
+    }
+
+    fn codegen(&self, programs: Vec<Program>) -> Result<Vec<Artifact>, Box<dyn Error>> {
+        let triple = match &self.triple {
+            Some(triple) => triple,
+            None => {
+                let llvm_code = codegen(programs, self.level)?;
+                return Ok(vec![Artifact::new("ll", llvm_code)]);
+            }
+        };
+        let machine = TargetMachine::new(triple, &self.cpu, &self.features, self.level)?;
+        let ctx = CodegenContext::default();
+        lower(&ctx, programs, self.level, Some(&machine))?;
+        Ok(vec![
+            Artifact::new("ll", ctx.print()),
+            Artifact::new("o", machine.emit_object(&ctx)?),
+        ])
+    }
+}
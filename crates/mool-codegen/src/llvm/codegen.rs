@@ -1,53 +1,80 @@
 use super::super::scope::Scope;
-use super::codegen_program::codegen_program;
+use super::codegen_program::{codegen_program, codegen_release_owned};
+use super::context::CodegenContext;
+use super::error::CodegenError;
+use super::optimize::{optimize, OptLevel};
+use super::runtime::{build_release, build_reserve};
+use super::target::TargetMachine;
+use super::verify::verify;
 use llvm_sys as llvm;
 use mool_ir::ast;
-use std::ffi::CStr;
+use mool_ir::pass::workspace_size;
 use std::ptr;
 
-pub unsafe fn codegen(programs: Vec<ast::Program>) -> String {
-    // 创建context、module、builder、names
-    let context = llvm::core::LLVMContextCreate();
-    let module = llvm::core::LLVMModuleCreateWithName(b"example_moddule\0".as_ptr() as *const _);
-    let builder = llvm::core::LLVMCreateBuilderInContext(context);
-    let mut scope = Scope::new();
-
-    // 创建main函数
-    let int_type = llvm::core::LLVMInt64TypeInContext(context);
-    let function_type = llvm::core::LLVMFunctionType(int_type, ptr::null_mut(), 0, 0);
-    let function =
-        llvm::core::LLVMAddFunction(module, b"main\0".as_ptr() as *const _, function_type);
-
-    // 创建BasicBlock
-    let basic_block = llvm::core::LLVMAppendBasicBlockInContext(
-        context,
-        function,
-        b"entry\0".as_ptr() as *const _,
-    );
-    llvm::core::LLVMPositionBuilderAtEnd(builder, basic_block);
+/// 将 Mool 抽象语法树翻译为 LLVM IR 代码，并按 level 运行 LLVM 的优化流水线
+///
+/// 生成的模块先经过 LLVM 的验证，不合法时返回出错的函数和验证器的信息，不输出 LLVM IR。
+pub fn codegen(programs: Vec<ast::Program>, level: OptLevel) -> Result<String, CodegenError> {
+    // 创建 context、module、builder，离开作用域时自动释放
+    let ctx = CodegenContext::default();
+    lower(&ctx, programs, level, None)?;
+    Ok(ctx.print())
+}
 
-    // 根据AST生成代码
-    for program in programs {
-        codegen_program(context, module, builder, basic_block, &mut scope, program);
-        // 重置 builder 的位置
-        llvm::core::LLVMPositionBuilderAtEnd(builder, basic_block);
+/// 生成、验证并优化模块，指定目标机器时先设置模块的 triple 和数据布局
+pub(super) fn lower(
+    ctx: &CodegenContext,
+    programs: Vec<ast::Program>,
+    level: OptLevel,
+    machine: Option<&TargetMachine>,
+) -> Result<(), CodegenError> {
+    if let Some(machine) = machine {
+        machine.configure(ctx);
     }
+    let scope = build(ctx, programs);
+    verify(ctx, &scope)?;
+    optimize(ctx, level, machine)
+}
+
+/// 在 ctx 的模块中生成顶层代码对应的 main 函数和其中定义的函数，返回全局作用域
+///
+/// 经过内存规划时，main 在开头分配整个程序需要的工作区。
+pub(super) fn build(ctx: &CodegenContext, programs: Vec<ast::Program>) -> Scope {
+    let mut scope = Scope::new();
+    let workspace = workspace_size(&programs);
 
-    // 设置 main 函数默认返回值 0
-    let default_return = llvm::core::LLVMConstInt(int_type, 0, 0);
-    llvm::core::LLVMBuildRet(builder, default_return);
+    unsafe {
+        // 创建main函数
+        let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+        let function_type = llvm::core::LLVMFunctionType(int_type, ptr::null_mut(), 0, 0);
+        let function = llvm::core::LLVMAddFunction(
+            ctx.module(),
+            b"main\0".as_ptr() as *const _,
+            function_type,
+        );
+        scope.name_function(function, "顶层代码".to_string());
 
-    // 保存 LLVM IR 代码
-    let module_string: String = CStr::from_ptr(llvm::core::LLVMPrintModuleToString(module))
-        .to_str()
-        .unwrap()
-        .to_owned();
+        // 创建BasicBlock
+        let basic_block = llvm::core::LLVMAppendBasicBlockInContext(
+            ctx.context(),
+            function,
+            b"entry\0".as_ptr() as *const _,
+        );
+        llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), basic_block);
+        if workspace > 0 {
+            build_reserve(ctx, workspace);
+        }
 
-    // 清理
-    llvm::core::LLVMDisposeBuilder(builder);
-    llvm::core::LLVMDisposeModule(module);
-    llvm::core::LLVMContextDispose(context);
+        // 根据AST生成代码，顶层表达式的值不再使用
+        for program in programs {
+            let value = codegen_program(ctx, basic_block, &mut scope, program);
+            build_release(ctx, value);
+        }
+        codegen_release_owned(ctx, &scope);
 
-    // 返回 LLVM IR 代码
-    module_string
+        // 设置 main 函数默认返回值 0
+        let default_return = llvm::core::LLVMConstInt(int_type, 0, 0);
+        llvm::core::LLVMBuildRet(ctx.builder(), default_return);
+    }
+    scope
 }
@@ -1,30 +1,34 @@
 use super::super::scope::Scope;
+use super::codegen_if::codegen_if;
 use super::codegen_literal::codegen_literal;
+use super::codegen_loop::{codegen_for, codegen_jump, codegen_while};
 use super::codegen_operator::codegen_operator;
-use super::codegen_program::codegen_program;
+use super::codegen_program::{
+    codegen_alloca, codegen_program, codegen_release_owned, codegen_store,
+};
+use super::context::CodegenContext;
+use super::runtime::{build_check_shape, build_release, build_retain, tensor_type};
 use llvm_sys as llvm;
 use mool_ir::ast;
-use regex::Regex;
+use std::collections::HashMap;
 use std::vec::Vec;
 
 pub unsafe fn codegen_expr(
-    context: llvm::prelude::LLVMContextRef,
-    module: llvm::prelude::LLVMModuleRef,
-    builder: llvm::prelude::LLVMBuilderRef,
+    ctx: &CodegenContext,
     block: llvm::prelude::LLVMBasicBlockRef,
     scope: &mut Scope,
     expr: ast::Expr,
 ) -> llvm::prelude::LLVMValueRef {
     match expr {
-        ast::Expr::Literal(literal) => codegen_literal(context, literal),
+        ast::Expr::Literal(literal) => codegen_literal(ctx, literal),
         ast::Expr::Assign(variable, expr) => {
             // 获取右值
-            let value = codegen_expr(context, module, builder, block, scope, *expr);
+            let value = codegen_expr(ctx, block, scope, *expr);
             // 检查作用域内变量，如果存在就更新值，如果不存在就报错
             match scope.get(&variable.name) {
                 Some(alloca) => {
-                    llvm::core::LLVMBuildStore(builder, value, alloca);
-                    scope.register(variable.name, value);
+                    codegen_store(ctx, value, alloca);
+                    build_retain(ctx, value);
                     value
                 }
                 None => {
@@ -32,21 +36,63 @@ pub unsafe fn codegen_expr(
                 }
             }
         }
-        ast::Expr::Operator(operator) => {
-            codegen_operator(context, module, builder, block, scope, operator)
+        ast::Expr::Operator(operator) => codegen_operator(ctx, block, scope, operator),
+        ast::Expr::Tuple(exprs) => {
+            let mut values = Vec::new();
+            for expr in exprs {
+                values.push(codegen_expr(ctx, block, scope, expr));
+            }
+            // 元组为 LLVM 的结构体，从 undef 开始依次插入元素
+            let mut element_types: Vec<llvm::prelude::LLVMTypeRef> = values
+                .iter()
+                .map(|value| llvm::core::LLVMTypeOf(*value))
+                .collect();
+            let tuple_type = llvm::core::LLVMStructTypeInContext(
+                ctx.context(),
+                element_types.as_mut_ptr(),
+                element_types.len() as u32,
+                0,
+            );
+            let mut tuple = llvm::core::LLVMGetUndef(tuple_type);
+            for (i, value) in values.into_iter().enumerate() {
+                tuple = llvm::core::LLVMBuildInsertValue(
+                    ctx.builder(),
+                    tuple,
+                    value,
+                    i as u32,
+                    b"tuple\0".as_ptr() as *const _,
+                );
+            }
+            tuple
+        }
+        ast::Expr::Field(expr, index) => {
+            let tuple = codegen_expr(ctx, block, scope, *expr);
+            let ty = llvm::core::LLVMTypeOf(tuple);
+            if llvm::core::LLVMGetTypeKind(ty) != llvm::LLVMTypeKind::LLVMStructTypeKind {
+                panic!("只能取元组的元素");
+            }
+            let count = llvm::core::LLVMCountStructElementTypes(ty) as usize;
+            if index >= count {
+                panic!("元组只有{}个元素，下标{}越界", count, index);
+            }
+            let field = llvm::core::LLVMBuildExtractValue(
+                ctx.builder(),
+                tuple,
+                index as u32,
+                b"field\0".as_ptr() as *const _,
+            );
+            // 元素持有自己的引用，元组中的其他值不再使用
+            build_retain(ctx, field);
+            build_release(ctx, tuple);
+            field
         }
         ast::Expr::Function(function) => {
             // 获取函数返回值
-            let return_type = mool_type_ref(context, module, builder, function.rtn.clone());
+            let return_type = mool_type_ref(ctx, &function.rtn);
             // 生成参数类型列表
             let mut arg_types: Vec<llvm::prelude::LLVMTypeRef> = Vec::new();
             for arg in function.args.iter() {
-                arg_types.push(mool_type_ref(
-                    context,
-                    module,
-                    builder,
-                    arg.annotation.clone(),
-                ));
+                arg_types.push(mool_type_ref(ctx, &arg.annotation));
             }
             // 创建函数
             let function_type = llvm::core::LLVMFunctionType(
@@ -56,92 +102,129 @@ pub unsafe fn codegen_expr(
                 0,
             );
             let func = llvm::core::LLVMAddFunction(
-                module,
+                ctx.module(),
                 b"function\0".as_ptr() as *const _,
                 function_type,
             );
-            // 创建 Add 作用域
+            // 记录 builder 当前的位置
+            let current_block = llvm::core::LLVMGetInsertBlock(ctx.builder());
+            // 创建函数作用域
             scope.push();
-            // 注册形参
-            for i in 0..arg_types.len() {
-                let value = llvm::core::LLVMGetParam(func, i as u32);
-                scope.register(function.args[i].arg.name.clone(), value);
-            }
             // 创建BasicBlock
             let basic_block = llvm::core::LLVMAppendBasicBlockInContext(
-                context,
+                ctx.context(),
                 func,
                 b"function_entry\0".as_ptr() as *const _,
             );
             // 重置 builder 的位置
-            llvm::core::LLVMPositionBuilderAtEnd(builder, basic_block);
+            llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), basic_block);
+            // 注册形参，形参保存在 alloca 中以便在函数体内重新赋值
+            // 调用方在调用之后释放实参，形参的 alloca 持有自己的引用
+            for (i, arg) in function.args.iter().enumerate() {
+                let value = llvm::core::LLVMGetParam(func, i as u32);
+                let alloca = codegen_alloca(ctx, arg_types[i], &arg.arg.name);
+                build_retain(ctx, value);
+                llvm::core::LLVMBuildStore(ctx.builder(), value, alloca);
+                scope.register(arg.arg.name.clone(), alloca);
+                scope.own(alloca);
+            }
+            // 张量形参的形状以运行时描述符为准，与类型注解不一致时 trap，
+            // 同名的符号维度在所有形参中大小相同
+            let mut symbols = HashMap::new();
+            for (i, arg) in function.args.iter().enumerate() {
+                if let ast::Type::Tensor(shape, _) = &arg.annotation {
+                    let value = llvm::core::LLVMGetParam(func, i as u32);
+                    build_check_shape(ctx, value, shape, &mut symbols);
+                }
+            }
             // 设置默认返回值
-            let int_type = llvm::core::LLVMInt64TypeInContext(context);
+            let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
             let mut return_value = llvm::core::LLVMConstInt(int_type, 0, 0);
-            // 解析函数体，获取返回值
+            // 解析函数体，获取返回值，其余表达式的值不再使用
             for program in function.body {
-                return_value =
-                    codegen_program(context, module, builder, basic_block, scope, program);
+                let value = codegen_program(ctx, basic_block, scope, program);
+                build_release(ctx, return_value);
+                return_value = value;
             }
-            // 构造返回值
-            llvm::core::LLVMBuildRet(builder, return_value);
-            // 弹出 Add 作用域
+            // 释放函数中的变量，构造返回值
+            codegen_release_owned(ctx, scope);
+            llvm::core::LLVMBuildRet(ctx.builder(), return_value);
+            // 弹出函数作用域
             scope.pop();
-            llvm::core::LLVMPositionBuilderAtEnd(builder, block);
+            llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), current_block);
             // 返回函数
             func
         }
         ast::Expr::Variable(variable) => match scope.get(&variable.name) {
-            Some(value) => value,
+            Some(value) => {
+                // 读取的值持有自己的引用
+                let value = codegen_load(ctx, value);
+                build_retain(ctx, value);
+                value
+            }
             None => panic!("没有找到变量"),
         },
-        ast::Expr::Call(name, exprs) => {
-            match scope.get(&name) {
-                Some(func) => {
-                    let mut real_args = Vec::new();
-                    for expr in exprs {
-                        real_args.push(codegen_expr(context, module, builder, block, scope, expr));
-                    }
-                    llvm::core::LLVMBuildCall(
-                        builder,
-                        func,
-                        real_args.as_mut_ptr(),
-                        2,
-                        b"result\0".as_ptr() as *const _,
-                    );
+        ast::Expr::Call(name, exprs) => match scope.get(&name) {
+            Some(func) => {
+                let func = codegen_load(ctx, func);
+                let mut real_args = Vec::new();
+                for expr in exprs {
+                    real_args.push(codegen_expr(ctx, block, scope, expr));
+                }
+                let result = llvm::core::LLVMBuildCall(
+                    ctx.builder(),
+                    func,
+                    real_args.as_mut_ptr(),
+                    real_args.len() as u32,
+                    b"result\0".as_ptr() as *const _,
+                );
+                for arg in real_args {
+                    build_release(ctx, arg);
                 }
-                None => panic!("没有找到变量"),
+                result
             }
-            let int_type = llvm::core::LLVMInt64TypeInContext(context);
-            let zero = llvm::core::LLVMConstInt(int_type, 0, 0);
-            zero
-        }
+            None => panic!("没有找到变量"),
+        },
+        ast::Expr::If(if_else) => codegen_if(ctx, block, scope, if_else),
+        ast::Expr::For(for_loop) => codegen_for(ctx, block, scope, for_loop),
+        ast::Expr::While(while_loop) => codegen_while(ctx, block, scope, while_loop),
+        ast::Expr::Break => codegen_jump(ctx, scope, true),
+        ast::Expr::Continue => codegen_jump(ctx, scope, false),
     }
 }
 
-unsafe fn mool_type_ref(
-    context: llvm::prelude::LLVMContextRef,
-    module: llvm::prelude::LLVMModuleRef,
-    builder: llvm::prelude::LLVMBuilderRef,
-    ty: String,
+/// 变量保存在 alloca 中时读取变量的值
+unsafe fn codegen_load(
+    ctx: &CodegenContext,
+    value: llvm::prelude::LLVMValueRef,
+) -> llvm::prelude::LLVMValueRef {
+    if llvm::core::LLVMIsAAllocaInst(value).is_null() {
+        value
+    } else {
+        llvm::core::LLVMBuildLoad(ctx.builder(), value, b"load\0".as_ptr() as *const _)
+    }
+}
+
+pub(super) unsafe fn mool_type_ref(
+    ctx: &CodegenContext,
+    ty: &ast::Type,
 ) -> llvm::prelude::LLVMTypeRef {
-    match ty.as_str() {
-        // Int 返回值
-        "int" => llvm::core::LLVMInt64TypeInContext(context),
-        // Bool 返回值
-        "bool" => llvm::core::LLVMInt1TypeInContext(context),
-        // Double 返回值
-        "float" => llvm::core::LLVMDoubleTypeInContext(context),
-        // 默认返回值0
-        _ => {
-            let re = Regex::new(r"Tensor\[\((\d+)\),(int|float|bool)\]").unwrap();
-            match re.captures(ty.as_str()) {
-                None => llvm::core::LLVMInt64TypeInContext(context),
-                Some(cap) => llvm::core::LLVMVectorType(
-                    mool_type_ref(context, module, builder, cap[2].to_string()),
-                    cap[1].parse().unwrap(),
-                ),
-            }
+    match ty {
+        ast::Type::Int => llvm::core::LLVMInt64TypeInContext(ctx.context()),
+        ast::Type::Bool => llvm::core::LLVMInt1TypeInContext(ctx.context()),
+        ast::Type::Float => llvm::core::LLVMDoubleTypeInContext(ctx.context()),
+        // 张量为指向运行时描述符的指针
+        ast::Type::Tensor(_, dtype) => tensor_type(ctx, mool_type_ref(ctx, dtype)),
+        // 元组为 LLVM 的结构体
+        ast::Type::Tuple(types) => {
+            let mut element_types: Vec<llvm::prelude::LLVMTypeRef> =
+                types.iter().map(|ty| mool_type_ref(ctx, ty)).collect();
+            llvm::core::LLVMStructTypeInContext(
+                ctx.context(),
+                element_types.as_mut_ptr(),
+                element_types.len() as u32,
+                0,
+            )
         }
     }
 }
@@ -0,0 +1,186 @@
+use super::super::scope::Scope;
+use super::codegen_expr::codegen_expr;
+use super::codegen_operator::{
+    build_arithmetic, build_compare, build_map, build_relu, Arithmetic, Compare,
+};
+use super::context::CodegenContext;
+use super::runtime::{build_release, is_tensor, tensor_element, tensor_type};
+use llvm_sys as llvm;
+use mool_ir::ast;
+use mool_ir::pass::is_elementwise;
+
+/// 融合算子：链的叶子在调用处求值后作为参数传入 fused 函数，
+/// fused 函数中用一个循环逐个元素计算整条链，结果逐个写入返回的张量。
+/// 标量和只有一个元素的张量广播到所有元素。workspace 为内存规划给出的偏移时，结果放在工作区中。
+pub unsafe fn codegen_fused(
+    ctx: &CodegenContext,
+    block: llvm::prelude::LLVMBasicBlockRef,
+    scope: &mut Scope,
+    chain: ast::Expr,
+    workspace: Option<usize>,
+) -> llvm::prelude::LLVMValueRef {
+    // 构建 fused 的实参
+    let mut leaves = Vec::new();
+    collect_leaves(&chain, &mut leaves);
+    let mut real_args: Vec<llvm::prelude::LLVMValueRef> = leaves
+        .into_iter()
+        .map(|leaf| codegen_expr(ctx, block, scope, leaf))
+        .collect();
+    let mut arg_types: Vec<llvm::prelude::LLVMTypeRef> = real_args
+        .iter()
+        .map(|value| llvm::core::LLVMTypeOf(*value))
+        .collect();
+    let element_types: Vec<llvm::prelude::LLVMTypeRef> = arg_types
+        .iter()
+        .map(|ty| tensor_element(*ty).unwrap_or(*ty))
+        .collect();
+    let element_type = chain_type(ctx, &chain, &element_types, &mut 0);
+    // 有张量时返回张量，全部为标量时返回标量
+    let has_tensor = arg_types.iter().any(|ty| is_tensor(*ty));
+    let return_type = if has_tensor {
+        tensor_type(ctx, element_type)
+    } else {
+        element_type
+    };
+    // 创建 fused 函数
+    let function_type = llvm::core::LLVMFunctionType(
+        return_type,
+        arg_types.as_mut_ptr(),
+        arg_types.len() as u32,
+        0,
+    );
+    let fused =
+        llvm::core::LLVMAddFunction(ctx.module(), b"fused\0".as_ptr() as *const _, function_type);
+    scope.name_function(fused, "Fused 算子".to_string());
+    // 记录 builder 当前的位置，调用 fused 函数，调用之后实参不再使用
+    let current_block = llvm::core::LLVMGetInsertBlock(ctx.builder());
+    let result = llvm::core::LLVMBuildCall(
+        ctx.builder(),
+        fused,
+        real_args.as_mut_ptr(),
+        real_args.len() as u32,
+        b"result\0".as_ptr() as *const _,
+    );
+    for arg in real_args {
+        build_release(ctx, arg);
+    }
+    let params: Vec<llvm::prelude::LLVMValueRef> = (0..arg_types.len())
+        .map(|i| llvm::core::LLVMGetParam(fused, i as u32))
+        .collect();
+    let entry = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        fused,
+        b"fused_entry\0".as_ptr() as *const _,
+    );
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), entry);
+    let value = if has_tensor {
+        build_map(ctx, &params, element_type, workspace, |elements| {
+            codegen_chain(ctx, &chain, elements, &mut 0)
+        })
+    } else {
+        // 全部为标量时直接计算
+        codegen_chain(ctx, &chain, &params, &mut 0)
+    };
+    llvm::core::LLVMBuildRet(ctx.builder(), value);
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), current_block);
+    result
+}
+
+/// 按计算顺序收集链的叶子
+fn collect_leaves(expr: &ast::Expr, leaves: &mut Vec<ast::Expr>) {
+    match expr {
+        ast::Expr::Operator(operator) if is_elementwise(operator) => {
+            for operand in operands(operator) {
+                collect_leaves(operand, leaves);
+            }
+        }
+        leaf => leaves.push(leaf.clone()),
+    }
+}
+
+fn operands(operator: &ast::Operator) -> Vec<&ast::Expr> {
+    match operator {
+        ast::Operator::Add(x, y)
+        | ast::Operator::Sub(x, y)
+        | ast::Operator::Mul(x, y)
+        | ast::Operator::Div(x, y)
+        | ast::Operator::Matmul(x, y)
+        | ast::Operator::Lt(x, y)
+        | ast::Operator::Le(x, y)
+        | ast::Operator::Gt(x, y)
+        | ast::Operator::Ge(x, y)
+        | ast::Operator::Eq(x, y)
+        | ast::Operator::Ne(x, y) => vec![x, y],
+        ast::Operator::Relu(x) | ast::Operator::Fused(x) | ast::Operator::Workspace(x, _, _) => {
+            vec![x]
+        }
+        ast::Operator::Tensor(_) => Vec::new(),
+    }
+}
+
+/// 链的元素类型，比较的结果为 bool，其余算子与操作数相同
+unsafe fn chain_type(
+    ctx: &CodegenContext,
+    expr: &ast::Expr,
+    leaves: &[llvm::prelude::LLVMTypeRef],
+    next: &mut usize,
+) -> llvm::prelude::LLVMTypeRef {
+    let operator = match expr {
+        ast::Expr::Operator(operator) if is_elementwise(operator) => operator,
+        _ => {
+            *next += 1;
+            return leaves[*next - 1];
+        }
+    };
+    let types: Vec<llvm::prelude::LLVMTypeRef> = operands(operator)
+        .into_iter()
+        .map(|operand| chain_type(ctx, operand, leaves, next))
+        .collect();
+    if types.iter().any(|ty| *ty != types[0]) {
+        panic!("融合算子中两侧类型必须相等")
+    }
+    match operator {
+        ast::Operator::Lt(_, _)
+        | ast::Operator::Le(_, _)
+        | ast::Operator::Gt(_, _)
+        | ast::Operator::Ge(_, _)
+        | ast::Operator::Eq(_, _)
+        | ast::Operator::Ne(_, _) => llvm::core::LLVMInt1TypeInContext(ctx.context()),
+        _ => types[0],
+    }
+}
+
+/// 用标量计算整条链，leaves 为按计算顺序排列的叶子的值
+unsafe fn codegen_chain(
+    ctx: &CodegenContext,
+    expr: &ast::Expr,
+    leaves: &[llvm::prelude::LLVMValueRef],
+    next: &mut usize,
+) -> llvm::prelude::LLVMValueRef {
+    let operator = match expr {
+        ast::Expr::Operator(operator) if is_elementwise(operator) => operator,
+        _ => {
+            *next += 1;
+            return leaves[*next - 1];
+        }
+    };
+    let mut values = operands(operator)
+        .into_iter()
+        .map(|operand| codegen_chain(ctx, operand, leaves, next));
+    let x = values.next().unwrap();
+    let mut y = || values.next().unwrap();
+    match operator {
+        ast::Operator::Add(_, _) => build_arithmetic(ctx, Arithmetic::Add, x, y()),
+        ast::Operator::Sub(_, _) => build_arithmetic(ctx, Arithmetic::Sub, x, y()),
+        ast::Operator::Mul(_, _) => build_arithmetic(ctx, Arithmetic::Mul, x, y()),
+        ast::Operator::Div(_, _) => build_arithmetic(ctx, Arithmetic::Div, x, y()),
+        ast::Operator::Relu(_) => build_relu(ctx, x),
+        ast::Operator::Lt(_, _) => build_compare(ctx, x, y(), Compare::Lt),
+        ast::Operator::Le(_, _) => build_compare(ctx, x, y(), Compare::Le),
+        ast::Operator::Gt(_, _) => build_compare(ctx, x, y(), Compare::Gt),
+        ast::Operator::Ge(_, _) => build_compare(ctx, x, y(), Compare::Ge),
+        ast::Operator::Eq(_, _) => build_compare(ctx, x, y(), Compare::Eq),
+        ast::Operator::Ne(_, _) => build_compare(ctx, x, y(), Compare::Ne),
+        _ => unreachable!(),
+    }
+}
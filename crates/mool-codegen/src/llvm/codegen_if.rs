@@ -0,0 +1,108 @@
+use super::super::scope::Scope;
+use super::codegen_expr::codegen_expr;
+use super::codegen_program::{codegen_alloca, codegen_program};
+use super::context::CodegenContext;
+use super::runtime::build_release;
+use llvm_sys as llvm;
+use mool_ir::ast;
+
+pub unsafe fn codegen_if(
+    ctx: &CodegenContext,
+    block: llvm::prelude::LLVMBasicBlockRef,
+    scope: &mut Scope,
+    if_else: ast::If,
+) -> llvm::prelude::LLVMValueRef {
+    let cond = codegen_expr(ctx, block, scope, *if_else.cond);
+    let bool_type = llvm::core::LLVMInt1TypeInContext(ctx.context());
+    if llvm::core::LLVMTypeOf(cond) != bool_type {
+        panic!("if 的条件必须为 bool 类型");
+    }
+    // 创建两个分支和汇合点三个 BasicBlock
+    let function =
+        llvm::core::LLVMGetBasicBlockParent(llvm::core::LLVMGetInsertBlock(ctx.builder()));
+    let then_block = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        b"if_then\0".as_ptr() as *const _,
+    );
+    let else_block = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        b"if_else\0".as_ptr() as *const _,
+    );
+    let merge_block = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        b"if_merge\0".as_ptr() as *const _,
+    );
+    llvm::core::LLVMBuildCondBr(ctx.builder(), cond, then_block, else_block);
+    // 生成两个分支，记录各自最后一个表达式的值
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), then_block);
+    let then_value = codegen_branch(ctx, block, scope, if_else.then);
+    let then_end = llvm::core::LLVMGetInsertBlock(ctx.builder());
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), else_block);
+    let else_value = codegen_branch(ctx, block, scope, if_else.otherwise);
+    let else_end = llvm::core::LLVMGetInsertBlock(ctx.builder());
+    // 两个分支的值类型相同时，if 表达式的值保存在 alloca 中
+    let result = match (then_value, else_value) {
+        (Some(then_value), Some(else_value))
+            if llvm::core::LLVMTypeOf(then_value) == llvm::core::LLVMTypeOf(else_value) =>
+        {
+            let alloca = codegen_alloca(ctx, llvm::core::LLVMTypeOf(then_value), "if_result");
+            for (end, value) in [(then_end, then_value), (else_end, else_value)] {
+                if llvm::core::LLVMGetBasicBlockTerminator(end).is_null() {
+                    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), end);
+                    llvm::core::LLVMBuildStore(ctx.builder(), value, alloca);
+                }
+            }
+            Some(alloca)
+        }
+        // 类型不同时 if 表达式没有值，两个分支的值不再使用
+        _ => {
+            for (end, value) in [(then_end, then_value), (else_end, else_value)] {
+                if let Some(value) = value {
+                    if llvm::core::LLVMGetBasicBlockTerminator(end).is_null() {
+                        llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), end);
+                        build_release(ctx, value);
+                    }
+                }
+            }
+            None
+        }
+    };
+    // 跳转到汇合点
+    for end in [then_end, else_end] {
+        if llvm::core::LLVMGetBasicBlockTerminator(end).is_null() {
+            llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), end);
+            llvm::core::LLVMBuildBr(ctx.builder(), merge_block);
+        }
+    }
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), merge_block);
+    match result {
+        Some(alloca) => {
+            llvm::core::LLVMBuildLoad(ctx.builder(), alloca, b"if_value\0".as_ptr() as *const _)
+        }
+        None => {
+            let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+            llvm::core::LLVMConstInt(int_type, 0, 0)
+        }
+    }
+}
+
+/// 生成分支内的代码，分支为空时没有值
+unsafe fn codegen_branch(
+    ctx: &CodegenContext,
+    block: llvm::prelude::LLVMBasicBlockRef,
+    scope: &mut Scope,
+    programs: Vec<ast::Program>,
+) -> Option<llvm::prelude::LLVMValueRef> {
+    let mut value = None;
+    for program in programs {
+        let next = codegen_program(ctx, block, scope, program);
+        if let Some(value) = value {
+            build_release(ctx, value);
+        }
+        value = Some(next);
+    }
+    value
+}
@@ -0,0 +1,193 @@
+use super::super::scope::{LoopTarget, Scope};
+use super::codegen_expr::codegen_expr;
+use super::codegen_program::{codegen_alloca, codegen_program};
+use super::context::CodegenContext;
+use super::runtime::build_release;
+use llvm_sys as llvm;
+use mool_ir::ast;
+
+pub unsafe fn codegen_for(
+    ctx: &CodegenContext,
+    block: llvm::prelude::LLVMBasicBlockRef,
+    scope: &mut Scope,
+    for_loop: ast::For,
+) -> llvm::prelude::LLVMValueRef {
+    let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+    // 计算循环范围，只在进入循环前求值一次
+    let start = codegen_expr(ctx, block, scope, *for_loop.start);
+    let end = codegen_expr(ctx, block, scope, *for_loop.end);
+    let step = codegen_expr(ctx, block, scope, *for_loop.step);
+    // 归纳变量和函数内的其他变量一样保存在 alloca 中，循环结束后仍然可见
+    let induction = match scope.get(&for_loop.var.name) {
+        Some(alloca) => alloca,
+        None => {
+            let alloca = codegen_alloca(ctx, int_type, &for_loop.var.name);
+            scope.register(for_loop.var.name.clone(), alloca);
+            alloca
+        }
+    };
+    llvm::core::LLVMBuildStore(ctx.builder(), start, induction);
+    // 创建循环头、循环体、步进和出口四个 BasicBlock
+    let function =
+        llvm::core::LLVMGetBasicBlockParent(llvm::core::LLVMGetInsertBlock(ctx.builder()));
+    let header = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        b"for_header\0".as_ptr() as *const _,
+    );
+    let body = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        b"for_body\0".as_ptr() as *const _,
+    );
+    let latch = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        b"for_latch\0".as_ptr() as *const _,
+    );
+    let exit = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        b"for_exit\0".as_ptr() as *const _,
+    );
+    llvm::core::LLVMBuildBr(ctx.builder(), header);
+    // 循环头：步长为正时判断 i < end，步长为负时判断 i > end
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), header);
+    let current = llvm::core::LLVMBuildLoad(ctx.builder(), induction, b"i\0".as_ptr() as *const _);
+    let zero = llvm::core::LLVMConstInt(int_type, 0, 0);
+    let ascending = llvm::core::LLVMBuildICmp(
+        ctx.builder(),
+        llvm::LLVMIntPredicate::LLVMIntSGT,
+        step,
+        zero,
+        b"ascending\0".as_ptr() as *const _,
+    );
+    let below = llvm::core::LLVMBuildICmp(
+        ctx.builder(),
+        llvm::LLVMIntPredicate::LLVMIntSLT,
+        current,
+        end,
+        b"below\0".as_ptr() as *const _,
+    );
+    let above = llvm::core::LLVMBuildICmp(
+        ctx.builder(),
+        llvm::LLVMIntPredicate::LLVMIntSGT,
+        current,
+        end,
+        b"above\0".as_ptr() as *const _,
+    );
+    let cond = llvm::core::LLVMBuildSelect(
+        ctx.builder(),
+        ascending,
+        below,
+        above,
+        b"for_cond\0".as_ptr() as *const _,
+    );
+    llvm::core::LLVMBuildCondBr(ctx.builder(), cond, body, exit);
+    // 循环体
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), body);
+    scope.push_loop(LoopTarget { next: latch, exit });
+    for program in for_loop.body {
+        let value = codegen_program(ctx, block, scope, program);
+        build_release(ctx, value);
+    }
+    scope.pop_loop();
+    codegen_fallthrough(ctx, latch);
+    // 步进：i += step
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), latch);
+    let current = llvm::core::LLVMBuildLoad(ctx.builder(), induction, b"i\0".as_ptr() as *const _);
+    let next = llvm::core::LLVMBuildAdd(
+        ctx.builder(),
+        current,
+        step,
+        b"i_next\0".as_ptr() as *const _,
+    );
+    llvm::core::LLVMBuildStore(ctx.builder(), next, induction);
+    llvm::core::LLVMBuildBr(ctx.builder(), header);
+    // 继续在出口生成后续代码
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), exit);
+    zero
+}
+
+pub unsafe fn codegen_while(
+    ctx: &CodegenContext,
+    block: llvm::prelude::LLVMBasicBlockRef,
+    scope: &mut Scope,
+    while_loop: ast::While,
+) -> llvm::prelude::LLVMValueRef {
+    // 创建循环头、循环体和出口三个 BasicBlock
+    let function =
+        llvm::core::LLVMGetBasicBlockParent(llvm::core::LLVMGetInsertBlock(ctx.builder()));
+    let header = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        b"while_header\0".as_ptr() as *const _,
+    );
+    let body = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        b"while_body\0".as_ptr() as *const _,
+    );
+    let exit = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        b"while_exit\0".as_ptr() as *const _,
+    );
+    llvm::core::LLVMBuildBr(ctx.builder(), header);
+    // 循环头：每次迭代重新计算条件
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), header);
+    let cond = codegen_expr(ctx, block, scope, *while_loop.cond);
+    let bool_type = llvm::core::LLVMInt1TypeInContext(ctx.context());
+    if llvm::core::LLVMTypeOf(cond) != bool_type {
+        panic!("while 循环的条件必须为 bool 类型");
+    }
+    llvm::core::LLVMBuildCondBr(ctx.builder(), cond, body, exit);
+    // 循环体
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), body);
+    scope.push_loop(LoopTarget { next: header, exit });
+    for program in while_loop.body {
+        let value = codegen_program(ctx, block, scope, program);
+        build_release(ctx, value);
+    }
+    scope.pop_loop();
+    codegen_fallthrough(ctx, header);
+    // 继续在出口生成后续代码
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), exit);
+    let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+    llvm::core::LLVMConstInt(int_type, 0, 0)
+}
+
+/// break 跳转到循环出口，continue 跳转到下一次迭代
+pub unsafe fn codegen_jump(
+    ctx: &CodegenContext,
+    scope: &mut Scope,
+    is_break: bool,
+) -> llvm::prelude::LLVMValueRef {
+    let target = match scope.current_loop() {
+        Some(target) => target,
+        None => panic!("break 和 continue 只能在循环中使用"),
+    };
+    llvm::core::LLVMBuildBr(
+        ctx.builder(),
+        if is_break { target.exit } else { target.next },
+    );
+    // 跳转之后的代码不可达，放到新的 BasicBlock 中
+    let function =
+        llvm::core::LLVMGetBasicBlockParent(llvm::core::LLVMGetInsertBlock(ctx.builder()));
+    let unreachable = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        b"after_jump\0".as_ptr() as *const _,
+    );
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), unreachable);
+    let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+    llvm::core::LLVMConstInt(int_type, 0, 0)
+}
+
+/// 当前 BasicBlock 没有终结指令时跳转到 target
+unsafe fn codegen_fallthrough(ctx: &CodegenContext, target: llvm::prelude::LLVMBasicBlockRef) {
+    let current = llvm::core::LLVMGetInsertBlock(ctx.builder());
+    if llvm::core::LLVMGetBasicBlockTerminator(current).is_null() {
+        llvm::core::LLVMBuildBr(ctx.builder(), target);
+    }
+}
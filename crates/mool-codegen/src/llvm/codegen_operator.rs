@@ -1,313 +1,608 @@
 use super::super::scope::Scope;
 use super::codegen_expr::codegen_expr;
+use super::codegen_fused::codegen_fused;
 use super::codegen_literal::codegen_literal;
+use super::codegen_program::codegen_alloca;
+use super::context::CodegenContext;
+use super::runtime::{
+    build_alloc, build_data, build_load_element, build_numel, build_rank, build_release,
+    build_shape, build_shape_of, build_store_element, is_tensor, storage_type, tensor_element,
+};
+use super::value::Type;
 use llvm_sys as llvm;
 use mool_ir::ast;
-use regex::Regex;
-use std::ffi::CStr;
 
 pub unsafe fn codegen_operator(
-    context: llvm::prelude::LLVMContextRef,
-    module: llvm::prelude::LLVMModuleRef,
-    builder: llvm::prelude::LLVMBuilderRef,
+    ctx: &CodegenContext,
     block: llvm::prelude::LLVMBasicBlockRef,
     scope: &mut Scope,
     operator: ast::Operator,
+) -> llvm::prelude::LLVMValueRef {
+    codegen_operator_in(ctx, block, scope, operator, None)
+}
+
+/// workspace 为内存规划给出的偏移时，算子的张量结果放在工作区中
+unsafe fn codegen_operator_in(
+    ctx: &CodegenContext,
+    block: llvm::prelude::LLVMBasicBlockRef,
+    scope: &mut Scope,
+    operator: ast::Operator,
+    workspace: Option<usize>,
 ) -> llvm::prelude::LLVMValueRef {
     match operator {
-        ast::Operator::Tensor(tensors) => {
-            let mut tensor: Vec<llvm::prelude::LLVMValueRef> = tensors
-                .into_iter()
-                .map(|x| codegen_literal(context, x))
-                .collect();
-            llvm::core::LLVMConstVector(tensor.as_mut_ptr(), tensor.len() as u32)
-        }
+        ast::Operator::Tensor(tensors) => codegen_tensor(ctx, tensors, workspace),
         ast::Operator::Add(x, y) => {
-            // 构建 Add 的实参
-            let x_value = codegen_expr(context, module, builder, block, scope, *x);
-            let x_type = llvm::core::LLVMTypeOf(x_value);
-            let y_value = codegen_expr(context, module, builder, block, scope, *y);
-            let y_type = llvm::core::LLVMTypeOf(y_value);
-            // 创建 Add 函数
-            let mut arg_types = vec![x_type, y_type];
-            let function_type = llvm::core::LLVMFunctionType(x_type, arg_types.as_mut_ptr(), 2, 0);
-            let add =
-                llvm::core::LLVMAddFunction(module, b"add\0".as_ptr() as *const _, function_type);
-            // 创建 Add 作用域
-            scope.push();
-            // 调用 Add 函数
-            let mut real_args = vec![x_value, y_value];
-            llvm::core::LLVMBuildCall(
-                builder,
-                add,
-                real_args.as_mut_ptr(),
-                2,
-                b"result\0".as_ptr() as *const _,
-            );
-            // 注册形参
-            let x_value = llvm::core::LLVMGetParam(add, 0);
-            scope.register("x".to_string(), x_value);
-            let y_value = llvm::core::LLVMGetParam(add, 1);
-            scope.register("x".to_string(), y_value);
-            // 创建BasicBlock
-            let basic_block = llvm::core::LLVMAppendBasicBlockInContext(
-                context,
-                add,
-                b"add_entry\0".as_ptr() as *const _,
-            );
-            // 重置 builder 的位置
-            llvm::core::LLVMPositionBuilderAtEnd(builder, basic_block);
-            // 判断张量类型是否相等
-            if x_type != y_type {
-                panic!("张量加法中张量类型必须相等")
-            }
-            // 分配 Add 算子返回值, 最大长度即为返回的张量长度
-            let return_type = x_type;
-            let return_alloca = llvm::core::LLVMBuildAlloca(
-                builder,
-                return_type,
-                b"return_alloca\0".as_ptr() as *const _,
-            );
-            let add_temp = llvm::core::LLVMBuildAdd(
-                builder,
-                x_value,
-                y_value,
-                b"add_temp\0".as_ptr() as *const _,
-            );
-            llvm::core::LLVMBuildStore(builder, add_temp, return_alloca);
-            llvm::core::LLVMBuildRet(
-                builder,
-                llvm::core::LLVMBuildLoad(
-                    builder,
-                    return_alloca,
-                    b"return_value\0".as_ptr() as *const _,
-                ),
-            );
-            // 弹出 Add 作用域
-            scope.pop();
-            llvm::core::LLVMPositionBuilderAtEnd(builder, block);
-            // 返回值
-            return_alloca
+            codegen_arithmetic(ctx, block, scope, *x, *y, Arithmetic::Add, workspace)
         }
         ast::Operator::Sub(x, y) => {
-            // 构建 Sub 的实参
-            let x_value = codegen_expr(context, module, builder, block, scope, *x);
-            let x_type = llvm::core::LLVMTypeOf(x_value);
-            let y_value = codegen_expr(context, module, builder, block, scope, *y);
-            let y_type = llvm::core::LLVMTypeOf(y_value);
-            // 创建 Sub 函数
-            let mut arg_types = vec![x_type, y_type];
-            let function_type = llvm::core::LLVMFunctionType(x_type, arg_types.as_mut_ptr(), 2, 0);
-            let sub =
-                llvm::core::LLVMAddFunction(module, b"sub\0".as_ptr() as *const _, function_type);
-            // 创建 Sub 作用域
-            scope.push();
-            // 调用 Sub 函数
-            let mut real_args = vec![x_value, y_value];
-            llvm::core::LLVMBuildCall(
-                builder,
-                sub,
-                real_args.as_mut_ptr(),
-                2,
-                b"result\0".as_ptr() as *const _,
-            );
-            // 注册形参
-            let x_value = llvm::core::LLVMGetParam(sub, 0);
-            scope.register("x".to_string(), x_value);
-            let y_value = llvm::core::LLVMGetParam(sub, 1);
-            scope.register("x".to_string(), y_value);
-            // 创建BasicBlock
-            let basic_block = llvm::core::LLVMAppendBasicBlockInContext(
-                context,
-                sub,
-                b"sub_entry\0".as_ptr() as *const _,
-            );
-            // 重置 builder 的位置
-            llvm::core::LLVMPositionBuilderAtEnd(builder, basic_block);
-            // 判断张量类型是否相等
-            if x_type != y_type {
-                panic!("张量减法中张量类型必须相等")
-            }
-            // 分配 Sub 算子返回值, 最大长度即为返回的张量长度
-            let return_type = x_type;
-            let return_alloca = llvm::core::LLVMBuildAlloca(
-                builder,
-                return_type,
-                b"return_alloca\0".as_ptr() as *const _,
-            );
-            let sub_temp = llvm::core::LLVMBuildSub(
-                builder,
-                x_value,
-                y_value,
-                b"sub_temp\0".as_ptr() as *const _,
-            );
-            llvm::core::LLVMBuildStore(builder, sub_temp, return_alloca);
-            llvm::core::LLVMBuildRet(
-                builder,
-                llvm::core::LLVMBuildLoad(
-                    builder,
-                    return_alloca,
-                    b"return_value\0".as_ptr() as *const _,
-                ),
-            );
-            // 弹出 Sub 作用域
-            scope.pop();
-            llvm::core::LLVMPositionBuilderAtEnd(builder, block);
-            // 返回值
-            return_alloca
+            codegen_arithmetic(ctx, block, scope, *x, *y, Arithmetic::Sub, workspace)
         }
         ast::Operator::Mul(x, y) => {
-            // 构建 Mul 的实参
-            let x_value = codegen_expr(context, module, builder, block, scope, *x);
-            let x_type = llvm::core::LLVMTypeOf(x_value);
-            let y_value = codegen_expr(context, module, builder, block, scope, *y);
-            let y_type = llvm::core::LLVMTypeOf(y_value);
-            // 创建 Mul 函数
-            let mut arg_types = vec![x_type, y_type];
-            let function_type = llvm::core::LLVMFunctionType(x_type, arg_types.as_mut_ptr(), 2, 0);
-            let mul =
-                llvm::core::LLVMAddFunction(module, b"mul\0".as_ptr() as *const _, function_type);
-            // 创建 Mul 作用域
-            scope.push();
-            // 调用 Mul 函数
-            let mut real_args = vec![x_value, y_value];
-            llvm::core::LLVMBuildCall(
-                builder,
-                mul,
-                real_args.as_mut_ptr(),
-                2,
-                b"result\0".as_ptr() as *const _,
-            );
-            // 注册形参
-            let x_value = llvm::core::LLVMGetParam(mul, 0);
-            scope.register("x".to_string(), x_value);
-            let y_value = llvm::core::LLVMGetParam(mul, 1);
-            scope.register("x".to_string(), y_value);
-            // 创建BasicBlock
-            let basic_block = llvm::core::LLVMAppendBasicBlockInContext(
-                context,
-                mul,
-                b"mul_entry\0".as_ptr() as *const _,
-            );
-            // 重置 builder 的位置
-            llvm::core::LLVMPositionBuilderAtEnd(builder, basic_block);
-            // 判断张量类型是否相等
-            if x_type != y_type {
-                panic!("张量减法中张量类型必须相等")
+            codegen_arithmetic(ctx, block, scope, *x, *y, Arithmetic::Mul, workspace)
+        }
+        ast::Operator::Div(x, y) => {
+            codegen_arithmetic(ctx, block, scope, *x, *y, Arithmetic::Div, workspace)
+        }
+        ast::Operator::Matmul(x, y) => codegen_matmul(ctx, block, scope, *x, *y),
+        ast::Operator::Relu(x) => codegen_relu(ctx, block, scope, *x, workspace),
+        ast::Operator::Lt(x, y) => {
+            codegen_compare(ctx, block, scope, *x, *y, Compare::Lt, workspace)
+        }
+        ast::Operator::Le(x, y) => {
+            codegen_compare(ctx, block, scope, *x, *y, Compare::Le, workspace)
+        }
+        ast::Operator::Gt(x, y) => {
+            codegen_compare(ctx, block, scope, *x, *y, Compare::Gt, workspace)
+        }
+        ast::Operator::Ge(x, y) => {
+            codegen_compare(ctx, block, scope, *x, *y, Compare::Ge, workspace)
+        }
+        ast::Operator::Eq(x, y) => {
+            codegen_compare(ctx, block, scope, *x, *y, Compare::Eq, workspace)
+        }
+        ast::Operator::Ne(x, y) => {
+            codegen_compare(ctx, block, scope, *x, *y, Compare::Ne, workspace)
+        }
+        ast::Operator::Fused(x) => codegen_fused(ctx, block, scope, *x, workspace),
+        // 内存规划过的算子，结果放在工作区中
+        ast::Operator::Workspace(x, offset, _) => match *x {
+            ast::Expr::Operator(operator) => {
+                codegen_operator_in(ctx, block, scope, operator, Some(offset))
             }
-            // 分配 Mul 算子返回值, 最大长度即为返回的张量长度
-            let return_type = x_type;
+            x => codegen_expr(ctx, block, scope, x),
+        },
+    }
+}
+
+/// 张量字面量保存为模块中的常量数组，运行时复制到新分配的张量中
+unsafe fn codegen_tensor(
+    ctx: &CodegenContext,
+    literals: Vec<ast::Literal>,
+    workspace: Option<usize>,
+) -> llvm::prelude::LLVMValueRef {
+    let values: Vec<llvm::prelude::LLVMValueRef> = literals
+        .into_iter()
+        .map(|x| codegen_literal(ctx, x))
+        .collect();
+    let element = match values.first() {
+        Some(value) => llvm::core::LLVMTypeOf(*value),
+        None => panic!("张量不能为空"),
+    };
+    let storage = storage_type(ctx, element);
+    let mut elements: Vec<llvm::prelude::LLVMValueRef> = values
+        .iter()
+        .map(|value| {
+            if llvm::core::LLVMTypeOf(*value) != element {
+                panic!("张量的元素类型必须相同")
+            }
+            llvm::core::LLVMConstZExtOrBitCast(*value, storage)
+        })
+        .collect();
+    let array = llvm::core::LLVMConstArray(storage, elements.as_mut_ptr(), elements.len() as u32);
+    let literal = llvm::core::LLVMAddGlobal(
+        ctx.module(),
+        llvm::core::LLVMTypeOf(array),
+        b"tensor_literal\0".as_ptr() as *const _,
+    );
+    llvm::core::LLVMSetInitializer(literal, array);
+    llvm::core::LLVMSetGlobalConstant(literal, 1);
+    llvm::core::LLVMSetLinkage(literal, llvm::LLVMLinkage::LLVMPrivateLinkage);
+    llvm::core::LLVMSetUnnamedAddress(literal, llvm::LLVMUnnamedAddr::LLVMGlobalUnnamedAddr);
+    // 分配一维张量并复制数据
+    let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+    let length = llvm::core::LLVMConstInt(int_type, elements.len() as u64, 0);
+    let shape = build_shape(ctx, &[length]);
+    let tensor = build_alloc(
+        ctx,
+        element,
+        llvm::core::LLVMConstInt(int_type, 1, 0),
+        shape,
+        workspace,
+    );
+    llvm::core::LLVMBuildMemCpy(
+        ctx.builder(),
+        build_data(ctx, tensor),
+        0,
+        literal,
+        0,
+        llvm::core::LLVMSizeOf(llvm::core::LLVMTypeOf(array)),
+    );
+    tensor
+}
+
+#[derive(Clone, Copy)]
+pub(super) enum Arithmetic {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// 四则运算生成一个单独的函数：标量直接计算，张量逐个元素计算，返回新分配的张量。
+/// 标量和只有一个元素的张量与另一侧的张量广播计算。
+#[allow(clippy::too_many_arguments)]
+unsafe fn codegen_arithmetic(
+    ctx: &CodegenContext,
+    block: llvm::prelude::LLVMBasicBlockRef,
+    scope: &mut Scope,
+    x: ast::Expr,
+    y: ast::Expr,
+    arithmetic: Arithmetic,
+    workspace: Option<usize>,
+) -> llvm::prelude::LLVMValueRef {
+    let (name, entry_name, operator_name, operation): (&[u8], &[u8], _, _) = match arithmetic {
+        Arithmetic::Add => (b"add\0", b"add_entry\0", "Add 算子", "加法"),
+        Arithmetic::Sub => (b"sub\0", b"sub_entry\0", "Sub 算子", "减法"),
+        Arithmetic::Mul => (b"mul\0", b"mul_entry\0", "Mul 算子", "乘法"),
+        Arithmetic::Div => (b"div\0", b"div_entry\0", "Div 算子", "除法"),
+    };
+    // 构建实参
+    let x_value = codegen_expr(ctx, block, scope, x);
+    let x_type = llvm::core::LLVMTypeOf(x_value);
+    let y_value = codegen_expr(ctx, block, scope, y);
+    let y_type = llvm::core::LLVMTypeOf(y_value);
+    // 判断元素类型是否相等，有张量时结果为张量
+    if element_type(x_type) != element_type(y_type) {
+        panic!("张量{}中元素类型必须相等", operation)
+    }
+    let return_type = if is_tensor(x_type) { x_type } else { y_type };
+    // 创建算子函数
+    let mut arg_types = vec![x_type, y_type];
+    let function_type = llvm::core::LLVMFunctionType(return_type, arg_types.as_mut_ptr(), 2, 0);
+    let function =
+        llvm::core::LLVMAddFunction(ctx.module(), name.as_ptr() as *const _, function_type);
+    scope.name_function(function, operator_name.to_string());
+    // 记录 builder 当前的位置
+    let current_block = llvm::core::LLVMGetInsertBlock(ctx.builder());
+    // 调用算子函数，调用之后实参不再使用
+    let mut real_args = vec![x_value, y_value];
+    let result = llvm::core::LLVMBuildCall(
+        ctx.builder(),
+        function,
+        real_args.as_mut_ptr(),
+        2,
+        b"result\0".as_ptr() as *const _,
+    );
+    build_release(ctx, x_value);
+    build_release(ctx, y_value);
+    let x_value = llvm::core::LLVMGetParam(function, 0);
+    let y_value = llvm::core::LLVMGetParam(function, 1);
+    // 创建BasicBlock
+    let basic_block = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        entry_name.as_ptr() as *const _,
+    );
+    // 重置 builder 的位置
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), basic_block);
+    match tensor_element(return_type) {
+        Some(element) => {
+            let tensor = build_map(ctx, &[x_value, y_value], element, workspace, |values| {
+                build_arithmetic(ctx, arithmetic, values[0], values[1])
+            });
+            llvm::core::LLVMBuildRet(ctx.builder(), tensor);
+        }
+        None => {
+            // 分配算子返回值
             let return_alloca = llvm::core::LLVMBuildAlloca(
-                builder,
-                return_type,
+                ctx.builder(),
+                x_type,
                 b"return_alloca\0".as_ptr() as *const _,
             );
-            let mul_temp = llvm::core::LLVMBuildMul(
-                builder,
-                x_value,
-                y_value,
-                b"mul_temp\0".as_ptr() as *const _,
-            );
-            llvm::core::LLVMBuildStore(builder, mul_temp, return_alloca);
+            let temp = build_arithmetic(ctx, arithmetic, x_value, y_value);
+            llvm::core::LLVMBuildStore(ctx.builder(), temp, return_alloca);
             llvm::core::LLVMBuildRet(
-                builder,
+                ctx.builder(),
                 llvm::core::LLVMBuildLoad(
-                    builder,
+                    ctx.builder(),
                     return_alloca,
                     b"return_value\0".as_ptr() as *const _,
                 ),
             );
-            // 弹出 Mul 作用域
-            scope.pop();
-            llvm::core::LLVMPositionBuilderAtEnd(builder, block);
-            // 返回值
-            return_alloca
         }
-        ast::Operator::Div(x, y) => {
-            // 构建 Div 的实参
-            let x_value = codegen_expr(context, module, builder, block, scope, *x);
-            let x_type = llvm::core::LLVMTypeOf(x_value);
-            let y_value = codegen_expr(context, module, builder, block, scope, *y);
-            let y_type = llvm::core::LLVMTypeOf(y_value);
-            // 创建 Div 函数
-            let mut arg_types = vec![x_type, y_type];
-            let function_type = llvm::core::LLVMFunctionType(x_type, arg_types.as_mut_ptr(), 2, 0);
-            let div =
-                llvm::core::LLVMAddFunction(module, b"div\0".as_ptr() as *const _, function_type);
-            // 创建 Div 作用域
-            scope.push();
-            // 调用 Div 函数
-            let mut real_args = vec![x_value, y_value];
-            llvm::core::LLVMBuildCall(
-                builder,
-                div,
-                real_args.as_mut_ptr(),
-                2,
-                b"result\0".as_ptr() as *const _,
-            );
-            // 注册形参
-            let x_value = llvm::core::LLVMGetParam(div, 0);
-            scope.register("x".to_string(), x_value);
-            let y_value = llvm::core::LLVMGetParam(div, 1);
-            scope.register("x".to_string(), y_value);
-            // 创建BasicBlock
-            let basic_block = llvm::core::LLVMAppendBasicBlockInContext(
-                context,
-                div,
-                b"div_entry\0".as_ptr() as *const _,
-            );
-            // 重置 builder 的位置
-            llvm::core::LLVMPositionBuilderAtEnd(builder, basic_block);
-            // 判断张量类型是否相等
-            if x_type != y_type {
-                panic!("张量减法中张量类型必须相等")
+    }
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), current_block);
+    // 返回值
+    result
+}
+
+/// 张量的元素类型，标量为自身
+unsafe fn element_type(ty: llvm::prelude::LLVMTypeRef) -> llvm::prelude::LLVMTypeRef {
+    tensor_element(ty).unwrap_or(ty)
+}
+
+/// 对标量生成四则运算的指令，浮点数使用 FAdd 等指令，整数除法使用无符号除法
+pub(super) unsafe fn build_arithmetic(
+    ctx: &CodegenContext,
+    arithmetic: Arithmetic,
+    x_value: llvm::prelude::LLVMValueRef,
+    y_value: llvm::prelude::LLVMValueRef,
+) -> llvm::prelude::LLVMValueRef {
+    let is_float = Type::new(llvm::core::LLVMTypeOf(x_value)).is_float();
+    let builder = ctx.builder();
+    match arithmetic {
+        Arithmetic::Add if is_float => llvm::core::LLVMBuildFAdd(
+            builder,
+            x_value,
+            y_value,
+            b"add_temp\0".as_ptr() as *const _,
+        ),
+        Arithmetic::Add => llvm::core::LLVMBuildAdd(
+            builder,
+            x_value,
+            y_value,
+            b"add_temp\0".as_ptr() as *const _,
+        ),
+        Arithmetic::Sub if is_float => llvm::core::LLVMBuildFSub(
+            builder,
+            x_value,
+            y_value,
+            b"sub_temp\0".as_ptr() as *const _,
+        ),
+        Arithmetic::Sub => llvm::core::LLVMBuildSub(
+            builder,
+            x_value,
+            y_value,
+            b"sub_temp\0".as_ptr() as *const _,
+        ),
+        Arithmetic::Mul if is_float => llvm::core::LLVMBuildFMul(
+            builder,
+            x_value,
+            y_value,
+            b"mul_temp\0".as_ptr() as *const _,
+        ),
+        Arithmetic::Mul => llvm::core::LLVMBuildMul(
+            builder,
+            x_value,
+            y_value,
+            b"mul_temp\0".as_ptr() as *const _,
+        ),
+        Arithmetic::Div if is_float => llvm::core::LLVMBuildFDiv(
+            builder,
+            x_value,
+            y_value,
+            b"div_temp\0".as_ptr() as *const _,
+        ),
+        Arithmetic::Div => llvm::core::LLVMBuildUDiv(
+            builder,
+            x_value,
+            y_value,
+            b"div_temp\0".as_ptr() as *const _,
+        ),
+    }
+}
+
+/// 生成下标从 0 到 count 的循环，body 在循环体中生成代码，参数为当前的下标
+pub(super) unsafe fn build_loop(
+    ctx: &CodegenContext,
+    count: llvm::prelude::LLVMValueRef,
+    mut body: impl FnMut(llvm::prelude::LLVMValueRef),
+) {
+    let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+    let preheader = llvm::core::LLVMGetInsertBlock(ctx.builder());
+    let function = llvm::core::LLVMGetBasicBlockParent(preheader);
+    // 创建循环头、循环体和出口三个 BasicBlock
+    let header = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        b"loop_header\0".as_ptr() as *const _,
+    );
+    let body_block = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        b"loop_body\0".as_ptr() as *const _,
+    );
+    let exit = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        b"loop_exit\0".as_ptr() as *const _,
+    );
+    llvm::core::LLVMBuildBr(ctx.builder(), header);
+    // 循环头：判断 i < count
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), header);
+    let index = llvm::core::LLVMBuildPhi(ctx.builder(), int_type, b"i\0".as_ptr() as *const _);
+    let cond = llvm::core::LLVMBuildICmp(
+        ctx.builder(),
+        llvm::LLVMIntPredicate::LLVMIntSLT,
+        index,
+        count,
+        b"loop_cond\0".as_ptr() as *const _,
+    );
+    llvm::core::LLVMBuildCondBr(ctx.builder(), cond, body_block, exit);
+    // 循环体
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), body_block);
+    body(index);
+    let next = llvm::core::LLVMBuildAdd(
+        ctx.builder(),
+        index,
+        llvm::core::LLVMConstInt(int_type, 1, 0),
+        b"i_next\0".as_ptr() as *const _,
+    );
+    let latch = llvm::core::LLVMGetInsertBlock(ctx.builder());
+    llvm::core::LLVMBuildBr(ctx.builder(), header);
+    let mut values = [llvm::core::LLVMConstInt(int_type, 0, 0), next];
+    let mut blocks = [preheader, latch];
+    llvm::core::LLVMAddIncoming(index, values.as_mut_ptr(), blocks.as_mut_ptr(), 2);
+    // 继续在出口生成后续代码
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), exit);
+}
+
+/// 逐个元素计算，compute 由输入的元素计算结果的元素，返回新分配的、元素类型为 element 的张量
+///
+/// 结果的形状与元素最多的输入相同，标量和只有一个元素的张量广播到所有元素。
+/// workspace 为内存规划给出的偏移时，结果的数据放在工作区中。
+pub(super) unsafe fn build_map(
+    ctx: &CodegenContext,
+    inputs: &[llvm::prelude::LLVMValueRef],
+    element: llvm::prelude::LLVMTypeRef,
+    workspace: Option<usize>,
+    mut compute: impl FnMut(&[llvm::prelude::LLVMValueRef]) -> llvm::prelude::LLVMValueRef,
+) -> llvm::prelude::LLVMValueRef {
+    let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+    let tensors: Vec<(llvm::prelude::LLVMValueRef, llvm::prelude::LLVMValueRef)> = inputs
+        .iter()
+        .filter(|input| is_tensor(llvm::core::LLVMTypeOf(**input)))
+        .map(|tensor| (*tensor, build_numel(ctx, *tensor)))
+        .collect();
+    let (first, mut count) = match tensors.first() {
+        Some(tensor) => *tensor,
+        None => panic!("逐个元素计算的输入中没有张量"),
+    };
+    // 取元素最多的张量的形状
+    let mut rank = build_rank(ctx, first);
+    let mut shape = build_shape_of(ctx, first);
+    for (tensor, numel) in tensors.iter().skip(1) {
+        let larger = llvm::core::LLVMBuildICmp(
+            ctx.builder(),
+            llvm::LLVMIntPredicate::LLVMIntUGT,
+            *numel,
+            count,
+            b"larger\0".as_ptr() as *const _,
+        );
+        let select = |then, otherwise, name: &[u8]| {
+            llvm::core::LLVMBuildSelect(
+                ctx.builder(),
+                larger,
+                then,
+                otherwise,
+                name.as_ptr() as *const _,
+            )
+        };
+        count = select(*numel, count, b"count\0");
+        rank = select(build_rank(ctx, *tensor), rank, b"rank\0");
+        shape = select(build_shape_of(ctx, *tensor), shape, b"shape\0");
+    }
+    let result = build_alloc(ctx, element, rank, shape, workspace);
+    // 有多个张量时，只有一个元素的张量广播
+    let broadcasts: Vec<Option<llvm::prelude::LLVMValueRef>> = tensors
+        .iter()
+        .map(|(_, numel)| {
+            if tensors.len() == 1 {
+                return None;
             }
-            // 分配 Div 算子返回值, 最大长度即为返回的张量长度
-            let return_type = x_type;
-            let return_alloca = llvm::core::LLVMBuildAlloca(
-                builder,
-                return_type,
-                b"return_alloca\0".as_ptr() as *const _,
-            );
-            // 判断张量类型，如果是 int 和 bool 类型则使用 LLVMBuildUDiv，否则使用 LLVMBuildFDiv
-            let type_string = CStr::from_ptr(llvm::core::LLVMPrintTypeToString(x_type))
-                .to_str()
-                .unwrap();
-            let re = Regex::new(r"<(\d+) x (double|i64|i1)>").unwrap();
-            let cap = re.captures(type_string).unwrap();
-            let div_temp;
-            match &cap[2] {
-                "double" => {
-                    div_temp = llvm::core::LLVMBuildFDiv(
-                        builder,
-                        x_value,
-                        y_value,
-                        b"div_temp\0".as_ptr() as *const _,
-                    );
-                }
-                _ => {
-                    div_temp = llvm::core::LLVMBuildUDiv(
-                        builder,
-                        x_value,
-                        y_value,
-                        b"div_temp\0".as_ptr() as *const _,
-                    );
+            Some(llvm::core::LLVMBuildICmp(
+                ctx.builder(),
+                llvm::LLVMIntPredicate::LLVMIntEQ,
+                *numel,
+                llvm::core::LLVMConstInt(int_type, 1, 0),
+                b"broadcast\0".as_ptr() as *const _,
+            ))
+        })
+        .collect();
+    build_loop(ctx, count, |index| {
+        let mut broadcasts = broadcasts.iter();
+        let elements: Vec<llvm::prelude::LLVMValueRef> = inputs
+            .iter()
+            .map(|input| {
+                if !is_tensor(llvm::core::LLVMTypeOf(*input)) {
+                    return *input;
                 }
-            }
-            llvm::core::LLVMBuildStore(builder, div_temp, return_alloca);
-            llvm::core::LLVMBuildRet(
-                builder,
-                llvm::core::LLVMBuildLoad(
-                    builder,
-                    return_alloca,
-                    b"return_value\0".as_ptr() as *const _,
-                ),
-            );
-            // 弹出 Div 作用域
-            scope.pop();
-            llvm::core::LLVMPositionBuilderAtEnd(builder, block);
-            // 返回值
-            return_alloca
+                let position = match broadcasts.next().unwrap() {
+                    Some(broadcast) => llvm::core::LLVMBuildSelect(
+                        ctx.builder(),
+                        *broadcast,
+                        llvm::core::LLVMConstInt(int_type, 0, 0),
+                        index,
+                        b"position\0".as_ptr() as *const _,
+                    ),
+                    None => index,
+                };
+                build_load_element(ctx, *input, position)
+            })
+            .collect();
+        let value = compute(&elements);
+        build_store_element(ctx, result, index, value);
+    });
+    result
+}
+
+/// 一维张量的矩阵乘法即为向量点积：逐个元素相乘再累加
+unsafe fn codegen_matmul(
+    ctx: &CodegenContext,
+    block: llvm::prelude::LLVMBasicBlockRef,
+    scope: &mut Scope,
+    x: ast::Expr,
+    y: ast::Expr,
+) -> llvm::prelude::LLVMValueRef {
+    let x_value = codegen_expr(ctx, block, scope, x);
+    let y_value = codegen_expr(ctx, block, scope, y);
+    let x_type = llvm::core::LLVMTypeOf(x_value);
+    if x_type != llvm::core::LLVMTypeOf(y_value) {
+        panic!("矩阵乘法中张量类型必须相等")
+    }
+    let result = match tensor_element(x_type) {
+        Some(element) => {
+            let dot = codegen_alloca(ctx, element, "dot");
+            llvm::core::LLVMBuildStore(ctx.builder(), llvm::core::LLVMConstNull(element), dot);
+            build_loop(ctx, build_numel(ctx, x_value), |index| {
+                let product = build_arithmetic(
+                    ctx,
+                    Arithmetic::Mul,
+                    build_load_element(ctx, x_value, index),
+                    build_load_element(ctx, y_value, index),
+                );
+                let sum =
+                    llvm::core::LLVMBuildLoad(ctx.builder(), dot, b"sum\0".as_ptr() as *const _);
+                let sum = build_arithmetic(ctx, Arithmetic::Add, sum, product);
+                llvm::core::LLVMBuildStore(ctx.builder(), sum, dot);
+            });
+            llvm::core::LLVMBuildLoad(ctx.builder(), dot, b"dot\0".as_ptr() as *const _)
+        }
+        // 标量直接返回乘积
+        None => build_arithmetic(ctx, Arithmetic::Mul, x_value, y_value),
+    };
+    build_release(ctx, x_value);
+    build_release(ctx, y_value);
+    result
+}
+
+/// relu(x) = x > 0 ? x : 0，张量按元素计算
+unsafe fn codegen_relu(
+    ctx: &CodegenContext,
+    block: llvm::prelude::LLVMBasicBlockRef,
+    scope: &mut Scope,
+    x: ast::Expr,
+    workspace: Option<usize>,
+) -> llvm::prelude::LLVMValueRef {
+    let x_value = codegen_expr(ctx, block, scope, x);
+    let result = match tensor_element(llvm::core::LLVMTypeOf(x_value)) {
+        Some(element) => build_map(ctx, &[x_value], element, workspace, |values| {
+            build_relu(ctx, values[0])
+        }),
+        None => build_relu(ctx, x_value),
+    };
+    build_release(ctx, x_value);
+    result
+}
+
+/// 对标量生成 relu 的指令
+pub(super) unsafe fn build_relu(
+    ctx: &CodegenContext,
+    x_value: llvm::prelude::LLVMValueRef,
+) -> llvm::prelude::LLVMValueRef {
+    let x_type = llvm::core::LLVMTypeOf(x_value);
+    let zero = llvm::core::LLVMConstNull(x_type);
+    let positive = match llvm::core::LLVMGetTypeKind(x_type) {
+        llvm::LLVMTypeKind::LLVMDoubleTypeKind => llvm::core::LLVMBuildFCmp(
+            ctx.builder(),
+            llvm::LLVMRealPredicate::LLVMRealOGT,
+            x_value,
+            zero,
+            b"positive\0".as_ptr() as *const _,
+        ),
+        _ => llvm::core::LLVMBuildICmp(
+            ctx.builder(),
+            llvm::LLVMIntPredicate::LLVMIntSGT,
+            x_value,
+            zero,
+            b"positive\0".as_ptr() as *const _,
+        ),
+    };
+    llvm::core::LLVMBuildSelect(
+        ctx.builder(),
+        positive,
+        x_value,
+        zero,
+        b"relu\0".as_ptr() as *const _,
+    )
+}
+
+#[derive(Clone, Copy)]
+pub(super) enum Compare {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+/// 比较算子直接生成 icmp/fcmp 指令，结果为 bool，张量按元素比较，结果为 bool 张量，
+/// 标量和只有一个元素的张量与另一侧的张量广播比较
+#[allow(clippy::too_many_arguments)]
+unsafe fn codegen_compare(
+    ctx: &CodegenContext,
+    block: llvm::prelude::LLVMBasicBlockRef,
+    scope: &mut Scope,
+    x: ast::Expr,
+    y: ast::Expr,
+    compare: Compare,
+    workspace: Option<usize>,
+) -> llvm::prelude::LLVMValueRef {
+    let x_value = codegen_expr(ctx, block, scope, x);
+    let y_value = codegen_expr(ctx, block, scope, y);
+    let x_type = llvm::core::LLVMTypeOf(x_value);
+    let y_type = llvm::core::LLVMTypeOf(y_value);
+    let result = if is_tensor(x_type) || is_tensor(y_type) {
+        if element_type(x_type) != element_type(y_type) {
+            panic!("比较运算中两侧元素类型必须相等")
+        }
+        let bool_type = llvm::core::LLVMInt1TypeInContext(ctx.context());
+        build_map(ctx, &[x_value, y_value], bool_type, workspace, |values| {
+            build_compare(ctx, values[0], values[1], compare)
+        })
+    } else {
+        build_compare(ctx, x_value, y_value, compare)
+    };
+    build_release(ctx, x_value);
+    build_release(ctx, y_value);
+    result
+}
+
+/// 对标量生成比较指令
+pub(super) unsafe fn build_compare(
+    ctx: &CodegenContext,
+    x_value: llvm::prelude::LLVMValueRef,
+    y_value: llvm::prelude::LLVMValueRef,
+    compare: Compare,
+) -> llvm::prelude::LLVMValueRef {
+    let x_type = llvm::core::LLVMTypeOf(x_value);
+    if x_type != llvm::core::LLVMTypeOf(y_value) {
+        panic!("比较运算中两侧类型必须相等")
+    }
+    let name = b"cmp_temp\0".as_ptr() as *const _;
+    match llvm::core::LLVMGetTypeKind(x_type) {
+        llvm::LLVMTypeKind::LLVMDoubleTypeKind => {
+            let predicate = match compare {
+                Compare::Lt => llvm::LLVMRealPredicate::LLVMRealOLT,
+                Compare::Le => llvm::LLVMRealPredicate::LLVMRealOLE,
+                Compare::Gt => llvm::LLVMRealPredicate::LLVMRealOGT,
+                Compare::Ge => llvm::LLVMRealPredicate::LLVMRealOGE,
+                Compare::Eq => llvm::LLVMRealPredicate::LLVMRealOEQ,
+                Compare::Ne => llvm::LLVMRealPredicate::LLVMRealONE,
+            };
+            llvm::core::LLVMBuildFCmp(ctx.builder(), predicate, x_value, y_value, name)
+        }
+        _ => {
+            let predicate = match compare {
+                Compare::Lt => llvm::LLVMIntPredicate::LLVMIntSLT,
+                Compare::Le => llvm::LLVMIntPredicate::LLVMIntSLE,
+                Compare::Gt => llvm::LLVMIntPredicate::LLVMIntSGT,
+                Compare::Ge => llvm::LLVMIntPredicate::LLVMIntSGE,
+                Compare::Eq => llvm::LLVMIntPredicate::LLVMIntEQ,
+                Compare::Ne => llvm::LLVMIntPredicate::LLVMIntNE,
+            };
+            llvm::core::LLVMBuildICmp(ctx.builder(), predicate, x_value, y_value, name)
         }
     }
 }
@@ -1,38 +1,131 @@
 use super::super::scope::Scope;
 use super::codegen_expr::codegen_expr;
+use super::context::CodegenContext;
+use super::runtime::{build_release, build_retain, contains_tensor};
 use llvm_sys as llvm;
 use mool_ir::ast;
+use std::ffi::CString;
 
 pub unsafe fn codegen_program(
-    context: llvm::prelude::LLVMContextRef,
-    module: llvm::prelude::LLVMModuleRef,
-    builder: llvm::prelude::LLVMBuilderRef,
+    ctx: &CodegenContext,
     block: llvm::prelude::LLVMBasicBlockRef,
     scope: &mut Scope,
     program: ast::Program,
 ) -> llvm::prelude::LLVMValueRef {
     match program {
-        ast::Program::Expr(expr) => codegen_expr(context, module, builder, block, scope, expr),
+        ast::Program::Expr(expr) => codegen_expr(ctx, block, scope, expr),
         ast::Program::Let(variable, expr) => {
             // 获取右值
-            let value = codegen_expr(context, module, builder, block, scope, expr);
-            // 检查作用域内变量，如果存在就更新值，如果不存在就创建值
-            match scope.get(&variable.name) {
-                Some(alloca) => {
-                    llvm::core::LLVMBuildStore(builder, value, alloca);
-                    scope.register(variable.name, alloca);
-                }
-                None => {
-                    let alloca = llvm::core::LLVMBuildAlloca(
-                        builder,
-                        llvm::core::LLVMTypeOf(value),
-                        variable.name.as_ptr() as *const _,
-                    );
-                    llvm::core::LLVMBuildStore(builder, value, alloca);
-                    scope.register(variable.name, alloca);
-                }
+            let value = codegen_expr(ctx, block, scope, expr);
+            codegen_bind(ctx, scope, variable, value);
+            // 变量持有一个引用，返回的值持有另一个引用
+            build_retain(ctx, value);
+            value
+        }
+        ast::Program::LetTuple(variables, expr) => {
+            let value = codegen_expr(ctx, block, scope, expr);
+            let ty = llvm::core::LLVMTypeOf(value);
+            if llvm::core::LLVMGetTypeKind(ty) != llvm::LLVMTypeKind::LLVMStructTypeKind {
+                panic!("只能解构元组");
+            }
+            let count = llvm::core::LLVMCountStructElementTypes(ty) as usize;
+            if count != variables.len() {
+                panic!("元组有{}个元素，不能解构为{}个变量", count, variables.len());
+            }
+            // 依次取出元组的元素绑定到变量
+            for (i, variable) in variables.into_iter().enumerate() {
+                let name = CString::new(variable.name.as_str()).unwrap();
+                let element = llvm::core::LLVMBuildExtractValue(
+                    ctx.builder(),
+                    value,
+                    i as u32,
+                    name.as_ptr(),
+                );
+                build_retain(ctx, element);
+                codegen_bind(ctx, scope, variable, element);
             }
             value
         }
     }
 }
+
+/// 把值绑定到变量，如果变量已经存在就更新值，如果不存在就创建变量
+///
+/// 变量取得 value 的引用，原来的值中的张量被释放。
+pub(super) unsafe fn codegen_bind(
+    ctx: &CodegenContext,
+    scope: &mut Scope,
+    variable: ast::Variable,
+    value: llvm::prelude::LLVMValueRef,
+) {
+    match scope.get(&variable.name) {
+        Some(alloca) if !llvm::core::LLVMIsAAllocaInst(alloca).is_null() => {
+            codegen_store(ctx, value, alloca);
+            scope.register(variable.name, alloca);
+        }
+        // 函数是全局的，直接注册，在其他函数中也可以调用
+        _ if !llvm::core::LLVMIsAFunction(value).is_null() => {
+            scope.name_function(value, format!("%{}", variable.name));
+            scope.register(variable.name, value);
+        }
+        _ => {
+            let alloca = codegen_alloca(ctx, llvm::core::LLVMTypeOf(value), &variable.name);
+            // 循环中的 let 每次迭代都会执行，同样需要释放上一次迭代的值
+            codegen_store(ctx, value, alloca);
+            scope.register(variable.name, alloca);
+            scope.own(alloca);
+        }
+    }
+}
+
+/// 把值写入变量的 alloca，先释放变量原来的值
+pub(super) unsafe fn codegen_store(
+    ctx: &CodegenContext,
+    value: llvm::prelude::LLVMValueRef,
+    alloca: llvm::prelude::LLVMValueRef,
+) {
+    if contains_tensor(llvm::core::LLVMTypeOf(value)) {
+        let old = llvm::core::LLVMBuildLoad(ctx.builder(), alloca, b"old\0".as_ptr() as *const _);
+        build_release(ctx, old);
+    }
+    llvm::core::LLVMBuildStore(ctx.builder(), value, alloca);
+}
+
+/// 释放当前作用域中创建的变量，在函数返回之前调用
+pub(super) unsafe fn codegen_release_owned(ctx: &CodegenContext, scope: &Scope) {
+    for alloca in scope.owned() {
+        if !contains_tensor(llvm::core::LLVMGetAllocatedType(*alloca)) {
+            continue;
+        }
+        let value =
+            llvm::core::LLVMBuildLoad(ctx.builder(), *alloca, b"owned\0".as_ptr() as *const _);
+        build_release(ctx, value);
+    }
+}
+
+/// 在当前函数的入口块分配变量，避免循环体内重复分配栈空间
+pub unsafe fn codegen_alloca(
+    ctx: &CodegenContext,
+    ty: llvm::prelude::LLVMTypeRef,
+    name: &str,
+) -> llvm::prelude::LLVMValueRef {
+    let current_block = llvm::core::LLVMGetInsertBlock(ctx.builder());
+    let function = llvm::core::LLVMGetBasicBlockParent(current_block);
+    let entry = llvm::core::LLVMGetEntryBasicBlock(function);
+    // 使用临时 builder 在入口块的开头插入 alloca
+    let entry_builder = llvm::core::LLVMCreateBuilderInContext(llvm::core::LLVMGetTypeContext(ty));
+    let first = llvm::core::LLVMGetFirstInstruction(entry);
+    if first.is_null() {
+        llvm::core::LLVMPositionBuilderAtEnd(entry_builder, entry);
+    } else {
+        llvm::core::LLVMPositionBuilderBefore(entry_builder, first);
+    }
+    let name = CString::new(name).unwrap();
+    let alloca = llvm::core::LLVMBuildAlloca(entry_builder, ty, name.as_ptr());
+    // 含有张量的变量初始化为空指针，释放旧值时不需要判断是否赋过值
+    if contains_tensor(ty) {
+        llvm::core::LLVMBuildStore(entry_builder, llvm::core::LLVMConstNull(ty), alloca);
+    }
+    llvm::core::LLVMDisposeBuilder(entry_builder);
+    alloca
+}
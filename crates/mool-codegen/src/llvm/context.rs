@@ -0,0 +1,79 @@
+use super::value::Value;
+use llvm_sys as llvm;
+use std::ffi::{CStr, CString};
+
+/// 代码生成的上下文，持有 LLVM 的 context、module 和 builder，离开作用域时自动释放
+#[derive(Debug)]
+pub struct CodegenContext {
+    context: llvm::prelude::LLVMContextRef,
+    module: llvm::prelude::LLVMModuleRef,
+    builder: llvm::prelude::LLVMBuilderRef,
+}
+
+impl CodegenContext {
+    /// 创建名为 name 的模块
+    pub fn new(name: &str) -> Self {
+        let name = CString::new(name).unwrap();
+        unsafe {
+            let context = llvm::core::LLVMContextCreate();
+            let module = llvm::core::LLVMModuleCreateWithNameInContext(name.as_ptr(), context);
+            let builder = llvm::core::LLVMCreateBuilderInContext(context);
+            Self {
+                context,
+                module,
+                builder,
+            }
+        }
+    }
+
+    pub(super) fn context(&self) -> llvm::prelude::LLVMContextRef {
+        self.context
+    }
+
+    pub(super) fn module(&self) -> llvm::prelude::LLVMModuleRef {
+        self.module
+    }
+
+    pub(super) fn builder(&self) -> llvm::prelude::LLVMBuilderRef {
+        self.builder
+    }
+
+    /// 模块中的所有函数，包括只有声明的函数
+    pub fn functions(&self) -> Vec<Value<'_>> {
+        let mut functions = Vec::new();
+        unsafe {
+            let mut function = llvm::core::LLVMGetFirstFunction(self.module);
+            while !function.is_null() {
+                functions.push(Value::new(function));
+                function = llvm::core::LLVMGetNextFunction(function);
+            }
+        }
+        functions
+    }
+
+    /// 输出模块的 LLVM IR 代码
+    pub fn print(&self) -> String {
+        unsafe {
+            let message = llvm::core::LLVMPrintModuleToString(self.module);
+            let module_string = CStr::from_ptr(message).to_string_lossy().into_owned();
+            llvm::core::LLVMDisposeMessage(message);
+            module_string
+        }
+    }
+}
+
+impl Default for CodegenContext {
+    fn default() -> Self {
+        Self::new("example_moddule")
+    }
+}
+
+impl Drop for CodegenContext {
+    fn drop(&mut self) {
+        unsafe {
+            llvm::core::LLVMDisposeBuilder(self.builder);
+            llvm::core::LLVMDisposeModule(self.module);
+            llvm::core::LLVMContextDispose(self.context);
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::fmt;
+
+/// 生成 LLVM IR 时的错误
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodegenError {
+    /// 生成的 LLVM IR 没有通过 LLVM 的验证
+    Verify {
+        /// 不合法的函数，用 Mool 中的名称表示，例如 %a、Add 算子
+        functions: Vec<String>,
+        /// LLVM 验证器输出的信息
+        message: String,
+    },
+    /// LLVM 优化流水线执行失败
+    Optimize(String),
+    /// 创建目标机器或生成目标文件失败
+    Target(String),
+    /// 调用链接器失败
+    Link(String),
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodegenError::Verify { functions, message } if functions.is_empty() => {
+                write!(f, "生成的 LLVM IR 不合法：\n{}", message.trim_end())
+            }
+            CodegenError::Verify { functions, message } => write!(
+                f,
+                "生成的 LLVM IR 不合法，出错的函数：{}\n{}",
+                functions.join("、"),
+                message.trim_end()
+            ),
+            CodegenError::Optimize(message) => write!(f, "LLVM 优化失败：{}", message),
+            CodegenError::Target(message) => write!(f, "生成目标文件失败：{}", message),
+            CodegenError::Link(message) => write!(f, "链接失败：{}", message),
+        }
+    }
+}
+
+impl std::error::Error for CodegenError {}
@@ -0,0 +1,120 @@
+use super::context::CodegenContext;
+use super::error::CodegenError;
+use super::target::TargetMachine;
+use super::value::Value;
+use llvm_sys as llvm;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::ptr;
+use std::str::FromStr;
+
+/// LLVM 优化级别，对应 clang 的 -O0、-O1、-O2、-O3 和 -Os
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptLevel {
+    #[default]
+    O0,
+    O1,
+    O2,
+    O3,
+    Os,
+}
+
+impl OptLevel {
+    /// 新 pass manager 的默认优化流水线
+    fn pipeline(self) -> &'static str {
+        match self {
+            OptLevel::O0 => "default<O0>",
+            OptLevel::O1 => "default<O1>",
+            OptLevel::O2 => "default<O2>",
+            OptLevel::O3 => "default<O3>",
+            OptLevel::Os => "default<Os>",
+        }
+    }
+}
+
+impl FromStr for OptLevel {
+    type Err = String;
+
+    /// 接受 0、1、2、3、s，也可以带上前缀 O
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level.trim_start_matches('O') {
+            "0" => Ok(OptLevel::O0),
+            "1" => Ok(OptLevel::O1),
+            "2" => Ok(OptLevel::O2),
+            "3" => Ok(OptLevel::O3),
+            "s" => Ok(OptLevel::Os),
+            _ => Err(format!("没有优化级别{}", level)),
+        }
+    }
+}
+
+impl fmt::Display for OptLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = match self {
+            OptLevel::O0 => "O0",
+            OptLevel::O1 => "O1",
+            OptLevel::O2 => "O2",
+            OptLevel::O3 => "O3",
+            OptLevel::Os => "Os",
+        };
+        write!(f, "{}", level)
+    }
+}
+
+/// 用 LLVM 新 pass manager 的流水线优化模块，O0 时不做任何处理，模块需要已经通过验证
+///
+/// 除 main、WebAssembly 的导出函数和弱链接的函数以外，函数都只在模块内部使用，优化前改为内部链接，
+/// 这样算子函数被内联之后可以直接删除。指定目标机器时按目标平台的代价模型优化。
+pub(super) fn optimize(
+    ctx: &CodegenContext,
+    level: OptLevel,
+    machine: Option<&TargetMachine>,
+) -> Result<(), CodegenError> {
+    if level == OptLevel::O0 {
+        return Ok(());
+    }
+    for function in ctx.functions() {
+        if !function.is_declaration()
+            && function.name() != "main"
+            && !is_exported(function)
+            && unsafe { llvm::core::LLVMGetLinkage(function.raw()) }
+                == llvm::LLVMLinkage::LLVMExternalLinkage
+        {
+            unsafe {
+                llvm::core::LLVMSetLinkage(function.raw(), llvm::LLVMLinkage::LLVMInternalLinkage)
+            };
+        }
+    }
+    let pipeline = CString::new(level.pipeline()).unwrap();
+    unsafe {
+        let options = llvm::transforms::pass_builder::LLVMCreatePassBuilderOptions();
+        let error = llvm::transforms::pass_builder::LLVMRunPasses(
+            ctx.module(),
+            pipeline.as_ptr(),
+            machine.map_or(ptr::null_mut(), |machine| machine.raw()),
+            options,
+        );
+        llvm::transforms::pass_builder::LLVMDisposePassBuilderOptions(options);
+        if error.is_null() {
+            return Ok(());
+        }
+        let message = llvm::error::LLVMGetErrorMessage(error);
+        let text = CStr::from_ptr(message).to_string_lossy().into_owned();
+        llvm::error::LLVMDisposeErrorMessage(message);
+        Err(CodegenError::Optimize(text))
+    }
+}
+
+/// 函数是否带有 wasm-export-name 属性
+fn is_exported(function: Value) -> bool {
+    let key = "wasm-export-name";
+    unsafe {
+        !llvm::core::LLVMGetStringAttributeAtIndex(
+            function.raw(),
+            llvm::LLVMAttributeFunctionIndex,
+            key.as_ptr() as *const _,
+            key.len() as u32,
+        )
+        .is_null()
+    }
+}
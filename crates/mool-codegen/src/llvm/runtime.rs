@@ -0,0 +1,534 @@
+use super::codegen_operator::build_loop;
+use super::codegen_program::codegen_alloca;
+use super::context::CodegenContext;
+use llvm_sys as llvm;
+use mool_ir::ast;
+use mool_runtime::DType;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+/// 张量为指向运行时描述符的指针，描述符的布局与 mool_runtime::MoolTensor 一致：
+/// { i8* data, i32 dtype, i64 rank, i64* shape, i64* strides, i64 refcount }
+///
+/// 每种元素类型使用一个同样布局的具名结构体，LLVM 类型上仍然可以区分张量的元素类型。
+pub(super) unsafe fn tensor_type(
+    ctx: &CodegenContext,
+    element: llvm::prelude::LLVMTypeRef,
+) -> llvm::prelude::LLVMTypeRef {
+    let name = CString::new(format!("mool_tensor.{}", dtype_name(element))).unwrap();
+    let mut descriptor = llvm::core::LLVMGetTypeByName2(ctx.context(), name.as_ptr());
+    if descriptor.is_null() {
+        descriptor = llvm::core::LLVMStructCreateNamed(ctx.context(), name.as_ptr());
+        let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+        let int_pointer = llvm::core::LLVMPointerType(int_type, 0);
+        let mut field_types = [
+            llvm::core::LLVMPointerType(llvm::core::LLVMInt8TypeInContext(ctx.context()), 0),
+            llvm::core::LLVMInt32TypeInContext(ctx.context()),
+            int_type,
+            int_pointer,
+            int_pointer,
+            int_type,
+        ];
+        llvm::core::LLVMStructSetBody(
+            descriptor,
+            field_types.as_mut_ptr(),
+            field_types.len() as u32,
+            0,
+        );
+    }
+    llvm::core::LLVMPointerType(descriptor, 0)
+}
+
+/// 张量的元素类型，不是张量时返回 None
+pub(super) unsafe fn tensor_element(
+    ty: llvm::prelude::LLVMTypeRef,
+) -> Option<llvm::prelude::LLVMTypeRef> {
+    if llvm::core::LLVMGetTypeKind(ty) != llvm::LLVMTypeKind::LLVMPointerTypeKind {
+        return None;
+    }
+    let descriptor = llvm::core::LLVMGetElementType(ty);
+    if llvm::core::LLVMGetTypeKind(descriptor) != llvm::LLVMTypeKind::LLVMStructTypeKind {
+        return None;
+    }
+    let name = llvm::core::LLVMGetStructName(descriptor);
+    if name.is_null() {
+        return None;
+    }
+    let context = llvm::core::LLVMGetTypeContext(ty);
+    match CStr::from_ptr(name).to_bytes() {
+        b"mool_tensor.int" => Some(llvm::core::LLVMInt64TypeInContext(context)),
+        b"mool_tensor.float" => Some(llvm::core::LLVMDoubleTypeInContext(context)),
+        b"mool_tensor.bool" => Some(llvm::core::LLVMInt1TypeInContext(context)),
+        _ => None,
+    }
+}
+
+pub(super) unsafe fn is_tensor(ty: llvm::prelude::LLVMTypeRef) -> bool {
+    tensor_element(ty).is_some()
+}
+
+/// 类型中是否有张量，包括元组中的张量
+pub(super) unsafe fn contains_tensor(ty: llvm::prelude::LLVMTypeRef) -> bool {
+    match llvm::core::LLVMGetTypeKind(ty) {
+        llvm::LLVMTypeKind::LLVMStructTypeKind => {
+            let count = llvm::core::LLVMCountStructElementTypes(ty);
+            (0..count).any(|i| contains_tensor(llvm::core::LLVMStructGetTypeAtIndex(ty, i)))
+        }
+        llvm::LLVMTypeKind::LLVMArrayTypeKind => {
+            contains_tensor(llvm::core::LLVMGetElementType(ty))
+        }
+        _ => is_tensor(ty),
+    }
+}
+
+fn dtype_name(element: llvm::prelude::LLVMTypeRef) -> &'static str {
+    match dtype(element) {
+        DType::Int => "int",
+        DType::Float => "float",
+        DType::Bool => "bool",
+    }
+}
+
+fn dtype(element: llvm::prelude::LLVMTypeRef) -> DType {
+    match unsafe { llvm::core::LLVMGetTypeKind(element) } {
+        llvm::LLVMTypeKind::LLVMDoubleTypeKind => DType::Float,
+        llvm::LLVMTypeKind::LLVMIntegerTypeKind
+            if unsafe { llvm::core::LLVMGetIntTypeWidth(element) } == 1 =>
+        {
+            DType::Bool
+        }
+        llvm::LLVMTypeKind::LLVMIntegerTypeKind => DType::Int,
+        _ => panic!("张量的元素只能是 int、float 或 bool"),
+    }
+}
+
+/// 元素在数据中的类型，bool 占一个字节
+pub(super) unsafe fn storage_type(
+    ctx: &CodegenContext,
+    element: llvm::prelude::LLVMTypeRef,
+) -> llvm::prelude::LLVMTypeRef {
+    match dtype(element) {
+        DType::Bool => llvm::core::LLVMInt8TypeInContext(ctx.context()),
+        _ => element,
+    }
+}
+
+/// 获取运行时函数或 LLVM 的内建函数，第一次使用时在模块中声明
+unsafe fn runtime_function(
+    ctx: &CodegenContext,
+    name: &[u8],
+    return_type: llvm::prelude::LLVMTypeRef,
+    mut param_types: Vec<llvm::prelude::LLVMTypeRef>,
+) -> llvm::prelude::LLVMValueRef {
+    let function = llvm::core::LLVMGetNamedFunction(ctx.module(), name.as_ptr() as *const _);
+    if !function.is_null() {
+        return function;
+    }
+    let function_type = llvm::core::LLVMFunctionType(
+        return_type,
+        param_types.as_mut_ptr(),
+        param_types.len() as u32,
+        0,
+    );
+    llvm::core::LLVMAddFunction(ctx.module(), name.as_ptr() as *const _, function_type)
+}
+
+/// 运行时函数的参数和返回值中张量为 i8*
+unsafe fn opaque_type(ctx: &CodegenContext) -> llvm::prelude::LLVMTypeRef {
+    llvm::core::LLVMPointerType(llvm::core::LLVMInt8TypeInContext(ctx.context()), 0)
+}
+
+unsafe fn opaque(
+    ctx: &CodegenContext,
+    tensor: llvm::prelude::LLVMValueRef,
+) -> llvm::prelude::LLVMValueRef {
+    llvm::core::LLVMBuildBitCast(
+        ctx.builder(),
+        tensor,
+        opaque_type(ctx),
+        b"opaque\0".as_ptr() as *const _,
+    )
+}
+
+/// 读取描述符的第 index 个字段
+unsafe fn build_field(
+    ctx: &CodegenContext,
+    tensor: llvm::prelude::LLVMValueRef,
+    index: u32,
+    name: &[u8],
+) -> llvm::prelude::LLVMValueRef {
+    let pointer = llvm::core::LLVMBuildStructGEP(
+        ctx.builder(),
+        tensor,
+        index,
+        b"field\0".as_ptr() as *const _,
+    );
+    llvm::core::LLVMBuildLoad(ctx.builder(), pointer, name.as_ptr() as *const _)
+}
+
+/// 分配元素类型为 element、rank 维、形状为 shape 的张量，数据初始化为 0，引用计数为 1
+///
+/// workspace 为内存规划给出的偏移时，数据放在工作区中，不初始化，由调用方写入全部元素。
+/// 运行时分配失败时返回空指针，这时执行 trap。
+pub(super) unsafe fn build_alloc(
+    ctx: &CodegenContext,
+    element: llvm::prelude::LLVMTypeRef,
+    rank: llvm::prelude::LLVMValueRef,
+    shape: llvm::prelude::LLVMValueRef,
+    workspace: Option<usize>,
+) -> llvm::prelude::LLVMValueRef {
+    let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+    let dtype_type = llvm::core::LLVMInt32TypeInContext(ctx.context());
+    let mut param_types = vec![
+        dtype_type,
+        int_type,
+        llvm::core::LLVMPointerType(int_type, 0),
+    ];
+    let mut args = vec![
+        llvm::core::LLVMConstInt(dtype_type, dtype(element) as u64, 0),
+        rank,
+        shape,
+    ];
+    let name: &[u8] = match workspace {
+        Some(offset) => {
+            param_types.push(int_type);
+            args.push(llvm::core::LLVMConstInt(int_type, offset as u64, 0));
+            b"mool_tensor_alloc_at\0"
+        }
+        None => b"mool_tensor_alloc\0",
+    };
+    let alloc = runtime_function(ctx, name, opaque_type(ctx), param_types);
+    let tensor = llvm::core::LLVMBuildCall(
+        ctx.builder(),
+        alloc,
+        args.as_mut_ptr(),
+        args.len() as u32,
+        b"alloc\0".as_ptr() as *const _,
+    );
+    let allocated =
+        llvm::core::LLVMBuildIsNotNull(ctx.builder(), tensor, b"allocated\0".as_ptr() as *const _);
+    build_trap_unless(ctx, allocated, b"alloc_error\0", b"alloc_ok\0");
+    llvm::core::LLVMBuildBitCast(
+        ctx.builder(),
+        tensor,
+        tensor_type(ctx, element),
+        b"tensor\0".as_ptr() as *const _,
+    )
+}
+
+/// 确保运行时的工作区至少有 size 字节，在入口函数的开头调用，之后工作区中的张量不再重新分配
+pub(super) unsafe fn build_reserve(ctx: &CodegenContext, size: usize) {
+    let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+    let reserve = runtime_function(
+        ctx,
+        b"mool_workspace_reserve\0",
+        opaque_type(ctx),
+        vec![int_type],
+    );
+    let mut args = [llvm::core::LLVMConstInt(int_type, size as u64, 0)];
+    llvm::core::LLVMBuildCall(
+        ctx.builder(),
+        reserve,
+        args.as_mut_ptr(),
+        args.len() as u32,
+        b"workspace\0".as_ptr() as *const _,
+    );
+}
+
+/// 在栈上保存形状，返回指向第一维的指针
+pub(super) unsafe fn build_shape(
+    ctx: &CodegenContext,
+    dims: &[llvm::prelude::LLVMValueRef],
+) -> llvm::prelude::LLVMValueRef {
+    let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+    let shape = codegen_alloca(
+        ctx,
+        llvm::core::LLVMArrayType(int_type, dims.len() as u32),
+        "shape",
+    );
+    let shape = llvm::core::LLVMBuildBitCast(
+        ctx.builder(),
+        shape,
+        llvm::core::LLVMPointerType(int_type, 0),
+        b"shape\0".as_ptr() as *const _,
+    );
+    for (i, dim) in dims.iter().enumerate() {
+        let mut index = llvm::core::LLVMConstInt(int_type, i as u64, 0);
+        let pointer = llvm::core::LLVMBuildInBoundsGEP(
+            ctx.builder(),
+            shape,
+            &mut index,
+            1,
+            b"dim\0".as_ptr() as *const _,
+        );
+        llvm::core::LLVMBuildStore(ctx.builder(), *dim, pointer);
+    }
+    shape
+}
+
+/// 张量的维数
+pub(super) unsafe fn build_rank(
+    ctx: &CodegenContext,
+    tensor: llvm::prelude::LLVMValueRef,
+) -> llvm::prelude::LLVMValueRef {
+    build_field(ctx, tensor, 2, b"rank\0")
+}
+
+/// 张量的形状，指向第一维的指针
+pub(super) unsafe fn build_shape_of(
+    ctx: &CodegenContext,
+    tensor: llvm::prelude::LLVMValueRef,
+) -> llvm::prelude::LLVMValueRef {
+    build_field(ctx, tensor, 3, b"shape\0")
+}
+
+/// 张量第 index 维的大小
+pub(super) unsafe fn build_dim(
+    ctx: &CodegenContext,
+    shape: llvm::prelude::LLVMValueRef,
+    index: llvm::prelude::LLVMValueRef,
+) -> llvm::prelude::LLVMValueRef {
+    let mut index = index;
+    let pointer = llvm::core::LLVMBuildInBoundsGEP(
+        ctx.builder(),
+        shape,
+        &mut index,
+        1,
+        b"dim_pointer\0".as_ptr() as *const _,
+    );
+    llvm::core::LLVMBuildLoad(ctx.builder(), pointer, b"dim\0".as_ptr() as *const _)
+}
+
+/// 张量的元素个数，从描述符中读取每一维的大小相乘，按元素计算的循环以它为上界
+pub(super) unsafe fn build_numel(
+    ctx: &CodegenContext,
+    tensor: llvm::prelude::LLVMValueRef,
+) -> llvm::prelude::LLVMValueRef {
+    let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+    let numel = codegen_alloca(ctx, int_type, "numel");
+    llvm::core::LLVMBuildStore(
+        ctx.builder(),
+        llvm::core::LLVMConstInt(int_type, 1, 0),
+        numel,
+    );
+    let shape = build_shape_of(ctx, tensor);
+    build_loop(ctx, build_rank(ctx, tensor), |index| {
+        let dim = build_dim(ctx, shape, index);
+        let product =
+            llvm::core::LLVMBuildLoad(ctx.builder(), numel, b"product\0".as_ptr() as *const _);
+        let product = llvm::core::LLVMBuildMul(
+            ctx.builder(),
+            product,
+            dim,
+            b"product\0".as_ptr() as *const _,
+        );
+        llvm::core::LLVMBuildStore(ctx.builder(), product, numel);
+    });
+    llvm::core::LLVMBuildLoad(ctx.builder(), numel, b"numel\0".as_ptr() as *const _)
+}
+
+/// 检查描述符中张量的形状是否与类型注解一致，不一致时执行 trap
+///
+/// symbols 记录符号维度第一次出现时的大小，之后出现的同名符号维度需要与它相等。
+pub(super) unsafe fn build_check_shape(
+    ctx: &CodegenContext,
+    tensor: llvm::prelude::LLVMValueRef,
+    shape: &[ast::Dim],
+    symbols: &mut HashMap<String, llvm::prelude::LLVMValueRef>,
+) {
+    let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+    let constant = |value: usize| llvm::core::LLVMConstInt(int_type, value as u64, 0);
+    let same_rank = llvm::core::LLVMBuildICmp(
+        ctx.builder(),
+        llvm::LLVMIntPredicate::LLVMIntEQ,
+        build_rank(ctx, tensor),
+        constant(shape.len()),
+        b"same_rank\0".as_ptr() as *const _,
+    );
+    build_assert(ctx, same_rank);
+    let pointer = build_shape_of(ctx, tensor);
+    for (i, dim) in shape.iter().enumerate() {
+        let size = build_dim(ctx, pointer, constant(i));
+        let expected = match dim {
+            ast::Dim::Const(expected) => constant(*expected),
+            ast::Dim::Symbol(symbol) => match symbols.get(symbol) {
+                Some(expected) => *expected,
+                None => {
+                    symbols.insert(symbol.clone(), size);
+                    continue;
+                }
+            },
+        };
+        let same_dim = llvm::core::LLVMBuildICmp(
+            ctx.builder(),
+            llvm::LLVMIntPredicate::LLVMIntEQ,
+            size,
+            expected,
+            b"same_dim\0".as_ptr() as *const _,
+        );
+        build_assert(ctx, same_dim);
+    }
+}
+
+/// 形状不匹配时执行 trap，之后的代码在形状匹配的分支中生成
+unsafe fn build_assert(ctx: &CodegenContext, cond: llvm::prelude::LLVMValueRef) {
+    build_trap_unless(ctx, cond, b"shape_error\0", b"shape_ok\0");
+}
+
+/// cond 为假时执行 trap，之后的代码在 cond 为真的分支中生成
+unsafe fn build_trap_unless(
+    ctx: &CodegenContext,
+    cond: llvm::prelude::LLVMValueRef,
+    error_name: &[u8],
+    ok_name: &[u8],
+) {
+    let function =
+        llvm::core::LLVMGetBasicBlockParent(llvm::core::LLVMGetInsertBlock(ctx.builder()));
+    let error = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        error_name.as_ptr() as *const _,
+    );
+    let ok = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        ok_name.as_ptr() as *const _,
+    );
+    llvm::core::LLVMBuildCondBr(ctx.builder(), cond, ok, error);
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), error);
+    let trap = runtime_function(
+        ctx,
+        b"llvm.trap\0",
+        llvm::core::LLVMVoidTypeInContext(ctx.context()),
+        Vec::new(),
+    );
+    llvm::core::LLVMBuildCall(
+        ctx.builder(),
+        trap,
+        ptr::null_mut(),
+        0,
+        b"\0".as_ptr() as *const _,
+    );
+    llvm::core::LLVMBuildUnreachable(ctx.builder());
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), ok);
+}
+
+/// 张量的数据，指向第一个元素的指针
+pub(super) unsafe fn build_data(
+    ctx: &CodegenContext,
+    tensor: llvm::prelude::LLVMValueRef,
+) -> llvm::prelude::LLVMValueRef {
+    let element = tensor_element(llvm::core::LLVMTypeOf(tensor)).expect("只能读取张量的数据");
+    let data = build_field(ctx, tensor, 0, b"data\0");
+    llvm::core::LLVMBuildBitCast(
+        ctx.builder(),
+        data,
+        llvm::core::LLVMPointerType(storage_type(ctx, element), 0),
+        b"elements\0".as_ptr() as *const _,
+    )
+}
+
+unsafe fn build_element_pointer(
+    ctx: &CodegenContext,
+    tensor: llvm::prelude::LLVMValueRef,
+    index: llvm::prelude::LLVMValueRef,
+) -> llvm::prelude::LLVMValueRef {
+    let mut index = index;
+    llvm::core::LLVMBuildInBoundsGEP(
+        ctx.builder(),
+        build_data(ctx, tensor),
+        &mut index,
+        1,
+        b"element_pointer\0".as_ptr() as *const _,
+    )
+}
+
+/// 读取张量按行优先排列的第 index 个元素
+pub(super) unsafe fn build_load_element(
+    ctx: &CodegenContext,
+    tensor: llvm::prelude::LLVMValueRef,
+    index: llvm::prelude::LLVMValueRef,
+) -> llvm::prelude::LLVMValueRef {
+    let pointer = build_element_pointer(ctx, tensor, index);
+    let value =
+        llvm::core::LLVMBuildLoad(ctx.builder(), pointer, b"element\0".as_ptr() as *const _);
+    match tensor_element(llvm::core::LLVMTypeOf(tensor)).map(dtype) {
+        Some(DType::Bool) => llvm::core::LLVMBuildICmp(
+            ctx.builder(),
+            llvm::LLVMIntPredicate::LLVMIntNE,
+            value,
+            llvm::core::LLVMConstNull(llvm::core::LLVMTypeOf(value)),
+            b"bool\0".as_ptr() as *const _,
+        ),
+        _ => value,
+    }
+}
+
+/// 写入张量按行优先排列的第 index 个元素
+pub(super) unsafe fn build_store_element(
+    ctx: &CodegenContext,
+    tensor: llvm::prelude::LLVMValueRef,
+    index: llvm::prelude::LLVMValueRef,
+    value: llvm::prelude::LLVMValueRef,
+) {
+    let pointer = build_element_pointer(ctx, tensor, index);
+    let value = match dtype(llvm::core::LLVMTypeOf(value)) {
+        DType::Bool => llvm::core::LLVMBuildZExt(
+            ctx.builder(),
+            value,
+            llvm::core::LLVMGetElementType(llvm::core::LLVMTypeOf(pointer)),
+            b"byte\0".as_ptr() as *const _,
+        ),
+        _ => value,
+    };
+    llvm::core::LLVMBuildStore(ctx.builder(), value, pointer);
+}
+
+/// 增加值中所有张量的引用计数，标量什么也不做
+pub(super) unsafe fn build_retain(ctx: &CodegenContext, value: llvm::prelude::LLVMValueRef) {
+    build_refcount(ctx, value, b"mool_tensor_retain\0");
+}
+
+/// 减少值中所有张量的引用计数，标量什么也不做
+pub(super) unsafe fn build_release(ctx: &CodegenContext, value: llvm::prelude::LLVMValueRef) {
+    build_refcount(ctx, value, b"mool_tensor_release\0");
+}
+
+/// 对值中的每个张量调用运行时函数 name，元组逐个元素处理
+unsafe fn build_refcount(ctx: &CodegenContext, value: llvm::prelude::LLVMValueRef, name: &[u8]) {
+    let ty = llvm::core::LLVMTypeOf(value);
+    if !contains_tensor(ty) {
+        return;
+    }
+    let count = match llvm::core::LLVMGetTypeKind(ty) {
+        llvm::LLVMTypeKind::LLVMStructTypeKind => llvm::core::LLVMCountStructElementTypes(ty),
+        llvm::LLVMTypeKind::LLVMArrayTypeKind => llvm::core::LLVMGetArrayLength(ty),
+        _ => {
+            let function = runtime_function(
+                ctx,
+                name,
+                llvm::core::LLVMVoidTypeInContext(ctx.context()),
+                vec![opaque_type(ctx)],
+            );
+            let mut args = [opaque(ctx, value)];
+            llvm::core::LLVMBuildCall(
+                ctx.builder(),
+                function,
+                args.as_mut_ptr(),
+                1,
+                b"\0".as_ptr() as *const _,
+            );
+            return;
+        }
+    };
+    for i in 0..count {
+        let element = llvm::core::LLVMBuildExtractValue(
+            ctx.builder(),
+            value,
+            i,
+            b"element\0".as_ptr() as *const _,
+        );
+        build_refcount(ctx, element, name);
+    }
+}
@@ -0,0 +1,120 @@
+use super::context::CodegenContext;
+use super::error::CodegenError;
+use super::optimize::OptLevel;
+use llvm_sys as llvm;
+use std::ffi::{CStr, CString};
+use std::ptr;
+use std::sync::Once;
+
+/// LLVM 的目标机器，负责把模块编译为目标平台的目标文件，离开作用域时自动释放
+#[derive(Debug)]
+pub(super) struct TargetMachine {
+    raw: llvm::target_machine::LLVMTargetMachineRef,
+    triple: CString,
+}
+
+impl TargetMachine {
+    /// 创建 triple 对应的目标机器，例如 x86_64-unknown-linux-gnu、aarch64-linux-gnu
+    ///
+    /// cpu 为空时使用 generic，features 为 LLVM 的目标特性，例如 +avx2,+fma。
+    pub(super) fn new(
+        triple: &str,
+        cpu: &str,
+        features: &str,
+        level: OptLevel,
+    ) -> Result<Self, CodegenError> {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| unsafe {
+            llvm::target::LLVM_InitializeAllTargetInfos();
+            llvm::target::LLVM_InitializeAllTargets();
+            llvm::target::LLVM_InitializeAllTargetMCs();
+            llvm::target::LLVM_InitializeAllAsmPrinters();
+        });
+        let triple = CString::new(triple).unwrap();
+        let cpu = CString::new(if cpu.is_empty() { "generic" } else { cpu }).unwrap();
+        let features = CString::new(features).unwrap();
+        let level = match level {
+            OptLevel::O0 => llvm::target_machine::LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+            OptLevel::O1 => llvm::target_machine::LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+            OptLevel::O2 | OptLevel::Os => {
+                llvm::target_machine::LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault
+            }
+            OptLevel::O3 => llvm::target_machine::LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+        };
+        unsafe {
+            let mut target = ptr::null_mut();
+            let mut message = ptr::null_mut();
+            if llvm::target_machine::LLVMGetTargetFromTriple(
+                triple.as_ptr(),
+                &mut target,
+                &mut message,
+            ) != 0
+            {
+                return Err(CodegenError::Target(take_message(message)));
+            }
+            let raw = llvm::target_machine::LLVMCreateTargetMachine(
+                target,
+                triple.as_ptr(),
+                cpu.as_ptr(),
+                features.as_ptr(),
+                level,
+                llvm::target_machine::LLVMRelocMode::LLVMRelocDefault,
+                llvm::target_machine::LLVMCodeModel::LLVMCodeModelDefault,
+            );
+            Ok(Self { raw, triple })
+        }
+    }
+
+    pub(super) fn raw(&self) -> llvm::target_machine::LLVMTargetMachineRef {
+        self.raw
+    }
+
+    /// 设置模块的 triple 和数据布局，需要在优化之前调用
+    pub(super) fn configure(&self, ctx: &CodegenContext) {
+        unsafe {
+            llvm::core::LLVMSetTarget(ctx.module(), self.triple.as_ptr());
+            let layout = llvm::target_machine::LLVMCreateTargetDataLayout(self.raw);
+            llvm::target::LLVMSetModuleDataLayout(ctx.module(), layout);
+            llvm::target::LLVMDisposeTargetData(layout);
+        }
+    }
+
+    /// 把模块编译为目标文件
+    pub(super) fn emit_object(&self, ctx: &CodegenContext) -> Result<Vec<u8>, CodegenError> {
+        unsafe {
+            let mut message = ptr::null_mut();
+            let mut buffer = ptr::null_mut();
+            if llvm::target_machine::LLVMTargetMachineEmitToMemoryBuffer(
+                self.raw,
+                ctx.module(),
+                llvm::target_machine::LLVMCodeGenFileType::LLVMObjectFile,
+                &mut message,
+                &mut buffer,
+            ) != 0
+            {
+                return Err(CodegenError::Target(take_message(message)));
+            }
+            let start = llvm::core::LLVMGetBufferStart(buffer) as *const u8;
+            let size = llvm::core::LLVMGetBufferSize(buffer);
+            let object = std::slice::from_raw_parts(start, size).to_vec();
+            llvm::core::LLVMDisposeMemoryBuffer(buffer);
+            Ok(object)
+        }
+    }
+}
+
+impl Drop for TargetMachine {
+    fn drop(&mut self) {
+        unsafe { llvm::target_machine::LLVMDisposeTargetMachine(self.raw) }
+    }
+}
+
+/// 取出 LLVM 返回的错误信息并释放
+pub(super) unsafe fn take_message(message: *mut std::os::raw::c_char) -> String {
+    if message.is_null() {
+        return String::new();
+    }
+    let text = CStr::from_ptr(message).to_string_lossy().into_owned();
+    llvm::core::LLVMDisposeMessage(message);
+    text
+}
@@ -0,0 +1,96 @@
+use super::runtime::{is_tensor, tensor_element};
+use llvm_sys as llvm;
+use std::ffi::CStr;
+use std::marker::PhantomData;
+
+/// LLVM 值，生命周期不超过创建它的 CodegenContext
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Value<'ctx> {
+    raw: llvm::prelude::LLVMValueRef,
+    marker: PhantomData<&'ctx ()>,
+}
+
+/// LLVM 类型，生命周期不超过创建它的 CodegenContext
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Type<'ctx> {
+    raw: llvm::prelude::LLVMTypeRef,
+    marker: PhantomData<&'ctx ()>,
+}
+
+impl<'ctx> Value<'ctx> {
+    /// 包装 LLVM 值，调用方需要保证值属于仍然存活的 CodegenContext
+    pub(super) unsafe fn new(raw: llvm::prelude::LLVMValueRef) -> Self {
+        Self {
+            raw,
+            marker: PhantomData,
+        }
+    }
+
+    pub(super) fn raw(self) -> llvm::prelude::LLVMValueRef {
+        self.raw
+    }
+
+    /// 值的名称，匿名的值为空字符串
+    pub fn name(self) -> String {
+        unsafe {
+            let mut length = 0;
+            let name = llvm::core::LLVMGetValueName2(self.raw, &mut length);
+            let name = std::slice::from_raw_parts(name as *const u8, length);
+            String::from_utf8_lossy(name).into_owned()
+        }
+    }
+
+    /// 值的类型
+    pub fn type_of(self) -> Type<'ctx> {
+        unsafe { Type::new(llvm::core::LLVMTypeOf(self.raw)) }
+    }
+
+    /// 函数是否只有声明，没有函数体
+    pub fn is_declaration(self) -> bool {
+        unsafe { llvm::core::LLVMIsDeclaration(self.raw) != 0 }
+    }
+}
+
+impl<'ctx> Type<'ctx> {
+    /// 包装 LLVM 类型，调用方需要保证类型属于仍然存活的 CodegenContext
+    pub(super) unsafe fn new(raw: llvm::prelude::LLVMTypeRef) -> Self {
+        Self {
+            raw,
+            marker: PhantomData,
+        }
+    }
+
+    /// 类型的种类
+    pub fn kind(self) -> llvm::LLVMTypeKind {
+        unsafe { llvm::core::LLVMGetTypeKind(self.raw) }
+    }
+
+    /// 张量的元素类型，标量返回自身
+    pub fn element_type(self) -> Type<'ctx> {
+        match unsafe { tensor_element(self.raw) } {
+            Some(element) => unsafe { Type::new(element) },
+            None => self,
+        }
+    }
+
+    /// 是否为张量，即指向运行时描述符的指针
+    pub fn is_tensor(self) -> bool {
+        unsafe { is_tensor(self.raw) }
+    }
+
+    /// 标量或张量的元素是否为浮点数
+    pub fn is_float(self) -> bool {
+        self.element_type().kind() == llvm::LLVMTypeKind::LLVMDoubleTypeKind
+    }
+}
+
+impl std::fmt::Display for Type<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        unsafe {
+            let message = llvm::core::LLVMPrintTypeToString(self.raw);
+            let result = write!(f, "{}", CStr::from_ptr(message).to_string_lossy());
+            llvm::core::LLVMDisposeMessage(message);
+            result
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use super::super::scope::Scope;
+use super::context::CodegenContext;
+use super::error::CodegenError;
+use llvm_sys as llvm;
+use std::ffi::CStr;
+use std::ptr;
+
+/// 用 LLVM 的验证器检查模块
+///
+/// 先验证整个模块得到详细信息，不合法时再逐个验证函数，找出不合法的函数并换成 Mool 中的名称。
+pub(super) fn verify(ctx: &CodegenContext, scope: &Scope) -> Result<(), CodegenError> {
+    let mut message = ptr::null_mut();
+    let (broken, text) = unsafe {
+        let broken = llvm::analysis::LLVMVerifyModule(
+            ctx.module(),
+            llvm::analysis::LLVMVerifierFailureAction::LLVMReturnStatusAction,
+            &mut message,
+        ) != 0;
+        let text = if message.is_null() {
+            String::new()
+        } else {
+            let text = CStr::from_ptr(message).to_string_lossy().into_owned();
+            llvm::core::LLVMDisposeMessage(message);
+            text
+        };
+        (broken, text)
+    };
+    if !broken {
+        return Ok(());
+    }
+    let functions = ctx
+        .functions()
+        .into_iter()
+        .filter(|function| {
+            !function.is_declaration()
+                && unsafe {
+                    llvm::analysis::LLVMVerifyFunction(
+                        function.raw(),
+                        llvm::analysis::LLVMVerifierFailureAction::LLVMReturnStatusAction,
+                    ) != 0
+                }
+        })
+        .map(|function| match scope.function_name(function.raw()) {
+            Some(name) => name.clone(),
+            None => format!("@{}", function.name()),
+        })
+        .collect();
+    Err(CodegenError::Verify {
+        functions,
+        message: text,
+    })
+}
@@ -0,0 +1,570 @@
+use super::super::backend::{top_level_functions, Artifact, Backend};
+use super::codegen::build;
+use super::codegen_expr::mool_type_ref;
+use super::context::CodegenContext;
+use super::error::CodegenError;
+use super::optimize::{optimize, OptLevel};
+use super::runtime::{build_alloc, build_data, build_release, build_reserve, build_shape};
+use super::target::{take_message, TargetMachine};
+use super::verify::verify;
+use llvm_sys as llvm;
+use mool_ir::ast::{self, Program};
+use mool_ir::pass::workspace_size;
+use std::error::Error;
+use std::ffi::CString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const TRIPLE: &str = "wasm32-unknown-unknown";
+/// 用 bulk memory 的指令实现 memcpy、memset，模块不依赖 libc
+const FEATURES: &str = "+bulk-memory";
+
+/// WebAssembly 后端，用 LLVM 的 WebAssembly 后端输出 wasm32-unknown-unknown 的目标文件（.o），
+/// 配置了链接器时再链接为可以直接加载的模块（.wasm）
+///
+/// 顶层定义的函数 %f 以 f 为名导出，顶层代码以 main 为名导出。导出函数的参数和返回值：
+///
+/// - int、float 为 i64、f64，bool 为 i32（0 或 1）
+/// - 张量和元组保存在线性内存中，按 C 的布局排列，bool 元素占一个字节，
+///   参数传入指向值的 i32 指针，返回值写入最后一个参数指向的内存，函数本身没有返回值
+///
+/// 签名中有符号维度的函数在线性内存中没有固定的布局，不导出，只能在 Mool 中调用。
+///
+/// 链接后的模块导出 memory 和 __heap_base，调用方从 __heap_base 开始存放张量，
+/// 不够时用 memory.grow 增长线性内存，新增的页归调用方使用。
+/// 函数内部的张量由链接进模块的运行时分配，同样只使用运行时自己增长的页。
+#[derive(Debug, Clone, Default)]
+pub struct WasmBackend {
+    level: OptLevel,
+    linker: Option<PathBuf>,
+}
+
+impl WasmBackend {
+    /// linker 为 wasm-ld 或 rust-lld 的路径
+    pub fn new(level: OptLevel, linker: Option<PathBuf>) -> Self {
+        Self { level, linker }
+    }
+
+    /// 生成 WebAssembly 目标文件
+    pub fn object(&self, programs: Vec<Program>) -> Result<Vec<u8>, CodegenError> {
+        // 导出函数需要 Mool 的参数和返回值类型，先从顶层定义中取出签名
+        let mut signatures: Vec<(String, Vec<ast::Type>, ast::Type)> = Vec::new();
+        for program in programs.iter() {
+            if let Program::Let(variable, ast::Expr::Function(function)) = program {
+                signatures.retain(|(name, _, _)| *name != variable.name);
+                let annotations = function.args.iter().map(|arg| &arg.annotation);
+                if !annotations.chain([&function.rtn]).all(is_static) {
+                    continue;
+                }
+                signatures.push((
+                    variable.name.clone(),
+                    function
+                        .args
+                        .iter()
+                        .map(|arg| arg.annotation.clone())
+                        .collect(),
+                    function.rtn.clone(),
+                ));
+            }
+        }
+        let workspace = workspace_size(&programs);
+        let machine = TargetMachine::new(TRIPLE, "", FEATURES, self.level)?;
+        let ctx = CodegenContext::default();
+        machine.configure(&ctx);
+        let mut scope = build(&ctx, programs);
+        unsafe { link_runtime(&ctx) };
+        // 只有导出函数对外可见，其他函数内联之后可以删除
+        for function in ctx.functions() {
+            if !function.is_declaration() {
+                unsafe {
+                    llvm::core::LLVMSetLinkage(
+                        function.raw(),
+                        llvm::LLVMLinkage::LLVMInternalLinkage,
+                    )
+                };
+            }
+        }
+        signatures.push(("main".to_string(), Vec::new(), ast::Type::Int));
+        for (name, args, rtn) in signatures {
+            let function = match name.as_str() {
+                "main" => unsafe {
+                    llvm::core::LLVMGetNamedFunction(ctx.module(), b"main\0".as_ptr() as *const _)
+                },
+                _ => scope.get(&name).unwrap(),
+            };
+            let wrapper = unsafe { export(&ctx, &name, function, &args, &rtn, workspace) };
+            scope.name_function(wrapper, format!("%{}的导出函数", name));
+        }
+        unsafe { builtins(&ctx) };
+        verify(&ctx, &scope)?;
+        optimize(&ctx, self.level, Some(&machine))?;
+        machine.emit_object(&ctx)
+    }
+
+    /// 只包含运行时的目标文件，运行时的 C 接口以原来的名字导出，
+    /// 供其他语言编写的 WebAssembly 程序链接，也用来和 mool_runtime 对照测试
+    pub fn runtime(&self) -> Result<Vec<u8>, CodegenError> {
+        let machine = TargetMachine::new(TRIPLE, "", FEATURES, self.level)?;
+        let ctx = CodegenContext::default();
+        machine.configure(&ctx);
+        unsafe { link_runtime(&ctx) };
+        for function in ctx.functions() {
+            let linkage = unsafe { llvm::core::LLVMGetLinkage(function.raw()) };
+            if function.is_declaration() || linkage != llvm::LLVMLinkage::LLVMExternalLinkage {
+                continue;
+            }
+            let name = CString::new(function.name()).unwrap();
+            unsafe {
+                llvm::core::LLVMAddTargetDependentFunctionAttr(
+                    function.raw(),
+                    b"wasm-export-name\0".as_ptr() as *const _,
+                    name.as_ptr(),
+                )
+            };
+        }
+        optimize(&ctx, self.level, Some(&machine))?;
+        machine.emit_object(&ctx)
+    }
+}
+
+impl Backend for WasmBackend {
+    fn name(&self) -> &'static str {
+        "wasm"
+    }
+
+    fn codegen(&self, programs: Vec<Program>) -> Result<Vec<Artifact>, Box<dyn Error>> {
+        let object = self.object(programs)?;
+        let mut artifacts = Vec::new();
+        if let Some(linker) = &self.linker {
+            artifacts.push(Artifact::new("wasm", link(linker, &object)?));
+        }
+        artifacts.push(Artifact::new("o", object));
+        Ok(artifacts)
+    }
+
+    fn roots(&self, programs: &[Program]) -> Vec<String> {
+        top_level_functions(programs)
+    }
+}
+
+/// 调用 wasm-ld 把目标文件链接为模块，没有入口函数，额外导出 __heap_base
+///
+/// rust-lld 需要用 -flavor wasm 指定按 wasm-ld 的方式链接。
+fn link(linker: &Path, object: &[u8]) -> Result<Vec<u8>, CodegenError> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "mool-wasm-{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    let error = |error: std::io::Error| CodegenError::Link(error.to_string());
+    fs::create_dir_all(&dir).map_err(error)?;
+    fs::write(dir.join("module.o"), object).map_err(error)?;
+    let mut command = Command::new(linker);
+    if linker.file_stem().is_some_and(|stem| stem == "rust-lld") {
+        command.args(["-flavor", "wasm"]);
+    }
+    let output = command
+        .current_dir(&dir)
+        .args([
+            "--no-entry",
+            "--export=__heap_base",
+            "module.o",
+            "-o",
+            "module.wasm",
+        ])
+        .output()
+        .map_err(|e| CodegenError::Link(format!("{}：{}", linker.display(), e)));
+    let module = match output {
+        Ok(output) if output.status.success() => fs::read(dir.join("module.wasm")).map_err(error),
+        Ok(output) => Err(CodegenError::Link(
+            String::from_utf8_lossy(&output.stderr)
+                .trim_end()
+                .to_string(),
+        )),
+        Err(error) => Err(error),
+    };
+    fs::remove_dir_all(&dir).map_err(error)?;
+    module
+}
+
+/// 把运行时的 WebAssembly 实现链接到模块中，张量的内存从线性内存中分配
+unsafe fn link_runtime(ctx: &CodegenContext) {
+    let source = include_str!("wasm_runtime.ll");
+    let buffer = llvm::core::LLVMCreateMemoryBufferWithMemoryRangeCopy(
+        source.as_ptr() as *const _,
+        source.len(),
+        b"wasm_runtime\0".as_ptr() as *const _,
+    );
+    let mut runtime = ptr::null_mut();
+    let mut message = ptr::null_mut();
+    if llvm::ir_reader::LLVMParseIRInContext(ctx.context(), buffer, &mut runtime, &mut message) != 0
+    {
+        panic!("运行时的 LLVM IR 不正确：{}", take_message(message));
+    }
+    llvm::core::LLVMSetDataLayout(runtime, llvm::core::LLVMGetDataLayoutStr(ctx.module()));
+    llvm::core::LLVMSetTarget(runtime, llvm::core::LLVMGetTarget(ctx.module()));
+    if llvm::linker::LLVMLinkModules2(ctx.module(), runtime) != 0 {
+        panic!("链接运行时失败");
+    }
+}
+
+/// 生成 LLVM 可能调用的 compiler-rt 函数，wasm32 没有可以链接的 compiler-rt
+///
+/// 优化后的循环可能用 i128 乘法计算归纳变量的终值，LLVM 会把它翻译为对 __multi3 的调用。
+/// 这里用 64 位乘法实现，弱链接保证优化时不会被删除，没有用到时由链接器删除。
+unsafe fn builtins(ctx: &CodegenContext) {
+    let builder = ctx.builder();
+    let i64_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+    let i128_type = llvm::core::LLVMInt128TypeInContext(ctx.context());
+    let mut param_types = [i128_type, i128_type];
+    let function_type = llvm::core::LLVMFunctionType(i128_type, param_types.as_mut_ptr(), 2, 0);
+    let function = llvm::core::LLVMAddFunction(
+        ctx.module(),
+        b"__multi3\0".as_ptr() as *const _,
+        function_type,
+    );
+    llvm::core::LLVMSetLinkage(function, llvm::LLVMLinkage::LLVMWeakAnyLinkage);
+    let basic_block = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        function,
+        b"entry\0".as_ptr() as *const _,
+    );
+    llvm::core::LLVMPositionBuilderAtEnd(builder, basic_block);
+    let name = b"multi3\0".as_ptr() as *const _;
+    let constant = |value: u64| llvm::core::LLVMConstInt(i64_type, value, 0);
+    // 拆分为高低 64 位
+    let halves = |value: llvm::prelude::LLVMValueRef| {
+        let low = llvm::core::LLVMBuildTrunc(builder, value, i64_type, name);
+        let shift = llvm::core::LLVMConstInt(i128_type, 64, 0);
+        let high = llvm::core::LLVMBuildLShr(builder, value, shift, name);
+        (
+            low,
+            llvm::core::LLVMBuildTrunc(builder, high, i64_type, name),
+        )
+    };
+    let (a_low, a_high) = halves(llvm::core::LLVMGetParam(function, 0));
+    let (b_low, b_high) = halves(llvm::core::LLVMGetParam(function, 1));
+    // 低 64 位相乘得到完整的 128 位结果，按 32 位拆分避免溢出
+    let mask = constant(0xffff_ffff);
+    let split = |value| {
+        (
+            llvm::core::LLVMBuildAnd(builder, value, mask, name),
+            llvm::core::LLVMBuildLShr(builder, value, constant(32), name),
+        )
+    };
+    let (x0, x1) = split(a_low);
+    let (y0, y1) = split(b_low);
+    let p00 = llvm::core::LLVMBuildMul(builder, x0, y0, name);
+    let p01 = llvm::core::LLVMBuildMul(builder, x0, y1, name);
+    let p10 = llvm::core::LLVMBuildMul(builder, x1, y0, name);
+    let p11 = llvm::core::LLVMBuildMul(builder, x1, y1, name);
+    let (p00_low, p00_high) = split(p00);
+    let (p01_low, p01_high) = split(p01);
+    let (p10_low, p10_high) = split(p10);
+    let middle = llvm::core::LLVMBuildAdd(builder, p00_high, p01_low, name);
+    let middle = llvm::core::LLVMBuildAdd(builder, middle, p10_low, name);
+    let low = llvm::core::LLVMBuildShl(builder, middle, constant(32), name);
+    let low = llvm::core::LLVMBuildOr(builder, low, p00_low, name);
+    let high = llvm::core::LLVMBuildAdd(builder, p11, p01_high, name);
+    let high = llvm::core::LLVMBuildAdd(builder, high, p10_high, name);
+    let carry = llvm::core::LLVMBuildLShr(builder, middle, constant(32), name);
+    let high = llvm::core::LLVMBuildAdd(builder, high, carry, name);
+    // 交叉项只影响高 64 位
+    let cross = llvm::core::LLVMBuildMul(builder, a_low, b_high, name);
+    let high = llvm::core::LLVMBuildAdd(builder, high, cross, name);
+    let cross = llvm::core::LLVMBuildMul(builder, a_high, b_low, name);
+    let high = llvm::core::LLVMBuildAdd(builder, high, cross, name);
+    let high = llvm::core::LLVMBuildZExt(builder, high, i128_type, name);
+    let high = llvm::core::LLVMBuildShl(
+        builder,
+        high,
+        llvm::core::LLVMConstInt(i128_type, 64, 0),
+        name,
+    );
+    let low = llvm::core::LLVMBuildZExt(builder, low, i128_type, name);
+    let result = llvm::core::LLVMBuildOr(builder, high, low, name);
+    llvm::core::LLVMBuildRet(builder, result);
+}
+
+/// 为函数生成以 name 导出的包装函数，按线性内存的约定转换参数和返回值
+///
+/// 导出函数都是入口，workspace 不为 0 时先分配内存规划需要的工作区。
+unsafe fn export(
+    ctx: &CodegenContext,
+    name: &str,
+    function: llvm::prelude::LLVMValueRef,
+    args: &[ast::Type],
+    rtn: &ast::Type,
+    workspace: usize,
+) -> llvm::prelude::LLVMValueRef {
+    let mut param_types: Vec<llvm::prelude::LLVMTypeRef> =
+        args.iter().map(|ty| abi_type(ctx, ty)).collect();
+    let return_type = if is_scalar(rtn) {
+        abi_type(ctx, rtn)
+    } else {
+        param_types.push(abi_type(ctx, rtn));
+        llvm::core::LLVMVoidTypeInContext(ctx.context())
+    };
+    let function_type = llvm::core::LLVMFunctionType(
+        return_type,
+        param_types.as_mut_ptr(),
+        param_types.len() as u32,
+        0,
+    );
+    let wrapper_name = CString::new(format!("mool_export_{}", name)).unwrap();
+    let wrapper = llvm::core::LLVMAddFunction(ctx.module(), wrapper_name.as_ptr(), function_type);
+    let export_name = CString::new(name).unwrap();
+    llvm::core::LLVMAddTargetDependentFunctionAttr(
+        wrapper,
+        b"wasm-export-name\0".as_ptr() as *const _,
+        export_name.as_ptr(),
+    );
+    let basic_block = llvm::core::LLVMAppendBasicBlockInContext(
+        ctx.context(),
+        wrapper,
+        b"entry\0".as_ptr() as *const _,
+    );
+    llvm::core::LLVMPositionBuilderAtEnd(ctx.builder(), basic_block);
+    if workspace > 0 {
+        build_reserve(ctx, workspace);
+    }
+    // 把参数转换为 Mool 的值
+    let mut real_args = Vec::new();
+    for (i, ty) in args.iter().enumerate() {
+        let param = llvm::core::LLVMGetParam(wrapper, i as u32);
+        real_args.push(match ty {
+            ast::Type::Bool => llvm::core::LLVMBuildICmp(
+                ctx.builder(),
+                llvm::LLVMIntPredicate::LLVMIntNE,
+                param,
+                llvm::core::LLVMConstNull(llvm::core::LLVMTypeOf(param)),
+                b"arg\0".as_ptr() as *const _,
+            ),
+            ast::Type::Int | ast::Type::Float => param,
+            _ => load_memory(ctx, param, ty),
+        });
+    }
+    let result = llvm::core::LLVMBuildCall(
+        ctx.builder(),
+        function,
+        real_args.as_mut_ptr(),
+        real_args.len() as u32,
+        b"result\0".as_ptr() as *const _,
+    );
+    for arg in real_args {
+        build_release(ctx, arg);
+    }
+    match rtn {
+        ast::Type::Bool => {
+            let result = llvm::core::LLVMBuildZExt(
+                ctx.builder(),
+                result,
+                return_type,
+                b"rtn\0".as_ptr() as *const _,
+            );
+            llvm::core::LLVMBuildRet(ctx.builder(), result);
+        }
+        ast::Type::Int | ast::Type::Float => {
+            llvm::core::LLVMBuildRet(ctx.builder(), result);
+        }
+        _ => {
+            let out = llvm::core::LLVMGetParam(wrapper, args.len() as u32);
+            store_memory(ctx, result, out, rtn);
+            build_release(ctx, result);
+            llvm::core::LLVMBuildRetVoid(ctx.builder());
+        }
+    }
+    wrapper
+}
+
+fn is_scalar(ty: &ast::Type) -> bool {
+    matches!(ty, ast::Type::Int | ast::Type::Float | ast::Type::Bool)
+}
+
+/// 类型中的张量是否都没有符号维度
+fn is_static(ty: &ast::Type) -> bool {
+    match ty {
+        ast::Type::Tensor(shape, _) => ast::numel(shape).is_some(),
+        ast::Type::Tuple(types) => types.iter().all(is_static),
+        ast::Type::Int | ast::Type::Float | ast::Type::Bool => true,
+    }
+}
+
+/// 导出函数的参数类型，张量和元组为指向线性内存的指针
+unsafe fn abi_type(ctx: &CodegenContext, ty: &ast::Type) -> llvm::prelude::LLVMTypeRef {
+    match ty {
+        ast::Type::Int => llvm::core::LLVMInt64TypeInContext(ctx.context()),
+        ast::Type::Float => llvm::core::LLVMDoubleTypeInContext(ctx.context()),
+        ast::Type::Bool => llvm::core::LLVMInt32TypeInContext(ctx.context()),
+        _ => llvm::core::LLVMPointerType(memory_type(ctx, ty), 0),
+    }
+}
+
+/// 值在线性内存中的类型，张量为数组，bool 占一个字节
+unsafe fn memory_type(ctx: &CodegenContext, ty: &ast::Type) -> llvm::prelude::LLVMTypeRef {
+    match ty {
+        ast::Type::Bool => llvm::core::LLVMInt8TypeInContext(ctx.context()),
+        ast::Type::Int | ast::Type::Float => mool_type_ref(ctx, ty),
+        ast::Type::Tensor(shape, dtype) => {
+            let size = ast::numel(shape).expect("导出函数的张量不能有符号维度");
+            llvm::core::LLVMArrayType(memory_type(ctx, dtype), size as u32)
+        }
+        ast::Type::Tuple(types) => {
+            let mut element_types: Vec<llvm::prelude::LLVMTypeRef> =
+                types.iter().map(|ty| memory_type(ctx, ty)).collect();
+            llvm::core::LLVMStructTypeInContext(
+                ctx.context(),
+                element_types.as_mut_ptr(),
+                element_types.len() as u32,
+                0,
+            )
+        }
+    }
+}
+
+/// 元组的第 index 个元素的指针
+unsafe fn element_pointer(
+    ctx: &CodegenContext,
+    pointer: llvm::prelude::LLVMValueRef,
+    index: usize,
+) -> llvm::prelude::LLVMValueRef {
+    let int_type = llvm::core::LLVMInt32TypeInContext(ctx.context());
+    let mut indices = [
+        llvm::core::LLVMConstInt(int_type, 0, 0),
+        llvm::core::LLVMConstInt(int_type, index as u64, 0),
+    ];
+    llvm::core::LLVMBuildInBoundsGEP(
+        ctx.builder(),
+        pointer,
+        indices.as_mut_ptr(),
+        indices.len() as u32,
+        b"element\0".as_ptr() as *const _,
+    )
+}
+
+/// 从线性内存读取 Mool 的值
+unsafe fn load_memory(
+    ctx: &CodegenContext,
+    pointer: llvm::prelude::LLVMValueRef,
+    ty: &ast::Type,
+) -> llvm::prelude::LLVMValueRef {
+    match ty {
+        ast::Type::Int | ast::Type::Float => {
+            llvm::core::LLVMBuildLoad(ctx.builder(), pointer, b"load\0".as_ptr() as *const _)
+        }
+        ast::Type::Bool => {
+            let byte =
+                llvm::core::LLVMBuildLoad(ctx.builder(), pointer, b"load\0".as_ptr() as *const _);
+            llvm::core::LLVMBuildICmp(
+                ctx.builder(),
+                llvm::LLVMIntPredicate::LLVMIntNE,
+                byte,
+                llvm::core::LLVMConstNull(llvm::core::LLVMTypeOf(byte)),
+                b"bool\0".as_ptr() as *const _,
+            )
+        }
+        // 张量复制到新分配的运行时张量中，数据的布局相同
+        ast::Type::Tensor(shape, dtype) => {
+            let int_type = llvm::core::LLVMInt64TypeInContext(ctx.context());
+            let dims: Vec<llvm::prelude::LLVMValueRef> = shape
+                .iter()
+                .map(|dim| {
+                    let size = dim.size().expect("导出函数的张量不能有符号维度");
+                    llvm::core::LLVMConstInt(int_type, size as u64, 0)
+                })
+                .collect();
+            let tensor = build_alloc(
+                ctx,
+                mool_type_ref(ctx, dtype),
+                llvm::core::LLVMConstInt(int_type, dims.len() as u64, 0),
+                build_shape(ctx, &dims),
+                None,
+            );
+            llvm::core::LLVMBuildMemCpy(
+                ctx.builder(),
+                build_data(ctx, tensor),
+                0,
+                pointer,
+                0,
+                llvm::core::LLVMSizeOf(memory_type(ctx, ty)),
+            );
+            tensor
+        }
+        ast::Type::Tuple(types) => load_elements(ctx, pointer, ty, types.iter()),
+    }
+}
+
+unsafe fn load_elements<'a>(
+    ctx: &CodegenContext,
+    pointer: llvm::prelude::LLVMValueRef,
+    ty: &ast::Type,
+    types: impl Iterator<Item = &'a ast::Type>,
+) -> llvm::prelude::LLVMValueRef {
+    let mut value = llvm::core::LLVMGetUndef(mool_type_ref(ctx, ty));
+    for (i, element_type) in types.enumerate() {
+        let element = load_memory(ctx, element_pointer(ctx, pointer, i), element_type);
+        value = llvm::core::LLVMBuildInsertValue(
+            ctx.builder(),
+            value,
+            element,
+            i as u32,
+            b"aggregate\0".as_ptr() as *const _,
+        );
+    }
+    value
+}
+
+/// 把 Mool 的值写入线性内存
+unsafe fn store_memory(
+    ctx: &CodegenContext,
+    value: llvm::prelude::LLVMValueRef,
+    pointer: llvm::prelude::LLVMValueRef,
+    ty: &ast::Type,
+) {
+    match ty {
+        ast::Type::Int | ast::Type::Float => {
+            llvm::core::LLVMBuildStore(ctx.builder(), value, pointer);
+        }
+        ast::Type::Bool => {
+            let byte = llvm::core::LLVMBuildZExt(
+                ctx.builder(),
+                value,
+                memory_type(ctx, ty),
+                b"byte\0".as_ptr() as *const _,
+            );
+            llvm::core::LLVMBuildStore(ctx.builder(), byte, pointer);
+        }
+        ast::Type::Tensor(_, _) => {
+            llvm::core::LLVMBuildMemCpy(
+                ctx.builder(),
+                pointer,
+                0,
+                build_data(ctx, value),
+                0,
+                llvm::core::LLVMSizeOf(memory_type(ctx, ty)),
+            );
+        }
+        ast::Type::Tuple(types) => store_elements(ctx, value, pointer, types.iter()),
+    }
+}
+
+unsafe fn store_elements<'a>(
+    ctx: &CodegenContext,
+    value: llvm::prelude::LLVMValueRef,
+    pointer: llvm::prelude::LLVMValueRef,
+    types: impl Iterator<Item = &'a ast::Type>,
+) {
+    for (i, element_type) in types.enumerate() {
+        let element = llvm::core::LLVMBuildExtractValue(
+            ctx.builder(),
+            value,
+            i as u32,
+            b"element\0".as_ptr() as *const _,
+        );
+        store_memory(ctx, element, element_pointer(ctx, pointer, i), element_type);
+    }
+}
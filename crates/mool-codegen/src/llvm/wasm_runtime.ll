@@ -0,0 +1,392 @@
+; Mool 运行时的 WebAssembly 实现，与 mool_runtime 的 C 接口一致，链接到生成的模块中
+;
+; 描述符、形状、步长和数据分配在同一块内存中，块的前 8 个字节记录块的大小和空闲链表中的下一个块。
+; 内存来自 memory.grow 新增的页，不会占用调用方从 __heap_base 开始存放的数据。
+; 引用计数减到 0 的块放入空闲链表，分配时优先复用大小相同的块。
+; 与 mool_runtime 一样，参数不正确、工作区不够大或者内存不足时返回 null 或 -1。
+; 内存规划过的中间张量的数据放在工作区中，块中只有描述符、形状和步长。
+
+%mool_tensor = type { i8*, i32, i64, i64*, i64*, i64 }
+%mool_block = type { i32, %mool_block* }
+
+@mool_heap_next = internal global i32 0
+@mool_heap_end = internal global i32 0
+@mool_free_list = internal global %mool_block* null
+@mool_workspace_data = internal global i8* null
+@mool_workspace_capacity = internal global i64 0
+; 运行时分配的工作区所在的块，调用方提供的工作区为 null
+@mool_workspace_block = internal global %mool_block* null
+
+declare i32 @llvm.wasm.memory.grow.i32(i32, i32)
+declare void @llvm.memset.p0i8.i32(i8*, i8, i32, i1)
+
+; 分配 size 字节的块，size 包括块头，为 8 的倍数，内存初始化为 0，线性内存不能增长时返回 null
+define internal %mool_block* @mool_block_alloc(i32 %size) {
+entry:
+  br label %search
+
+search:
+  %link = phi %mool_block** [ @mool_free_list, %entry ], [ %next_link, %skip ]
+  %block = load %mool_block*, %mool_block** %link
+  %empty = icmp eq %mool_block* %block, null
+  br i1 %empty, label %bump, label %check
+
+check:
+  %block_size_pointer = getelementptr %mool_block, %mool_block* %block, i32 0, i32 0
+  %block_size = load i32, i32* %block_size_pointer
+  %same = icmp eq i32 %block_size, %size
+  %next_link = getelementptr %mool_block, %mool_block* %block, i32 0, i32 1
+  br i1 %same, label %reuse, label %skip
+
+skip:
+  br label %search
+
+reuse:
+  %reuse_next = load %mool_block*, %mool_block** %next_link
+  store %mool_block* %reuse_next, %mool_block** %link
+  %bytes = bitcast %mool_block* %block to i8*
+  call void @llvm.memset.p0i8.i32(i8* %bytes, i8 0, i32 %size, i1 false)
+  store i32 %size, i32* %block_size_pointer
+  ret %mool_block* %block
+
+bump:
+  %next = load i32, i32* @mool_heap_next
+  %end = load i32, i32* @mool_heap_end
+  %rest = sub i32 %end, %next
+  %fits = icmp ule i32 %size, %rest
+  br i1 %fits, label %take, label %grow
+
+grow:
+  %rounded = add i32 %size, 65535
+  %pages = lshr i32 %rounded, 16
+  %old_pages = call i32 @llvm.wasm.memory.grow.i32(i32 0, i32 %pages)
+  %failed = icmp eq i32 %old_pages, -1
+  br i1 %failed, label %out_of_memory, label %grown
+
+out_of_memory:
+  ret %mool_block* null
+
+grown:
+  %start = shl i32 %old_pages, 16
+  %grown_size = shl i32 %pages, 16
+  %grown_end = add i32 %start, %grown_size
+  store i32 %grown_end, i32* @mool_heap_end
+  br label %take
+
+take:
+  %base = phi i32 [ %next, %bump ], [ %start, %grown ]
+  %after = add i32 %base, %size
+  store i32 %after, i32* @mool_heap_next
+  %fresh = inttoptr i32 %base to %mool_block*
+  %fresh_size_pointer = getelementptr %mool_block, %mool_block* %fresh, i32 0, i32 0
+  store i32 %size, i32* %fresh_size_pointer
+  ret %mool_block* %fresh
+}
+
+define i8* @mool_tensor_alloc(i32 %dtype, i64 %rank, i64* %shape) {
+entry:
+  %tensor = call i8* @mool_tensor_new(i32 %dtype, i64 %rank, i64* %shape, i1 true)
+  ret i8* %tensor
+}
+
+; 数据在工作区中 offset 处的张量，超出工作区时返回 null
+define i8* @mool_tensor_alloc_at(i32 %dtype, i64 %rank, i64* %shape, i64 %offset) {
+entry:
+  %raw = call i8* @mool_tensor_new(i32 %dtype, i64 %rank, i64* %shape, i1 false)
+  %failed = icmp eq i8* %raw, null
+  br i1 %failed, label %error, label %check
+
+error:
+  ret i8* null
+
+check:
+  %numel = call i64 @mool_tensor_numel(i8* %raw)
+  %is_bool = icmp eq i32 %dtype, 2
+  %element_size = select i1 %is_bool, i64 1, i64 8
+  %data_size = mul i64 %numel, %element_size
+  %size = load i64, i64* @mool_workspace_capacity
+  ; offset + data_size <= size，先比较 offset 避免加法溢出，没有元素的张量可以在工作区的末尾
+  %inside = icmp ule i64 %offset, %size
+  %rest = sub i64 %size, %offset
+  %fits = icmp ule i64 %data_size, %rest
+  %valid = and i1 %inside, %fits
+  br i1 %valid, label %place, label %overflow
+
+overflow:
+  call void @mool_tensor_release(i8* %raw)
+  ret i8* null
+
+place:
+  %base = load i8*, i8** @mool_workspace_data
+  %data = getelementptr i8, i8* %base, i64 %offset
+  %tensor = bitcast i8* %raw to %mool_tensor*
+  %data_field = getelementptr %mool_tensor, %mool_tensor* %tensor, i32 0, i32 0
+  store i8* %data, i8** %data_field
+  ret i8* %raw
+}
+
+; 分配描述符、形状和步长，with_data 为 true 时数据放在同一块中
+; 元素类型或形状不正确、块超过 2GB 或者内存不足时返回 null
+define internal i8* @mool_tensor_new(i32 %dtype, i64 %rank, i64* %shape, i1 %with_data) {
+entry:
+  %is_bool = icmp eq i32 %dtype, 2
+  %element_size = select i1 %is_bool, i64 1, i64 8
+  %bad_dtype = icmp ugt i32 %dtype, 2
+  %bad_rank = icmp slt i64 %rank, 0
+  %has_dims = icmp sgt i64 %rank, 0
+  %no_shape = icmp eq i64* %shape, null
+  %bad_shape = and i1 %has_dims, %no_shape
+  %bad_header = or i1 %bad_dtype, %bad_rank
+  %bad = or i1 %bad_header, %bad_shape
+  br i1 %bad, label %invalid, label %numel_header
+
+invalid:
+  ret i8* null
+
+numel_header:
+  %i = phi i64 [ 0, %entry ], [ %i_next, %numel_step ]
+  %numel = phi i64 [ 1, %entry ], [ %numel_next, %numel_step ]
+  %numel_done = icmp sge i64 %i, %rank
+  br i1 %numel_done, label %allocate, label %numel_body
+
+numel_body:
+  %dim_pointer = getelementptr i64, i64* %shape, i64 %i
+  %dim = load i64, i64* %dim_pointer
+  %negative = icmp slt i64 %dim, 0
+  br i1 %negative, label %invalid, label %numel_step
+
+numel_step:
+  %numel_next = mul i64 %numel, %dim
+  %i_next = add i64 %i, 1
+  br label %numel_header
+
+allocate:
+  ; 块头和描述符之后依次是形状、步长和数据，总大小按 8 字节对齐
+  %header_size = add i64 ptrtoint (%mool_block* getelementptr (%mool_block, %mool_block* null, i32 1) to i64), ptrtoint (%mool_tensor* getelementptr (%mool_tensor, %mool_tensor* null, i32 1) to i64)
+  %dims_size = shl i64 %rank, 4
+  %data_offset = add i64 %header_size, %dims_size
+  %full_size = mul i64 %numel, %element_size
+  %data_size = select i1 %with_data, i64 %full_size, i64 0
+  %unaligned = add i64 %data_offset, %data_size
+  %padded = add i64 %unaligned, 7
+  %total = and i64 %padded, -8
+  %too_large = icmp ugt i64 %total, 2147483647
+  br i1 %too_large, label %invalid, label %take
+
+take:
+  %total32 = trunc i64 %total to i32
+  %block = call %mool_block* @mool_block_alloc(i32 %total32)
+  %out_of_memory = icmp eq %mool_block* %block, null
+  br i1 %out_of_memory, label %invalid, label %fill
+
+fill:
+  %descriptor = getelementptr %mool_block, %mool_block* %block, i32 1
+  %tensor = bitcast %mool_block* %descriptor to %mool_tensor*
+  %base = bitcast %mool_block* %block to i8*
+  %shape_raw = getelementptr i8, i8* %base, i64 %header_size
+  %shape_copy = bitcast i8* %shape_raw to i64*
+  %strides_copy = getelementptr i64, i64* %shape_copy, i64 %rank
+  %data = getelementptr i8, i8* %base, i64 %data_offset
+  %data_field = getelementptr %mool_tensor, %mool_tensor* %tensor, i32 0, i32 0
+  store i8* %data, i8** %data_field
+  %dtype_field = getelementptr %mool_tensor, %mool_tensor* %tensor, i32 0, i32 1
+  store i32 %dtype, i32* %dtype_field
+  %rank_field = getelementptr %mool_tensor, %mool_tensor* %tensor, i32 0, i32 2
+  store i64 %rank, i64* %rank_field
+  %shape_field = getelementptr %mool_tensor, %mool_tensor* %tensor, i32 0, i32 3
+  store i64* %shape_copy, i64** %shape_field
+  %strides_field = getelementptr %mool_tensor, %mool_tensor* %tensor, i32 0, i32 4
+  store i64* %strides_copy, i64** %strides_field
+  %refcount_field = getelementptr %mool_tensor, %mool_tensor* %tensor, i32 0, i32 5
+  store i64 1, i64* %refcount_field
+  br label %strides_header
+
+strides_header:
+  ; 从最后一维开始复制形状并计算行优先的步长
+  %j = phi i64 [ %rank, %fill ], [ %j_prev, %strides_body ]
+  %stride = phi i64 [ 1, %fill ], [ %stride_next, %strides_body ]
+  %strides_done = icmp sle i64 %j, 0
+  br i1 %strides_done, label %exit, label %strides_body
+
+strides_body:
+  %j_prev = sub i64 %j, 1
+  %source = getelementptr i64, i64* %shape, i64 %j_prev
+  %size = load i64, i64* %source
+  %shape_slot = getelementptr i64, i64* %shape_copy, i64 %j_prev
+  store i64 %size, i64* %shape_slot
+  %stride_slot = getelementptr i64, i64* %strides_copy, i64 %j_prev
+  store i64 %stride, i64* %stride_slot
+  %stride_next = mul i64 %stride, %size
+  br label %strides_header
+
+exit:
+  %result = bitcast %mool_tensor* %tensor to i8*
+  ret i8* %result
+}
+
+define void @mool_tensor_retain(i8* %raw) {
+entry:
+  %empty = icmp eq i8* %raw, null
+  br i1 %empty, label %exit, label %retain
+
+retain:
+  %tensor = bitcast i8* %raw to %mool_tensor*
+  %refcount_field = getelementptr %mool_tensor, %mool_tensor* %tensor, i32 0, i32 5
+  %refcount = load i64, i64* %refcount_field
+  %refcount_next = add i64 %refcount, 1
+  store i64 %refcount_next, i64* %refcount_field
+  br label %exit
+
+exit:
+  ret void
+}
+
+define void @mool_tensor_release(i8* %raw) {
+entry:
+  %empty = icmp eq i8* %raw, null
+  br i1 %empty, label %exit, label %release
+
+release:
+  %tensor = bitcast i8* %raw to %mool_tensor*
+  %refcount_field = getelementptr %mool_tensor, %mool_tensor* %tensor, i32 0, i32 5
+  %refcount = load i64, i64* %refcount_field
+  %refcount_next = sub i64 %refcount, 1
+  store i64 %refcount_next, i64* %refcount_field
+  %dead = icmp sle i64 %refcount_next, 0
+  br i1 %dead, label %free, label %exit
+
+free:
+  ; 块头在描述符之前，放入空闲链表
+  %descriptor = bitcast i8* %raw to %mool_block*
+  %block = getelementptr %mool_block, %mool_block* %descriptor, i32 -1
+  %next_field = getelementptr %mool_block, %mool_block* %block, i32 0, i32 1
+  %free_list = load %mool_block*, %mool_block** @mool_free_list
+  store %mool_block* %free_list, %mool_block** %next_field
+  store %mool_block* %block, %mool_block** @mool_free_list
+  br label %exit
+
+exit:
+  ret void
+}
+
+define i64 @mool_tensor_numel(i8* %raw) {
+entry:
+  %tensor = bitcast i8* %raw to %mool_tensor*
+  %rank_field = getelementptr %mool_tensor, %mool_tensor* %tensor, i32 0, i32 2
+  %rank = load i64, i64* %rank_field
+  %shape_field = getelementptr %mool_tensor, %mool_tensor* %tensor, i32 0, i32 3
+  %shape = load i64*, i64** %shape_field
+  br label %header
+
+header:
+  %i = phi i64 [ 0, %entry ], [ %i_next, %body ]
+  %numel = phi i64 [ 1, %entry ], [ %numel_next, %body ]
+  %done = icmp sge i64 %i, %rank
+  br i1 %done, label %exit, label %body
+
+body:
+  %dim_pointer = getelementptr i64, i64* %shape, i64 %i
+  %dim = load i64, i64* %dim_pointer
+  %numel_next = mul i64 %numel, %dim
+  %i_next = add i64 %i, 1
+  br label %header
+
+exit:
+  ret i64 %numel
+}
+
+; 使用调用方提供的内存作为工作区，运行时之前分配的工作区放回空闲链表
+; data 没有按 8 字节对齐或 size 为负数时返回 -1，工作区不变
+define i32 @mool_workspace_init(i8* %data, i64 %size) {
+entry:
+  %address = ptrtoint i8* %data to i32
+  %misaligned_bits = and i32 %address, 7
+  %misaligned = icmp ne i32 %misaligned_bits, 0
+  %negative = icmp slt i64 %size, 0
+  %invalid = or i1 %misaligned, %negative
+  br i1 %invalid, label %error, label %init
+
+error:
+  ret i32 -1
+
+init:
+  call void @mool_workspace_free()
+  store i8* %data, i8** @mool_workspace_data
+  store i64 %size, i64* @mool_workspace_capacity
+  ret i32 0
+}
+
+; 确保工作区至少有 size 字节，调用方提供的工作区不够大、size 为负数或者内存不足时返回 null，工作区不变
+define i8* @mool_workspace_reserve(i64 %size) {
+entry:
+  %data = load i8*, i8** @mool_workspace_data
+  %current = load i64, i64* @mool_workspace_capacity
+  %exists = icmp ne i8* %data, null
+  %enough = icmp uge i64 %current, %size
+  %reuse = and i1 %exists, %enough
+  br i1 %reuse, label %exit, label %check
+
+check:
+  %block = load %mool_block*, %mool_block** @mool_workspace_block
+  %foreign = icmp eq %mool_block* %block, null
+  %too_small = and i1 %exists, %foreign
+  %negative = icmp slt i64 %size, 0
+  %invalid = or i1 %too_small, %negative
+  br i1 %invalid, label %exit, label %allocate
+
+allocate:
+  ; 块头之后是工作区，总大小按 8 字节对齐
+  %header_end = getelementptr %mool_block, %mool_block* null, i32 1
+  %header_size = ptrtoint %mool_block* %header_end to i64
+  %unaligned = add i64 %header_size, %size
+  %padded = add i64 %unaligned, 7
+  %total = and i64 %padded, -8
+  %too_large = icmp ugt i64 %total, 2147483647
+  br i1 %too_large, label %exit, label %grow
+
+grow:
+  %total32 = trunc i64 %total to i32
+  %fresh = call %mool_block* @mool_block_alloc(i32 %total32)
+  %out_of_memory = icmp eq %mool_block* %fresh, null
+  br i1 %out_of_memory, label %exit, label %replace
+
+replace:
+  call void @mool_workspace_free()
+  %after_header = getelementptr %mool_block, %mool_block* %fresh, i32 1
+  %fresh_data = bitcast %mool_block* %after_header to i8*
+  store i8* %fresh_data, i8** @mool_workspace_data
+  store i64 %size, i64* @mool_workspace_capacity
+  store %mool_block* %fresh, %mool_block** @mool_workspace_block
+  br label %exit
+
+exit:
+  %result = phi i8* [ %data, %entry ], [ null, %check ], [ null, %allocate ], [ null, %grow ], [ %fresh_data, %replace ]
+  ret i8* %result
+}
+
+define i64 @mool_workspace_size() {
+entry:
+  %size = load i64, i64* @mool_workspace_capacity
+  ret i64 %size
+}
+
+; 不再使用当前的工作区，运行时分配的块放回空闲链表
+define internal void @mool_workspace_free() {
+entry:
+  %block = load %mool_block*, %mool_block** @mool_workspace_block
+  %owned = icmp ne %mool_block* %block, null
+  br i1 %owned, label %free, label %exit
+
+free:
+  %next_field = getelementptr %mool_block, %mool_block* %block, i32 0, i32 1
+  %free_list = load %mool_block*, %mool_block** @mool_free_list
+  store %mool_block* %free_list, %mool_block** %next_field
+  store %mool_block* %block, %mool_block** @mool_free_list
+  br label %exit
+
+exit:
+  store i8* null, i8** @mool_workspace_data
+  store i64 0, i64* @mool_workspace_capacity
+  store %mool_block* null, %mool_block** @mool_workspace_block
+  ret void
+}
@@ -4,11 +4,23 @@ use std::collections::HashMap;
 #[derive(Debug)]
 pub struct Scope {
     current: Option<Box<ScopeNode>>,
+    loops: Vec<LoopTarget>,
+    /// 生成的 LLVM 函数对应的 Mool 名称，用于报告错误
+    functions: HashMap<llvm::prelude::LLVMValueRef, String>,
+}
+
+/// 循环的跳转目标，continue 跳转到 next，break 跳转到 exit
+#[derive(Debug, Clone, Copy)]
+pub struct LoopTarget {
+    pub next: llvm::prelude::LLVMBasicBlockRef,
+    pub exit: llvm::prelude::LLVMBasicBlockRef,
 }
 
 #[derive(Debug, Clone)]
 struct ScopeNode {
     names: HashMap<String, llvm::prelude::LLVMValueRef>,
+    /// 作用域中创建的变量的 alloca，函数返回前释放其中的张量
+    owned: Vec<llvm::prelude::LLVMValueRef>,
     next: Option<Box<ScopeNode>>,
 }
 
@@ -18,8 +30,11 @@ impl Scope {
         Self {
             current: Some(Box::new(ScopeNode {
                 names: HashMap::new(),
+                owned: Vec::new(),
                 next: None,
             })),
+            loops: Vec::new(),
+            functions: HashMap::new(),
         }
     }
 
@@ -27,6 +42,7 @@ impl Scope {
     pub fn push(&mut self) {
         let mut new_scope = Box::new(ScopeNode {
             names: HashMap::new(),
+            owned: Vec::new(),
             next: None,
         });
         let next = self.current.take();
@@ -63,9 +79,52 @@ impl Scope {
     pub fn get(&mut self, name: &String) -> Option<llvm::prelude::LLVMValueRef> {
         match self.current.as_mut() {
             None => panic!("作用域不能为空"),
-            Some(scope) => scope.clone().get(&name),
+            Some(scope) => scope.clone().get(name),
+        }
+    }
+
+    /// 记录当前作用域中创建的变量
+    pub fn own(&mut self, alloca: llvm::prelude::LLVMValueRef) {
+        match self.current.as_mut() {
+            None => panic!("作用域不能为空"),
+            Some(scope) => scope.owned.push(alloca),
+        }
+    }
+
+    /// 当前作用域中创建的变量
+    pub fn owned(&self) -> &[llvm::prelude::LLVMValueRef] {
+        match self.current.as_ref() {
+            None => panic!("作用域不能为空"),
+            Some(scope) => &scope.owned,
         }
     }
+
+    /// 进入循环
+    pub fn push_loop(&mut self, target: LoopTarget) {
+        self.loops.push(target);
+    }
+
+    /// 离开循环
+    pub fn pop_loop(&mut self) {
+        if self.loops.pop().is_none() {
+            panic!("不在循环中");
+        }
+    }
+
+    /// 获取最内层循环的跳转目标
+    pub fn current_loop(&self) -> Option<LoopTarget> {
+        self.loops.last().copied()
+    }
+
+    /// 记录 LLVM 函数对应的 Mool 名称，已经记录过的函数保持原来的名称
+    pub fn name_function(&mut self, function: llvm::prelude::LLVMValueRef, name: String) {
+        self.functions.entry(function).or_insert(name);
+    }
+
+    /// 获取 LLVM 函数对应的 Mool 名称
+    pub fn function_name(&self, function: llvm::prelude::LLVMValueRef) -> Option<&String> {
+        self.functions.get(&function)
+    }
 }
 
 impl ScopeNode {
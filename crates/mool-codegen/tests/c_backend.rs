@@ -0,0 +1,262 @@
+//! C 后端的集成测试：用系统的 C 编译器（可以用环境变量 CC 指定）编译生成的代码并运行
+
+use mool_codegen::c::{codegen, CCode};
+use mool_ir::pass::{Fuse, Pass};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn generate(code: &str) -> CCode {
+    codegen(mool_ir::parse(code).unwrap(), "model").unwrap()
+}
+
+/// 编译生成的代码和 driver，运行后返回标准输出
+fn run(name: &str, code: &CCode, driver: &str) -> String {
+    let dir: PathBuf = env::temp_dir().join(format!("mool-c-{}-{}", std::process::id(), name));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("model.c"), &code.source).unwrap();
+    fs::write(dir.join("model.h"), &code.header).unwrap();
+    fs::write(
+        dir.join("driver.c"),
+        format!(
+            "#include <inttypes.h>\n#include <stdio.h>\n#include \"model.h\"\n\nint main(void) {{\n{}\n    return 0;\n}}\n",
+            driver
+        ),
+    )
+    .unwrap();
+    let compiler = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let output = Command::new(compiler)
+        .current_dir(&dir)
+        .args([
+            "-std=c99",
+            "-pedantic-errors",
+            "model.c",
+            "driver.c",
+            "-o",
+            "model",
+        ])
+        .output()
+        .expect("找不到 C 编译器");
+    assert!(
+        output.status.success(),
+        "编译失败：\n{}\n{}",
+        String::from_utf8_lossy(&output.stderr),
+        code.source
+    );
+    let output = Command::new(dir.join("model")).output().unwrap();
+    assert!(output.status.success());
+    fs::remove_dir_all(&dir).unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn scalar_function_with_loops() {
+    let code = generate(
+        "let %sum = fn(%n: int) -> int {
+            let %total = 0
+            for %i in range(0, %n, 1) {
+                %total = Add(%total, %i)
+            }
+            let %j = %n
+            while Gt(%j, 0) {
+                %j = Sub(%j, 1)
+                if Eq(%j, 3) { break } else { continue }
+            }
+            Add(%total, %j)
+        }
+        %sum(10)",
+    );
+    assert!(code.header.contains("int64_t mool_fn_sum(int64_t v_n);"));
+    assert!(code.header.contains("int64_t mool_model_main(void);"));
+    let output = run(
+        "scalar",
+        &code,
+        r#"    printf("%" PRId64 " %" PRId64 "\n", mool_fn_sum(10), mool_model_main());"#,
+    );
+    assert_eq!(output, "48 0\n");
+}
+
+#[test]
+fn headers_for_several_modules_and_cpp() {
+    // 保护宏和入口函数由模块名得到，两个模块的头文件可以在同一个 C++ 文件中包含
+    let first = codegen(mool_ir::parse("Add(1, 2)").unwrap(), "first-net").unwrap();
+    let second = codegen(mool_ir::parse("Mul(2, 3)").unwrap(), "second").unwrap();
+    assert!(first
+        .header
+        .contains("#ifndef MOOL_FIRST_NET_H\n#define MOOL_FIRST_NET_H"));
+    assert!(first.header.contains("int64_t mool_first_net_main(void);"));
+    assert!(second.header.contains("#ifndef MOOL_SECOND_H"));
+    assert!(second
+        .header
+        .contains("#ifdef __cplusplus\nextern \"C\" {\n#endif"));
+    let dir: PathBuf = env::temp_dir().join(format!("mool-c-{}-cpp", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for (name, code) in [("first", &first), ("second", &second)] {
+        fs::write(dir.join(format!("{}.c", name)), &code.source).unwrap();
+        fs::write(dir.join(format!("{}.h", name)), &code.header).unwrap();
+    }
+    fs::write(
+        dir.join("driver.cpp"),
+        "#include <cstdio>\n#include \"first.h\"\n#include \"second.h\"\n\nint main() {\n    std::printf(\"%d %d\\n\", (int)mool_first_net_main(), (int)mool_second_main());\n    return 0;\n}\n",
+    )
+    .unwrap();
+    let compiler = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let cxx = env::var("CXX").unwrap_or_else(|_| "c++".to_string());
+    for (compiler, args) in [
+        (&compiler, ["-std=c99", "-c", "first.c"]),
+        (&compiler, ["-std=c99", "-c", "second.c"]),
+        (&cxx, ["-c", "driver.cpp", "-Wall"]),
+    ] {
+        let output = Command::new(compiler)
+            .current_dir(&dir)
+            .args(args)
+            .output()
+            .expect("找不到 C/C++ 编译器");
+        assert!(
+            output.status.success(),
+            "编译失败：\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let output = Command::new(&cxx)
+        .current_dir(&dir)
+        .args(["first.o", "second.o", "driver.o", "-o", "model"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let output = Command::new(dir.join("model")).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    // 顶层代码的值不会返回，入口函数总是返回 0
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "0 0\n");
+}
+
+#[test]
+fn tensor_operators() {
+    let code = generate(
+        "let %ops = fn(%x: Tensor[(3),int], %y: Tensor[(3),int]) -> (Tensor[(3),int], Tensor[(3),int], Tensor[(3),bool], int) {
+            (Sub(Mul(%x, %y), %y), Div(%x, %y), Le(%x, %y), Matmul(%x, %y))
+        }
+        let %float = fn(%x: Tensor[(2),float]) -> Tensor[(2),float] {
+            Relu(Div(%x, Tensor([2.0, -0.5])))
+        }
+        %ops(Tensor([4,9,-3]), Tensor([2,3,1]))",
+    );
+    let output = run(
+        "tensor",
+        &code,
+        r#"    mool_tensor_3_i64 x = {{4, 9, 3}};
+    mool_tensor_3_i64 y = {{2, 3, 4}};
+    mool_tuple_0 r = mool_fn_ops(x, y);
+    mool_tensor_2_f64 f = mool_fn_float((mool_tensor_2_f64){{3.0, 1.5}});
+    for (int i = 0; i < 3; i++) {
+        printf("%" PRId64 " %" PRId64 " %d\n", r.f0.data[i], r.f1.data[i], r.f2.data[i]);
+    }
+    printf("%" PRId64 " %.2f %.2f\n", r.f3, f.data[0], f.data[1]);"#,
+    );
+    assert_eq!(output, "6 2 0\n24 3 0\n8 0 1\n47 1.50 0.00\n");
+}
+
+#[test]
+fn fused_chain_broadcasts() {
+    let programs = mool_ir::parse(
+        "let %f = fn(%x: Tensor[(4), float], %w: float, %b: Tensor[(1), float]) -> Tensor[(4), float] {
+            Relu(Add(Mul(%x, %w), %b))
+        }",
+    )
+    .unwrap();
+    let code = codegen(Fuse::default().run(programs), "model").unwrap();
+    // 整条链在一个循环中计算，不需要逐个算子的辅助函数
+    assert!(!code.source.contains("mool_add_"));
+    let output = run(
+        "fused",
+        &code,
+        r#"    mool_tensor_4_f64 x = {{1.0, -2.0, 3.0, -4.0}};
+    mool_tensor_1_f64 b = {{0.5}};
+    mool_tensor_4_f64 r = mool_fn_f(x, 2.0, b);
+    printf("%.1f %.1f %.1f %.1f\n", r.data[0], r.data[1], r.data[2], r.data[3]);"#,
+    );
+    assert_eq!(output, "2.5 0.0 6.5 0.0\n");
+}
+
+#[test]
+fn unfused_operators_broadcast() {
+    // 不经过算子融合时，标量和只有一个元素的张量同样广播到另一侧的张量
+    let code = generate(
+        "let %f = fn(%x: Tensor[(3), int], %s: int, %b: Tensor[(1), int]) -> (Tensor[(3), int], Tensor[(3), int], Tensor[(3), bool]) {
+            (Mul(Tensor([1, 2, 3]), %s), Sub(%b, %x), Gt(%x, %s))
+        }",
+    );
+    let output = run(
+        "broadcast",
+        &code,
+        r#"    mool_tensor_3_i64 x = {{4, 9, 3}};
+    mool_tensor_1_i64 b = {{10}};
+    mool_tuple_0 r = mool_fn_f(x, 4, b);
+    for (int i = 0; i < 3; i++) {
+        printf("%" PRId64 " %" PRId64 " %d\n", r.f0.data[i], r.f1.data[i], r.f2.data[i]);
+    }"#,
+    );
+    assert_eq!(output, "4 6 0\n8 1 1\n12 7 0\n");
+}
+
+#[test]
+fn tuples_and_if_values() {
+    let code = generate(
+        "let %pair = fn(%x: int, %flag: bool) -> (int, float) {
+            let %y = if %flag { Mul(%x, 2) } else { Sub(%x, 1) }
+            (%y, 2.5)
+        }
+        let %first = fn(%x: int) -> int {
+            let (%a, %b) = %pair(%x, Lt(%x, 5))
+            Add(%a, %pair(%a, false).0)
+        }",
+    );
+    let output = run(
+        "tuple",
+        &code,
+        r#"    printf("%" PRId64 " %" PRId64 " %.1f\n", mool_fn_first(3), mool_fn_first(9), mool_fn_pair(1, true).f1);"#,
+    );
+    assert_eq!(output, "11 15 2.5\n");
+}
+
+#[test]
+fn type_errors() {
+    let programs = mool_ir::parse("Add(Tensor([1,2]), Tensor([1.0,2.0]))").unwrap();
+    let error = codegen(programs, "model").unwrap_err().to_string();
+    assert!(error.contains("add 算子两侧的类型必须相等"), "{}", error);
+    let programs = mool_ir::parse(
+        "let %f = fn(%x: int) -> float {
+            %x
+        }",
+    )
+    .unwrap();
+    let error = codegen(programs, "model").unwrap_err().to_string();
+    assert!(error.contains("函数%f的返回值类型"), "{}", error);
+}
+
+#[test]
+fn multidimensional_tensors() {
+    let code = generate(
+        "let %double = fn(%x: Tensor[(2, 3), int]) -> Tensor[(2, 3), int] {
+            Add(%x, %x)
+        }",
+    );
+    let output = run(
+        "shape",
+        &code,
+        r#"    mool_tensor_2x3_i64 x = {{1, 2, 3, 4, 5, 6}};
+    mool_tensor_2x3_i64 r = mool_fn_double(x);
+    printf("%" PRId64 " %" PRId64 "\n", r.data[0], r.data[5]);"#,
+    );
+    assert_eq!(output, "2 12\n");
+    // C 中张量为定长数组，不能表示符号维度
+    let programs = mool_ir::parse(
+        "let %f = fn(%x: Tensor[(B, 3), int]) -> Tensor[(B, 3), int] {
+            %x
+        }",
+    )
+    .unwrap();
+    let error = codegen(programs, "model").unwrap_err().to_string();
+    assert!(error.contains("不支持符号维度B"), "{}", error);
+}
@@ -0,0 +1,170 @@
+//! LLVM 后端的集成测试：生成的模块在优化之前经过 LLVM 的验证，检查生成的 LLVM IR，以及各后端的死代码删除根
+
+use mool_codegen::backend::{create, TargetOptions};
+use mool_codegen::llvm::{codegen, OptLevel};
+
+/// 生成并验证模块，返回名为 name 的函数的定义
+fn function(code: &str, name: &str) -> String {
+    let module = codegen(mool_ir::parse(code).unwrap(), OptLevel::O0).unwrap();
+    let start = module
+        .find(&format!("@{}(", name))
+        .unwrap_or_else(|| panic!("没有函数{}：\n{}", name, module));
+    let end = start + module[start..].find("\n}\n").unwrap();
+    module[start..end].to_string()
+}
+
+/// 函数中以 label 开头的基本块
+fn block<'a>(function: &'a str, label: &str) -> &'a str {
+    function
+        .split("\n\n")
+        .find(|block| block.starts_with(&format!("{}:", label)))
+        .unwrap_or_else(|| panic!("没有基本块{}：\n{}", label, function))
+}
+
+/// 基本块的最后一条指令
+fn terminator(block: &str) -> &str {
+    block.lines().last().unwrap().trim()
+}
+
+#[test]
+fn loop_carried_tensor_with_break_and_continue() {
+    let ir = function(
+        "let %f = fn(%x: Tensor[(4), float], %n: int) -> Tensor[(4), float] {
+            let %y = %x
+            for %i in range(0, %n, 1) {
+                if Gt(%i, 5) { break } else { 0 }
+                if Eq(%i, 2) { continue } else { 0 }
+                %y = Add(%y, %x)
+            }
+            %y
+        }",
+        "function",
+    );
+    // 循环头判断是否继续，latch 更新归纳变量后回到循环头
+    assert!(terminator(block(&ir, "for_header")).ends_with("label %for_body, label %for_exit"));
+    let latch = block(&ir, "for_latch");
+    assert!(latch.contains("%i_next = add i64"), "{}", latch);
+    assert_eq!(terminator(latch), "br label %for_header");
+    // break 跳到出口，continue 跳到 latch
+    assert_eq!(terminator(block(&ir, "if_then")), "br label %for_exit");
+    assert_eq!(terminator(block(&ir, "if_then12")), "br label %for_latch");
+    // 循环中的张量先释放旧值再保存新值，循环结束后从同一个变量读取
+    let body = block(&ir, "if_merge14");
+    let store = body
+        .find("store %mool_tensor.float* %result, %mool_tensor.float** %y")
+        .unwrap();
+    let previous = body[..store].trim_end().lines().last().unwrap();
+    assert!(previous.contains("@mool_tensor_release"), "{}", body);
+    assert_eq!(terminator(body), "br label %for_latch");
+    assert!(block(&ir, "for_exit").contains("load %mool_tensor.float*, %mool_tensor.float** %y"));
+}
+
+#[test]
+fn while_loop_with_break_and_continue() {
+    let ir = function(
+        "let %f = fn(%x: Tensor[(4), int], %n: int) -> Tensor[(4), int] {
+            let %y = %x
+            let %j = %n
+            while Gt(%j, 0) {
+                %j = Sub(%j, 1)
+                if Eq(%j, 3) { continue } else { 0 }
+                %y = Mul(%y, Tensor([2]))
+                if Lt(%j, 1) { break } else { 0 }
+            }
+            %y
+        }",
+        "function",
+    );
+    assert!(
+        terminator(block(&ir, "while_header")).ends_with("label %while_body, label %while_exit")
+    );
+    // continue 回到循环头，break 跳到出口
+    assert_eq!(terminator(block(&ir, "if_then")), "br label %while_header");
+    assert_eq!(terminator(block(&ir, "if_then28")), "br label %while_exit");
+    assert_eq!(
+        terminator(block(&ir, "if_merge30")),
+        "br label %while_header"
+    );
+    assert!(
+        ir.contains("store %mool_tensor.int* %result19, %mool_tensor.int** %y"),
+        "{}",
+        ir
+    );
+}
+
+const MODEL: &str =
+    "let %model = fn(%x: Tensor[(4), float]) -> (Tensor[(4), float], Tensor[(4), float]) {
+    let %hidden = Relu(%x)
+    let %logits = Mul(%hidden, 2.0)
+    (%logits, %hidden)
+}
+";
+
+#[test]
+fn destructure_returned_tuple() {
+    let code = format!(
+        "{}let %f = fn(%x: Tensor[(4), float]) -> Tensor[(4), float] {{
+            let (%logits, %hidden) = %model(%x)
+            Add(%logits, %hidden)
+        }}",
+        MODEL
+    );
+    // 函数按定义的顺序命名为 function、function.1
+    let model = function(&code, "function");
+    let tuple = "{ %mool_tensor.float*, %mool_tensor.float* }";
+    assert!(
+        model
+            .lines()
+            .last()
+            .unwrap()
+            .trim()
+            .starts_with(&format!("ret {}", tuple)),
+        "{}",
+        model
+    );
+    // 调用处按下标取出元素，每个变量持有一个引用
+    let ir = function(&code, "function.1");
+    assert!(
+        ir.contains(&format!("%result = call {} @function(", tuple)),
+        "{}",
+        ir
+    );
+    for (index, name) in ["logits", "hidden"].iter().enumerate() {
+        let extract = format!("%{} = extractvalue {} %result, {}", name, tuple, index);
+        let start = ir.find(&extract).unwrap_or_else(|| panic!("{}", ir));
+        let retain = ir[start..].lines().nth(2).unwrap();
+        assert!(retain.contains("@mool_tensor_retain"), "{}", ir);
+    }
+}
+
+#[test]
+#[should_panic(expected = "元组有2个元素，不能解构为3个变量")]
+fn destructure_with_wrong_arity() {
+    function(
+        &format!(
+            "{}let %f = fn(%x: Tensor[(4), float]) -> Tensor[(4), float] {{
+                let (%logits, %hidden, %extra) = %model(%x)
+                %logits
+            }}",
+            MODEL
+        ),
+        "function.1",
+    );
+}
+
+#[test]
+fn dead_code_roots() {
+    // LLVM 后端不导出顶层函数，只以顶层代码为根；C 和 WebAssembly 后端导出所有顶层函数
+    let programs = mool_ir::parse(
+        "let %f = fn(%x: int) -> int { Add(%x, 1) }
+        let %g = fn(%x: int) -> int { %f(%x) }
+        let %a = 1
+        %g(%a)",
+    )
+    .unwrap();
+    let options = TargetOptions::default();
+    let roots = |name: &str| create(name, &options).unwrap().roots(&programs);
+    assert!(roots("llvm").is_empty());
+    assert_eq!(roots("c"), ["f", "g"]);
+    assert_eq!(roots("wasm"), ["f", "g"]);
+}
@@ -0,0 +1,724 @@
+//! WebAssembly 后端的集成测试：用 rust-lld（可以用环境变量 WASM_LD 指定 wasm-ld）链接生成的目标文件，
+//! 在 wasmi 解释器中运行
+
+use mool_codegen::backend::Backend;
+use mool_codegen::llvm::{OptLevel, WasmBackend};
+use mool_ir::pass::{Fuse, MemoryPlan, Pass};
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use wasmi::core::Pages;
+use wasmi::{Engine, Instance, Linker, Memory, Store, Value};
+
+/// 优先使用 WASM_LD，否则使用 Rust 工具链自带的 rust-lld
+fn linker() -> PathBuf {
+    if let Ok(linker) = env::var("WASM_LD") {
+        return PathBuf::from(linker);
+    }
+    let output = Command::new("rustc")
+        .args(["--print", "sysroot"])
+        .output()
+        .unwrap();
+    let sysroot = PathBuf::from(String::from_utf8(output.stdout).unwrap().trim());
+    let rustlib = sysroot.join("lib").join("rustlib");
+    rustlib
+        .read_dir()
+        .unwrap()
+        .map(|entry| entry.unwrap().path().join("bin").join("rust-lld"))
+        .find(|path| path.exists())
+        .expect("找不到 rust-lld")
+}
+
+/// 加载到解释器中的模块，从 __heap_base 开始依次分配张量的内存，
+/// 不够时增长线性内存，从新增的页继续分配，不占用运行时分配的页
+struct Module {
+    store: Store<()>,
+    instance: Instance,
+    memory: Memory,
+    heap: usize,
+    end: usize,
+    /// 作为运行时测试时，当前工作区的地址
+    workspace: i64,
+}
+
+impl Module {
+    fn new(programs: Vec<mool_ir::ast::Program>, level: OptLevel) -> Self {
+        let artifacts = WasmBackend::new(level, Some(linker()))
+            .codegen(programs)
+            .unwrap();
+        let wasm = artifacts
+            .iter()
+            .find(|artifact| artifact.extension == "wasm")
+            .unwrap();
+        Self::instantiate(&wasm.content)
+    }
+
+    /// 只有运行时的模块，导出运行时的 C 接口
+    fn runtime(level: OptLevel) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let object = WasmBackend::new(level, None).runtime().unwrap();
+        let dir = env::temp_dir().join(format!(
+            "mool-wasm-runtime-{}-{}",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("runtime.o"), object).unwrap();
+        let linker = linker();
+        let mut command = Command::new(&linker);
+        if linker.file_stem().is_some_and(|stem| stem == "rust-lld") {
+            command.args(["-flavor", "wasm"]);
+        }
+        let status = command
+            .current_dir(&dir)
+            .args([
+                "--no-entry",
+                "--export=__heap_base",
+                "runtime.o",
+                "-o",
+                "runtime.wasm",
+            ])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let wasm = fs::read(dir.join("runtime.wasm")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        Self::instantiate(&wasm)
+    }
+
+    fn instantiate(wasm: &[u8]) -> Self {
+        let engine = Engine::default();
+        let module = wasmi::Module::new(&engine, wasm).unwrap();
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::<()>::new(&engine)
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+        let memory = instance.get_memory(&store, "memory").unwrap();
+        let heap = match instance
+            .get_global(&store, "__heap_base")
+            .unwrap()
+            .get(&store)
+        {
+            Value::I32(heap) => heap as usize,
+            value => panic!("__heap_base 的类型不正确：{:?}", value),
+        };
+        let end = u32::from(memory.current_pages(&store)) as usize * 65536;
+        Self {
+            store,
+            instance,
+            memory,
+            heap,
+            end,
+            workspace: 0,
+        }
+    }
+
+    /// 分配 size 字节的内存，返回传给导出函数的指针，内存不够时增长线性内存
+    fn alloc(&mut self, size: usize) -> Value {
+        let size = size.div_ceil(8) * 8;
+        if self.heap + size > self.end {
+            let pages = size.div_ceil(65536);
+            let start = self
+                .memory
+                .grow(&mut self.store, Pages::new(pages as u32).unwrap())
+                .unwrap();
+            self.heap = u32::from(start) as usize * 65536;
+            self.end = self.heap + pages * 65536;
+        }
+        let pointer = self.heap;
+        self.heap += size;
+        Value::I32(pointer as i32)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Value {
+        let pointer = self.alloc(bytes.len());
+        self.memory
+            .write(&mut self.store, pointer.i32().unwrap() as usize, bytes)
+            .unwrap();
+        pointer
+    }
+
+    fn write_i64(&mut self, values: &[i64]) -> Value {
+        let bytes: Vec<u8> = values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        self.write(&bytes)
+    }
+
+    fn write_f64(&mut self, values: &[f64]) -> Value {
+        let bytes: Vec<u8> = values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        self.write(&bytes)
+    }
+
+    fn read(&self, pointer: &Value, offset: usize, size: usize) -> Vec<u8> {
+        let mut bytes = vec![0; size];
+        self.memory
+            .read(
+                &self.store,
+                pointer.i32().unwrap() as usize + offset,
+                &mut bytes,
+            )
+            .unwrap();
+        bytes
+    }
+
+    fn read_i64(&self, pointer: &Value, offset: usize, count: usize) -> Vec<i64> {
+        self.read(pointer, offset, count * 8)
+            .chunks(8)
+            .map(|chunk| i64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    fn read_f64(&self, pointer: &Value, offset: usize, count: usize) -> Vec<f64> {
+        self.read(pointer, offset, count * 8)
+            .chunks(8)
+            .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    /// 调用导出函数，返回 int 或 bool 的值，返回值写入内存的函数没有返回值
+    fn call(&mut self, name: &str, args: &[Value]) -> Option<i64> {
+        let func = self.instance.get_func(&self.store, name).unwrap();
+        let mut results: Vec<Value> = func
+            .ty(&self.store)
+            .results()
+            .iter()
+            .map(|ty| Value::default(*ty))
+            .collect();
+        func.call(&mut self.store, args, &mut results).unwrap();
+        results.pop().map(|result| match result {
+            Value::I64(result) => result,
+            Value::I32(result) => result as i64,
+            result => panic!("返回值的类型不正确：{:?}", result),
+        })
+    }
+}
+
+fn load(code: &str, level: OptLevel) -> Module {
+    Module::new(mool_ir::parse(code).unwrap(), level)
+}
+
+#[test]
+fn scalar_function_with_loops() {
+    for level in [OptLevel::O0, OptLevel::O2] {
+        let mut module = load(
+            "let %sum = fn(%n: int) -> int {
+                let %total = 0
+                for %i in range(0, %n, 1) {
+                    %total = Add(%total, %i)
+                }
+                let %j = %n
+                while Gt(%j, 0) {
+                    %j = Sub(%j, 1)
+                    if Eq(%j, 3) { break } else { continue }
+                }
+                Add(%total, %j)
+            }
+            let %positive = fn(%x: float, %flag: bool) -> bool {
+                if %flag { Gt(%x, 0.0) } else { false }
+            }
+            %sum(10)",
+            level,
+        );
+        assert_eq!(module.call("sum", &[Value::I64(10)]), Some(48));
+        assert_eq!(module.call("main", &[]), Some(0));
+        let positive = module.call("positive", &[Value::F64(1.5.into()), Value::I32(1)]);
+        assert_eq!(positive, Some(1));
+        let positive = module.call("positive", &[Value::F64(1.5.into()), Value::I32(0)]);
+        assert_eq!(positive, Some(0));
+    }
+}
+
+#[test]
+fn tensors_in_linear_memory() {
+    for level in [OptLevel::O0, OptLevel::O3] {
+        let mut module = load(
+            "let %ops = fn(%x: Tensor[(3),int], %y: Tensor[(3),int]) -> (Tensor[(3),int], Tensor[(3),int], Tensor[(3),bool], int) {
+                (Sub(Mul(%x, %y), %y), Div(%x, %y), Le(%x, %y), Matmul(%x, %y))
+            }
+            let %float = fn(%x: Tensor[(2),float]) -> Tensor[(2),float] {
+                Relu(Div(%x, Tensor([2.0, -0.5])))
+            }",
+            level,
+        );
+        let x = module.write_i64(&[4, 9, 3]);
+        let y = module.write_i64(&[2, 3, 4]);
+        // 结果按 C 的结构体布局排列：两个 int 张量、3 个字节的 bool 张量，最后的 int 按 8 字节对齐
+        let out = module.alloc(64);
+        assert_eq!(module.call("ops", &[x, y, out.clone()]), None);
+        assert_eq!(module.read_i64(&out, 0, 3), [6, 24, 8]);
+        assert_eq!(module.read_i64(&out, 24, 3), [2, 3, 0]);
+        assert_eq!(module.read(&out, 48, 3), [0, 0, 1]);
+        assert_eq!(module.read_i64(&out, 56, 1), [47]);
+        let x = module.write_f64(&[3.0, 1.5]);
+        let out = module.alloc(16);
+        module.call("float", &[x, out.clone()]);
+        assert_eq!(module.read_f64(&out, 0, 2), [1.5, 0.0]);
+    }
+}
+
+#[test]
+fn fused_chain_broadcasts() {
+    let programs = mool_ir::parse(
+        "let %f = fn(%x: Tensor[(4), float], %w: float, %b: Tensor[(1), float], %mask: Tensor[(4), bool]) -> (Tensor[(4), float], Tensor[(4), bool]) {
+            (Relu(Add(Mul(%x, %w), %b)), %mask)
+        }",
+    )
+    .unwrap();
+    let mut module = Module::new(Fuse::default().run(programs), OptLevel::O2);
+    let x = module.write_f64(&[1.0, -2.0, 3.0, -4.0]);
+    let b = module.write_f64(&[0.5]);
+    let mask = module.write(&[1, 0, 0, 1]);
+    let out = module.alloc(40);
+    module.call("f", &[x, Value::F64(2.0.into()), b, mask, out.clone()]);
+    assert_eq!(module.read_f64(&out, 0, 4), [2.5, 0.0, 6.5, 0.0]);
+    assert_eq!(module.read(&out, 32, 4), [1, 0, 0, 1]);
+}
+
+#[test]
+fn unfused_operators_broadcast() {
+    // 不经过算子融合时，标量和只有一个元素的张量同样广播到另一侧的张量
+    for level in [OptLevel::O0, OptLevel::O2] {
+        let mut module = load(
+            "let %f = fn(%x: Tensor[(3), int], %s: int, %b: Tensor[(1), int]) -> (Tensor[(3), int], Tensor[(3), int], Tensor[(3), bool]) {
+                (Mul(Tensor([1, 2, 3]), %s), Sub(%b, %x), Gt(%x, %s))
+            }",
+            level,
+        );
+        let x = module.write_i64(&[4, 9, 3]);
+        let b = module.write_i64(&[10]);
+        let out = module.alloc(56);
+        module.call("f", &[x, Value::I64(4), b, out.clone()]);
+        assert_eq!(module.read_i64(&out, 0, 3), [4, 8, 12]);
+        assert_eq!(module.read_i64(&out, 24, 3), [6, 1, 7]);
+        assert_eq!(module.read(&out, 48, 3), [0, 1, 0]);
+    }
+}
+
+#[test]
+fn fused_chain_with_matmul_leaf() {
+    // 矩阵乘法的结果是标量，作为融合算子的叶子广播到所有元素
+    let programs = mool_ir::parse(
+        "let %f = fn(%x: Tensor[(4), float], %y: Tensor[(4), float]) -> Tensor[(4), float] {
+            Relu(Sub(Mul(%x, Matmul(%x, %y)), %y))
+        }",
+    )
+    .unwrap();
+    for level in [OptLevel::O0, OptLevel::O2] {
+        let fused = Fuse::default().run(programs.clone());
+        let mut module = Module::new(fused, level);
+        let x = module.write_f64(&[1.0, -2.0, 3.0, -4.0]);
+        let y = module.write_f64(&[1.0, 1.0, 1.0, 1.0]);
+        let out = module.alloc(32);
+        module.call("f", &[x, y, out.clone()]);
+        assert_eq!(module.read_f64(&out, 0, 4), [0.0, 3.0, 0.0, 7.0]);
+    }
+}
+
+#[test]
+fn loop_carried_tensor_with_break_and_continue() {
+    for level in [OptLevel::O0, OptLevel::O2] {
+        let mut module = load(
+            "let %f = fn(%x: Tensor[(4), float], %n: int) -> Tensor[(4), float] {
+                let %y = %x
+                for %i in range(0, %n, 1) {
+                    if Gt(%i, 5) { break } else { 0 }
+                    if Eq(%i, 2) { continue } else { 0 }
+                    %y = Add(%y, %x)
+                }
+                %y
+            }",
+            level,
+        );
+        let x = module.write_f64(&[1.0, 2.0, 3.0, 4.0]);
+        let out = module.alloc(32);
+        // i 为 0、1、3、4、5 时累加，i 为 6 时跳出循环
+        module.call("f", &[x, Value::I64(10), out.clone()]);
+        assert_eq!(module.read_f64(&out, 0, 4), [6.0, 12.0, 18.0, 24.0]);
+    }
+}
+
+#[test]
+fn large_tensors_reuse_memory() {
+    for level in [OptLevel::O0, OptLevel::O2] {
+        let mut module = load(
+            "let %f = fn(%x: Tensor[(10000),int], %n: int) -> int {
+                let %y = %x
+                for %i in range(0, %n, 1) {
+                    %y = Relu(Add(%y, %x))
+                }
+                Matmul(%y, %x)
+            }",
+            level,
+        );
+        let x = module.write_i64(&[1; 10000]);
+        let pages = u32::from(module.memory.current_pages(&module.store));
+        assert_eq!(module.call("f", &[x, Value::I64(20)]), Some(10000 * 21));
+        // 每次迭代释放的张量被下一次迭代复用，线性内存不随迭代次数增长
+        let grown = u32::from(module.memory.current_pages(&module.store)) - pages;
+        assert!(grown < 20, "线性内存增长了 {} 页", grown);
+    }
+}
+
+#[test]
+fn memory_plan_reuses_workspace() {
+    let code = "let %f = fn(%x: Tensor[(4),float], %y: Tensor[(4),float]) -> Tensor[(4),float] {
+        let %a = Mul(%x, %y)
+        let %b = Add(%a, %x)
+        let %c = Relu(Sub(Mul(%b, %b), %y))
+        Add(Mul(%c, %a), Div(Add(%c, %x), %y))
+    }";
+    for level in [OptLevel::O0, OptLevel::O2] {
+        let plain = mool_ir::parse(code).unwrap();
+        let planned = MemoryPlan::default().run(mool_ir::parse(code).unwrap());
+        let fused = Fuse::default().run(mool_ir::parse(code).unwrap());
+        let fused = MemoryPlan::default().run(fused);
+        for programs in [plain, planned, fused] {
+            let mut module = Module::new(programs, level);
+            let x = module.write_f64(&[1.0, 2.0, 3.0, 4.0]);
+            let y = module.write_f64(&[1.0, 1.0, 2.0, 2.0]);
+            // 多次调用复用同一个工作区
+            for _ in 0..3 {
+                let out = module.alloc(32);
+                module.call("f", &[x.clone(), y.clone(), out.clone()]);
+                assert_eq!(module.read_f64(&out, 0, 4), [7.0, 47.0, 515.0, 1209.0]);
+            }
+        }
+    }
+}
+
+#[test]
+fn symbolic_dims_from_descriptors() {
+    for level in [OptLevel::O0, OptLevel::O2] {
+        let mut module = load(
+            "let %square_add = fn(%x: Tensor[(B, 4), float], %y: Tensor[(B, 4), float]) -> Tensor[(B, 4), float] {
+                Add(Mul(%x, %x), %y)
+            }
+            let %dot = fn(%x: Tensor[(N), int], %y: Tensor[(N), int]) -> int {
+                Matmul(%x, %y)
+            }
+            let %f = fn(%x: Tensor[(2, 4), float], %y: Tensor[(2, 4), float]) -> Tensor[(2, 4), float] {
+                %square_add(%x, %y)
+            }
+            let %g = fn(%x: Tensor[(3), int], %y: Tensor[(3), int]) -> int {
+                %dot(%x, %y)
+            }
+            let %h = fn(%x: Tensor[(5), int], %y: Tensor[(3), int]) -> int {
+                %dot(%x, %y)
+            }",
+            level,
+        );
+        // 签名中有符号维度的函数不导出
+        assert!(module
+            .instance
+            .get_func(&module.store, "square_add")
+            .is_none());
+        assert!(module.instance.get_func(&module.store, "dot").is_none());
+        let x = module.write_f64(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+        let y = module.write_f64(&[1.0; 8]);
+        let out = module.alloc(64);
+        module.call("f", &[x, y, out.clone()]);
+        assert_eq!(
+            module.read_f64(&out, 0, 8),
+            [2.0, 5.0, 10.0, 17.0, 26.0, 37.0, 50.0, 65.0]
+        );
+        let x = module.write_i64(&[1, 2, 3]);
+        let y = module.write_i64(&[4, 5, 6]);
+        assert_eq!(module.call("g", &[x, y]), Some(32));
+        // 同一个符号维度在两个实参中的大小不同，函数入口的形状检查 trap
+        let x = module.write_i64(&[1; 5]);
+        let y = module.write_i64(&[1; 3]);
+        let h = module.instance.get_func(&module.store, "h").unwrap();
+        let mut results = [Value::I64(0)];
+        assert!(h.call(&mut module.store, &[x, y], &mut results).is_err());
+    }
+}
+
+#[test]
+fn object_without_linker() {
+    let programs = mool_ir::parse("let %f = fn(%x: int) -> int { Add(%x, 1) }").unwrap();
+    let backend = WasmBackend::new(OptLevel::O1, None);
+    assert_eq!(backend.name(), "wasm");
+    let artifacts = backend.codegen(programs).unwrap();
+    assert_eq!(artifacts.len(), 1);
+    assert_eq!(artifacts[0].extension, "o");
+    assert!(artifacts[0].content.starts_with(b"\0asm"));
+}
+
+/// 从描述符中读出的张量，offset 为有元素的张量的数据在工作区中的偏移，
+/// zeroed 表示不在工作区中的数据是否全为 0
+#[derive(Debug, PartialEq)]
+struct Tensor {
+    dtype: i32,
+    shape: Vec<i64>,
+    strides: Vec<i64>,
+    numel: i64,
+    refcount: i64,
+    offset: Option<i64>,
+    zeroed: bool,
+}
+
+/// 运行时的 C 接口，张量和工作区用地址表示
+trait Runtime {
+    /// 用新分配的 size 字节内存作为工作区，misaligned 时传入没有按 8 字节对齐的地址
+    fn init(&mut self, size: i64, misaligned: bool) -> i32;
+    fn reserve(&mut self, size: i64) -> bool;
+    fn workspace_size(&mut self) -> i64;
+    fn alloc(&mut self, dtype: i32, shape: &[i64], offset: Option<i64>) -> Option<i64>;
+    fn retain(&mut self, tensor: i64);
+    fn release(&mut self, tensor: i64);
+    fn describe(&mut self, tensor: i64) -> Tensor;
+}
+
+/// mool_runtime 实现的运行时，工作区是全局的，结束时不再使用调用方提供的内存
+#[derive(Default)]
+struct Native {
+    buffers: Vec<Vec<u64>>,
+    workspace: i64,
+}
+
+impl Runtime for Native {
+    fn init(&mut self, size: i64, misaligned: bool) -> i32 {
+        let mut buffer = vec![0u64; (size.max(0) as usize).div_ceil(8)];
+        let data = buffer.as_mut_ptr() as *mut u8;
+        let data = if misaligned {
+            data.wrapping_add(1)
+        } else {
+            data
+        };
+        self.buffers.push(buffer);
+        let code = unsafe { mool_runtime::mool_workspace_init(data, size) };
+        if code == 0 {
+            self.workspace = data as i64;
+        }
+        code
+    }
+
+    fn reserve(&mut self, size: i64) -> bool {
+        let data = unsafe { mool_runtime::mool_workspace_reserve(size) };
+        if !data.is_null() {
+            self.workspace = data as i64;
+        }
+        !data.is_null()
+    }
+
+    fn workspace_size(&mut self) -> i64 {
+        mool_runtime::mool_workspace_size()
+    }
+
+    fn alloc(&mut self, dtype: i32, shape: &[i64], offset: Option<i64>) -> Option<i64> {
+        let rank = shape.len() as i64;
+        let tensor = unsafe {
+            match offset {
+                Some(offset) => {
+                    mool_runtime::mool_tensor_alloc_at(dtype, rank, shape.as_ptr(), offset)
+                }
+                None => mool_runtime::mool_tensor_alloc(dtype, rank, shape.as_ptr()),
+            }
+        };
+        (!tensor.is_null()).then_some(tensor as i64)
+    }
+
+    fn retain(&mut self, tensor: i64) {
+        unsafe { mool_runtime::mool_tensor_retain(tensor as *mut _) }
+    }
+
+    fn release(&mut self, tensor: i64) {
+        unsafe { mool_runtime::mool_tensor_release(tensor as *mut _) }
+    }
+
+    fn describe(&mut self, tensor: i64) -> Tensor {
+        let tensor = unsafe { &*(tensor as *const mool_runtime::MoolTensor) };
+        let numel = unsafe { mool_runtime::mool_tensor_numel(tensor) };
+        let offset = tensor.data as i64 - self.workspace;
+        let inside = (0..self.workspace_size()).contains(&offset);
+        let data = unsafe { std::slice::from_raw_parts(tensor.data, tensor.size()) };
+        Tensor {
+            dtype: tensor.dtype,
+            shape: tensor.shape().to_vec(),
+            strides: tensor.strides().to_vec(),
+            numel,
+            refcount: tensor.refcount,
+            offset: (inside && numel > 0).then_some(offset),
+            zeroed: inside || data.iter().all(|byte| *byte == 0),
+        }
+    }
+}
+
+impl Drop for Native {
+    fn drop(&mut self) {
+        unsafe { mool_runtime::mool_workspace_init(std::ptr::null_mut(), 0) };
+    }
+}
+
+/// 链接进 WebAssembly 模块的运行时，描述符按 wasm32 的 C 布局排列
+impl Runtime for Module {
+    fn init(&mut self, size: i64, misaligned: bool) -> i32 {
+        let data = self.alloc(size.max(0) as usize).i32().unwrap() + misaligned as i32;
+        let code = self
+            .call("mool_workspace_init", &[Value::I32(data), Value::I64(size)])
+            .unwrap();
+        if code == 0 {
+            self.workspace = data as i64;
+        }
+        code as i32
+    }
+
+    fn reserve(&mut self, size: i64) -> bool {
+        let data = self
+            .call("mool_workspace_reserve", &[Value::I64(size)])
+            .unwrap();
+        if data != 0 {
+            self.workspace = data;
+        }
+        data != 0
+    }
+
+    fn workspace_size(&mut self) -> i64 {
+        self.call("mool_workspace_size", &[]).unwrap()
+    }
+
+    fn alloc(&mut self, dtype: i32, shape: &[i64], offset: Option<i64>) -> Option<i64> {
+        let mut args = vec![
+            Value::I32(dtype),
+            Value::I64(shape.len() as i64),
+            self.write_i64(shape),
+        ];
+        let name = match offset {
+            Some(offset) => {
+                args.push(Value::I64(offset));
+                "mool_tensor_alloc_at"
+            }
+            None => "mool_tensor_alloc",
+        };
+        let tensor = self.call(name, &args).unwrap();
+        (tensor != 0).then_some(tensor)
+    }
+
+    fn retain(&mut self, tensor: i64) {
+        self.call("mool_tensor_retain", &[Value::I32(tensor as i32)]);
+    }
+
+    fn release(&mut self, tensor: i64) {
+        self.call("mool_tensor_release", &[Value::I32(tensor as i32)]);
+    }
+
+    fn describe(&mut self, tensor: i64) -> Tensor {
+        let pointer = Value::I32(tensor as i32);
+        let field = |offset| {
+            let bytes = self.read(&pointer, offset, 4);
+            Value::I32(i32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        let (data, dtype, shape, strides) = (field(0), field(4), field(16), field(20));
+        let rank = self.read_i64(&pointer, 8, 1)[0] as usize;
+        let refcount = self.read_i64(&pointer, 24, 1)[0];
+        let numel = self
+            .call("mool_tensor_numel", &[Value::I32(tensor as i32)])
+            .unwrap();
+        let dtype = dtype.i32().unwrap();
+        let size = numel as usize * if dtype == 2 { 1 } else { 8 };
+        let offset = data.i32().unwrap() as i64 - self.workspace;
+        let inside = (0..self.workspace_size()).contains(&offset);
+        Tensor {
+            dtype,
+            shape: self.read_i64(&shape, 0, rank),
+            strides: self.read_i64(&strides, 0, rank),
+            numel,
+            refcount,
+            offset: (inside && numel > 0).then_some(offset),
+            zeroed: inside || self.read(&data, 0, size).iter().all(|byte| *byte == 0),
+        }
+    }
+}
+
+/// 依次分配、引用和释放张量，记录每一步的结果
+fn exercise(runtime: &mut dyn Runtime) -> Vec<String> {
+    const INT: i32 = 0;
+    const FLOAT: i32 = 1;
+    const BOOL: i32 = 2;
+    let mut log = Vec::new();
+    // 没有工作区时由运行时分配
+    log.push(format!(
+        "{} {}",
+        runtime.reserve(64),
+        runtime.workspace_size()
+    ));
+    let a = runtime.alloc(FLOAT, &[2, 3], None).unwrap();
+    log.push(format!("{:?}", runtime.describe(a)));
+    runtime.retain(a);
+    log.push(format!("{:?}", runtime.describe(a)));
+    runtime.release(a);
+    log.push(format!("{:?}", runtime.describe(a)));
+    let b = runtime.alloc(INT, &[3], Some(16)).unwrap();
+    log.push(format!("{:?}", runtime.describe(b)));
+    // 没有元素的张量可以在工作区的末尾
+    let empty = runtime.alloc(INT, &[0], Some(64)).unwrap();
+    log.push(format!("{:?}", runtime.describe(empty)));
+    runtime.release(empty);
+    // 超出工作区、元素类型或形状不正确时分配失败
+    for (dtype, shape, offset) in [
+        (INT, &[3][..], Some(48)),
+        (INT, &[0], Some(72)),
+        (INT, &[1], Some(-8)),
+        (7, &[3], None),
+        (FLOAT, &[2, -1], None),
+    ] {
+        log.push(format!("{:?}", runtime.alloc(dtype, shape, offset)));
+    }
+    // 释放之后重新分配
+    runtime.release(a);
+    runtime.release(b);
+    let c = runtime.alloc(BOOL, &[2, 2, 2], None).unwrap();
+    log.push(format!("{:?}", runtime.describe(c)));
+    let scalar = runtime.alloc(INT, &[], None).unwrap();
+    log.push(format!("{:?}", runtime.describe(scalar)));
+    runtime.release(c);
+    runtime.release(scalar);
+    // 运行时分配的工作区不够大时重新分配
+    log.push(format!(
+        "{} {}",
+        runtime.reserve(128),
+        runtime.workspace_size()
+    ));
+    // 调用方提供的工作区需要对齐，不够大时不能重新分配
+    log.push(format!(
+        "{} {}",
+        runtime.init(32, true),
+        runtime.workspace_size()
+    ));
+    log.push(format!(
+        "{} {}",
+        runtime.init(32, false),
+        runtime.workspace_size()
+    ));
+    log.push(format!("{} {}", runtime.reserve(16), runtime.reserve(64)));
+    log.push(format!(
+        "{} {}",
+        runtime.reserve(-1),
+        runtime.workspace_size()
+    ));
+    let d = runtime.alloc(INT, &[4], Some(0)).unwrap();
+    log.push(format!("{:?}", runtime.describe(d)));
+    runtime.release(d);
+    log
+}
+
+#[test]
+fn runtime_matches_mool_runtime() {
+    let expected = exercise(&mut Native::default());
+    assert_eq!(expected[0], "true 64");
+    assert_eq!(expected[6..11], ["None"; 5]);
+    assert_eq!(expected[expected.len() - 3], "true false");
+    for level in [OptLevel::O0, OptLevel::O2] {
+        assert_eq!(exercise(&mut Module::runtime(level)), expected);
+    }
+}
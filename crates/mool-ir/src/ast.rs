@@ -1,8 +1,11 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Program {
     Let(Variable, Expr),
+    /// 元组解构，例如 let (%a, %b) = %f(%x)
+    LetTuple(Vec<Variable>, Expr),
     Expr(Expr),
 }
 
@@ -14,6 +17,15 @@ pub enum Expr {
     Function(Function),
     Call(String, Vec<Expr>),
     Operator(Operator),
+    /// 元组，例如 (%a, 1)
+    Tuple(Vec<Expr>),
+    /// 取元组的元素，例如 %t.0
+    Field(Box<Expr>, usize),
+    If(If),
+    For(For),
+    While(While),
+    Break,
+    Continue,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -32,14 +44,112 @@ pub enum Literal {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Function {
     pub args: Vec<FunctionArg>,
-    pub rtn: String,
+    pub rtn: Type,
     pub body: Vec<Program>,
+    pub inline: Inline,
+}
+
+/// 函数的内联属性，例如 #[inline] fn(%x: int) -> int { %x }
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Inline {
+    /// 由函数的大小决定
+    #[default]
+    Auto,
+    /// #[inline]，总是内联
+    Always,
+    /// #[noinline]，从不内联
+    Never,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FunctionArg {
     pub arg: Variable,
-    pub annotation: String,
+    pub annotation: Type,
+}
+
+/// Mool 的类型
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    /// 张量，例如 Tensor[(3),int]，维度可以是符号，例如 Tensor[(B, 128),float]
+    Tensor(Vec<Dim>, Box<Type>),
+    /// 元组，例如 (int, float)
+    Tuple(Vec<Type>),
+}
+
+/// 张量的一个维度，符号维度的大小在运行时由张量描述符给出，
+/// 同一个函数签名中名称相同的符号维度大小相同
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Dim {
+    Const(usize),
+    Symbol(String),
+}
+
+impl Dim {
+    /// 维度的大小，符号维度返回 None
+    pub fn size(&self) -> Option<usize> {
+        match self {
+            Dim::Const(size) => Some(*size),
+            Dim::Symbol(_) => None,
+        }
+    }
+}
+
+/// 张量的元素个数，有符号维度时返回 None
+pub fn numel(shape: &[Dim]) -> Option<usize> {
+    shape.iter().map(Dim::size).product()
+}
+
+impl fmt::Display for Dim {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Dim::Const(size) => write!(f, "{}", size),
+            Dim::Symbol(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// 输出 Mool 的类型注解，例如 Tensor[(B, 128),float]
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::Tensor(shape, dtype) => {
+                let dims: Vec<String> = shape.iter().map(Dim::to_string).collect();
+                write!(f, "Tensor[({}),{}]", dims.join(", "), dtype)
+            }
+            Type::Tuple(types) => {
+                let types: Vec<String> = types.iter().map(Type::to_string).collect();
+                write!(f, "({})", types.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct If {
+    pub cond: Box<Expr>,
+    pub then: Vec<Program>,
+    pub otherwise: Vec<Program>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct For {
+    pub var: Variable,
+    pub start: Box<Expr>,
+    pub end: Box<Expr>,
+    pub step: Box<Expr>,
+    pub body: Vec<Program>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct While {
+    pub cond: Box<Expr>,
+    pub body: Vec<Program>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -48,5 +158,19 @@ pub enum Operator {
     Sub(Box<Expr>, Box<Expr>),
     Mul(Box<Expr>, Box<Expr>),
     Div(Box<Expr>, Box<Expr>),
+    Matmul(Box<Expr>, Box<Expr>),
+    Relu(Box<Expr>),
+    Lt(Box<Expr>, Box<Expr>),
+    Le(Box<Expr>, Box<Expr>),
+    Gt(Box<Expr>, Box<Expr>),
+    Ge(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    Ne(Box<Expr>, Box<Expr>),
     Tensor(Vec<Literal>),
+    /// 融合的按元素计算的算子链，例如 Fused(Relu(Add(Mul(%x, %w), %b)))，
+    /// 代码生成时在一个循环中逐个元素计算整条链
+    Fused(Box<Expr>),
+    /// 结果放在工作区中的算子，例如 Workspace(Add(%x, %y), 64, 24)，
+    /// 两个整数为结果的数据在工作区中的偏移和字节数，由内存规划生成
+    Workspace(Box<Expr>, usize, usize),
 }
@@ -1,3 +1,4 @@
 pub mod ast;
 mod parser;
+pub mod pass;
 pub use parser::mool_parser::program as parse;
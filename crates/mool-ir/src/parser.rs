@@ -2,88 +2,183 @@ use super::ast;
 
 peg::parser! {
     pub grammar mool_parser() for str {
-        use ast::{Program, Variable, Expr, Literal, Function, FunctionArg, Operator};
+        use ast::{Program, Variable, Expr, Literal, Function, FunctionArg, Inline, Operator, If, For, While, Type, Dim};
         pub rule program() -> Vec<Program> =
-            p:((expression_program() / let())*) { p }
+            p:((ig_line() p:(expression_program() / let_tuple() / let()) { p })*) ig_line() { p }
+        rule let_tuple() -> Program =
+            "let" ig_space() "(" names:((ig_line() v:variable() ig_line() { v }) ** ",") ","? ig_line() ")" ig_space()
+                "=" ig_line() e:expression() ig_line() {
+                Program::LetTuple(names, e)
+            }
         rule let() -> Program =
             "let" ig_space() name:variable() ig_space() "=" ig_line() e:expression() ig_line() {
                 Program::Let(name, e)
             }
         rule expression_program() -> Program = ig_line() e:expression() ig_line() { Program::Expr(e) }
+        // 元组取元素可以连续使用，例如 %t.0.1
         rule expression() -> Expr =
-            literal()
+            e:primary() fields:("." p:position!() n:$(['0'..='9']+) ig_space() {
+                match n.parse::<usize>() {
+                    Ok(n) => n,
+                    Err(e) => panic!("{}:无法解析元组的下标", p),
+                }
+            })* {
+                fields.into_iter().fold(e, |e, n| Expr::Field(Box::new(e), n))
+            }
+        rule primary() -> Expr =
+            tuple()
+            / literal()
             / function()
+            / if_else()
+            / for_loop()
+            / while_loop()
+            / "break" !identifier() ig_space() { Expr::Break }
+            / "continue" !identifier() ig_space() { Expr::Continue }
             / operator()
             / call()
             / assign()
             / v:variable() { Expr::Variable(v) }
+        // 只有一个元素的元组需要以逗号结尾，例如 (1,)
+        rule tuple() -> Expr =
+            ig_space() "(" ig_line() items:((ig_line() e:expression() ig_line() { e }) ** ",") trailing:","? ig_line() ")" ig_space() {?
+                if items.len() == 1 && trailing.is_none() {
+                    Err("(expression,)")
+                } else {
+                    Ok(Expr::Tuple(items))
+                }
+            }
         rule assign() -> Expr = 
             ig_space() name:variable() ig_space() "=" ig_line() e:expression() ig_line() {
                 Expr::Assign(name, Box::new(e))
             }
         rule literal() -> Expr = l:(int_literal() / float_literal() / bool_literal()) { Expr::Literal(l) }
-        rule int_literal() -> Literal = p:position!() ig_space() n:$(['0'..='9']+) !"." ig_space() {
+        rule int_literal() -> Literal = p:position!() ig_space() n:$("-"? ['0'..='9']+) !"." ig_space() {
             match n.parse::<i64>(){
                 Ok(t) => { Literal::Int(t) },
                 Err(e) => { panic!("{}:无法解析为整数类型", p) }
             }
         }
-        rule float_literal() -> Literal = p:position!() n:$(['0'..='9']+"."['0'..='9']*) {
+        rule float_literal() -> Literal = p:position!() ig_space() n:$("-"? ['0'..='9']+"."['0'..='9']* (['e' | 'E'] ['+' | '-']? ['0'..='9']+)?) {
             match n.parse::<f64>() {
                 Ok(t) => { Literal::Float(t) },
                 Err(e) => { panic!("{}:无法解析为浮点类型", p) }
             }
         }
         rule bool_literal() -> Literal = ig_space() b:$("true" / "false") ig_space(){
-            Literal::Bool(if b == "true" { true } else { false })
+            Literal::Bool(b == "true")
         }
         rule function() -> Expr =
-            "fn" ig_space() "(" ig_line() args:function_args() ig_line() ")" ig_space()
+            inline:inline_attribute()? "fn" ig_space() "(" ig_line() args:function_args() ig_line() ")" ig_space()
                 "->" ig_space() rt:mool_type() ig_space() "{" ig_line() e:program() ig_line() "}" ig_line(){
-                Expr::Function(Function{args:args, rtn:rt.to_string(), body:e})
+                Expr::Function(Function{args, rtn:rt, body:e, inline:inline.unwrap_or_default()})
+            }
+        rule inline_attribute() -> Inline =
+            "#[" ig_space() a:$("inline" / "noinline") ig_space() "]" ig_line() {
+                match a {
+                    "inline" => Inline::Always,
+                    _ => Inline::Never,
+                }
+            }
+        rule for_loop() -> Expr =
+            "for" ig_space() var:variable() ig_space() "in" ig_space() "range" ig_space()
+                "(" bounds:((ig_line() e:expression() ig_line() { e }) **<1,3> ",") ")" ig_space()
+                "{" ig_line() body:program() ig_line() "}" ig_line() {
+                let mut bounds = bounds.into_iter();
+                let first = bounds.next().unwrap();
+                let (start, end) = match bounds.next() {
+                    Some(end) => (first, end),
+                    None => (Expr::Literal(Literal::Int(0)), first),
+                };
+                let step = bounds.next().unwrap_or(Expr::Literal(Literal::Int(1)));
+                Expr::For(For{var, start:Box::new(start), end:Box::new(end), step:Box::new(step), body})
+            }
+        rule if_else() -> Expr =
+            "if" ig_space() cond:expression() ig_space() "{" ig_line() then:program() ig_line() "}" ig_space()
+                otherwise:("else" ig_space() "{" ig_line() e:program() ig_line() "}" { e })? ig_line() {
+                Expr::If(If{cond:Box::new(cond), then, otherwise:otherwise.unwrap_or_default()})
+            }
+        rule while_loop() -> Expr =
+            "while" ig_space() cond:expression() ig_space() "{" ig_line() body:program() ig_line() "}" ig_line() {
+                Expr::While(While{cond:Box::new(cond), body})
             }
         rule function_args() -> Vec<FunctionArg> = args:(function_arg() ** ",") ","? { args }
         rule function_arg() -> FunctionArg =
             ig_line() arg:variable() ig_line() ":" ig_line() annotation:mool_type() ig_line() {
-                FunctionArg{arg: arg, annotation: annotation.to_string()}
+                FunctionArg{arg, annotation}
             }
         rule variable() -> Variable =
             scope:$("%"/"@") name:identifier() {
-                Variable{name: name, global: if scope == "@" { true } else { false }}
+                Variable{name, global: scope == "@"}
             }
         rule identifier() -> String =
             id:$(['a'..='z' | 'A'..='Z' | '_']['a'..='z' | 'A'..='Z' | '0'..='9' | '_' ]*) {
                 id.to_owned()
             }
-        rule mool_type() -> String =
-            id:$("int"/"bool"/"float"
-                /(
-                    "Tensor" "[" ig_line() "(" ig_line() ['0'..='9']* ig_line() ")" ig_space() "," ig_space() ("int"/"bool"/"float") ig_line() "]"
-                )) {
-                id.to_owned()
+        rule mool_type() -> Type =
+            scalar_type()
+            / "Tensor" "[" ig_line() "(" shape:((ig_line() d:dim() ig_line() { d }) ++ ",") ")" ig_space() "," ig_space() t:scalar_type() ig_line() "]" {
+                Type::Tensor(shape, Box::new(t))
             }
+            / "(" ig_line() t:((ig_line() t:mool_type() ig_line() { t }) ** ",") ","? ig_line() ")" { Type::Tuple(t) }
+        rule dim() -> Dim =
+            p:position!() n:$(['0'..='9']+) {
+                match n.parse::<usize>() {
+                    Ok(n) => Dim::Const(n),
+                    Err(e) => panic!("{}:无法解析张量的长度", p),
+                }
+            }
+            / name:identifier() { Dim::Symbol(name) }
+        rule scalar_type() -> Type =
+            "int" !identifier() { Type::Int }
+            / "bool" !identifier() { Type::Bool }
+            / "float" !identifier() { Type::Float }
         rule operator() -> Expr =
-            p:position!() op:$("Add" / "Sub" / "Mul" / "Div") ig_space()
+            p:position!() op:$("Add" / "Sub" / "Mul" / "Div" / "Matmul" / "Lt" / "Le" / "Gt" / "Ge" / "Eq" / "Ne") ig_space()
                 "(" ig_line() x:expression() ig_line() "," ig_line() y:expression() ig_line() ")" ig_space() {
                 match op {
                     "Add" => { Expr::Operator(Operator::Add(Box::new(x), Box::new(y))) },
                     "Sub" => { Expr::Operator(Operator::Sub(Box::new(x), Box::new(y))) },
                     "Mul" => { Expr::Operator(Operator::Mul(Box::new(x), Box::new(y))) },
                     "Div" => { Expr::Operator(Operator::Div(Box::new(x), Box::new(y))) },
+                    "Matmul" => { Expr::Operator(Operator::Matmul(Box::new(x), Box::new(y))) },
+                    "Lt" => { Expr::Operator(Operator::Lt(Box::new(x), Box::new(y))) },
+                    "Le" => { Expr::Operator(Operator::Le(Box::new(x), Box::new(y))) },
+                    "Gt" => { Expr::Operator(Operator::Gt(Box::new(x), Box::new(y))) },
+                    "Ge" => { Expr::Operator(Operator::Ge(Box::new(x), Box::new(y))) },
+                    "Eq" => { Expr::Operator(Operator::Eq(Box::new(x), Box::new(y))) },
+                    "Ne" => { Expr::Operator(Operator::Ne(Box::new(x), Box::new(y))) },
                     _ => { panic!("{}:暂不支持{}算子", p ,op) }
                 }
             }
+            / ig_space() "Relu" ig_space() "(" ig_line() x:expression() ig_line() ")" ig_space() {
+                Expr::Operator(Operator::Relu(Box::new(x)))
+            }
+            / ig_space() "Fused" ig_space() "(" ig_line() x:expression() ig_line() ")" ig_space() {
+                Expr::Operator(Operator::Fused(Box::new(x)))
+            }
+            / ig_space() "Workspace" ig_space() "(" ig_line() x:expression() ig_line() "," ig_line() offset:bytes() ig_line() "," ig_line() size:bytes() ig_line() ")" ig_space() {
+                Expr::Operator(Operator::Workspace(Box::new(x), offset, size))
+            }
             / ig_space() "Tensor" ig_line() "(" ig_line() t:tensor() ig_line() ")" ig_space() {
                 Expr::Operator(Operator::Tensor(t))
             }
+        rule bytes() -> usize =
+            p:position!() n:$(['0'..='9']+) {
+                match n.parse::<usize>() {
+                    Ok(n) => n,
+                    Err(e) => panic!("{}:无法解析工作区的偏移和大小", p),
+                }
+            }
         rule tensor() -> Vec<Literal> = "[" ig_line() t:(tensor_type()** ",") ig_line() "]" { t }
         rule tensor_type() -> Literal = int_literal() / float_literal() / bool_literal()
         rule call() -> Expr = ("%"/"@") id:identifier() ig_line() "(" ig_line() args:call_args() ig_line() ")" ig_line() {
             Expr::Call(id, args)
         }
-        rule call_args() -> Vec<Expr> = args:(expression() ** ",") ","? { args }
-        rule not_keyword() = !("let" / "fn")
-        rule ig_space() = quiet!{ [' ' | '\t']* }
-        rule ig_line() = quiet!{ [' ' | '\t' | '\n']* }
+        rule call_args() -> Vec<Expr> = args:((ig_line() e:expression() ig_line() { e }) ** ",") ","? { args }
+        rule not_keyword() = !("let" / "fn" / "if" / "else" / "for" / "while" / "break" / "continue")
+        // 支持 // 行注释和 /* */ 块注释
+        rule comment() = "//" [^'\n']* / "/*" (!"*/" [_])* "*/"
+        rule ig_space() = quiet!{ ([' ' | '\t' | '\r'] / comment())* }
+        rule ig_line() = quiet!{ ([' ' | '\t' | '\r' | '\n'] / comment())* }
     }
 }
@@ -0,0 +1,142 @@
+use super::ast::Program;
+use std::time::{Duration, Instant};
+
+mod cse;
+mod dce;
+mod fold;
+mod fuse;
+mod inline;
+mod plan;
+mod typecheck;
+mod verify;
+
+pub use cse::CommonSubexpression;
+pub use dce::DeadCode;
+pub use fold::Fold;
+pub use fuse::{is_elementwise, Fuse};
+pub use inline::{Inliner, INLINE_THRESHOLD};
+pub use plan::{workspace_size, MemoryPlan};
+pub use typecheck::typecheck;
+pub use verify::verify;
+
+/// Mool IR 上的一个变换
+pub trait Pass {
+    /// 名称，命令行按名称选择 pass
+    fn name(&self) -> &'static str;
+
+    fn run(&mut self, programs: Vec<Program>) -> Vec<Program>;
+
+    /// 上一次执行做了哪些修改，例如删除了哪些代码
+    fn report(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// 按名称创建 pass
+pub fn create(name: &str) -> Option<Box<dyn Pass>> {
+    match name {
+        "fold" => Some(Box::new(Fold)),
+        "dce" => Some(Box::new(DeadCode::default())),
+        "cse" => Some(Box::new(CommonSubexpression::default())),
+        "fuse" => Some(Box::new(Fuse::default())),
+        "inline" => Some(Box::new(Inliner::default())),
+        "plan" => Some(Box::new(MemoryPlan::default())),
+        _ => None,
+    }
+}
+
+/// 输出每个 pass 执行之后的 Mool IR
+type Dump = Box<dyn FnMut(&str, &[Program])>;
+
+/// 按添加的顺序依次执行 pass
+///
+/// 执行前和每个 pass 之后验证 Mool IR，并记录每个 pass 的耗时和修改。
+/// 设置 dump 之后，每个 pass 执行完都会把结果交给 dump 输出。
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    verify: bool,
+    dump: Option<Dump>,
+    timings: Vec<(&'static str, Duration)>,
+    reports: Vec<(&'static str, Vec<String>)>,
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        Self {
+            passes: Vec::new(),
+            verify: true,
+            dump: None,
+            timings: Vec::new(),
+            reports: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, pass: Box<dyn Pass>) {
+        self.passes.push(pass);
+    }
+
+    /// 按名称添加 pass
+    pub fn add_by_name(&mut self, name: &str) -> Result<(), String> {
+        match create(name) {
+            Some(pass) => {
+                self.add(pass);
+                Ok(())
+            }
+            None => Err(format!("没有名为{}的 pass", name)),
+        }
+    }
+
+    /// 是否在 pass 之间验证 Mool IR，默认验证
+    pub fn verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    pub fn dump(&mut self, dump: impl FnMut(&str, &[Program]) + 'static) {
+        self.dump = Some(Box::new(dump));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    /// 最近一次执行中每个 pass 的耗时
+    pub fn timings(&self) -> &[(&'static str, Duration)] {
+        &self.timings
+    }
+
+    /// 最近一次执行中每个 pass 的修改
+    pub fn reports(&self) -> &[(&'static str, Vec<String>)] {
+        &self.reports
+    }
+
+    pub fn run(&mut self, mut programs: Vec<Program>) -> Result<Vec<Program>, String> {
+        self.timings.clear();
+        self.reports.clear();
+        if self.passes.is_empty() {
+            return Ok(programs);
+        }
+        if self.verify {
+            verify(&programs).map_err(|error| format!("输入的 Mool IR 不合法：{}", error))?;
+        }
+        for pass in self.passes.iter_mut() {
+            let start = Instant::now();
+            programs = pass.run(programs);
+            self.timings.push((pass.name(), start.elapsed()));
+            self.reports.push((pass.name(), pass.report()));
+            if let Some(dump) = self.dump.as_mut() {
+                dump(pass.name(), &programs);
+            }
+            if self.verify {
+                verify(&programs)
+                    .map_err(|error| format!("{}之后 Mool IR 不合法：{}", pass.name(), error))?;
+            }
+        }
+        Ok(programs)
+    }
+}
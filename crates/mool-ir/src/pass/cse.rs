@@ -0,0 +1,417 @@
+use super::super::ast::{Expr, For, Function, If, Operator, Program, Variable, While};
+use super::dce::operator_name;
+use super::Pass;
+use std::collections::{HashMap, HashSet};
+
+/// 公共子表达式消除
+///
+/// 同一个语句块中结构相同、只依赖变量和常量的算子只计算一次：
+/// 第一次出现时提前绑定到新的变量，之后直接使用这个变量。
+/// 表达式依赖的变量被重新赋值之后，之前的结果不再可用。
+/// 嵌套的语句块（函数体、循环体和 if 分支）单独处理，不复用外层的结果。
+#[derive(Default)]
+pub struct CommonSubexpression {
+    /// 程序中已经使用的变量名，新变量不能与之重名
+    names: HashSet<String>,
+    counter: usize,
+    reused: Vec<String>,
+}
+
+impl Pass for CommonSubexpression {
+    fn name(&self) -> &'static str {
+        "cse"
+    }
+
+    fn run(&mut self, programs: Vec<Program>) -> Vec<Program> {
+        self.names.clear();
+        self.counter = 0;
+        self.reused.clear();
+        for program in programs.iter() {
+            collect_names_program(program, &mut self.names);
+        }
+        self.block(programs)
+    }
+
+    fn report(&self) -> Vec<String> {
+        self.reused.clone()
+    }
+}
+
+/// 可用的表达式
+struct Available {
+    /// 出现的序号，表达式失效后再次出现时序号不同
+    epoch: usize,
+    dependencies: HashSet<String>,
+    /// 已经绑定的变量
+    variable: Option<Variable>,
+}
+
+/// 在一个语句块中顺序遍历表达式，第一遍统计哪些表达式会被复用，第二遍改写
+struct Walker<'a> {
+    available: HashMap<String, Available>,
+    epoch: usize,
+    /// 第一遍的结果：会被复用的表达式及其序号
+    reused: &'a HashSet<(String, usize)>,
+    /// 第一遍中收集会被复用的表达式
+    hits: HashSet<(String, usize)>,
+    /// 当前语句之前需要插入的绑定
+    pending: Vec<Program>,
+}
+
+impl<'a> Walker<'a> {
+    fn new(reused: &'a HashSet<(String, usize)>) -> Self {
+        Walker {
+            available: HashMap::new(),
+            epoch: 0,
+            reused,
+            hits: HashSet::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// 变量被重新赋值，依赖它的表达式失效
+    fn invalidate(&mut self, name: &str) {
+        self.available
+            .retain(|_, available| !available.dependencies.contains(name));
+    }
+
+    fn expr(&mut self, expr: Expr, pass: &mut CommonSubexpression) -> Expr {
+        if !is_candidate(&expr) {
+            return expr;
+        }
+        let key = serde_json::to_string(&expr).unwrap();
+        if let Some(available) = self.available.get(&key) {
+            self.hits.insert((key, available.epoch));
+            return match (&available.variable, &expr) {
+                (Some(variable), Expr::Operator(operator)) => {
+                    pass.reused.push(format!(
+                        "复用%{}中的{}运算",
+                        variable.name,
+                        operator_name(operator)
+                    ));
+                    Expr::Variable(variable.clone())
+                }
+                _ => expr,
+            };
+        }
+        let (expr, epoch) = self.first(expr, pass);
+        let dependencies = variables(&expr);
+        let (expr, variable) = if self.reused.contains(&(key.clone(), epoch)) {
+            let variable = Variable {
+                name: pass.fresh_name(),
+                global: false,
+            };
+            self.pending.push(Program::Let(variable.clone(), expr));
+            (Expr::Variable(variable.clone()), Some(variable))
+        } else {
+            (expr, None)
+        };
+        self.available.insert(
+            key,
+            Available {
+                epoch,
+                dependencies,
+                variable,
+            },
+        );
+        expr
+    }
+
+    /// 表达式第一次出现，先处理操作数，再分配序号
+    fn first(&mut self, expr: Expr, pass: &mut CommonSubexpression) -> (Expr, usize) {
+        let expr = match expr {
+            Expr::Operator(operator) => Expr::Operator(self.operator(operator, pass)),
+            expr => expr,
+        };
+        self.epoch += 1;
+        (expr, self.epoch)
+    }
+
+    /// let 的右值第一次出现时直接复用 let 绑定的变量
+    fn bind(&mut self, variable: &Variable, expr: Expr, pass: &mut CommonSubexpression) -> Expr {
+        let key = serde_json::to_string(&expr).unwrap();
+        let (expr, epoch) = self.first(expr, pass);
+        let dependencies = variables(&expr);
+        self.invalidate(&variable.name);
+        if !dependencies.contains(&variable.name) {
+            self.available.insert(
+                key,
+                Available {
+                    epoch,
+                    dependencies,
+                    variable: Some(variable.clone()),
+                },
+            );
+        }
+        expr
+    }
+
+    fn operator(&mut self, operator: Operator, pass: &mut CommonSubexpression) -> Operator {
+        let mut walk = |x: Box<Expr>| Box::new(self.expr(*x, pass));
+        match operator {
+            Operator::Add(x, y) => Operator::Add(walk(x), walk(y)),
+            Operator::Sub(x, y) => Operator::Sub(walk(x), walk(y)),
+            Operator::Mul(x, y) => Operator::Mul(walk(x), walk(y)),
+            Operator::Div(x, y) => Operator::Div(walk(x), walk(y)),
+            Operator::Matmul(x, y) => Operator::Matmul(walk(x), walk(y)),
+            Operator::Lt(x, y) => Operator::Lt(walk(x), walk(y)),
+            Operator::Le(x, y) => Operator::Le(walk(x), walk(y)),
+            Operator::Gt(x, y) => Operator::Gt(walk(x), walk(y)),
+            Operator::Ge(x, y) => Operator::Ge(walk(x), walk(y)),
+            Operator::Eq(x, y) => Operator::Eq(walk(x), walk(y)),
+            Operator::Ne(x, y) => Operator::Ne(walk(x), walk(y)),
+            Operator::Relu(x) => Operator::Relu(walk(x)),
+            Operator::Fused(x) => Operator::Fused(walk(x)),
+            Operator::Workspace(x, offset, size) => Operator::Workspace(walk(x), offset, size),
+            Operator::Tensor(literals) => Operator::Tensor(literals),
+        }
+    }
+
+    /// 遍历语句块，nested 处理嵌套的语句块
+    fn programs(
+        &mut self,
+        programs: Vec<Program>,
+        pass: &mut CommonSubexpression,
+        nested: bool,
+    ) -> Vec<Program> {
+        let mut output = Vec::with_capacity(programs.len());
+        for program in programs {
+            let program = match program {
+                Program::Let(variable, expr)
+                    if is_candidate(&expr)
+                        && !self
+                            .available
+                            .contains_key(&serde_json::to_string(&expr).unwrap()) =>
+                {
+                    let expr = self.bind(&variable, expr, pass);
+                    Program::Let(variable, expr)
+                }
+                Program::Let(variable, expr) => {
+                    let expr = self.statement(expr, pass, nested);
+                    self.invalidate(&variable.name);
+                    Program::Let(variable, expr)
+                }
+                Program::LetTuple(variables, expr) => {
+                    let expr = self.statement(expr, pass, nested);
+                    for variable in variables.iter() {
+                        self.invalidate(&variable.name);
+                    }
+                    Program::LetTuple(variables, expr)
+                }
+                Program::Expr(Expr::Assign(variable, expr)) => {
+                    let expr = self.statement(*expr, pass, nested);
+                    self.invalidate(&variable.name);
+                    Program::Expr(Expr::Assign(variable, Box::new(expr)))
+                }
+                Program::Expr(expr) => Program::Expr(self.statement(expr, pass, nested)),
+            };
+            output.append(&mut self.pending);
+            output.push(program);
+        }
+        output
+    }
+
+    /// 语句的右值，包含赋值或控制流时不做消除
+    fn statement(&mut self, expr: Expr, pass: &mut CommonSubexpression, nested: bool) -> Expr {
+        if is_straight(&expr) {
+            return self.expr(expr, pass);
+        }
+        // 语句块中的 let 和赋值都可能修改外层的变量，函数体属于另一个作用域
+        let mut assigned = HashSet::new();
+        collect_names_expr(&expr, &mut assigned, false);
+        let expr = if nested { pass.nested(expr) } else { expr };
+        for name in assigned.iter() {
+            self.invalidate(name);
+        }
+        expr
+    }
+}
+
+impl CommonSubexpression {
+    fn fresh_name(&mut self) -> String {
+        loop {
+            let name = format!("cse{}", self.counter);
+            self.counter += 1;
+            if self.names.insert(name.clone()) {
+                return name;
+            }
+        }
+    }
+
+    fn block(&mut self, programs: Vec<Program>) -> Vec<Program> {
+        // 第一遍只统计，不处理嵌套的语句块
+        let empty = HashSet::new();
+        let mut counter = Walker::new(&empty);
+        let mut scratch = CommonSubexpression::default();
+        counter.programs(programs.clone(), &mut scratch, false);
+        let reused = counter.hits;
+        let mut walker = Walker::new(&reused);
+        walker.programs(programs, self, true)
+    }
+
+    /// 处理表达式中嵌套的语句块
+    fn nested(&mut self, expr: Expr) -> Expr {
+        match expr {
+            Expr::Function(function) => Expr::Function(Function {
+                args: function.args,
+                rtn: function.rtn,
+                body: self.block(function.body),
+                inline: function.inline,
+            }),
+            Expr::If(if_else) => Expr::If(If {
+                cond: if_else.cond,
+                then: self.block(if_else.then),
+                otherwise: self.block(if_else.otherwise),
+            }),
+            Expr::For(for_loop) => Expr::For(For {
+                var: for_loop.var,
+                start: for_loop.start,
+                end: for_loop.end,
+                step: for_loop.step,
+                body: self.block(for_loop.body),
+            }),
+            Expr::While(while_loop) => Expr::While(While {
+                cond: while_loop.cond,
+                body: self.block(while_loop.body),
+            }),
+            expr => expr,
+        }
+    }
+}
+
+/// 只由变量、常量和算子组成的算子表达式，常量张量本身不需要消除
+fn is_candidate(expr: &Expr) -> bool {
+    match expr {
+        Expr::Operator(Operator::Tensor(_)) => false,
+        Expr::Operator(_) => is_straight(expr),
+        _ => false,
+    }
+}
+
+/// 表达式中没有赋值、调用和控制流
+fn is_straight(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(_) | Expr::Variable(_) => true,
+        Expr::Operator(operator) => match operator {
+            Operator::Add(x, y)
+            | Operator::Sub(x, y)
+            | Operator::Mul(x, y)
+            | Operator::Div(x, y)
+            | Operator::Matmul(x, y)
+            | Operator::Lt(x, y)
+            | Operator::Le(x, y)
+            | Operator::Gt(x, y)
+            | Operator::Ge(x, y)
+            | Operator::Eq(x, y)
+            | Operator::Ne(x, y) => is_straight(x) && is_straight(y),
+            Operator::Relu(x) | Operator::Fused(x) | Operator::Workspace(x, _, _) => is_straight(x),
+            Operator::Tensor(_) => true,
+        },
+        _ => false,
+    }
+}
+
+/// 表达式依赖的变量
+fn variables(expr: &Expr) -> HashSet<String> {
+    let mut names = HashSet::new();
+    collect_names_expr(expr, &mut names, true);
+    names
+}
+
+/// 收集程序中出现的变量名
+fn collect_names_program(program: &Program, names: &mut HashSet<String>) {
+    match program {
+        Program::Let(variable, expr) => {
+            names.insert(variable.name.clone());
+            collect_names_expr(expr, names, true);
+        }
+        Program::LetTuple(variables, expr) => {
+            names.extend(variables.iter().map(|variable| variable.name.clone()));
+            collect_names_expr(expr, names, true);
+        }
+        Program::Expr(expr) => collect_names_expr(expr, names, true),
+    }
+}
+
+/// 收集变量名，reads 为 false 时只收集被赋值的变量名
+fn collect_names_expr(expr: &Expr, names: &mut HashSet<String>, reads: bool) {
+    let programs = |body: &[Program], names: &mut HashSet<String>| {
+        for program in body.iter() {
+            match program {
+                Program::Let(variable, expr) => {
+                    names.insert(variable.name.clone());
+                    collect_names_expr(expr, names, reads);
+                }
+                Program::LetTuple(variables, expr) => {
+                    names.extend(variables.iter().map(|variable| variable.name.clone()));
+                    collect_names_expr(expr, names, reads);
+                }
+                Program::Expr(expr) => collect_names_expr(expr, names, reads),
+            }
+        }
+    };
+    match expr {
+        Expr::Variable(variable) if reads => {
+            names.insert(variable.name.clone());
+        }
+        Expr::Assign(variable, expr) => {
+            names.insert(variable.name.clone());
+            collect_names_expr(expr, names, reads);
+        }
+        Expr::Function(function) if reads => {
+            names.extend(function.args.iter().map(|arg| arg.arg.name.clone()));
+            programs(&function.body, names);
+        }
+        Expr::Call(name, args) => {
+            if reads {
+                names.insert(name.clone());
+            }
+            for arg in args.iter() {
+                collect_names_expr(arg, names, reads);
+            }
+        }
+        Expr::Operator(operator) => match operator {
+            Operator::Add(x, y)
+            | Operator::Sub(x, y)
+            | Operator::Mul(x, y)
+            | Operator::Div(x, y)
+            | Operator::Matmul(x, y)
+            | Operator::Lt(x, y)
+            | Operator::Le(x, y)
+            | Operator::Gt(x, y)
+            | Operator::Ge(x, y)
+            | Operator::Eq(x, y)
+            | Operator::Ne(x, y) => {
+                collect_names_expr(x, names, reads);
+                collect_names_expr(y, names, reads);
+            }
+            Operator::Relu(x) | Operator::Fused(x) | Operator::Workspace(x, _, _) => {
+                collect_names_expr(x, names, reads)
+            }
+            Operator::Tensor(_) => {}
+        },
+        Expr::Tuple(items) => {
+            for item in items.iter() {
+                collect_names_expr(item, names, reads);
+            }
+        }
+        Expr::Field(tuple, _) => collect_names_expr(tuple, names, reads),
+        Expr::If(if_else) => {
+            collect_names_expr(&if_else.cond, names, reads);
+            programs(&if_else.then, names);
+            programs(&if_else.otherwise, names);
+        }
+        Expr::For(for_loop) => {
+            names.insert(for_loop.var.name.clone());
+            collect_names_expr(&for_loop.start, names, reads);
+            collect_names_expr(&for_loop.end, names, reads);
+            collect_names_expr(&for_loop.step, names, reads);
+            programs(&for_loop.body, names);
+        }
+        Expr::While(while_loop) => {
+            collect_names_expr(&while_loop.cond, names, reads);
+            programs(&while_loop.body, names);
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,291 @@
+use super::super::ast::{Expr, For, Function, If, Operator, Program, While};
+use super::Pass;
+use std::collections::{HashMap, HashSet};
+
+/// 死代码删除
+///
+/// 删除没有被使用的 let 绑定（包括没有被调用的函数）和结果被丢弃的运算。
+/// 顶层的函数从根出发按调用关系计算是否可达：默认的根是模块的入口，即顶层代码，
+/// 导出函数的后端（C 和 WebAssembly）用 with_roots 把导出的函数也作为根。
+/// 不可达的函数连同只被它们调用的函数一起删除，互相调用的函数也不例外。
+/// 只删除没有副作用的右值：字面量、变量、算子、元组和函数定义，函数调用和控制流总是保留。
+/// 函数体和 if 分支的最后一个语句是返回值，不会被删除。
+/// 删除之后可能有新的绑定不再被使用，因此重复执行直到没有变化。
+#[derive(Default)]
+pub struct DeadCode {
+    /// 除顶层代码以外总是保留的顶层函数
+    roots: Vec<String>,
+    removed: Vec<String>,
+}
+
+impl Pass for DeadCode {
+    fn name(&self) -> &'static str {
+        "dce"
+    }
+
+    fn run(&mut self, mut programs: Vec<Program>) -> Vec<Program> {
+        self.removed.clear();
+        loop {
+            let count = self.removed.len();
+            // 顶层语句的值不会被使用
+            let used = self.reachable(&programs);
+            programs = self.block(programs, false, &used);
+            if self.removed.len() == count {
+                return programs;
+            }
+        }
+    }
+
+    fn report(&self) -> Vec<String> {
+        self.removed.clone()
+    }
+}
+
+impl DeadCode {
+    /// 以顶层代码和 roots 中的函数为根，roots 通常是后端导出的函数
+    pub fn with_roots(roots: Vec<String>) -> Self {
+        DeadCode {
+            roots,
+            removed: Vec::new(),
+        }
+    }
+
+    /// 从根出发可达的名称：根、顶层代码使用的变量，以及可达的函数中使用的变量
+    fn reachable(&self, programs: &[Program]) -> HashSet<String> {
+        let mut functions = HashMap::new();
+        let mut pending = self.roots.clone();
+        for program in programs.iter() {
+            match program {
+                Program::Let(variable, Expr::Function(function)) => {
+                    functions.insert(variable.name.as_str(), function);
+                }
+                program => {
+                    let mut used = HashSet::new();
+                    collect_program(program, &mut used);
+                    pending.extend(used);
+                }
+            }
+        }
+        let mut reachable = HashSet::new();
+        while let Some(name) = pending.pop() {
+            if !reachable.insert(name.clone()) {
+                continue;
+            }
+            if let Some(function) = functions.get(name.as_str()) {
+                let mut used = HashSet::new();
+                for program in function.body.iter() {
+                    collect_program(program, &mut used);
+                }
+                pending.extend(used);
+            }
+        }
+        reachable
+    }
+
+    /// 处理一个函数体，变量的使用情况在整个函数中统计
+    fn frame(&mut self, programs: Vec<Program>) -> Vec<Program> {
+        let mut used = HashSet::new();
+        for program in programs.iter() {
+            collect_program(program, &mut used);
+        }
+        self.block(programs, true, &used)
+    }
+
+    fn block(
+        &mut self,
+        programs: Vec<Program>,
+        keep_last: bool,
+        used: &HashSet<String>,
+    ) -> Vec<Program> {
+        let last = programs.len().saturating_sub(1);
+        let mut output = Vec::with_capacity(programs.len());
+        for (i, program) in programs.into_iter().enumerate() {
+            if keep_last && i == last {
+                output.push(self.program(program, used));
+                continue;
+            }
+            match program {
+                Program::Let(variable, expr)
+                    if !used.contains(&variable.name) && is_pure(&expr) =>
+                {
+                    self.removed.push(match expr {
+                        Expr::Function(_) => format!("未使用的函数%{}", variable.name),
+                        _ => format!("未使用的变量%{}", variable.name),
+                    });
+                }
+                Program::Expr(expr) if is_pure(&expr) => {
+                    self.removed.push(match expr {
+                        Expr::Operator(operator) => {
+                            format!("未使用的{}运算", operator_name(&operator))
+                        }
+                        _ => "未使用的表达式".to_string(),
+                    });
+                }
+                program => output.push(self.program(program, used)),
+            }
+        }
+        output
+    }
+
+    fn program(&mut self, program: Program, used: &HashSet<String>) -> Program {
+        match program {
+            Program::Let(variable, expr) => Program::Let(variable, self.expr(expr, used)),
+            Program::LetTuple(variables, expr) => {
+                Program::LetTuple(variables, self.expr(expr, used))
+            }
+            Program::Expr(expr) => Program::Expr(self.expr(expr, used)),
+        }
+    }
+
+    /// 只有函数和控制流中包含语句块
+    fn expr(&mut self, expr: Expr, used: &HashSet<String>) -> Expr {
+        match expr {
+            Expr::Function(function) => Expr::Function(Function {
+                args: function.args,
+                rtn: function.rtn,
+                body: self.frame(function.body),
+                inline: function.inline,
+            }),
+            Expr::If(if_else) => Expr::If(If {
+                cond: if_else.cond,
+                then: self.block(if_else.then, true, used),
+                otherwise: self.block(if_else.otherwise, true, used),
+            }),
+            Expr::For(for_loop) => Expr::For(For {
+                var: for_loop.var,
+                start: for_loop.start,
+                end: for_loop.end,
+                step: for_loop.step,
+                body: self.block(for_loop.body, false, used),
+            }),
+            Expr::While(while_loop) => Expr::While(While {
+                cond: while_loop.cond,
+                body: self.block(while_loop.body, false, used),
+            }),
+            expr => expr,
+        }
+    }
+}
+
+fn is_pure(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(_) | Expr::Variable(_) | Expr::Function(_) => true,
+        Expr::Operator(operator) => match operator {
+            Operator::Add(x, y)
+            | Operator::Sub(x, y)
+            | Operator::Mul(x, y)
+            | Operator::Div(x, y)
+            | Operator::Matmul(x, y)
+            | Operator::Lt(x, y)
+            | Operator::Le(x, y)
+            | Operator::Gt(x, y)
+            | Operator::Ge(x, y)
+            | Operator::Eq(x, y)
+            | Operator::Ne(x, y) => is_pure(x) && is_pure(y),
+            Operator::Relu(x) | Operator::Fused(x) | Operator::Workspace(x, _, _) => is_pure(x),
+            Operator::Tensor(_) => true,
+        },
+        Expr::Tuple(items) => items.iter().all(is_pure),
+        Expr::Field(tuple, _) => is_pure(tuple),
+        _ => false,
+    }
+}
+
+pub(super) fn operator_name(operator: &Operator) -> &'static str {
+    match operator {
+        Operator::Add(_, _) => "Add",
+        Operator::Sub(_, _) => "Sub",
+        Operator::Mul(_, _) => "Mul",
+        Operator::Div(_, _) => "Div",
+        Operator::Matmul(_, _) => "Matmul",
+        Operator::Relu(_) => "Relu",
+        Operator::Lt(_, _) => "Lt",
+        Operator::Le(_, _) => "Le",
+        Operator::Gt(_, _) => "Gt",
+        Operator::Ge(_, _) => "Ge",
+        Operator::Eq(_, _) => "Eq",
+        Operator::Ne(_, _) => "Ne",
+        Operator::Tensor(_) => "Tensor",
+        Operator::Fused(_) => "Fused",
+        Operator::Workspace(_, _, _) => "Workspace",
+    }
+}
+
+/// 收集读取、赋值和调用的变量名，嵌套函数中的使用也计算在内
+fn collect_program(program: &Program, used: &mut HashSet<String>) {
+    match program {
+        Program::Let(_, expr) | Program::LetTuple(_, expr) | Program::Expr(expr) => {
+            collect_expr(expr, used)
+        }
+    }
+}
+
+fn collect_expr(expr: &Expr, used: &mut HashSet<String>) {
+    match expr {
+        Expr::Variable(variable) => {
+            used.insert(variable.name.clone());
+        }
+        Expr::Assign(variable, expr) => {
+            used.insert(variable.name.clone());
+            collect_expr(expr, used);
+        }
+        Expr::Literal(_) | Expr::Break | Expr::Continue => {}
+        Expr::Function(function) => {
+            for program in function.body.iter() {
+                collect_program(program, used);
+            }
+        }
+        Expr::Call(name, args) => {
+            used.insert(name.clone());
+            for arg in args.iter() {
+                collect_expr(arg, used);
+            }
+        }
+        Expr::Operator(operator) => match operator {
+            Operator::Add(x, y)
+            | Operator::Sub(x, y)
+            | Operator::Mul(x, y)
+            | Operator::Div(x, y)
+            | Operator::Matmul(x, y)
+            | Operator::Lt(x, y)
+            | Operator::Le(x, y)
+            | Operator::Gt(x, y)
+            | Operator::Ge(x, y)
+            | Operator::Eq(x, y)
+            | Operator::Ne(x, y) => {
+                collect_expr(x, used);
+                collect_expr(y, used);
+            }
+            Operator::Relu(x) | Operator::Fused(x) | Operator::Workspace(x, _, _) => {
+                collect_expr(x, used)
+            }
+            Operator::Tensor(_) => {}
+        },
+        Expr::Tuple(items) => {
+            for item in items.iter() {
+                collect_expr(item, used);
+            }
+        }
+        Expr::Field(tuple, _) => collect_expr(tuple, used),
+        Expr::If(if_else) => {
+            collect_expr(&if_else.cond, used);
+            for program in if_else.then.iter().chain(if_else.otherwise.iter()) {
+                collect_program(program, used);
+            }
+        }
+        Expr::For(for_loop) => {
+            collect_expr(&for_loop.start, used);
+            collect_expr(&for_loop.end, used);
+            collect_expr(&for_loop.step, used);
+            for program in for_loop.body.iter() {
+                collect_program(program, used);
+            }
+        }
+        Expr::While(while_loop) => {
+            collect_expr(&while_loop.cond, used);
+            for program in while_loop.body.iter() {
+                collect_program(program, used);
+            }
+        }
+    }
+}
@@ -0,0 +1,469 @@
+use super::super::ast::{Dim, Expr, For, Function, If, Literal, Operator, Program, Type, While};
+use super::Pass;
+use std::collections::HashMap;
+
+/// 常量折叠和代数化简
+///
+/// 算子的操作数都是字面量或常量张量时直接计算出结果，计算规则与 LLVM 代码生成保持一致：
+/// 整数运算溢出时回绕，整数除法为无符号除法，因此只折叠非负整数的除法。
+/// 另外化简 x * 1、x / 1、x + 0、x - 0 以及整数变量的 x - x，
+/// 其中 1 和 0 会广播，只有结果的类型与 x 相同时才化简。
+pub struct Fold;
+
+impl Pass for Fold {
+    fn name(&self) -> &'static str {
+        "fold"
+    }
+
+    fn run(&mut self, programs: Vec<Program>) -> Vec<Program> {
+        let mut folder = Folder {
+            scopes: vec![HashMap::new()],
+        };
+        folder.programs(programs)
+    }
+}
+
+struct Folder {
+    /// 每层作用域中变量的类型，无法确定类型的变量为 None
+    scopes: Vec<HashMap<String, Option<Type>>>,
+}
+
+impl Folder {
+    fn programs(&mut self, programs: Vec<Program>) -> Vec<Program> {
+        programs
+            .into_iter()
+            .map(|program| self.program(program))
+            .collect()
+    }
+
+    /// 在新的作用域中化简语句块
+    fn block(
+        &mut self,
+        programs: Vec<Program>,
+        variables: Vec<(String, Option<Type>)>,
+    ) -> Vec<Program> {
+        self.scopes.push(variables.into_iter().collect());
+        let programs = self.programs(programs);
+        self.scopes.pop();
+        programs
+    }
+
+    fn program(&mut self, program: Program) -> Program {
+        match program {
+            Program::Let(variable, expr) => {
+                let expr = self.expr(expr);
+                let ty = self.type_of(&expr);
+                self.define(&variable.name, ty);
+                Program::Let(variable, expr)
+            }
+            Program::LetTuple(variables, expr) => {
+                let expr = self.expr(expr);
+                for variable in variables.iter() {
+                    self.define(&variable.name, None);
+                }
+                Program::LetTuple(variables, expr)
+            }
+            Program::Expr(expr) => Program::Expr(self.expr(expr)),
+        }
+    }
+
+    fn define(&mut self, name: &str, ty: Option<Type>) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), ty);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Type> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .cloned()
+            .flatten()
+    }
+
+    fn type_of(&self, expr: &Expr) -> Option<Type> {
+        match expr {
+            Expr::Literal(literal) => Some(literal_type(literal)),
+            Expr::Operator(Operator::Tensor(literals)) => literals.first().map(|first| {
+                Type::Tensor(
+                    vec![Dim::Const(literals.len())],
+                    Box::new(literal_type(first)),
+                )
+            }),
+            Expr::Variable(variable) => self.lookup(&variable.name),
+            _ => None,
+        }
+    }
+
+    /// x 与单位元 identity 按元素计算的结果是否与 x 的类型相同：
+    /// 单位元为标量，或者 x 是张量并且单位元的长度为 1 或与 x 的形状相同
+    fn keeps_type(&self, x: &Expr, identity: &Expr) -> bool {
+        let size = match identity {
+            Expr::Literal(_) => return true,
+            Expr::Operator(Operator::Tensor(literals)) => literals.len(),
+            _ => return false,
+        };
+        match self.type_of(x) {
+            Some(Type::Tensor(shape, _)) => size == 1 || shape[..] == [Dim::Const(size)],
+            _ => false,
+        }
+    }
+
+    fn expr(&mut self, expr: Expr) -> Expr {
+        match expr {
+            Expr::Assign(variable, expr) => Expr::Assign(variable, Box::new(self.expr(*expr))),
+            Expr::Function(function) => {
+                let variables = function
+                    .args
+                    .iter()
+                    .map(|arg| (arg.arg.name.clone(), Some(arg.annotation.clone())))
+                    .collect();
+                let body = self.block(function.body, variables);
+                Expr::Function(Function {
+                    args: function.args,
+                    rtn: function.rtn,
+                    body,
+                    inline: function.inline,
+                })
+            }
+            Expr::Call(name, args) => {
+                Expr::Call(name, args.into_iter().map(|arg| self.expr(arg)).collect())
+            }
+            Expr::Operator(operator) => self.operator(operator),
+            Expr::Tuple(items) => {
+                Expr::Tuple(items.into_iter().map(|item| self.expr(item)).collect())
+            }
+            Expr::Field(tuple, index) => match self.expr(*tuple) {
+                // 元组字面量直接取出元素
+                Expr::Tuple(mut items) if index < items.len() && items.iter().all(is_constant) => {
+                    items.swap_remove(index)
+                }
+                tuple => Expr::Field(Box::new(tuple), index),
+            },
+            Expr::If(if_else) => {
+                let cond = self.expr(*if_else.cond);
+                let then = self.block(if_else.then, Vec::new());
+                let otherwise = self.block(if_else.otherwise, Vec::new());
+                Expr::If(If {
+                    cond: Box::new(cond),
+                    then,
+                    otherwise,
+                })
+            }
+            Expr::For(for_loop) => {
+                let start = self.expr(*for_loop.start);
+                let end = self.expr(*for_loop.end);
+                let step = self.expr(*for_loop.step);
+                let body = self.block(
+                    for_loop.body,
+                    vec![(for_loop.var.name.clone(), Some(Type::Int))],
+                );
+                Expr::For(For {
+                    var: for_loop.var,
+                    start: Box::new(start),
+                    end: Box::new(end),
+                    step: Box::new(step),
+                    body,
+                })
+            }
+            Expr::While(while_loop) => {
+                let cond = self.expr(*while_loop.cond);
+                let body = self.block(while_loop.body, Vec::new());
+                Expr::While(While {
+                    cond: Box::new(cond),
+                    body,
+                })
+            }
+            expr => expr,
+        }
+    }
+
+    fn operator(&mut self, operator: Operator) -> Expr {
+        match operator {
+            Operator::Add(x, y) => {
+                let (x, y) = (self.expr(*x), self.expr(*y));
+                if let Some(value) = elementwise(&x, &y, |x, y| arithmetic(Arithmetic::Add, x, y)) {
+                    return value;
+                }
+                // 浮点数 -0.0 + 0.0 = 0.0，只化简整数
+                match (is_all(&x, is_int_zero), is_all(&y, is_int_zero)) {
+                    (_, true) if self.keeps_type(&x, &y) => x,
+                    (true, _) if self.keeps_type(&y, &x) => y,
+                    _ => Expr::Operator(Operator::Add(Box::new(x), Box::new(y))),
+                }
+            }
+            Operator::Sub(x, y) => {
+                let (x, y) = (self.expr(*x), self.expr(*y));
+                if let Some(value) = elementwise(&x, &y, |x, y| arithmetic(Arithmetic::Sub, x, y)) {
+                    return value;
+                }
+                if is_all(&y, is_zero) && self.keeps_type(&x, &y) {
+                    return x;
+                }
+                // 同一个整数变量相减为 0
+                if let (Expr::Variable(a), Expr::Variable(b)) = (&x, &y) {
+                    if a.name == b.name {
+                        match self.lookup(&a.name) {
+                            Some(Type::Int) => return Expr::Literal(Literal::Int(0)),
+                            // 张量字面量是一维的，只折叠长度已知的一维张量
+                            Some(Type::Tensor(shape, element)) if *element == Type::Int => {
+                                if let [Dim::Const(size)] = shape[..] {
+                                    return Expr::Operator(Operator::Tensor(vec![
+                                        Literal::Int(0);
+                                        size
+                                    ]));
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Expr::Operator(Operator::Sub(Box::new(x), Box::new(y)))
+            }
+            Operator::Mul(x, y) => {
+                let (x, y) = (self.expr(*x), self.expr(*y));
+                if let Some(value) = elementwise(&x, &y, |x, y| arithmetic(Arithmetic::Mul, x, y)) {
+                    return value;
+                }
+                match (is_all(&x, is_one), is_all(&y, is_one)) {
+                    (_, true) if self.keeps_type(&x, &y) => x,
+                    (true, _) if self.keeps_type(&y, &x) => y,
+                    _ => Expr::Operator(Operator::Mul(Box::new(x), Box::new(y))),
+                }
+            }
+            Operator::Div(x, y) => {
+                let (x, y) = (self.expr(*x), self.expr(*y));
+                if let Some(value) = elementwise(&x, &y, |x, y| arithmetic(Arithmetic::Div, x, y)) {
+                    return value;
+                }
+                if is_all(&y, is_one) && self.keeps_type(&x, &y) {
+                    return x;
+                }
+                Expr::Operator(Operator::Div(Box::new(x), Box::new(y)))
+            }
+            Operator::Matmul(x, y) => {
+                let (x, y) = (self.expr(*x), self.expr(*y));
+                match dot(&x, &y) {
+                    Some(literal) => Expr::Literal(literal),
+                    None => Expr::Operator(Operator::Matmul(Box::new(x), Box::new(y))),
+                }
+            }
+            Operator::Relu(x) => {
+                let x = self.expr(*x);
+                let relu = |literal: &Literal| match literal {
+                    Literal::Int(n) => Some(Literal::Int((*n).max(0))),
+                    Literal::Float(f) => Some(Literal::Float(if *f > 0.0 { *f } else { 0.0 })),
+                    Literal::Bool(_) => None,
+                };
+                let value = match &x {
+                    Expr::Literal(literal) => relu(literal).map(Expr::Literal),
+                    Expr::Operator(Operator::Tensor(literals)) => literals
+                        .iter()
+                        .map(relu)
+                        .collect::<Option<Vec<Literal>>>()
+                        .map(|literals| Expr::Operator(Operator::Tensor(literals))),
+                    _ => None,
+                };
+                value.unwrap_or_else(|| Expr::Operator(Operator::Relu(Box::new(x))))
+            }
+            Operator::Lt(x, y) => self.compare(*x, *y, Compare::Lt),
+            Operator::Le(x, y) => self.compare(*x, *y, Compare::Le),
+            Operator::Gt(x, y) => self.compare(*x, *y, Compare::Gt),
+            Operator::Ge(x, y) => self.compare(*x, *y, Compare::Ge),
+            Operator::Eq(x, y) => self.compare(*x, *y, Compare::Eq),
+            Operator::Ne(x, y) => self.compare(*x, *y, Compare::Ne),
+            Operator::Tensor(literals) => Expr::Operator(Operator::Tensor(literals)),
+            // 融合的算子链化简之后仍是算子时保持融合
+            Operator::Fused(x) => match self.expr(*x) {
+                Expr::Operator(Operator::Tensor(literals)) => {
+                    Expr::Operator(Operator::Tensor(literals))
+                }
+                Expr::Operator(operator) => {
+                    Expr::Operator(Operator::Fused(Box::new(Expr::Operator(operator))))
+                }
+                x => x,
+            },
+            // 工作区中的算子化简之后不再是算子时去掉工作区
+            Operator::Workspace(x, offset, size) => match self.expr(*x) {
+                Expr::Operator(operator) => Expr::Operator(Operator::Workspace(
+                    Box::new(Expr::Operator(operator)),
+                    offset,
+                    size,
+                )),
+                x => x,
+            },
+        }
+    }
+
+    fn compare(&mut self, x: Expr, y: Expr, compare: Compare) -> Expr {
+        let (x, y) = (self.expr(x), self.expr(y));
+        if let Some(value) = elementwise(&x, &y, |x, y| compare.apply(x, y)) {
+            return value;
+        }
+        let (x, y) = (Box::new(x), Box::new(y));
+        Expr::Operator(match compare {
+            Compare::Lt => Operator::Lt(x, y),
+            Compare::Le => Operator::Le(x, y),
+            Compare::Gt => Operator::Gt(x, y),
+            Compare::Ge => Operator::Ge(x, y),
+            Compare::Eq => Operator::Eq(x, y),
+            Compare::Ne => Operator::Ne(x, y),
+        })
+    }
+}
+
+fn literal_type(literal: &Literal) -> Type {
+    match literal {
+        Literal::Int(_) => Type::Int,
+        Literal::Float(_) => Type::Float,
+        Literal::Bool(_) => Type::Bool,
+    }
+}
+
+fn is_constant(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(_) | Expr::Operator(Operator::Tensor(_)) => true,
+        Expr::Tuple(items) => items.iter().all(is_constant),
+        _ => false,
+    }
+}
+
+/// 表达式是字面量或非空的常量张量，并且每个元素都满足条件
+fn is_all(expr: &Expr, predicate: fn(&Literal) -> bool) -> bool {
+    match expr {
+        Expr::Literal(literal) => predicate(literal),
+        Expr::Operator(Operator::Tensor(literals)) => {
+            !literals.is_empty() && literals.iter().all(predicate)
+        }
+        _ => false,
+    }
+}
+
+fn is_int_zero(literal: &Literal) -> bool {
+    matches!(literal, Literal::Int(0))
+}
+
+fn is_zero(literal: &Literal) -> bool {
+    match literal {
+        Literal::Int(n) => *n == 0,
+        // x - 0.0 对 -0.0 也成立
+        Literal::Float(f) => *f == 0.0 && f.is_sign_positive(),
+        Literal::Bool(_) => false,
+    }
+}
+
+fn is_one(literal: &Literal) -> bool {
+    match literal {
+        Literal::Int(n) => *n == 1,
+        Literal::Float(f) => *f == 1.0,
+        Literal::Bool(_) => false,
+    }
+}
+
+/// 两个标量或者两个等长张量按元素计算，任一元素无法计算时不折叠
+fn elementwise(
+    x: &Expr,
+    y: &Expr,
+    f: impl Fn(&Literal, &Literal) -> Option<Literal>,
+) -> Option<Expr> {
+    match (x, y) {
+        (Expr::Literal(x), Expr::Literal(y)) => f(x, y).map(Expr::Literal),
+        (Expr::Operator(Operator::Tensor(x)), Expr::Operator(Operator::Tensor(y)))
+            if x.len() == y.len() =>
+        {
+            x.iter()
+                .zip(y.iter())
+                .map(|(x, y)| f(x, y))
+                .collect::<Option<Vec<Literal>>>()
+                .map(|literals| Expr::Operator(Operator::Tensor(literals)))
+        }
+        _ => None,
+    }
+}
+
+enum Arithmetic {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+fn arithmetic(arithmetic: Arithmetic, x: &Literal, y: &Literal) -> Option<Literal> {
+    match (x, y) {
+        (Literal::Int(x), Literal::Int(y)) => Some(Literal::Int(match arithmetic {
+            Arithmetic::Add => x.wrapping_add(*y),
+            Arithmetic::Sub => x.wrapping_sub(*y),
+            Arithmetic::Mul => x.wrapping_mul(*y),
+            // 与 udiv 一致的情况：被除数非负，除数为正
+            Arithmetic::Div if *x >= 0 && *y > 0 => x / y,
+            Arithmetic::Div => return None,
+        })),
+        (Literal::Float(x), Literal::Float(y)) => Some(Literal::Float(match arithmetic {
+            Arithmetic::Add => x + y,
+            Arithmetic::Sub => x - y,
+            Arithmetic::Mul => x * y,
+            Arithmetic::Div => x / y,
+        })),
+        _ => None,
+    }
+}
+
+/// 与 llvm.vector.reduce 一致，从 0 开始按顺序累加
+fn dot(x: &Expr, y: &Expr) -> Option<Literal> {
+    let multiply = |x: &Literal, y: &Literal| arithmetic(Arithmetic::Mul, x, y);
+    match (x, y) {
+        (Expr::Literal(x), Expr::Literal(y)) => multiply(x, y),
+        (Expr::Operator(Operator::Tensor(x)), Expr::Operator(Operator::Tensor(y)))
+            if x.len() == y.len() && !x.is_empty() =>
+        {
+            let zero = match x[0] {
+                Literal::Int(_) => Literal::Int(0),
+                Literal::Float(_) => Literal::Float(0.0),
+                Literal::Bool(_) => return None,
+            };
+            x.iter().zip(y.iter()).try_fold(zero, |sum, (x, y)| {
+                arithmetic(Arithmetic::Add, &sum, &multiply(x, y)?)
+            })
+        }
+        _ => None,
+    }
+}
+
+enum Compare {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Compare {
+    /// 整数为有符号比较，浮点数为有序比较，与 NaN 比较总是 false
+    fn apply(&self, x: &Literal, y: &Literal) -> Option<Literal> {
+        let ordering = match (x, y) {
+            (Literal::Int(x), Literal::Int(y)) => Some(x.cmp(y)),
+            (Literal::Float(x), Literal::Float(y)) => x.partial_cmp(y),
+            // bool 为 i1，有符号比较时 true 小于 false，只折叠相等比较
+            (Literal::Bool(x), Literal::Bool(y)) => match self {
+                Compare::Eq => return Some(Literal::Bool(x == y)),
+                Compare::Ne => return Some(Literal::Bool(x != y)),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        let result = match ordering {
+            Some(ordering) => match self {
+                Compare::Lt => ordering.is_lt(),
+                Compare::Le => ordering.is_le(),
+                Compare::Gt => ordering.is_gt(),
+                Compare::Ge => ordering.is_ge(),
+                Compare::Eq => ordering.is_eq(),
+                Compare::Ne => ordering.is_ne(),
+            },
+            None => false,
+        };
+        Some(Literal::Bool(result))
+    }
+}
@@ -0,0 +1,139 @@
+use super::super::ast::{Expr, For, Function, If, Operator, Program, While};
+use super::dce::operator_name;
+use super::Pass;
+
+/// 按元素计算的算子融合
+///
+/// 表达式中相连的按元素计算的算子（四则运算、Relu 和比较）组成一条链，
+/// 链中有两个以上的算子时整体包装成 Fused，代码生成时只生成一个循环逐个元素计算，
+/// 中间结果不再写回内存。链的叶子（变量、常量、函数调用、矩阵乘法等）作为融合算子的输入，
+/// 其中的算子链单独融合。
+#[derive(Default)]
+pub struct Fuse {
+    fused: Vec<String>,
+}
+
+impl Pass for Fuse {
+    fn name(&self) -> &'static str {
+        "fuse"
+    }
+
+    fn run(&mut self, programs: Vec<Program>) -> Vec<Program> {
+        self.fused.clear();
+        self.programs(programs)
+    }
+
+    fn report(&self) -> Vec<String> {
+        self.fused.clone()
+    }
+}
+
+impl Fuse {
+    fn programs(&mut self, programs: Vec<Program>) -> Vec<Program> {
+        programs
+            .into_iter()
+            .map(|program| match program {
+                Program::Let(variable, expr) => Program::Let(variable, self.expr(expr)),
+                Program::LetTuple(variables, expr) => Program::LetTuple(variables, self.expr(expr)),
+                Program::Expr(expr) => Program::Expr(self.expr(expr)),
+            })
+            .collect()
+    }
+
+    fn exprs(&mut self, exprs: Vec<Expr>) -> Vec<Expr> {
+        exprs.into_iter().map(|expr| self.expr(expr)).collect()
+    }
+
+    fn expr(&mut self, expr: Expr) -> Expr {
+        match expr {
+            Expr::Operator(operator) if is_elementwise(&operator) => {
+                let mut names = Vec::new();
+                let chain = self.chain(Expr::Operator(operator), &mut names);
+                if names.len() < 2 {
+                    return chain;
+                }
+                self.fused.push(format!("融合{}运算", names.join("、")));
+                Expr::Operator(Operator::Fused(Box::new(chain)))
+            }
+            Expr::Operator(Operator::Matmul(x, y)) => Expr::Operator(Operator::Matmul(
+                Box::new(self.expr(*x)),
+                Box::new(self.expr(*y)),
+            )),
+            Expr::Operator(Operator::Workspace(x, offset, size)) => {
+                Expr::Operator(Operator::Workspace(Box::new(self.expr(*x)), offset, size))
+            }
+            Expr::Assign(variable, expr) => Expr::Assign(variable, Box::new(self.expr(*expr))),
+            Expr::Function(function) => Expr::Function(Function {
+                args: function.args,
+                rtn: function.rtn,
+                body: self.programs(function.body),
+                inline: function.inline,
+            }),
+            Expr::Call(name, args) => Expr::Call(name, self.exprs(args)),
+            Expr::Tuple(items) => Expr::Tuple(self.exprs(items)),
+            Expr::Field(tuple, index) => Expr::Field(Box::new(self.expr(*tuple)), index),
+            Expr::If(if_else) => Expr::If(If {
+                cond: Box::new(self.expr(*if_else.cond)),
+                then: self.programs(if_else.then),
+                otherwise: self.programs(if_else.otherwise),
+            }),
+            Expr::For(for_loop) => Expr::For(For {
+                var: for_loop.var,
+                start: Box::new(self.expr(*for_loop.start)),
+                end: Box::new(self.expr(*for_loop.end)),
+                step: Box::new(self.expr(*for_loop.step)),
+                body: self.programs(for_loop.body),
+            }),
+            Expr::While(while_loop) => Expr::While(While {
+                cond: Box::new(self.expr(*while_loop.cond)),
+                body: self.programs(while_loop.body),
+            }),
+            expr => expr,
+        }
+    }
+
+    /// 收集算子链，names 按计算顺序记录链中的算子
+    fn chain(&mut self, expr: Expr, names: &mut Vec<&'static str>) -> Expr {
+        let operator = match expr {
+            Expr::Operator(operator) if is_elementwise(&operator) => operator,
+            expr => return self.expr(expr),
+        };
+        let name = operator_name(&operator);
+        let mut link =
+            |x: Box<Expr>, names: &mut Vec<&'static str>| Box::new(self.chain(*x, names));
+        let operator = match operator {
+            Operator::Add(x, y) => Operator::Add(link(x, names), link(y, names)),
+            Operator::Sub(x, y) => Operator::Sub(link(x, names), link(y, names)),
+            Operator::Mul(x, y) => Operator::Mul(link(x, names), link(y, names)),
+            Operator::Div(x, y) => Operator::Div(link(x, names), link(y, names)),
+            Operator::Lt(x, y) => Operator::Lt(link(x, names), link(y, names)),
+            Operator::Le(x, y) => Operator::Le(link(x, names), link(y, names)),
+            Operator::Gt(x, y) => Operator::Gt(link(x, names), link(y, names)),
+            Operator::Ge(x, y) => Operator::Ge(link(x, names), link(y, names)),
+            Operator::Eq(x, y) => Operator::Eq(link(x, names), link(y, names)),
+            Operator::Ne(x, y) => Operator::Ne(link(x, names), link(y, names)),
+            Operator::Relu(x) => Operator::Relu(link(x, names)),
+            operator => operator,
+        };
+        names.push(name);
+        Expr::Operator(operator)
+    }
+}
+
+/// 按元素计算的算子，可以融合
+pub fn is_elementwise(operator: &Operator) -> bool {
+    matches!(
+        operator,
+        Operator::Add(_, _)
+            | Operator::Sub(_, _)
+            | Operator::Mul(_, _)
+            | Operator::Div(_, _)
+            | Operator::Relu(_)
+            | Operator::Lt(_, _)
+            | Operator::Le(_, _)
+            | Operator::Gt(_, _)
+            | Operator::Ge(_, _)
+            | Operator::Eq(_, _)
+            | Operator::Ne(_, _)
+    )
+}
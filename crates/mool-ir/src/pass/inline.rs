@@ -0,0 +1,583 @@
+use super::super::ast::{Expr, For, Function, If, Inline, Operator, Program, Variable, While};
+use super::Pass;
+use std::collections::{HashMap, HashSet};
+
+/// 不超过这个大小的函数自动内联，大小为函数体中语句和表达式的个数
+pub const INLINE_THRESHOLD: usize = 16;
+
+/// 函数内联
+///
+/// 把对小函数的调用替换为函数体：实参先绑定到新的变量，函数体中除最后一个语句外的语句
+/// 插入到调用所在的语句之前，最后一个表达式替换调用本身。函数体中的变量都换成新的名字。
+/// 没有被赋值的形参对应的实参是变量或常量时直接代入，方便之后的常量折叠和算子融合。
+///
+/// #[inline] 的函数总是内联，#[noinline] 的函数从不内联，其余函数按大小决定。
+/// 只内联名字唯一的函数，函数体中定义了函数或者最后一个语句不是表达式的函数不内联。
+/// 调用的求值顺序不能改变时（例如在 while 的条件中，或者语句中有赋值）保留调用。
+pub struct Inliner {
+    threshold: usize,
+    /// 可以内联的函数，已经内联了函数体中的调用
+    functions: HashMap<String, Function>,
+    /// 只定义了一次的函数名
+    unique: HashSet<String>,
+    /// 程序中已经使用的变量名，新变量不能与之重名
+    names: HashSet<String>,
+    inlined: Vec<String>,
+}
+
+impl Default for Inliner {
+    fn default() -> Self {
+        Self::new(INLINE_THRESHOLD)
+    }
+}
+
+impl Pass for Inliner {
+    fn name(&self) -> &'static str {
+        "inline"
+    }
+
+    fn run(&mut self, programs: Vec<Program>) -> Vec<Program> {
+        self.functions.clear();
+        self.inlined.clear();
+        self.names.clear();
+        let mut bindings = HashMap::new();
+        for program in programs.iter() {
+            collect_program(program, &mut self.names, &mut bindings);
+        }
+        self.unique = bindings
+            .into_iter()
+            .filter(|(_, count)| *count == 1)
+            .map(|(name, _)| name)
+            .collect();
+        self.block(programs)
+    }
+
+    fn report(&self) -> Vec<String> {
+        self.inlined.clone()
+    }
+}
+
+impl Inliner {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            functions: HashMap::new(),
+            unique: HashSet::new(),
+            names: HashSet::new(),
+            inlined: Vec::new(),
+        }
+    }
+
+    fn block(&mut self, programs: Vec<Program>) -> Vec<Program> {
+        let mut output = Vec::with_capacity(programs.len());
+        for program in programs {
+            let program = match program {
+                Program::Let(variable, Expr::Function(function)) => {
+                    let function = Function {
+                        args: function.args,
+                        rtn: function.rtn,
+                        body: self.block(function.body),
+                        inline: function.inline,
+                    };
+                    if self.unique.contains(&variable.name) && self.is_inlinable(&function) {
+                        self.functions
+                            .insert(variable.name.clone(), function.clone());
+                    }
+                    Program::Let(variable, Expr::Function(function))
+                }
+                Program::Let(variable, expr) => {
+                    Program::Let(variable, self.statement(expr, &mut output))
+                }
+                Program::LetTuple(variables, expr) => {
+                    Program::LetTuple(variables, self.statement(expr, &mut output))
+                }
+                Program::Expr(Expr::Assign(variable, expr)) => {
+                    let expr = self.statement(*expr, &mut output);
+                    Program::Expr(Expr::Assign(variable, Box::new(expr)))
+                }
+                Program::Expr(expr) => Program::Expr(self.statement(expr, &mut output)),
+            };
+            output.push(program);
+        }
+        output
+    }
+
+    fn is_inlinable(&self, function: &Function) -> bool {
+        let shape = matches!(function.body.last(), Some(Program::Expr(_)))
+            && !function.body.iter().any(defines_function);
+        shape
+            && match function.inline {
+                Inline::Always => true,
+                Inline::Never => false,
+                Inline::Auto => {
+                    function.body.iter().map(size_program).sum::<usize>() <= self.threshold
+                }
+            }
+    }
+
+    /// 语句的右值，内联之后插入的语句放到 output 中
+    fn statement(&mut self, expr: Expr, output: &mut Vec<Program>) -> Expr {
+        let hoist = !has_assign(&expr);
+        self.expr(expr, output, hoist)
+    }
+
+    /// hoist 表示表达式中的调用可以提前到语句之前计算
+    fn expr(&mut self, expr: Expr, output: &mut Vec<Program>, hoist: bool) -> Expr {
+        match expr {
+            Expr::Call(name, args) => {
+                let args: Vec<Expr> = args
+                    .into_iter()
+                    .map(|arg| self.expr(arg, output, hoist))
+                    .collect();
+                match self.functions.get(&name) {
+                    Some(function) if hoist && function.args.len() == args.len() => {
+                        let function = function.clone();
+                        self.inlined.push(format!("内联%{}", name));
+                        self.inline(&name, function, args, output)
+                    }
+                    _ => Expr::Call(name, args),
+                }
+            }
+            Expr::Assign(variable, expr) => {
+                Expr::Assign(variable, Box::new(self.expr(*expr, output, hoist)))
+            }
+            Expr::Operator(operator) => Expr::Operator(self.operator(operator, output, hoist)),
+            Expr::Tuple(items) => Expr::Tuple(
+                items
+                    .into_iter()
+                    .map(|item| self.expr(item, output, hoist))
+                    .collect(),
+            ),
+            Expr::Field(tuple, index) => {
+                Expr::Field(Box::new(self.expr(*tuple, output, hoist)), index)
+            }
+            Expr::Function(function) => Expr::Function(Function {
+                args: function.args,
+                rtn: function.rtn,
+                body: self.block(function.body),
+                inline: function.inline,
+            }),
+            Expr::If(if_else) => Expr::If(If {
+                cond: Box::new(self.expr(*if_else.cond, output, hoist)),
+                then: self.block(if_else.then),
+                otherwise: self.block(if_else.otherwise),
+            }),
+            Expr::For(for_loop) => Expr::For(For {
+                var: for_loop.var,
+                start: Box::new(self.expr(*for_loop.start, output, hoist)),
+                end: Box::new(self.expr(*for_loop.end, output, hoist)),
+                step: Box::new(self.expr(*for_loop.step, output, hoist)),
+                body: self.block(for_loop.body),
+            }),
+            // 循环条件每次迭代都要重新计算，不能提前
+            Expr::While(while_loop) => Expr::While(While {
+                cond: Box::new(self.expr(*while_loop.cond, output, false)),
+                body: self.block(while_loop.body),
+            }),
+            expr => expr,
+        }
+    }
+
+    fn operator(&mut self, operator: Operator, output: &mut Vec<Program>, hoist: bool) -> Operator {
+        let mut walk = |x: Box<Expr>| Box::new(self.expr(*x, output, hoist));
+        match operator {
+            Operator::Add(x, y) => Operator::Add(walk(x), walk(y)),
+            Operator::Sub(x, y) => Operator::Sub(walk(x), walk(y)),
+            Operator::Mul(x, y) => Operator::Mul(walk(x), walk(y)),
+            Operator::Div(x, y) => Operator::Div(walk(x), walk(y)),
+            Operator::Matmul(x, y) => Operator::Matmul(walk(x), walk(y)),
+            Operator::Lt(x, y) => Operator::Lt(walk(x), walk(y)),
+            Operator::Le(x, y) => Operator::Le(walk(x), walk(y)),
+            Operator::Gt(x, y) => Operator::Gt(walk(x), walk(y)),
+            Operator::Ge(x, y) => Operator::Ge(walk(x), walk(y)),
+            Operator::Eq(x, y) => Operator::Eq(walk(x), walk(y)),
+            Operator::Ne(x, y) => Operator::Ne(walk(x), walk(y)),
+            Operator::Relu(x) => Operator::Relu(walk(x)),
+            Operator::Fused(x) => Operator::Fused(walk(x)),
+            Operator::Workspace(x, offset, size) => Operator::Workspace(walk(x), offset, size),
+            Operator::Tensor(literals) => Operator::Tensor(literals),
+        }
+    }
+
+    /// 展开一次调用，返回替换调用的表达式
+    fn inline(
+        &mut self,
+        callee: &str,
+        function: Function,
+        args: Vec<Expr>,
+        output: &mut Vec<Program>,
+    ) -> Expr {
+        let mut assigned = HashSet::new();
+        let mut locals = HashMap::new();
+        for program in function.body.iter() {
+            collect_program(program, &mut HashSet::new(), &mut locals);
+            assigned_program(program, &mut assigned);
+        }
+        let mut renames: HashMap<String, Expr> = HashMap::new();
+        for (arg, value) in function.args.iter().zip(args) {
+            let name = &arg.arg.name;
+            let simple = matches!(
+                value,
+                Expr::Literal(_) | Expr::Variable(_) | Expr::Operator(Operator::Tensor(_))
+            );
+            if simple && !assigned.contains(name) {
+                renames.insert(name.clone(), value);
+            } else {
+                let variable = self.fresh_variable(callee, name);
+                output.push(Program::Let(variable.clone(), value));
+                renames.insert(name.clone(), Expr::Variable(variable));
+            }
+        }
+        // 函数体中定义的变量，外层的函数名保持不变
+        for name in locals.keys() {
+            if !renames.contains_key(name) && !function.args.iter().any(|arg| &arg.arg.name == name)
+            {
+                let variable = self.fresh_variable(callee, name);
+                renames.insert(name.clone(), Expr::Variable(variable));
+            }
+        }
+        let mut body = function.body;
+        let last = match body.pop() {
+            Some(Program::Expr(expr)) => expr,
+            _ => unreachable!(),
+        };
+        for program in body {
+            output.push(rename_program(program, &renames));
+        }
+        rename_expr(last, &renames)
+    }
+
+    fn fresh_variable(&mut self, callee: &str, name: &str) -> Variable {
+        let base = format!("{}_{}", callee, name);
+        let mut candidate = base.clone();
+        let mut counter = 0;
+        while !self.names.insert(candidate.clone()) {
+            counter += 1;
+            candidate = format!("{}{}", base, counter);
+        }
+        Variable {
+            name: candidate,
+            global: false,
+        }
+    }
+}
+
+/// 收集程序中出现的变量名，并统计每个名字被绑定的次数
+fn collect_program(
+    program: &Program,
+    names: &mut HashSet<String>,
+    bindings: &mut HashMap<String, usize>,
+) {
+    match program {
+        Program::Let(variable, expr) => {
+            bind(&variable.name, names, bindings);
+            collect_expr(expr, names, bindings);
+        }
+        Program::LetTuple(variables, expr) => {
+            for variable in variables.iter() {
+                bind(&variable.name, names, bindings);
+            }
+            collect_expr(expr, names, bindings);
+        }
+        Program::Expr(expr) => collect_expr(expr, names, bindings),
+    }
+}
+
+fn collect_programs(
+    programs: &[Program],
+    names: &mut HashSet<String>,
+    bindings: &mut HashMap<String, usize>,
+) {
+    for program in programs.iter() {
+        collect_program(program, names, bindings);
+    }
+}
+
+fn bind(name: &str, names: &mut HashSet<String>, bindings: &mut HashMap<String, usize>) {
+    names.insert(name.to_string());
+    *bindings.entry(name.to_string()).or_insert(0) += 1;
+}
+
+fn collect_expr(expr: &Expr, names: &mut HashSet<String>, bindings: &mut HashMap<String, usize>) {
+    match expr {
+        Expr::Variable(variable) => {
+            names.insert(variable.name.clone());
+        }
+        Expr::Assign(variable, expr) => {
+            names.insert(variable.name.clone());
+            collect_expr(expr, names, bindings);
+        }
+        Expr::Function(function) => {
+            for arg in function.args.iter() {
+                bind(&arg.arg.name, names, bindings);
+            }
+            collect_programs(&function.body, names, bindings);
+        }
+        Expr::Call(name, args) => {
+            names.insert(name.clone());
+            for arg in args.iter() {
+                collect_expr(arg, names, bindings);
+            }
+        }
+        Expr::Operator(operator) => {
+            for operand in operands(operator) {
+                collect_expr(operand, names, bindings);
+            }
+        }
+        Expr::Tuple(items) => {
+            for item in items.iter() {
+                collect_expr(item, names, bindings);
+            }
+        }
+        Expr::Field(tuple, _) => collect_expr(tuple, names, bindings),
+        Expr::If(if_else) => {
+            collect_expr(&if_else.cond, names, bindings);
+            collect_programs(&if_else.then, names, bindings);
+            collect_programs(&if_else.otherwise, names, bindings);
+        }
+        Expr::For(for_loop) => {
+            bind(&for_loop.var.name, names, bindings);
+            collect_expr(&for_loop.start, names, bindings);
+            collect_expr(&for_loop.end, names, bindings);
+            collect_expr(&for_loop.step, names, bindings);
+            collect_programs(&for_loop.body, names, bindings);
+        }
+        Expr::While(while_loop) => {
+            collect_expr(&while_loop.cond, names, bindings);
+            collect_programs(&while_loop.body, names, bindings);
+        }
+        Expr::Literal(_) | Expr::Break | Expr::Continue => {}
+    }
+}
+
+pub(super) fn operands(operator: &Operator) -> Vec<&Expr> {
+    match operator {
+        Operator::Add(x, y)
+        | Operator::Sub(x, y)
+        | Operator::Mul(x, y)
+        | Operator::Div(x, y)
+        | Operator::Matmul(x, y)
+        | Operator::Lt(x, y)
+        | Operator::Le(x, y)
+        | Operator::Gt(x, y)
+        | Operator::Ge(x, y)
+        | Operator::Eq(x, y)
+        | Operator::Ne(x, y) => vec![x, y],
+        Operator::Relu(x) | Operator::Fused(x) | Operator::Workspace(x, _, _) => vec![x],
+        Operator::Tensor(_) => Vec::new(),
+    }
+}
+
+/// 收集函数体中被赋值的变量
+fn assigned_program(program: &Program, assigned: &mut HashSet<String>) {
+    match program {
+        Program::Let(_, expr) | Program::LetTuple(_, expr) | Program::Expr(expr) => {
+            assigned_expr(expr, assigned)
+        }
+    }
+}
+
+fn assigned_expr(expr: &Expr, assigned: &mut HashSet<String>) {
+    match expr {
+        Expr::Assign(variable, expr) => {
+            assigned.insert(variable.name.clone());
+            assigned_expr(expr, assigned);
+        }
+        Expr::Call(_, items) | Expr::Tuple(items) => {
+            for item in items.iter() {
+                assigned_expr(item, assigned);
+            }
+        }
+        Expr::Operator(operator) => {
+            for operand in operands(operator) {
+                assigned_expr(operand, assigned);
+            }
+        }
+        Expr::Field(tuple, _) => assigned_expr(tuple, assigned),
+        Expr::If(if_else) => {
+            assigned_expr(&if_else.cond, assigned);
+            for program in if_else.then.iter().chain(if_else.otherwise.iter()) {
+                assigned_program(program, assigned);
+            }
+        }
+        Expr::For(for_loop) => {
+            assigned_expr(&for_loop.start, assigned);
+            assigned_expr(&for_loop.end, assigned);
+            assigned_expr(&for_loop.step, assigned);
+            for program in for_loop.body.iter() {
+                assigned_program(program, assigned);
+            }
+        }
+        Expr::While(while_loop) => {
+            assigned_expr(&while_loop.cond, assigned);
+            for program in while_loop.body.iter() {
+                assigned_program(program, assigned);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// 语句中（不包括嵌套的语句块）是否有赋值
+fn has_assign(expr: &Expr) -> bool {
+    match expr {
+        Expr::Assign(_, _) => true,
+        Expr::Call(_, items) | Expr::Tuple(items) => items.iter().any(has_assign),
+        Expr::Operator(operator) => operands(operator).into_iter().any(has_assign),
+        Expr::Field(tuple, _) => has_assign(tuple),
+        Expr::If(if_else) => has_assign(&if_else.cond),
+        Expr::For(for_loop) => {
+            has_assign(&for_loop.start) || has_assign(&for_loop.end) || has_assign(&for_loop.step)
+        }
+        _ => false,
+    }
+}
+
+fn defines_function(program: &Program) -> bool {
+    fn defines(expr: &Expr) -> bool {
+        match expr {
+            Expr::Function(_) => true,
+            Expr::Assign(_, x) | Expr::Field(x, _) => defines(x),
+            Expr::Call(_, items) | Expr::Tuple(items) => items.iter().any(defines),
+            Expr::Operator(operator) => operands(operator).into_iter().any(defines),
+            Expr::If(if_else) => {
+                defines(&if_else.cond)
+                    || if_else.then.iter().any(defines_function)
+                    || if_else.otherwise.iter().any(defines_function)
+            }
+            Expr::For(for_loop) => {
+                defines(&for_loop.start)
+                    || defines(&for_loop.end)
+                    || defines(&for_loop.step)
+                    || for_loop.body.iter().any(defines_function)
+            }
+            Expr::While(while_loop) => {
+                defines(&while_loop.cond) || while_loop.body.iter().any(defines_function)
+            }
+            _ => false,
+        }
+    }
+    match program {
+        Program::Let(_, x) | Program::LetTuple(_, x) | Program::Expr(x) => defines(x),
+    }
+}
+
+/// 函数的大小：语句和表达式的个数
+fn size_program(program: &Program) -> usize {
+    match program {
+        Program::Let(_, expr) | Program::LetTuple(_, expr) | Program::Expr(expr) => {
+            1 + size_expr(expr)
+        }
+    }
+}
+
+fn size_expr(expr: &Expr) -> usize {
+    let programs = |body: &[Program]| body.iter().map(size_program).sum::<usize>();
+    1 + match expr {
+        Expr::Assign(_, x) | Expr::Field(x, _) => size_expr(x),
+        Expr::Call(_, items) | Expr::Tuple(items) => items.iter().map(size_expr).sum(),
+        Expr::Operator(operator) => operands(operator).into_iter().map(size_expr).sum(),
+        Expr::Function(function) => programs(&function.body),
+        Expr::If(if_else) => {
+            size_expr(&if_else.cond) + programs(&if_else.then) + programs(&if_else.otherwise)
+        }
+        Expr::For(for_loop) => {
+            size_expr(&for_loop.start)
+                + size_expr(&for_loop.end)
+                + size_expr(&for_loop.step)
+                + programs(&for_loop.body)
+        }
+        Expr::While(while_loop) => size_expr(&while_loop.cond) + programs(&while_loop.body),
+        _ => 0,
+    }
+}
+
+/// 把函数体中的变量换成新的名字或者代入的实参
+fn rename_variable(variable: Variable, renames: &HashMap<String, Expr>) -> Variable {
+    match renames.get(&variable.name) {
+        Some(Expr::Variable(renamed)) => renamed.clone(),
+        _ => variable,
+    }
+}
+
+fn rename_programs(programs: Vec<Program>, renames: &HashMap<String, Expr>) -> Vec<Program> {
+    programs
+        .into_iter()
+        .map(|program| rename_program(program, renames))
+        .collect()
+}
+
+fn rename_program(program: Program, renames: &HashMap<String, Expr>) -> Program {
+    match program {
+        Program::Let(variable, expr) => Program::Let(
+            rename_variable(variable, renames),
+            rename_expr(expr, renames),
+        ),
+        Program::LetTuple(variables, expr) => Program::LetTuple(
+            variables
+                .into_iter()
+                .map(|variable| rename_variable(variable, renames))
+                .collect(),
+            rename_expr(expr, renames),
+        ),
+        Program::Expr(expr) => Program::Expr(rename_expr(expr, renames)),
+    }
+}
+
+fn rename_expr(expr: Expr, renames: &HashMap<String, Expr>) -> Expr {
+    let rename = |x: Box<Expr>| Box::new(rename_expr(*x, renames));
+    match expr {
+        Expr::Variable(variable) => match renames.get(&variable.name) {
+            Some(value) => value.clone(),
+            None => Expr::Variable(variable),
+        },
+        Expr::Assign(variable, x) => Expr::Assign(rename_variable(variable, renames), rename(x)),
+        Expr::Call(name, args) => Expr::Call(
+            name,
+            args.into_iter()
+                .map(|arg| rename_expr(arg, renames))
+                .collect(),
+        ),
+        Expr::Operator(operator) => Expr::Operator(match operator {
+            Operator::Add(x, y) => Operator::Add(rename(x), rename(y)),
+            Operator::Sub(x, y) => Operator::Sub(rename(x), rename(y)),
+            Operator::Mul(x, y) => Operator::Mul(rename(x), rename(y)),
+            Operator::Div(x, y) => Operator::Div(rename(x), rename(y)),
+            Operator::Matmul(x, y) => Operator::Matmul(rename(x), rename(y)),
+            Operator::Lt(x, y) => Operator::Lt(rename(x), rename(y)),
+            Operator::Le(x, y) => Operator::Le(rename(x), rename(y)),
+            Operator::Gt(x, y) => Operator::Gt(rename(x), rename(y)),
+            Operator::Ge(x, y) => Operator::Ge(rename(x), rename(y)),
+            Operator::Eq(x, y) => Operator::Eq(rename(x), rename(y)),
+            Operator::Ne(x, y) => Operator::Ne(rename(x), rename(y)),
+            Operator::Relu(x) => Operator::Relu(rename(x)),
+            Operator::Fused(x) => Operator::Fused(rename(x)),
+            Operator::Workspace(x, offset, size) => Operator::Workspace(rename(x), offset, size),
+            Operator::Tensor(literals) => Operator::Tensor(literals),
+        }),
+        Expr::Tuple(items) => Expr::Tuple(
+            items
+                .into_iter()
+                .map(|item| rename_expr(item, renames))
+                .collect(),
+        ),
+        Expr::Field(tuple, index) => Expr::Field(rename(tuple), index),
+        Expr::If(if_else) => Expr::If(If {
+            cond: rename(if_else.cond),
+            then: rename_programs(if_else.then, renames),
+            otherwise: rename_programs(if_else.otherwise, renames),
+        }),
+        Expr::For(for_loop) => Expr::For(For {
+            var: rename_variable(for_loop.var, renames),
+            start: rename(for_loop.start),
+            end: rename(for_loop.end),
+            step: rename(for_loop.step),
+            body: rename_programs(for_loop.body, renames),
+        }),
+        Expr::While(while_loop) => Expr::While(While {
+            cond: rename(while_loop.cond),
+            body: rename_programs(while_loop.body, renames),
+        }),
+        expr => expr,
+    }
+}
@@ -0,0 +1,654 @@
+use super::super::ast::{numel, Dim, Expr, For, Function, If, Operator, Program, Type, While};
+use super::dce::operator_name;
+use super::fuse::is_elementwise;
+use super::inline::operands;
+use super::typecheck::{elementwise_type, literal_type};
+use super::Pass;
+use std::collections::HashMap;
+
+/// 工作区中的数据按 8 字节对齐，与运行时一致
+const ALIGN: usize = 8;
+
+/// 中间张量的内存规划
+///
+/// 按计算顺序给算子编号，求出形状固定的中间张量从算出到最后一次使用的区间，
+/// 区间不相交的中间张量复用同一块缓冲区。每个函数的缓冲区依次排在同一块工作区中，
+/// 规划过的算子包装成 Workspace，代码生成时结果的数据直接放在工作区中，不再单独分配。
+///
+/// 只规划直接作为算子输入的结果，以及函数体顶层只作为算子输入的 let 变量；
+/// 返回值、函数调用的参数和元组中的值在算子之外仍然使用，不参与规划。
+/// 循环中使用的 let 变量存活到最外层的循环结束。规划之后不能再内联，需要作为最后一个 pass 执行。
+#[derive(Default)]
+pub struct MemoryPlan {
+    /// 工作区的大小，即每个函数的峰值内存之和
+    workspace: usize,
+    planned: Vec<String>,
+}
+
+impl Pass for MemoryPlan {
+    fn name(&self) -> &'static str {
+        "plan"
+    }
+
+    fn run(&mut self, programs: Vec<Program>) -> Vec<Program> {
+        self.workspace = 0;
+        self.planned.clear();
+        let programs = self.body(
+            "顶层代码".to_string(),
+            HashMap::new(),
+            HashMap::new(),
+            programs,
+        );
+        if self.workspace > 0 {
+            self.planned.push(format!("工作区共{}字节", self.workspace));
+        }
+        programs
+    }
+
+    fn report(&self) -> Vec<String> {
+        self.planned.clone()
+    }
+}
+
+impl MemoryPlan {
+    /// 上一次执行得到的工作区的大小
+    pub fn workspace(&self) -> usize {
+        self.workspace
+    }
+
+    /// 规划一个函数体：先求出中间张量的存活区间并分配缓冲区，
+    /// 再按同样的顺序遍历一遍，把规划过的算子放入工作区，同时规划嵌套的函数
+    fn body(
+        &mut self,
+        name: String,
+        types: HashMap<String, Option<Type>>,
+        functions: HashMap<String, Type>,
+        programs: Vec<Program>,
+    ) -> Vec<Program> {
+        let mut liveness = Liveness::new(None, Vec::new(), types.clone(), functions.clone());
+        let programs = liveness.body(programs);
+        let temporaries = liveness.temporaries;
+        let (offsets, buffers) = assign(&temporaries);
+        let peak: usize = buffers.iter().sum();
+        let base = self.workspace;
+        self.workspace += peak;
+        let planned: Vec<&Temporary> = temporaries
+            .iter()
+            .filter(|temporary| temporary.planned)
+            .collect();
+        if !planned.is_empty() {
+            let total: usize = planned.iter().map(|temporary| align(temporary.size)).sum();
+            self.planned.push(format!(
+                "{}：{}个中间张量复用{}块缓冲区，峰值{}字节，不复用时{}字节",
+                name,
+                planned.len(),
+                buffers.len(),
+                peak,
+                total
+            ));
+        }
+        let offsets = offsets
+            .into_iter()
+            .map(|offset| offset.map(|offset| base + offset))
+            .collect();
+        Liveness::new(Some(self), offsets, types, functions).body(programs)
+    }
+}
+
+/// 一个中间张量：由编号为 def 的算子算出，最后一次被编号为 end 的算子使用
+struct Temporary {
+    def: usize,
+    end: usize,
+    /// 数据的字节数
+    size: usize,
+    /// 是否只在算子中使用，可以放入工作区
+    planned: bool,
+}
+
+/// 表达式的类型，以及表达式的值是哪个中间张量
+struct Value {
+    ty: Option<Type>,
+    temporary: Option<usize>,
+}
+
+impl Value {
+    fn of(ty: Option<Type>) -> Self {
+        Self {
+            ty,
+            temporary: None,
+        }
+    }
+}
+
+/// 一个函数体中中间张量的存活区间，遍历的顺序与代码生成时计算的顺序一致
+struct Liveness<'a> {
+    /// 第二遍遍历时为正在执行的 pass，用于规划嵌套的函数
+    plan: Option<&'a mut MemoryPlan>,
+    /// 第二遍遍历时每个中间张量在工作区中的偏移，没有规划的为 None
+    offsets: Vec<Option<usize>>,
+    /// 变量的类型，推导不出时为 None
+    types: HashMap<String, Option<Type>>,
+    /// 可以调用的函数的返回值类型
+    functions: HashMap<String, Type>,
+    /// 已经计算的算子个数
+    counter: usize,
+    temporaries: Vec<Temporary>,
+    /// 函数体顶层的 let 变量对应的中间张量
+    bindings: HashMap<String, usize>,
+    /// 当前嵌套的循环层数
+    loops: usize,
+    /// 当前最外层的循环中使用的 let 变量
+    looped: Vec<usize>,
+}
+
+impl<'a> Liveness<'a> {
+    fn new(
+        plan: Option<&'a mut MemoryPlan>,
+        offsets: Vec<Option<usize>>,
+        types: HashMap<String, Option<Type>>,
+        functions: HashMap<String, Type>,
+    ) -> Self {
+        Self {
+            plan,
+            offsets,
+            types,
+            functions,
+            counter: 0,
+            temporaries: Vec::new(),
+            bindings: HashMap::new(),
+            loops: 0,
+            looped: Vec::new(),
+        }
+    }
+
+    /// 函数体的顶层，最后一个值是函数的返回值
+    fn body(&mut self, programs: Vec<Program>) -> Vec<Program> {
+        let last = programs.len();
+        let mut body = Vec::new();
+        for (i, program) in programs.into_iter().enumerate() {
+            body.push(self.program(program, i + 1 < last));
+        }
+        body
+    }
+
+    /// 嵌套的代码块，其中的 let 变量不参与规划
+    fn programs(&mut self, programs: Vec<Program>) -> Vec<Program> {
+        programs
+            .into_iter()
+            .map(|program| self.program(program, false))
+            .collect()
+    }
+
+    /// top 为 true 时是函数体顶层、不是返回值的语句
+    fn program(&mut self, program: Program, top: bool) -> Program {
+        match program {
+            Program::Let(variable, expr) => {
+                let (expr, value) = match expr {
+                    Expr::Function(function) => {
+                        let rtn = function.rtn.clone();
+                        let function = self.function(format!("%{}", variable.name), function);
+                        self.functions.insert(variable.name.clone(), rtn);
+                        (function, Value::of(None))
+                    }
+                    expr => self.expr(expr),
+                };
+                // 顶层重新定义的变量之后的使用都是新的值，代码块中重新定义时不知道之后使用的是哪一个
+                let previous = self.bindings.remove(&variable.name);
+                if !top {
+                    self.escape(previous);
+                }
+                match value.temporary {
+                    Some(temporary) if top => {
+                        self.bindings.insert(variable.name.clone(), temporary);
+                    }
+                    temporary => self.escape(temporary),
+                }
+                self.types.insert(variable.name.clone(), value.ty);
+                Program::Let(variable, expr)
+            }
+            Program::LetTuple(variables, expr) => {
+                let (expr, value) = self.expr(expr);
+                self.escape(value.temporary);
+                for (i, variable) in variables.iter().enumerate() {
+                    let previous = self.bindings.remove(&variable.name);
+                    self.escape(previous);
+                    let ty = match &value.ty {
+                        Some(Type::Tuple(types)) => types.get(i).cloned(),
+                        _ => None,
+                    };
+                    self.types.insert(variable.name.clone(), ty);
+                }
+                Program::LetTuple(variables, expr)
+            }
+            Program::Expr(expr) => Program::Expr(self.value(expr)),
+        }
+    }
+
+    /// 在算子之外使用的值，对应的中间张量不能放入工作区
+    fn value(&mut self, expr: Expr) -> Expr {
+        let (expr, value) = self.expr(expr);
+        self.escape(value.temporary);
+        expr
+    }
+
+    fn escape(&mut self, temporary: Option<usize>) {
+        if let Some(temporary) = temporary {
+            self.temporaries[temporary].planned = false;
+        }
+    }
+
+    fn expr(&mut self, expr: Expr) -> (Expr, Value) {
+        match expr {
+            Expr::Literal(literal) => {
+                let ty = literal_type(&literal);
+                (Expr::Literal(literal), Value::of(Some(ty)))
+            }
+            Expr::Variable(variable) => {
+                let ty = self.types.get(&variable.name).cloned().flatten();
+                let temporary = self.bindings.get(&variable.name).copied();
+                if let (Some(temporary), true) = (temporary, self.loops > 0) {
+                    self.looped.push(temporary);
+                }
+                (Expr::Variable(variable), Value { ty, temporary })
+            }
+            Expr::Assign(variable, expr) => {
+                let expr = self.value(*expr);
+                let previous = self.bindings.get(&variable.name).copied();
+                self.escape(previous);
+                (Expr::Assign(variable, Box::new(expr)), Value::of(None))
+            }
+            Expr::Function(function) => (
+                self.function("匿名函数".to_string(), function),
+                Value::of(None),
+            ),
+            Expr::Call(name, args) => {
+                let args = args.into_iter().map(|arg| self.value(arg)).collect();
+                let ty = self.functions.get(&name).cloned();
+                (Expr::Call(name, args), Value::of(ty))
+            }
+            Expr::Operator(operator) => self.operator(operator),
+            Expr::Tuple(items) => {
+                let mut types = Vec::new();
+                let mut values = Vec::new();
+                for item in items.into_iter() {
+                    let (item, value) = self.expr(item);
+                    self.escape(value.temporary);
+                    types.push(value.ty);
+                    values.push(item);
+                }
+                let ty = types.into_iter().collect::<Option<Vec<Type>>>();
+                (Expr::Tuple(values), Value::of(ty.map(Type::Tuple)))
+            }
+            Expr::Field(tuple, index) => {
+                let (tuple, value) = self.expr(*tuple);
+                self.escape(value.temporary);
+                let ty = match value.ty {
+                    Some(Type::Tuple(types)) => types.get(index).cloned(),
+                    _ => None,
+                };
+                (Expr::Field(Box::new(tuple), index), Value::of(ty))
+            }
+            Expr::If(if_else) => {
+                let cond = self.value(*if_else.cond);
+                let then = self.programs(if_else.then);
+                let otherwise = self.programs(if_else.otherwise);
+                (
+                    Expr::If(If {
+                        cond: Box::new(cond),
+                        then,
+                        otherwise,
+                    }),
+                    Value::of(None),
+                )
+            }
+            Expr::For(for_loop) => {
+                // 起止和步长只在循环开始之前计算一次
+                let start = self.value(*for_loop.start);
+                let end = self.value(*for_loop.end);
+                let step = self.value(*for_loop.step);
+                let previous = self.bindings.remove(&for_loop.var.name);
+                self.escape(previous);
+                self.types
+                    .insert(for_loop.var.name.clone(), Some(Type::Int));
+                self.loops += 1;
+                let body = self.programs(for_loop.body);
+                self.exit_loop();
+                (
+                    Expr::For(For {
+                        var: for_loop.var,
+                        start: Box::new(start),
+                        end: Box::new(end),
+                        step: Box::new(step),
+                        body,
+                    }),
+                    Value::of(None),
+                )
+            }
+            Expr::While(while_loop) => {
+                // 条件在每次迭代时都会计算
+                self.loops += 1;
+                let cond = self.value(*while_loop.cond);
+                let body = self.programs(while_loop.body);
+                self.exit_loop();
+                (
+                    Expr::While(While {
+                        cond: Box::new(cond),
+                        body,
+                    }),
+                    Value::of(None),
+                )
+            }
+            expr => (expr, Value::of(None)),
+        }
+    }
+
+    /// 循环中使用的 let 变量在之后的迭代中还会使用，存活到最外层的循环结束
+    fn exit_loop(&mut self) {
+        self.loops -= 1;
+        if self.loops > 0 {
+            return;
+        }
+        for temporary in std::mem::take(&mut self.looped) {
+            let temporary = &mut self.temporaries[temporary];
+            temporary.end = temporary.end.max(self.counter);
+        }
+    }
+
+    /// 嵌套的函数有自己的缓冲区，第二遍遍历时单独规划，第一遍保持不变
+    fn function(&mut self, name: String, function: Function) -> Expr {
+        let plan = match self.plan.as_mut() {
+            Some(plan) => plan,
+            None => return Expr::Function(function),
+        };
+        let types = function
+            .args
+            .iter()
+            .map(|arg| (arg.arg.name.clone(), Some(arg.annotation.clone())))
+            .collect();
+        let body = plan.body(name, types, self.functions.clone(), function.body);
+        Expr::Function(Function {
+            args: function.args,
+            rtn: function.rtn,
+            body,
+            inline: function.inline,
+        })
+    }
+
+    /// 算子的输入先计算，算子计算完成时输入的中间张量最后一次被使用，
+    /// 形状固定的张量结果成为新的中间张量
+    fn operator(&mut self, operator: Operator) -> (Expr, Value) {
+        let mut inputs = Vec::new();
+        let (operator, ty) = match operator {
+            // 重新规划时去掉原来的工作区
+            Operator::Workspace(x, _, _) => return self.expr(*x),
+            // 融合的算子链只分配一次结果，链中的算子不单独计算
+            Operator::Fused(chain) => {
+                let (chain, ty) = self.input(*chain, &mut inputs, true);
+                (Operator::Fused(Box::new(chain)), ty)
+            }
+            operator => self.split(operator, &mut inputs, false),
+        };
+        self.counter += 1;
+        for temporary in inputs.into_iter().flatten() {
+            let temporary = &mut self.temporaries[temporary];
+            temporary.end = temporary.end.max(self.counter);
+        }
+        // 没有元素的张量不占用工作区
+        let size = match ty.as_ref().and_then(tensor_size) {
+            Some(size) if size > 0 => size,
+            _ => return (Expr::Operator(operator), Value::of(ty)),
+        };
+        let temporary = self.temporaries.len();
+        self.temporaries.push(Temporary {
+            def: self.counter,
+            end: self.counter,
+            size,
+            planned: true,
+        });
+        let expr = match self.offsets.get(temporary).copied().flatten() {
+            Some(offset) => Expr::Operator(Operator::Workspace(
+                Box::new(Expr::Operator(operator)),
+                offset,
+                size,
+            )),
+            None => Expr::Operator(operator),
+        };
+        (
+            expr,
+            Value {
+                ty,
+                temporary: Some(temporary),
+            },
+        )
+    }
+
+    /// 算子的一个输入，fused 为 true 时输入中按元素计算的算子属于同一条融合的算子链
+    fn input(
+        &mut self,
+        expr: Expr,
+        inputs: &mut Vec<Option<usize>>,
+        fused: bool,
+    ) -> (Expr, Option<Type>) {
+        match expr {
+            Expr::Operator(operator) if fused && is_elementwise(&operator) => {
+                let (operator, ty) = self.split(operator, inputs, true);
+                (Expr::Operator(operator), ty)
+            }
+            expr => {
+                let (expr, value) = self.expr(expr);
+                inputs.push(value.temporary);
+                (expr, value.ty)
+            }
+        }
+    }
+
+    /// 依次计算算子的输入，并推导算子的结果类型
+    fn split(
+        &mut self,
+        operator: Operator,
+        inputs: &mut Vec<Option<usize>>,
+        fused: bool,
+    ) -> (Operator, Option<Type>) {
+        let name = operator_name(&operator);
+        let mut binary = |x: Box<Expr>, y: Box<Expr>| {
+            let (x, x_type) = self.input(*x, inputs, fused);
+            let (y, y_type) = self.input(*y, inputs, fused);
+            let ty = match (x_type, y_type) {
+                (Some(x), Some(y)) => elementwise_type(name, &x, &y, false).ok().flatten(),
+                _ => None,
+            };
+            (Box::new(x), Box::new(y), ty)
+        };
+        match operator {
+            Operator::Add(x, y) => {
+                let (x, y, ty) = binary(x, y);
+                (Operator::Add(x, y), ty)
+            }
+            Operator::Sub(x, y) => {
+                let (x, y, ty) = binary(x, y);
+                (Operator::Sub(x, y), ty)
+            }
+            Operator::Mul(x, y) => {
+                let (x, y, ty) = binary(x, y);
+                (Operator::Mul(x, y), ty)
+            }
+            Operator::Div(x, y) => {
+                let (x, y, ty) = binary(x, y);
+                (Operator::Div(x, y), ty)
+            }
+            // 矩阵乘法的结果是标量
+            Operator::Matmul(x, y) => {
+                let (x, y, ty) = binary(x, y);
+                let ty = ty.map(|ty| match ty {
+                    Type::Tensor(_, dtype) => *dtype,
+                    ty => ty,
+                });
+                (Operator::Matmul(x, y), ty)
+            }
+            Operator::Lt(x, y) => {
+                let (x, y, ty) = binary(x, y);
+                (Operator::Lt(x, y), to_bool(ty))
+            }
+            Operator::Le(x, y) => {
+                let (x, y, ty) = binary(x, y);
+                (Operator::Le(x, y), to_bool(ty))
+            }
+            Operator::Gt(x, y) => {
+                let (x, y, ty) = binary(x, y);
+                (Operator::Gt(x, y), to_bool(ty))
+            }
+            Operator::Ge(x, y) => {
+                let (x, y, ty) = binary(x, y);
+                (Operator::Ge(x, y), to_bool(ty))
+            }
+            Operator::Eq(x, y) => {
+                let (x, y, ty) = binary(x, y);
+                (Operator::Eq(x, y), to_bool(ty))
+            }
+            Operator::Ne(x, y) => {
+                let (x, y, ty) = binary(x, y);
+                (Operator::Ne(x, y), to_bool(ty))
+            }
+            Operator::Relu(x) => {
+                let (x, ty) = self.input(*x, inputs, fused);
+                (Operator::Relu(Box::new(x)), ty)
+            }
+            Operator::Tensor(literals) => {
+                let ty = literals.first().map(|first| {
+                    Type::Tensor(
+                        vec![Dim::Const(literals.len())],
+                        Box::new(literal_type(first)),
+                    )
+                });
+                (Operator::Tensor(literals), ty)
+            }
+            operator => (operator, None),
+        }
+    }
+}
+
+/// 比较的结果是 bool，张量按元素比较得到 bool 张量
+fn to_bool(ty: Option<Type>) -> Option<Type> {
+    ty.map(|ty| match ty {
+        Type::Tensor(shape, _) => Type::Tensor(shape, Box::new(Type::Bool)),
+        _ => Type::Bool,
+    })
+}
+
+/// 形状固定的张量的数据字节数，与运行时一致：int 和 float 为 8 字节，bool 为 1 字节
+fn tensor_size(ty: &Type) -> Option<usize> {
+    match ty {
+        Type::Tensor(shape, dtype) => {
+            let element = match dtype.as_ref() {
+                Type::Bool => 1,
+                _ => 8,
+            };
+            numel(shape).map(|numel| numel * element)
+        }
+        _ => None,
+    }
+}
+
+fn align(size: usize) -> usize {
+    (size + ALIGN - 1) & !(ALIGN - 1)
+}
+
+/// 缓冲区的大小和最后一个使用者的存活区间的结束
+struct Buffer {
+    size: usize,
+    end: usize,
+}
+
+/// 按算出的顺序给中间张量分配缓冲区：优先复用空闲的缓冲区中足够大的最小的一块，
+/// 都不够大时扩大空闲的最大的一块，没有空闲的缓冲区时新建一块。
+/// 返回每个中间张量在函数的缓冲区中的偏移和每块缓冲区的大小
+fn assign(temporaries: &[Temporary]) -> (Vec<Option<usize>>, Vec<usize>) {
+    let mut buffers: Vec<Buffer> = Vec::new();
+    let mut owners = Vec::new();
+    for temporary in temporaries.iter() {
+        if !temporary.planned {
+            owners.push(None);
+            continue;
+        }
+        let size = align(temporary.size);
+        let free = |buffer: &&Buffer| buffer.end < temporary.def;
+        let fit = buffers
+            .iter()
+            .enumerate()
+            .filter(|(_, buffer)| free(buffer) && buffer.size >= size)
+            .min_by_key(|(_, buffer)| buffer.size);
+        let largest = buffers
+            .iter()
+            .enumerate()
+            .filter(|(_, buffer)| free(buffer))
+            .max_by_key(|(_, buffer)| buffer.size);
+        let index = match fit.or(largest) {
+            Some((index, _)) => index,
+            None => {
+                buffers.push(Buffer { size: 0, end: 0 });
+                buffers.len() - 1
+            }
+        };
+        let buffer = &mut buffers[index];
+        buffer.size = buffer.size.max(size);
+        buffer.end = temporary.end;
+        owners.push(Some(index));
+    }
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    for buffer in buffers.iter() {
+        offsets.push(offset);
+        offset += buffer.size;
+    }
+    let offsets = owners
+        .into_iter()
+        .map(|owner| owner.map(|index| offsets[index]))
+        .collect();
+    (offsets, buffers.iter().map(|buffer| buffer.size).collect())
+}
+
+/// 执行生成的代码需要的工作区大小，即所有 Workspace 算子的数据结束位置的最大值
+pub fn workspace_size(programs: &[Program]) -> usize {
+    programs
+        .iter()
+        .map(|program| match program {
+            Program::Let(_, expr) | Program::LetTuple(_, expr) | Program::Expr(expr) => {
+                expr_workspace(expr)
+            }
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn expr_workspace(expr: &Expr) -> usize {
+    match expr {
+        Expr::Operator(operator) => {
+            let inner = operands(operator)
+                .into_iter()
+                .map(expr_workspace)
+                .max()
+                .unwrap_or(0);
+            match operator {
+                Operator::Workspace(_, offset, size) => inner.max(offset + size),
+                _ => inner,
+            }
+        }
+        Expr::Assign(_, expr) => expr_workspace(expr),
+        Expr::Function(function) => workspace_size(&function.body),
+        Expr::Call(_, exprs) | Expr::Tuple(exprs) => {
+            exprs.iter().map(expr_workspace).max().unwrap_or(0)
+        }
+        Expr::Field(tuple, _) => expr_workspace(tuple),
+        Expr::If(if_else) => expr_workspace(&if_else.cond)
+            .max(workspace_size(&if_else.then))
+            .max(workspace_size(&if_else.otherwise)),
+        Expr::For(for_loop) => expr_workspace(&for_loop.start)
+            .max(expr_workspace(&for_loop.end))
+            .max(expr_workspace(&for_loop.step))
+            .max(workspace_size(&for_loop.body)),
+        Expr::While(while_loop) => {
+            expr_workspace(&while_loop.cond).max(workspace_size(&while_loop.body))
+        }
+        Expr::Literal(_) | Expr::Variable(_) | Expr::Break | Expr::Continue => 0,
+    }
+}
@@ -0,0 +1,422 @@
+use super::super::ast::{numel, Dim, Expr, Function, Literal, Operator, Program, Type};
+use std::collections::HashMap;
+
+/// 检查 Mool IR 的类型
+///
+/// 形参和返回值的类型来自函数的类型注解，其余的类型由表达式推导，推导不出类型的表达式（例如循环）不参与检查。
+/// 张量的符号维度在函数体中是大小未知但固定的维度，只和同名的符号相等；
+/// 调用函数时把签名中的符号维度和实参的维度合一，同一个符号在所有实参中必须一致，
+/// 返回值的类型用合一的结果替换其中的符号。
+pub fn typecheck(programs: &[Program]) -> Result<(), String> {
+    let mut checker = TypeChecker {
+        scopes: vec![HashMap::new()],
+    };
+    checker.programs(programs).map(|_| ())
+}
+
+#[derive(Debug, Clone)]
+enum Binding {
+    Value(Option<Type>),
+    Function(Signature),
+}
+
+/// 函数的签名，符号维度在每次调用时重新合一
+#[derive(Debug, Clone)]
+struct Signature {
+    args: Vec<Type>,
+    rtn: Type,
+}
+
+impl Signature {
+    fn new(function: &Function) -> Self {
+        Self {
+            args: function
+                .args
+                .iter()
+                .map(|arg| arg.annotation.clone())
+                .collect(),
+            rtn: function.rtn.clone(),
+        }
+    }
+}
+
+struct TypeChecker {
+    scopes: Vec<HashMap<String, Binding>>,
+}
+
+impl TypeChecker {
+    /// 依次检查，返回最后一个值的类型
+    fn programs(&mut self, programs: &[Program]) -> Result<Option<Type>, String> {
+        let mut last = None;
+        for program in programs.iter() {
+            last = self.program(program)?;
+        }
+        Ok(last)
+    }
+
+    fn program(&mut self, program: &Program) -> Result<Option<Type>, String> {
+        match program {
+            Program::Let(variable, expr) => {
+                let ty = self.expr(expr)?;
+                let binding = match expr {
+                    Expr::Function(function) => Binding::Function(Signature::new(function)),
+                    Expr::Variable(source) => match self.lookup(&source.name) {
+                        Some(Binding::Function(signature)) => Binding::Function(signature.clone()),
+                        _ => Binding::Value(ty.clone()),
+                    },
+                    _ => Binding::Value(ty.clone()),
+                };
+                self.define(&variable.name, binding);
+                Ok(ty)
+            }
+            Program::LetTuple(variables, expr) => {
+                let ty = self.expr(expr)?;
+                for (i, variable) in variables.iter().enumerate() {
+                    let element = match &ty {
+                        Some(Type::Tuple(types)) if types.len() == variables.len() => {
+                            Some(types[i].clone())
+                        }
+                        Some(ty) => {
+                            return Err(format!(
+                                "不能把{}类型的值解构为{}个变量",
+                                ty,
+                                variables.len()
+                            ))
+                        }
+                        None => None,
+                    };
+                    self.define(&variable.name, Binding::Value(element));
+                }
+                Ok(ty)
+            }
+            Program::Expr(expr) => self.expr(expr),
+        }
+    }
+
+    fn define(&mut self, name: &str, binding: Binding) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), binding);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<Option<Type>, String> {
+        match expr {
+            Expr::Literal(literal) => Ok(Some(literal_type(literal))),
+            Expr::Variable(variable) => match self.lookup(&variable.name) {
+                Some(Binding::Value(ty)) => Ok(ty.clone()),
+                _ => Ok(None),
+            },
+            Expr::Assign(variable, expr) => {
+                let ty = self.expr(expr)?;
+                if let (Some(Binding::Value(Some(expected))), Some(actual)) =
+                    (self.lookup(&variable.name), &ty)
+                {
+                    if expected != actual {
+                        return Err(format!(
+                            "变量{}的类型为{}，不能赋值为{}类型的值",
+                            variable.name, expected, actual
+                        ));
+                    }
+                }
+                Ok(ty)
+            }
+            Expr::Function(function) => {
+                self.function(function)?;
+                Ok(None)
+            }
+            Expr::Call(name, args) => {
+                let mut types = Vec::new();
+                for arg in args.iter() {
+                    types.push(self.expr(arg)?);
+                }
+                let signature = match self.lookup(name) {
+                    Some(Binding::Function(signature)) => signature,
+                    _ => return Ok(None),
+                };
+                if signature.args.len() != types.len() {
+                    return Err(format!(
+                        "函数{}需要{}个参数，调用时传入了{}个",
+                        name,
+                        signature.args.len(),
+                        types.len()
+                    ));
+                }
+                let mut bindings = HashMap::new();
+                for (i, (expected, actual)) in signature.args.iter().zip(types.iter()).enumerate() {
+                    if let Some(actual) = actual {
+                        unify(expected, actual, &mut bindings).map_err(|error| {
+                            format!("调用{}的第{}个参数时{}", name, i + 1, error)
+                        })?;
+                    }
+                }
+                Ok(Some(substitute(&signature.rtn, &bindings)))
+            }
+            Expr::Operator(operator) => self.operator(operator),
+            Expr::Tuple(items) => {
+                let mut types = Vec::new();
+                for item in items.iter() {
+                    types.push(self.expr(item)?);
+                }
+                Ok(types
+                    .into_iter()
+                    .collect::<Option<Vec<Type>>>()
+                    .map(Type::Tuple))
+            }
+            Expr::Field(tuple, index) => match self.expr(tuple)? {
+                Some(Type::Tuple(types)) => match types.get(*index) {
+                    Some(ty) => Ok(Some(ty.clone())),
+                    None => Err(format!("元组只有{}个元素，下标{}越界", types.len(), index)),
+                },
+                Some(ty) => Err(format!("只能取元组的元素，实际为{}类型", ty)),
+                None => Ok(None),
+            },
+            Expr::If(if_else) => {
+                self.expr(&if_else.cond)?;
+                let then = self.programs(&if_else.then)?;
+                let otherwise = self.programs(&if_else.otherwise)?;
+                match (then, otherwise) {
+                    (Some(then), Some(otherwise)) if then == otherwise => Ok(Some(then)),
+                    _ => Ok(None),
+                }
+            }
+            Expr::For(for_loop) => {
+                self.expr(&for_loop.start)?;
+                self.expr(&for_loop.end)?;
+                self.expr(&for_loop.step)?;
+                self.define(&for_loop.var.name, Binding::Value(Some(Type::Int)));
+                self.programs(&for_loop.body)?;
+                Ok(None)
+            }
+            Expr::While(while_loop) => {
+                self.expr(&while_loop.cond)?;
+                self.programs(&while_loop.body)?;
+                Ok(None)
+            }
+            Expr::Break | Expr::Continue => Ok(None),
+        }
+    }
+
+    /// 在新的作用域中检查函数体，函数体的类型需要和返回值的类型一致。
+    /// 形参中的符号维度是固定的，只出现在返回值中的符号维度由函数体决定
+    fn function(&mut self, function: &Function) -> Result<(), String> {
+        let mut scope = HashMap::new();
+        let mut bindings = HashMap::new();
+        for arg in function.args.iter() {
+            scope.insert(
+                arg.arg.name.clone(),
+                Binding::Value(Some(arg.annotation.clone())),
+            );
+            fix_symbols(&arg.annotation, &mut bindings);
+        }
+        self.scopes.push(scope);
+        let result = self.programs(&function.body);
+        self.scopes.pop();
+        if let Some(actual) = result? {
+            unify(&function.rtn, &actual, &mut bindings)
+                .map_err(|error| format!("函数的返回值{}", error))?;
+        }
+        Ok(())
+    }
+
+    fn operator(&mut self, operator: &Operator) -> Result<Option<Type>, String> {
+        match operator {
+            Operator::Add(x, y) => self.elementwise("Add", x, y, false),
+            Operator::Sub(x, y) => self.elementwise("Sub", x, y, false),
+            Operator::Mul(x, y) => self.elementwise("Mul", x, y, false),
+            Operator::Div(x, y) => self.elementwise("Div", x, y, false),
+            Operator::Lt(x, y) => self.elementwise("Lt", x, y, true),
+            Operator::Le(x, y) => self.elementwise("Le", x, y, true),
+            Operator::Gt(x, y) => self.elementwise("Gt", x, y, true),
+            Operator::Ge(x, y) => self.elementwise("Ge", x, y, true),
+            Operator::Eq(x, y) => self.elementwise("Eq", x, y, true),
+            Operator::Ne(x, y) => self.elementwise("Ne", x, y, true),
+            Operator::Matmul(x, y) => match (self.expr(x)?, self.expr(y)?) {
+                (Some(x), Some(y)) => match (&x, &y) {
+                    // 张量的矩阵乘法按元素个数计算点积
+                    (Type::Tensor(_, dtype), Type::Tensor(_, _)) if x == y => {
+                        Ok(Some(dtype.as_ref().clone()))
+                    }
+                    (Type::Tensor(_, _), Type::Tensor(_, _)) => {
+                        Err(format!("Matmul 算子中张量的类型必须相同：{} 和 {}", x, y))
+                    }
+                    (x, y) if is_scalar(x) && x == y => Ok(Some(x.clone())),
+                    _ => Ok(None),
+                },
+                _ => Ok(None),
+            },
+            Operator::Relu(x) => self.expr(x),
+            Operator::Tensor(literals) => {
+                let dtype = match literals.first() {
+                    Some(first) => literal_type(first),
+                    None => return Err("张量不能为空".to_string()),
+                };
+                if literals
+                    .iter()
+                    .any(|literal| literal_type(literal) != dtype)
+                {
+                    return Err("张量的元素类型必须相同".to_string());
+                }
+                Ok(Some(Type::Tensor(
+                    vec![Dim::Const(literals.len())],
+                    Box::new(dtype),
+                )))
+            }
+            Operator::Fused(chain) | Operator::Workspace(chain, _, _) => self.expr(chain),
+        }
+    }
+
+    fn elementwise(
+        &mut self,
+        name: &str,
+        x: &Expr,
+        y: &Expr,
+        compare: bool,
+    ) -> Result<Option<Type>, String> {
+        match (self.expr(x)?, self.expr(y)?) {
+            (Some(x), Some(y)) => elementwise_type(name, &x, &y, compare),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// 按元素计算的算子的结果类型：张量的形状必须相同，元素个数为 1 的张量和标量广播到另一侧的形状
+pub(super) fn elementwise_type(
+    name: &str,
+    x: &Type,
+    y: &Type,
+    compare: bool,
+) -> Result<Option<Type>, String> {
+    let result = |dtype: &Type| if compare { Type::Bool } else { dtype.clone() };
+    match (x, y) {
+        (Type::Tensor(x_shape, x_dtype), Type::Tensor(y_shape, y_dtype)) => {
+            if x_dtype != y_dtype {
+                return Err(format!(
+                    "{} 算子中张量的元素类型必须相同：{} 和 {}",
+                    name, x, y
+                ));
+            }
+            let shape = if x_shape == y_shape || numel(y_shape) == Some(1) {
+                x_shape
+            } else if numel(x_shape) == Some(1) {
+                y_shape
+            } else {
+                return Err(format!("{} 算子中张量的形状不一致：{} 和 {}", name, x, y));
+            };
+            Ok(Some(Type::Tensor(shape.clone(), Box::new(result(x_dtype)))))
+        }
+        (Type::Tensor(shape, dtype), scalar) | (scalar, Type::Tensor(shape, dtype))
+            if is_scalar(scalar) =>
+        {
+            if dtype.as_ref() != scalar {
+                return Err(format!(
+                    "{} 算子中张量的元素类型和标量的类型必须相同：{} 和 {}",
+                    name, x, y
+                ));
+            }
+            Ok(Some(Type::Tensor(shape.clone(), Box::new(result(dtype)))))
+        }
+        (x, y) if is_scalar(x) && x == y => Ok(Some(result(x))),
+        (x, y) if is_scalar(x) && is_scalar(y) => {
+            Err(format!("{} 算子两侧的类型必须相同：{} 和 {}", name, x, y))
+        }
+        _ => Ok(None),
+    }
+}
+
+pub(super) fn literal_type(literal: &Literal) -> Type {
+    match literal {
+        Literal::Int(_) => Type::Int,
+        Literal::Float(_) => Type::Float,
+        Literal::Bool(_) => Type::Bool,
+    }
+}
+
+fn is_scalar(ty: &Type) -> bool {
+    matches!(ty, Type::Int | Type::Float | Type::Bool)
+}
+
+/// 把签名中的类型 expected 和实际的类型 actual 合一，expected 中符号维度的取值记录在 bindings 中
+fn unify(
+    expected: &Type,
+    actual: &Type,
+    bindings: &mut HashMap<String, Dim>,
+) -> Result<(), String> {
+    let mismatch = || format!("需要{}类型，实际为{}类型", expected, actual);
+    match (expected, actual) {
+        (Type::Tensor(shape, dtype), Type::Tensor(actual_shape, actual_dtype)) => {
+            if dtype != actual_dtype || shape.len() != actual_shape.len() {
+                return Err(mismatch());
+            }
+            for (dim, actual_dim) in shape.iter().zip(actual_shape.iter()) {
+                match dim {
+                    Dim::Symbol(symbol) => match bindings.get(symbol) {
+                        Some(bound) if bound != actual_dim => {
+                            return Err(format!(
+                                "符号维度{}既为{}又为{}",
+                                symbol, bound, actual_dim
+                            ))
+                        }
+                        Some(_) => {}
+                        None => {
+                            bindings.insert(symbol.clone(), actual_dim.clone());
+                        }
+                    },
+                    Dim::Const(_) if dim != actual_dim => return Err(mismatch()),
+                    Dim::Const(_) => {}
+                }
+            }
+            Ok(())
+        }
+        (Type::Tuple(types), Type::Tuple(actual_types)) if types.len() == actual_types.len() => {
+            for (ty, actual_ty) in types.iter().zip(actual_types.iter()) {
+                unify(ty, actual_ty, bindings)?;
+            }
+            Ok(())
+        }
+        _ if expected == actual => Ok(()),
+        _ => Err(mismatch()),
+    }
+}
+
+/// 把类型中的符号维度替换为合一的结果
+fn substitute(ty: &Type, bindings: &HashMap<String, Dim>) -> Type {
+    match ty {
+        Type::Tensor(shape, dtype) => Type::Tensor(
+            shape
+                .iter()
+                .map(|dim| match dim {
+                    Dim::Symbol(symbol) => bindings.get(symbol).unwrap_or(dim).clone(),
+                    Dim::Const(_) => dim.clone(),
+                })
+                .collect(),
+            dtype.clone(),
+        ),
+        Type::Tuple(types) => {
+            Type::Tuple(types.iter().map(|ty| substitute(ty, bindings)).collect())
+        }
+        Type::Int | Type::Float | Type::Bool => ty.clone(),
+    }
+}
+
+/// 函数体中形参的符号维度只等于自己
+fn fix_symbols(ty: &Type, bindings: &mut HashMap<String, Dim>) {
+    match ty {
+        Type::Tensor(shape, _) => {
+            for dim in shape.iter() {
+                if let Dim::Symbol(symbol) = dim {
+                    bindings.insert(symbol.clone(), dim.clone());
+                }
+            }
+        }
+        Type::Tuple(types) => {
+            for ty in types.iter() {
+                fix_symbols(ty, bindings);
+            }
+        }
+        Type::Int | Type::Float | Type::Bool => {}
+    }
+}
@@ -0,0 +1,191 @@
+use super::super::ast::{Expr, Operator, Program};
+use std::collections::{HashMap, HashSet};
+
+/// 检查 Mool IR 能否生成代码
+///
+/// 规则与 LLVM 代码生成一致：变量先定义后使用，只有函数会创建新的作用域，
+/// 函数中只能调用外层的函数而不能使用外层的变量，break 和 continue 只能出现在循环中。
+pub fn verify(programs: &[Program]) -> Result<(), String> {
+    let mut verifier = Verifier {
+        scopes: vec![HashMap::new()],
+        loops: 0,
+    };
+    verifier.programs(programs)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Binding {
+    Value,
+    Function,
+}
+
+struct Verifier {
+    scopes: Vec<HashMap<String, Binding>>,
+    /// 当前函数中嵌套的循环层数
+    loops: usize,
+}
+
+impl Verifier {
+    fn programs(&mut self, programs: &[Program]) -> Result<(), String> {
+        for program in programs.iter() {
+            self.program(program)?;
+        }
+        Ok(())
+    }
+
+    fn program(&mut self, program: &Program) -> Result<(), String> {
+        match program {
+            Program::Let(variable, expr) => {
+                self.expr(expr)?;
+                let binding = match expr {
+                    Expr::Function(_) => Binding::Function,
+                    Expr::Variable(source) => match self.lookup(&source.name) {
+                        Some((binding, _)) => binding,
+                        None => Binding::Value,
+                    },
+                    _ => Binding::Value,
+                };
+                self.define(&variable.name, binding);
+                Ok(())
+            }
+            Program::LetTuple(variables, expr) => {
+                self.expr(expr)?;
+                let mut names = HashSet::new();
+                for variable in variables.iter() {
+                    if !names.insert(&variable.name) {
+                        return Err(format!("元组解构中变量{}重复", variable.name));
+                    }
+                    self.define(&variable.name, Binding::Value);
+                }
+                Ok(())
+            }
+            Program::Expr(expr) => self.expr(expr),
+        }
+    }
+
+    fn define(&mut self, name: &str, binding: Binding) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), binding);
+        }
+    }
+
+    /// 查找变量，同时返回变量是否定义在当前函数中
+    fn lookup(&self, name: &str) -> Option<(Binding, bool)> {
+        let current = self.scopes.len() - 1;
+        self.scopes
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, scope)| scope.get(name).map(|binding| (*binding, i == current)))
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) -> Result<(), String> {
+        for expr in exprs.iter() {
+            self.expr(expr)?;
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<(), String> {
+        match expr {
+            Expr::Literal(_) | Expr::Operator(Operator::Tensor(_)) => Ok(()),
+            Expr::Variable(variable) => match self.lookup(&variable.name) {
+                Some((Binding::Value, false)) => {
+                    Err(format!("函数中不能使用外层的变量{}", variable.name))
+                }
+                Some(_) => Ok(()),
+                None => Err(format!("变量{}没有定义", variable.name)),
+            },
+            Expr::Assign(variable, expr) => {
+                self.expr(expr)?;
+                match self.lookup(&variable.name) {
+                    Some((Binding::Value, true)) => Ok(()),
+                    Some((Binding::Value, false)) => {
+                        Err(format!("函数中不能使用外层的变量{}", variable.name))
+                    }
+                    Some((Binding::Function, _)) => Err(format!("不能给函数{}赋值", variable.name)),
+                    None => Err(format!("变量{}没有定义", variable.name)),
+                }
+            }
+            Expr::Function(function) => {
+                let mut scope = HashMap::new();
+                for arg in function.args.iter() {
+                    if scope.insert(arg.arg.name.clone(), Binding::Value).is_some() {
+                        return Err(format!("函数的参数{}重复", arg.arg.name));
+                    }
+                }
+                self.scopes.push(scope);
+                let loops = std::mem::replace(&mut self.loops, 0);
+                let result = self.programs(&function.body);
+                self.loops = loops;
+                self.scopes.pop();
+                result
+            }
+            Expr::Call(name, args) => {
+                self.exprs(args)?;
+                match self.lookup(name) {
+                    Some((Binding::Function, _)) => Ok(()),
+                    Some((Binding::Value, _)) => Err(format!("{}不是函数", name)),
+                    None => Err(format!("函数{}没有定义", name)),
+                }
+            }
+            Expr::Operator(operator) => match operator {
+                Operator::Add(x, y)
+                | Operator::Sub(x, y)
+                | Operator::Mul(x, y)
+                | Operator::Div(x, y)
+                | Operator::Matmul(x, y)
+                | Operator::Lt(x, y)
+                | Operator::Le(x, y)
+                | Operator::Gt(x, y)
+                | Operator::Ge(x, y)
+                | Operator::Eq(x, y)
+                | Operator::Ne(x, y) => {
+                    self.expr(x)?;
+                    self.expr(y)
+                }
+                Operator::Relu(x) | Operator::Fused(x) | Operator::Workspace(x, _, _) => {
+                    self.expr(x)
+                }
+                Operator::Tensor(_) => Ok(()),
+            },
+            Expr::Tuple(items) => self.exprs(items),
+            Expr::Field(tuple, index) => {
+                self.expr(tuple)?;
+                match &**tuple {
+                    Expr::Tuple(items) if *index >= items.len() => {
+                        Err(format!("元组只有{}个元素，下标{}越界", items.len(), index))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Expr::If(if_else) => {
+                self.expr(&if_else.cond)?;
+                self.programs(&if_else.then)?;
+                self.programs(&if_else.otherwise)
+            }
+            Expr::For(for_loop) => {
+                self.expr(&for_loop.start)?;
+                self.expr(&for_loop.end)?;
+                self.expr(&for_loop.step)?;
+                self.define(&for_loop.var.name, Binding::Value);
+                self.loop_body(&for_loop.body)
+            }
+            Expr::While(while_loop) => {
+                self.expr(&while_loop.cond)?;
+                self.loop_body(&while_loop.body)
+            }
+            Expr::Break | Expr::Continue => match self.loops {
+                0 => Err("break 和 continue 只能出现在循环中".to_string()),
+                _ => Ok(()),
+            },
+        }
+    }
+
+    fn loop_body(&mut self, body: &[Program]) -> Result<(), String> {
+        self.loops += 1;
+        let result = self.programs(body);
+        self.loops -= 1;
+        result
+    }
+}
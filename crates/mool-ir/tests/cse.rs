@@ -0,0 +1,65 @@
+//! 公共子表达式消除前后的 Mool IR
+
+use mool_ir::pass::{CommonSubexpression, Pass};
+
+fn assert_cse(before: &str, after: &str) -> Vec<String> {
+    let mut cse = CommonSubexpression::default();
+    let output = cse.run(mool_ir::parse(before).unwrap());
+    let expected = mool_ir::parse(after).unwrap();
+    assert_eq!(
+        format!("{:?}", output),
+        format!("{:?}", expected),
+        "{}",
+        before
+    );
+    cse.report()
+}
+
+#[test]
+fn reuse_repeated_operators() {
+    let report = assert_cse(
+        "let %f = fn(%x: Tensor[(4), float], %y: Tensor[(4), float]) -> Tensor[(4), float] {
+            let %a = Mul(%x, %y)
+            let %b = Add(Relu(Add(%x, %y)), Relu(Add(%x, %y)))
+            Sub(Mul(%x, %y), %b)
+        }",
+        "let %f = fn(%x: Tensor[(4), float], %y: Tensor[(4), float]) -> Tensor[(4), float] {
+            let %a = Mul(%x, %y)
+            let %cse0 = Relu(Add(%x, %y))
+            let %b = Add(%cse0, %cse0)
+            Sub(%a, %b)
+        }",
+    );
+    assert_eq!(report, ["复用%cse0中的Relu运算", "复用%a中的Mul运算"]);
+}
+
+#[test]
+fn keep_side_effects_and_distinct_shapes() {
+    // 函数调用可能有副作用；赋值之后依赖它的表达式失效；不同函数中的同名变量形状不同
+    let code = "let %g = fn(%x: Tensor[(2), int]) -> Tensor[(2), int] {
+            Add(%x, %x)
+        }
+        let %h = fn(%x: Tensor[(3), int]) -> Tensor[(3), int] {
+            let %a = Add(%x, %x)
+            %x = Relu(%x)
+            let %b = Add(%x, %x)
+            let %c = Add(Tensor([1, 2, 3]), %a)
+            let %d = Add(Tensor([1, 2]), %g(Tensor([1, 2])))
+            let %e = Add(Tensor([1, 2]), %g(Tensor([1, 2])))
+            %b
+        }";
+    assert!(assert_cse(code, code).is_empty());
+}
+
+#[test]
+fn nested_blocks_are_separate() {
+    // 循环体和 if 分支不复用外层的结果
+    let code = "let %f = fn(%x: int, %n: int) -> int {
+            let %a = Mul(%x, %x)
+            for %i in range(0, %n, 1) {
+                let %b = Mul(%x, %x)
+            }
+            if Gt(%a, 0) { Mul(%x, %x) } else { %a }
+        }";
+    assert!(assert_cse(code, code).is_empty());
+}
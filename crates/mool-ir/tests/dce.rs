@@ -0,0 +1,121 @@
+//! 死代码删除前后的 Mool IR
+
+use mool_ir::pass::{DeadCode, Pass};
+
+fn assert_dce(before: &str, after: &str) -> Vec<String> {
+    assert_dce_with(DeadCode::default(), before, after)
+}
+
+fn assert_dce_with(mut dce: DeadCode, before: &str, after: &str) -> Vec<String> {
+    let output = dce.run(mool_ir::parse(before).unwrap());
+    let expected = mool_ir::parse(after).unwrap();
+    assert_eq!(
+        format!("{:?}", output),
+        format!("{:?}", expected),
+        "{}",
+        before
+    );
+    dce.report()
+}
+
+/// %scale 调用 %helper，%unused 调用 %helper2，%recursive 调用自身，都没有被顶层代码调用
+const FUNCTIONS: &str = "let %helper = fn(%x: int) -> int { Add(%x, 1) }
+    let %scale = fn(%x: int) -> int { Mul(%helper(%x), 2) }
+    let %helper2 = fn(%x: int) -> int { Sub(%x, 1) }
+    let %unused = fn(%x: int) -> int { %helper2(%x) }
+    let %recursive = fn(%x: int) -> int { %recursive(%x) }
+    let %main = fn(%x: int) -> int { Add(%x, %x) }
+    %main(3)";
+
+#[test]
+fn remove_unreachable_functions() {
+    // 默认只以顶层代码为根，不可达的函数连同只被它们调用的函数一起删除
+    let report = assert_dce(
+        FUNCTIONS,
+        "let %main = fn(%x: int) -> int { Add(%x, %x) }
+        %main(3)",
+    );
+    assert_eq!(
+        report,
+        [
+            "未使用的函数%helper",
+            "未使用的函数%scale",
+            "未使用的函数%helper2",
+            "未使用的函数%unused",
+            "未使用的函数%recursive"
+        ]
+    );
+}
+
+#[test]
+fn keep_exported_roots() {
+    // 导出的函数和它调用的函数保留
+    let report = assert_dce_with(
+        DeadCode::with_roots(vec!["scale".to_string()]),
+        FUNCTIONS,
+        "let %helper = fn(%x: int) -> int { Add(%x, 1) }
+        let %scale = fn(%x: int) -> int { Mul(%helper(%x), 2) }
+        let %main = fn(%x: int) -> int { Add(%x, %x) }
+        %main(3)",
+    );
+    assert_eq!(
+        report,
+        [
+            "未使用的函数%helper2",
+            "未使用的函数%unused",
+            "未使用的函数%recursive"
+        ]
+    );
+}
+
+#[test]
+fn remove_unused_bindings() {
+    let report = assert_dce(
+        "let %f = fn(%x: int) -> int {
+            let %helper = fn(%y: int) -> int { %y }
+            let %unused = Add(%x, 1)
+            let %used = Mul(%x, 2)
+            Sub(%x, %x)
+            if Gt(%x, 0) { Relu(%x) 1 } else { 2 }
+            %used
+        }
+        let %a = Add(1, 2)
+        let %b = %f(3)
+        Add(%b, 1)",
+        "let %f = fn(%x: int) -> int {
+            let %used = Mul(%x, 2)
+            if Gt(%x, 0) { 1 } else { 2 }
+            %used
+        }
+        let %b = %f(3)",
+    );
+    assert_eq!(
+        report,
+        [
+            "未使用的函数%helper",
+            "未使用的变量%unused",
+            "未使用的Sub运算",
+            "未使用的Relu运算",
+            "未使用的变量%a",
+            "未使用的Add运算"
+        ]
+    );
+}
+
+#[test]
+fn keep_side_effects() {
+    // 调用、赋值和循环可能有副作用，总是保留
+    let code = "let %f = fn(%n: int) -> int {
+            let %total = 0
+            for %i in range(0, %n, 1) {
+                %total = Add(%total, %i)
+            }
+            %total
+        }
+        %f(3)
+        let %t = 0
+        while Lt(%t, 3) {
+            %t = Add(%t, 1)
+        }";
+    assert!(assert_dce(code, code).is_empty());
+}
@@ -0,0 +1,68 @@
+//! 常量折叠和代数化简前后的 Mool IR
+
+use mool_ir::pass::{Fold, Pass};
+
+/// 化简 before 之后与 after 相同
+fn assert_fold(before: &str, after: &str) {
+    let folded = Fold.run(mool_ir::parse(before).unwrap());
+    let expected = mool_ir::parse(after).unwrap();
+    assert_eq!(
+        format!("{:?}", folded),
+        format!("{:?}", expected),
+        "{}",
+        before
+    );
+}
+
+#[test]
+fn fold_constants() {
+    assert_fold(
+        "let %a = Add(Tensor([1, 2]), Tensor([3, 4]))
+        let %b = Mul(Sub(10, 4), 2)
+        let %c = Matmul(Tensor([1.0, 2.0]), Tensor([3.0, 4.0]))
+        let %d = Relu(Tensor([-1, 2]))
+        let %e = Lt(Tensor([1, 5]), Tensor([2, 2]))
+        (%a, %b, %c, %d, %e, (1, 2).1)",
+        "let %a = Tensor([4, 6])
+        let %b = 12
+        let %c = 11.0
+        let %d = Tensor([0, 2])
+        let %e = Tensor([true, false])
+        (%a, %b, %c, %d, %e, 2)",
+    );
+}
+
+#[test]
+fn keep_unsafe_arithmetic() {
+    // 整数除法为无符号除法，不折叠负数；浮点数 x + 0.0 对 -0.0 不成立
+    let code = "let %f = fn(%x: float) -> (int, float) {
+            (Div(-7, 2), Add(%x, 0.0))
+        }";
+    assert_fold(code, code);
+}
+
+#[test]
+fn simplify_identities() {
+    assert_fold(
+        "let %f = fn(%x: Tensor[(3), int], %y: int) -> (Tensor[(3), int], Tensor[(3), int], Tensor[(3), int], int, int) {
+            (Mul(Tensor([1, 1, 1]), %x), Add(%x, Tensor([0])), Div(%x, 1), Sub(%y, 0), Sub(%y, %y))
+        }",
+        "let %f = fn(%x: Tensor[(3), int], %y: int) -> (Tensor[(3), int], Tensor[(3), int], Tensor[(3), int], int, int) {
+            (%x, %x, %x, %y, 0)
+        }",
+    );
+}
+
+#[test]
+fn keep_broadcasting_identities() {
+    // 单位元广播之后结果的类型与 x 不同，化简会改变返回值的类型
+    let code = "let %f = fn(%s: int, %x: Tensor[(1), int]) -> (Tensor[(3), int], Tensor[(3), int], Tensor[(2), int], Tensor[(2), int]) {
+            (Mul(Tensor([1, 1, 1]), %s), Add(%x, Tensor([0, 0, 0])), Sub(%x, Tensor([0, 0])), Div(%s, Tensor([1, 1])))
+        }";
+    assert_fold(code, code);
+    // 无法确定类型的操作数不化简
+    let code = "let %g = fn(%x: Tensor[(2), int]) -> Tensor[(2), int] {
+            Mul(Relu(%x), Tensor([1, 1]))
+        }";
+    assert_fold(code, code);
+}
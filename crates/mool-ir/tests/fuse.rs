@@ -0,0 +1,58 @@
+//! 算子融合前后的 Mool IR
+
+use mool_ir::pass::{Fuse, Pass};
+
+fn assert_fuse(before: &str, after: &str) -> Vec<String> {
+    let mut fuse = Fuse::default();
+    let output = fuse.run(mool_ir::parse(before).unwrap());
+    let expected = mool_ir::parse(after).unwrap();
+    assert_eq!(
+        format!("{:?}", output),
+        format!("{:?}", expected),
+        "{}",
+        before
+    );
+    fuse.report()
+}
+
+#[test]
+fn fuse_chain_with_broadcasting() {
+    // 标量和只有一个元素的张量作为链的叶子，代码生成时广播到所有元素
+    let report = assert_fuse(
+        "let %f = fn(%x: Tensor[(4), float], %w: float, %b: Tensor[(1), float]) -> Tensor[(4), bool] {
+            Gt(Relu(Add(Mul(%x, %w), %b)), 0.0)
+        }",
+        "let %f = fn(%x: Tensor[(4), float], %w: float, %b: Tensor[(1), float]) -> Tensor[(4), bool] {
+            Fused(Gt(Relu(Add(Mul(%x, %w), %b)), 0.0))
+        }",
+    );
+    assert_eq!(report, ["融合Mul、Add、Relu、Gt运算"]);
+}
+
+#[test]
+fn split_chain_at_shape_changes() {
+    // 矩阵乘法改变形状，两侧的算子链分别融合，只剩一个算子时不融合
+    let report = assert_fuse(
+        "let %f = fn(%x: Tensor[(4), float], %y: Tensor[(4), float]) -> float {
+            let %a = Add(Matmul(Relu(Sub(%x, %y)), Div(%x, 2.0)), 1.0)
+            Mul(Matmul(%x, %y), Add(%a, Matmul(Add(%x, %y), Mul(%y, %y))))
+        }",
+        "let %f = fn(%x: Tensor[(4), float], %y: Tensor[(4), float]) -> float {
+            let %a = Add(Matmul(Fused(Relu(Sub(%x, %y))), Div(%x, 2.0)), 1.0)
+            Fused(Mul(Matmul(%x, %y), Add(%a, Matmul(Add(%x, %y), Mul(%y, %y)))))
+        }",
+    );
+    assert_eq!(report, ["融合Sub、Relu运算", "融合Add、Mul运算"]);
+}
+
+#[test]
+fn keep_single_operators() {
+    let code = "let %f = fn(%x: Tensor[(2, 3), int], %n: int) -> Tensor[(2, 3), int] {
+            let %y = Relu(%x)
+            for %i in range(0, Sub(%n, 1), 1) {
+                %y = Add(%y, %x)
+            }
+            if Lt(%n, 0) { %y } else { Mul(%y, %n) }
+        }";
+    assert!(assert_fuse(code, code).is_empty());
+}
@@ -0,0 +1,90 @@
+//! 函数内联前后的 Mool IR
+
+use mool_ir::pass::{Inliner, Pass, PassManager};
+
+fn assert_inline(before: &str, after: &str) -> Vec<String> {
+    let mut inliner = Inliner::default();
+    let output = inliner.run(mool_ir::parse(before).unwrap());
+    let expected = mool_ir::parse(after).unwrap();
+    assert_eq!(
+        format!("{:?}", output),
+        format!("{:?}", expected),
+        "{}",
+        before
+    );
+    inliner.report()
+}
+
+#[test]
+fn inline_small_functions() {
+    // 简单的实参直接代入，其余的实参和函数体中的变量换成新的名字
+    let report = assert_inline(
+        "let %square = fn(%x: int) -> int {
+            let %y = Mul(%x, %x)
+            %y
+        }
+        let %f = fn(%a: int) -> int {
+            Add(%square(%a), %square(Sub(%a, 1)))
+        }",
+        "let %square = fn(%x: int) -> int {
+            let %y = Mul(%x, %x)
+            %y
+        }
+        let %f = fn(%a: int) -> int {
+            let %square_y = Mul(%a, %a)
+            let %square_x = Sub(%a, 1)
+            let %square_y1 = Mul(%square_x, %square_x)
+            Add(%square_y, %square_y1)
+        }",
+    );
+    assert_eq!(report, ["内联%square", "内联%square"]);
+}
+
+#[test]
+fn keep_noinline_and_unordered_calls() {
+    // #[noinline] 的函数、重名的函数、while 条件和有赋值的语句中的调用都保留
+    let code = "let %g = #[noinline] fn(%x: int) -> int { Add(%x, 1) }
+        let %h = fn(%x: int) -> int { Sub(%x, 1) }
+        let %h = fn(%x: int) -> int { Add(%x, 1) }
+        let %f = fn(%n: int) -> int {
+            let %i = %g(%n)
+            while Gt(%h(%i), 0) {
+                %i = Sub(%i, 1)
+            }
+            %i
+        }";
+    assert!(assert_inline(code, code).is_empty());
+}
+
+#[test]
+fn recursive_calls_are_expanded_once() {
+    // 函数体中的递归调用保留，每个调用处只展开一层，不会无限展开
+    let report = assert_inline(
+        "let %count = #[inline] fn(%n: int) -> int {
+            if Gt(%n, 0) { Add(%count(Sub(%n, 1)), 1) } else { 0 }
+        }
+        %count(3)",
+        "let %count = #[inline] fn(%n: int) -> int {
+            if Gt(%n, 0) { Add(%count(Sub(%n, 1)), 1) } else { 0 }
+        }
+        if Gt(3, 0) { Add(%count(Sub(3, 1)), 1) } else { 0 }",
+    );
+    assert_eq!(report, ["内联%count"]);
+}
+
+#[test]
+fn reject_recursive_functions() {
+    // 递归的函数不能生成代码，pass 执行之前就报错
+    let mut pass_manager = PassManager::new();
+    pass_manager.add(Box::new(Inliner::default()));
+    let programs = mool_ir::parse(
+        "let %count = fn(%n: int) -> int {
+            if Gt(%n, 0) { Add(%count(Sub(%n, 1)), 1) } else { 0 }
+        }",
+    )
+    .unwrap();
+    assert_eq!(
+        pass_manager.run(programs).unwrap_err(),
+        "输入的 Mool IR 不合法：函数count没有定义"
+    );
+}
@@ -0,0 +1,113 @@
+//! 内存规划的集成测试：存活区间、缓冲区复用和工作区的大小
+
+use mool_ir::ast::{Expr, Operator, Program};
+use mool_ir::pass::{workspace_size, MemoryPlan, Pass};
+
+fn plan(code: &str) -> (Vec<Program>, MemoryPlan) {
+    let mut plan = MemoryPlan::default();
+    let programs = plan.run(mool_ir::parse(code).unwrap());
+    (programs, plan)
+}
+
+/// 函数体的最后一个值
+fn result(programs: &[Program]) -> &Expr {
+    match &programs[0] {
+        Program::Let(_, Expr::Function(function)) => match function.body.last() {
+            Some(Program::Expr(expr)) => expr,
+            program => panic!("应该是表达式：{:?}", program),
+        },
+        program => panic!("应该是函数定义：{:?}", program),
+    }
+}
+
+#[test]
+fn reuse_buffers_by_liveness() {
+    let (programs, plan) = plan(
+        "let %f = fn(%x: Tensor[(4), float]) -> Tensor[(4), float] {
+            Relu(Relu(Relu(Relu(%x))))
+        }",
+    );
+    // 最内层和第三层 Relu 的区间不相交，复用同一块缓冲区；返回值不放入工作区
+    assert_eq!(
+        plan.report(),
+        [
+            "%f：3个中间张量复用2块缓冲区，峰值64字节，不复用时96字节",
+            "工作区共64字节"
+        ]
+    );
+    assert_eq!(plan.workspace(), 64);
+    assert_eq!(workspace_size(&programs), 64);
+    let offsets = match result(&programs) {
+        Expr::Operator(Operator::Relu(x)) => match x.as_ref() {
+            Expr::Operator(Operator::Workspace(x, second, 32)) => match x.as_ref() {
+                Expr::Operator(Operator::Relu(x)) => match x.as_ref() {
+                    Expr::Operator(Operator::Workspace(_, first, 32)) => (*first, *second),
+                    expr => panic!("应该放入工作区：{:?}", expr),
+                },
+                expr => panic!("应该是 Relu：{:?}", expr),
+            },
+            expr => panic!("应该放入工作区：{:?}", expr),
+        },
+        expr => panic!("返回值不应该放入工作区：{:?}", expr),
+    };
+    assert_eq!(offsets, (32, 0));
+    // 工作区算子可以解析，再次规划的结果相同
+    let programs = mool_ir::parse(
+        "let %f = fn(%x: Tensor[(4), float]) -> Tensor[(4), float] {
+            Relu(Workspace(Relu(%x), 64, 32))
+        }",
+    )
+    .unwrap();
+    assert_eq!(workspace_size(&programs), 96);
+    let mut again = MemoryPlan::default();
+    assert_eq!(workspace_size(&again.run(programs)), 32);
+}
+
+#[test]
+fn loop_variables_live_until_loop_end() {
+    let (programs, plan) = plan(
+        "let %f = fn(%n: int) -> Tensor[(2), int] {
+            let %a = Add(Tensor([1, 2]), Tensor([3, 4]))
+            let %s = Tensor([0, 0])
+            for %i in range(0, %n, 1) {
+                %s = Add(%s, Mul(%a, %a))
+            }
+            %s
+        }
+        let %g = fn(%x: Tensor[(2), bool]) -> Tensor[(2), bool] {
+            Eq(Lt(Tensor([1, 2]), Tensor([2, 1])), %x)
+        }",
+    );
+    // %a 存活到循环结束，循环中的 Mul 复用两个字面量之一的缓冲区；bool 张量按 8 字节对齐
+    assert_eq!(
+        plan.report(),
+        [
+            "%f：4个中间张量复用3块缓冲区，峰值48字节，不复用时64字节",
+            "%g：3个中间张量复用3块缓冲区，峰值40字节，不复用时40字节",
+            "工作区共88字节"
+        ]
+    );
+    // 最后一块缓冲区只用到 Lt 结果的 2 字节
+    assert_eq!(workspace_size(&programs), 82);
+}
+
+#[test]
+fn escaping_values_stay_on_heap() {
+    let (programs, plan) = plan(
+        "let %g = fn(%x: Tensor[(N), int]) -> Tensor[(N), int] {
+            Add(Add(%x, %x), %x)
+        }
+        let %h = fn(%x: Tensor[(2), int]) -> (Tensor[(2), int], Tensor[(2), int]) {
+            let %y = Add(%x, %x)
+            let %t = Mul(%x, %x)
+            %t = Add(%t, %x)
+            let %z = Sub(%x, %x)
+            if Gt(Matmul(%x, %x), 0) { let %z = %x } else { %z }
+            (%y, %g(Mul(%z, %x)))
+        }",
+    );
+    // 符号维度的大小未知；元组、调用的参数、赋值和代码块中重新定义的变量都在算子之外使用
+    assert!(plan.report().is_empty());
+    assert_eq!(plan.workspace(), 0);
+    assert_eq!(workspace_size(&programs), 0);
+}
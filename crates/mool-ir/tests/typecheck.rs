@@ -0,0 +1,125 @@
+//! 类型检查的集成测试：符号维度的解析和合一
+
+use mool_ir::ast::{Dim, Expr, Program, Type};
+use mool_ir::pass::typecheck;
+
+fn check(code: &str) -> Result<(), String> {
+    typecheck(&mool_ir::parse(code).unwrap())
+}
+
+#[test]
+fn parse_symbolic_dims() {
+    let programs =
+        mool_ir::parse("let %f = fn(%x: Tensor[(B, 128), float]) -> Tensor[(3),int] { %x }")
+            .unwrap();
+    let function = match &programs[0] {
+        Program::Let(_, Expr::Function(function)) => function,
+        program => panic!("应该是函数定义：{:?}", program),
+    };
+    let annotation = &function.args[0].annotation;
+    assert_eq!(
+        *annotation,
+        Type::Tensor(
+            vec![Dim::Symbol("B".to_string()), Dim::Const(128)],
+            Box::new(Type::Float)
+        )
+    );
+    assert_eq!(annotation.to_string(), "Tensor[(B, 128),float]");
+    assert_eq!(function.rtn.to_string(), "Tensor[(3),int]");
+}
+
+#[test]
+fn unify_symbols_at_calls() {
+    let square = "let %square = fn(%x: Tensor[(B, N), float], %y: Tensor[(B, N), float]) -> Tensor[(B, N), float] {
+            Add(Mul(%x, %x), %y)
+        }
+        let %dot = fn(%x: Tensor[(N), int], %y: Tensor[(N), int]) -> int {
+            Matmul(%x, %y)
+        }";
+    // 符号在调用处合一，返回值的类型用合一的结果替换
+    let code = format!(
+        "{}
+        let %f = fn(%x: Tensor[(S, 4), float]) -> Tensor[(S, 4), float] {{
+            %square(%x, %square(%x, %x))
+        }}
+        let %a = %dot(Tensor([1, 2, 3]), Tensor([4, 5, 6]))
+        Add(%a, 1)",
+        square
+    );
+    assert_eq!(check(&code), Ok(()));
+    let code = format!("{}\n%dot(Tensor([1, 2, 3]), Tensor([4, 5]))", square);
+    let error = check(&code).unwrap_err();
+    assert!(error.contains("符号维度N既为3又为2"), "{}", error);
+    let code = format!(
+        "{}
+        let %g = fn(%x: Tensor[(S, 4), float], %y: Tensor[(T, 4), float]) -> Tensor[(S, 4), float] {{
+            %square(%x, %y)
+        }}",
+        square
+    );
+    let error = check(&code).unwrap_err();
+    assert!(
+        error.contains("调用square的第2个参数时符号维度B既为S又为T"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn shapes_in_function_bodies() {
+    // 元素个数为 1 的张量广播到另一侧的形状
+    assert_eq!(
+        check(
+            "let %f = fn(%x: Tensor[(B), float], %b: Tensor[(1), float]) -> Tensor[(B), float] {
+                Relu(Add(%x, %b))
+            }"
+        ),
+        Ok(())
+    );
+    let error = check(
+        "let %f = fn(%x: Tensor[(B), float], %y: Tensor[(N), float]) -> Tensor[(B), float] {
+            Add(%x, %y)
+        }",
+    )
+    .unwrap_err();
+    assert!(error.contains("Add 算子中张量的形状不一致"), "{}", error);
+    let error = check(
+        "let %f = fn(%x: Tensor[(B), float]) -> Tensor[(4), float] {
+            %x
+        }",
+    )
+    .unwrap_err();
+    assert!(
+        error.contains("函数的返回值需要Tensor[(4),float]类型"),
+        "{}",
+        error
+    );
+    let error = check("let %f = fn(%x: int) -> int { %x }\n%f(1, 2)").unwrap_err();
+    assert!(error.contains("函数f需要1个参数"), "{}", error);
+}
+
+#[test]
+fn scalar_operands_match_element_type() {
+    // 标量与张量计算时广播，类型必须与张量的元素类型相同
+    assert_eq!(
+        check(
+            "let %f = fn(%x: Tensor[(4), float], %s: float) -> Tensor[(4), bool] {
+                Lt(Mul(%s, %x), 1.0)
+            }"
+        ),
+        Ok(())
+    );
+    let error = check(
+        "let %f = fn(%x: Tensor[(4), float]) -> Tensor[(4), float] {
+            Mul(%x, 2)
+        }",
+    )
+    .unwrap_err();
+    assert!(
+        error.contains("Mul 算子中张量的元素类型和标量的类型必须相同"),
+        "{}",
+        error
+    );
+    let error = check("let %f = fn(%x: int) -> float { Add(%x, 1.5) }").unwrap_err();
+    assert!(error.contains("Add 算子两侧的类型必须相同"), "{}", error);
+}
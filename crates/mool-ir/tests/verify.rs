@@ -0,0 +1,108 @@
+//! Mool IR 的合法性检查
+
+use mool_ir::ast::Program;
+use mool_ir::pass::{verify, DeadCode, Pass, PassManager};
+
+fn check(code: &str) -> Result<(), String> {
+    verify(&mool_ir::parse(code).unwrap())
+}
+
+#[test]
+fn accept_valid_programs() {
+    check(
+        "let %g = fn(%x: int) -> int { Add(%x, 1) }
+        let %h = %g
+        let %f = fn(%n: int) -> (int, int) {
+            let %i = 0
+            for %j in range(0, %n, 1) {
+                %i = Add(%i, %h(%j))
+                if Gt(%i, 10) { break } else { continue }
+            }
+            let (%a, %b) = (%i, %j)
+            (%a, (%b, %i).1)
+        }
+        %f(3)",
+    )
+    .unwrap();
+}
+
+#[test]
+fn reject_invalid_programs() {
+    let cases = [
+        ("let %f = fn(%x: int) -> int { %y }", "变量y没有定义"),
+        (
+            "let %f = fn(%x: int) -> int { %g(%x) }
+            let %g = fn(%x: int) -> int { %x }",
+            "函数g没有定义",
+        ),
+        (
+            "let %a = 1
+            let %f = fn(%x: int) -> int { Add(%x, %a) }",
+            "函数中不能使用外层的变量a",
+        ),
+        (
+            "let %a = 1
+            let %f = fn(%x: int) -> int { %a = %x }",
+            "函数中不能使用外层的变量a",
+        ),
+        (
+            "let %g = fn(%x: int) -> int { %x }
+            %g = 1",
+            "不能给函数g赋值",
+        ),
+        ("let %a = 1\n%a(2)", "a不是函数"),
+        (
+            "let %f = fn(%x: int, %x: int) -> int { %x }",
+            "函数的参数x重复",
+        ),
+        ("let (%a, %a) = (1, 2)", "元组解构中变量a重复"),
+        ("(1, 2).2", "元组只有2个元素，下标2越界"),
+        (
+            "if true { break } else { 0 }",
+            "break 和 continue 只能出现在循环中",
+        ),
+        (
+            "while true { let %f = fn(%x: int) -> int { continue } }",
+            "break 和 continue 只能出现在循环中",
+        ),
+    ];
+    for (code, error) in cases.iter() {
+        assert_eq!(check(code).unwrap_err(), *error, "{}", code);
+    }
+}
+
+/// 删除语句之后留下悬空引用的 pass
+struct Broken;
+
+impl Pass for Broken {
+    fn name(&self) -> &'static str {
+        "broken"
+    }
+
+    fn run(&mut self, mut programs: Vec<Program>) -> Vec<Program> {
+        programs.remove(0);
+        programs
+    }
+}
+
+#[test]
+fn verify_after_each_pass() {
+    let code = "let %f = fn(%x: int) -> int {
+            let %unused = 1
+            Add(%x, 1)
+        }
+        %f(1)";
+    let mut pass_manager = PassManager::new();
+    pass_manager.add(Box::new(DeadCode::default()));
+    pass_manager.add(Box::new(Broken));
+    let error = pass_manager.run(mool_ir::parse(code).unwrap()).unwrap_err();
+    assert_eq!(error, "broken之后 Mool IR 不合法：函数f没有定义");
+    // 关闭检查时按 pass 的输出原样返回
+    pass_manager.verify(false);
+    let output = pass_manager.run(mool_ir::parse(code).unwrap()).unwrap();
+    assert_eq!(output.len(), 1);
+    // 输入不合法时不执行任何 pass
+    pass_manager.verify(true);
+    let error = pass_manager.run(mool_ir::parse("%a").unwrap()).unwrap_err();
+    assert_eq!(error, "输入的 Mool IR 不合法：变量a没有定义");
+}
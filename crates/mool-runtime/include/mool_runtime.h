@@ -0,0 +1,51 @@
+#ifndef MOOL_RUNTIME_H
+#define MOOL_RUNTIME_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* 张量的元素类型 */
+enum {
+    MOOL_INT = 0,   /* int64_t */
+    MOOL_FLOAT = 1, /* double */
+    MOOL_BOOL = 2,  /* 占一个字节，0 或 1 */
+};
+
+/* 张量描述符，数据按行优先连续存放，strides 以元素为单位 */
+typedef struct mool_tensor {
+    void *data;
+    int32_t dtype;
+    int64_t rank;
+    int64_t *shape;
+    int64_t *strides;
+    int64_t refcount;
+} mool_tensor;
+
+/* 分配 rank 维、形状为 shape 的张量，数据初始化为 0，引用计数为 1，参数不正确或内存不足时返回 NULL */
+mool_tensor *mool_tensor_alloc(int32_t dtype, int64_t rank, const int64_t *shape);
+/* 增加引用计数 */
+void mool_tensor_retain(mool_tensor *tensor);
+/* 减少引用计数，减到 0 时释放张量 */
+void mool_tensor_release(mool_tensor *tensor);
+/* 张量的元素个数 */
+int64_t mool_tensor_numel(const mool_tensor *tensor);
+
+/* 分配数据在工作区中 offset 处的张量，数据不初始化，释放时只释放描述符，参数不正确或超出工作区时返回 NULL */
+mool_tensor *mool_tensor_alloc_at(int32_t dtype, int64_t rank, const int64_t *shape, int64_t offset);
+/* 使用调用方提供的、按 8 字节对齐的 size 字节内存作为工作区，在执行生成的代码之前调用
+ * 成功时返回 0，data 没有对齐或 size 为负数时返回 -1 */
+int32_t mool_workspace_init(void *data, int64_t size);
+/* 确保工作区至少有 size 字节，没有工作区时由运行时分配，生成的入口函数在开头调用
+ * 调用方提供的工作区不够大、size 为负数或内存不足时返回 NULL */
+void *mool_workspace_reserve(int64_t size);
+/* 工作区的字节数，没有工作区时为 0 */
+int64_t mool_workspace_size(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,337 @@
+//! Mool 的运行时：生成的代码中张量为指向 `MoolTensor` 描述符的指针，
+//! 数据、形状和步长都分配在堆上，用引用计数管理生命周期。
+//!
+//! 描述符按 C 的布局排列，`include/mool_runtime.h` 是对应的 C 头文件。
+//! LLVM 后端生成的目标文件需要链接 `libmool_runtime.a`。
+//!
+//! 内存规划过的中间张量的数据放在一块全局的工作区中，生成的代码不能在多个线程中同时执行。
+//!
+//! `extern "C"` 函数不会 panic，参数不正确或者内存不足时返回空指针或错误码，
+//! 生成的代码在分配失败时执行 trap。
+
+use std::alloc::{self, Layout};
+use std::convert::TryFrom;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+/// 张量的元素类型，取值与 `MoolTensor::dtype` 一致
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DType {
+    Int = 0,
+    Float = 1,
+    Bool = 2,
+}
+
+impl DType {
+    pub fn from_raw(raw: i32) -> Option<Self> {
+        match raw {
+            0 => Some(DType::Int),
+            1 => Some(DType::Float),
+            2 => Some(DType::Bool),
+            _ => None,
+        }
+    }
+
+    /// 元素占用的字节数，int 为 i64，float 为 f64，bool 占一个字节
+    pub fn size(self) -> usize {
+        match self {
+            DType::Int | DType::Float => 8,
+            DType::Bool => 1,
+        }
+    }
+}
+
+/// 张量描述符
+///
+/// 数据按行优先连续存放，strides 以元素为单位。refcount 为 0 时释放描述符和数据。
+#[repr(C)]
+#[derive(Debug)]
+pub struct MoolTensor {
+    pub data: *mut u8,
+    pub dtype: i32,
+    pub rank: i64,
+    pub shape: *mut i64,
+    pub strides: *mut i64,
+    pub refcount: i64,
+}
+
+/// 数据按 8 字节对齐，int 和 float 元素可以直接读写
+const ALIGN: usize = 8;
+
+impl MoolTensor {
+    pub fn dtype(&self) -> DType {
+        DType::from_raw(self.dtype).expect("张量的元素类型不正确")
+    }
+
+    pub fn shape(&self) -> &[i64] {
+        unsafe { slice::from_raw_parts(self.shape, self.rank as usize) }
+    }
+
+    pub fn strides(&self) -> &[i64] {
+        unsafe { slice::from_raw_parts(self.strides, self.rank as usize) }
+    }
+
+    /// 元素个数，rank 为 0 时为 1
+    pub fn numel(&self) -> usize {
+        self.shape().iter().product::<i64>() as usize
+    }
+
+    /// 数据占用的字节数
+    pub fn size(&self) -> usize {
+        self.numel() * self.dtype().size()
+    }
+
+    fn layout(&self) -> Layout {
+        Layout::from_size_align(self.size().max(1), ALIGN).unwrap()
+    }
+}
+
+/// 分配 rank 维、形状为 shape 的张量，数据初始化为 0，引用计数为 1
+///
+/// 元素类型或形状不正确、内存不足时返回空指针。
+///
+/// # Safety
+///
+/// rank 大于 0 时 shape 需要指向 rank 个 i64。
+#[no_mangle]
+pub unsafe extern "C" fn mool_tensor_alloc(
+    dtype: i32,
+    rank: i64,
+    shape: *const i64,
+) -> *mut MoolTensor {
+    let mut tensor = match descriptor(dtype, rank, shape) {
+        Some(tensor) => tensor,
+        None => return ptr::null_mut(),
+    };
+    tensor.data = alloc::alloc_zeroed(tensor.layout());
+    if tensor.data.is_null() {
+        free_descriptor(&tensor);
+        return ptr::null_mut();
+    }
+    Box::into_raw(tensor)
+}
+
+/// 分配数据在工作区中 offset 处的张量，引用计数为 1，数据不初始化
+///
+/// 释放张量时只释放描述符，数据留在工作区中。
+/// 元素类型或形状不正确、数据超出工作区时返回空指针。
+///
+/// # Safety
+///
+/// rank 大于 0 时 shape 需要指向 rank 个 i64。
+#[no_mangle]
+pub unsafe extern "C" fn mool_tensor_alloc_at(
+    dtype: i32,
+    rank: i64,
+    shape: *const i64,
+    offset: i64,
+) -> *mut MoolTensor {
+    let mut tensor = match descriptor(dtype, rank, shape) {
+        Some(tensor) => tensor,
+        None => return ptr::null_mut(),
+    };
+    let size = WORKSPACE_SIZE.load(Ordering::Relaxed);
+    let data = WORKSPACE_DATA.load(Ordering::Relaxed);
+    let outside = match usize::try_from(offset) {
+        Ok(offset) => offset
+            .checked_add(tensor.size())
+            .filter(|end| *end <= size)
+            .is_none(),
+        Err(_) => true,
+    };
+    if data.is_null() || outside {
+        free_descriptor(&tensor);
+        return ptr::null_mut();
+    }
+    // 没有元素的张量可以位于工作区的末尾，数据指向工作区中的最后一个字节，
+    // 这样释放时仍然能认出它在工作区中
+    let offset = (offset as usize).min(size.saturating_sub(1));
+    tensor.data = data.add(offset);
+    Box::into_raw(tensor)
+}
+
+/// 分配描述符、形状和步长，数据为空指针，元素类型或形状不正确、数据的字节数溢出时返回 None
+unsafe fn descriptor(dtype: i32, rank: i64, shape: *const i64) -> Option<Box<MoolTensor>> {
+    let element_size = DType::from_raw(dtype)?.size();
+    // rank 为 0 时 shape 可以为空指针
+    let shape: Box<[i64]> = match rank {
+        0 => Box::new([]),
+        _ if rank < 0 || shape.is_null() => return None,
+        _ => slice::from_raw_parts(shape, rank as usize).into(),
+    };
+    let mut size = element_size;
+    for dim in shape.iter() {
+        size = size.checked_mul(usize::try_from(*dim).ok()?)?;
+    }
+    Layout::from_size_align(size.max(1), ALIGN).ok()?;
+    let mut strides = vec![1; rank as usize].into_boxed_slice();
+    for i in (1..shape.len()).rev() {
+        strides[i - 1] = strides[i] * shape[i];
+    }
+    Some(Box::new(MoolTensor {
+        data: ptr::null_mut(),
+        dtype,
+        rank,
+        shape: Box::into_raw(shape) as *mut i64,
+        strides: Box::into_raw(strides) as *mut i64,
+        refcount: 1,
+    }))
+}
+
+/// 释放描述符、形状和步长，不释放数据
+unsafe fn free_descriptor(tensor: &MoolTensor) {
+    let rank = tensor.rank as usize;
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+        tensor.shape,
+        rank,
+    )));
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+        tensor.strides,
+        rank,
+    )));
+}
+
+/// 增加引用计数，tensor 为空指针时什么也不做
+///
+/// # Safety
+///
+/// tensor 需要为空指针或者 mool_tensor_alloc 返回的、没有释放的张量。
+#[no_mangle]
+pub unsafe extern "C" fn mool_tensor_retain(tensor: *mut MoolTensor) {
+    if let Some(tensor) = tensor.as_mut() {
+        tensor.refcount += 1;
+    }
+}
+
+/// 减少引用计数，减到 0 时释放张量，tensor 为空指针时什么也不做
+///
+/// # Safety
+///
+/// tensor 需要为空指针或者 mool_tensor_alloc 返回的、没有释放的张量。
+#[no_mangle]
+pub unsafe extern "C" fn mool_tensor_release(tensor: *mut MoolTensor) {
+    let refcount = match tensor.as_mut() {
+        Some(tensor) => {
+            tensor.refcount -= 1;
+            tensor.refcount
+        }
+        None => return,
+    };
+    if refcount > 0 {
+        return;
+    }
+    let tensor = Box::from_raw(tensor);
+    if !in_workspace(tensor.data) {
+        alloc::dealloc(tensor.data, tensor.layout());
+    }
+    free_descriptor(&tensor);
+}
+
+/// 张量的元素个数
+///
+/// # Safety
+///
+/// tensor 需要为 mool_tensor_alloc 返回的、没有释放的张量。
+#[no_mangle]
+pub unsafe extern "C" fn mool_tensor_numel(tensor: *const MoolTensor) -> i64 {
+    (*tensor).numel() as i64
+}
+
+/// 工作区的起始地址，没有工作区时为空指针
+static WORKSPACE_DATA: AtomicPtr<u8> = AtomicPtr::new(ptr::null_mut());
+/// 工作区的字节数
+static WORKSPACE_SIZE: AtomicUsize = AtomicUsize::new(0);
+/// 工作区是否由运行时分配，调用方提供的工作区由调用方释放
+static WORKSPACE_OWNED: AtomicBool = AtomicBool::new(false);
+
+/// 使用调用方提供的 size 字节内存作为工作区，在执行生成的代码之前调用
+///
+/// 运行时之前分配的工作区被释放。工作区中的张量都释放之前 data 需要一直有效。
+/// 成功时返回 0，data 没有按 8 字节对齐或 size 为负数时返回 -1，工作区不变。
+///
+/// # Safety
+///
+/// data 需要可以读写 size 个字节。
+#[no_mangle]
+pub unsafe extern "C" fn mool_workspace_init(data: *mut u8, size: i64) -> i32 {
+    if data as usize % ALIGN != 0 || size < 0 {
+        return -1;
+    }
+    free_workspace();
+    WORKSPACE_DATA.store(data, Ordering::Relaxed);
+    WORKSPACE_SIZE.store(size as usize, Ordering::Relaxed);
+    WORKSPACE_OWNED.store(false, Ordering::Relaxed);
+    0
+}
+
+/// 确保工作区至少有 size 字节，返回工作区的起始地址，生成的入口函数在开头调用
+///
+/// 没有工作区时由运行时分配，运行时分配的工作区不够大时重新分配。
+/// 调用方提供的工作区不够大、size 为负数或者内存不足时返回空指针，工作区不变，
+/// 之后在工作区中分配张量也会失败。
+///
+/// # Safety
+///
+/// 重新分配时工作区中不能有还在使用的张量。
+#[no_mangle]
+pub unsafe extern "C" fn mool_workspace_reserve(size: i64) -> *mut u8 {
+    let size = match usize::try_from(size) {
+        Ok(size) => size,
+        Err(_) => return ptr::null_mut(),
+    };
+    let data = WORKSPACE_DATA.load(Ordering::Relaxed);
+    let current = WORKSPACE_SIZE.load(Ordering::Relaxed);
+    if !data.is_null() && current >= size {
+        return data;
+    }
+    if !data.is_null() && !WORKSPACE_OWNED.load(Ordering::Relaxed) {
+        return ptr::null_mut();
+    }
+    let layout = match workspace_layout(size) {
+        Some(layout) => layout,
+        None => return ptr::null_mut(),
+    };
+    let fresh = alloc::alloc_zeroed(layout);
+    if fresh.is_null() {
+        return ptr::null_mut();
+    }
+    free_workspace();
+    let data = fresh;
+    WORKSPACE_DATA.store(data, Ordering::Relaxed);
+    WORKSPACE_SIZE.store(size, Ordering::Relaxed);
+    WORKSPACE_OWNED.store(true, Ordering::Relaxed);
+    data
+}
+
+/// 工作区的字节数，没有工作区时为 0
+#[no_mangle]
+pub extern "C" fn mool_workspace_size() -> i64 {
+    WORKSPACE_SIZE.load(Ordering::Relaxed) as i64
+}
+
+fn workspace_layout(size: usize) -> Option<Layout> {
+    Layout::from_size_align(size.max(1), ALIGN).ok()
+}
+
+/// 释放运行时分配的工作区，调用方提供的工作区只是不再使用
+unsafe fn free_workspace() {
+    let data = WORKSPACE_DATA.swap(ptr::null_mut(), Ordering::Relaxed);
+    let size = WORKSPACE_SIZE.swap(0, Ordering::Relaxed);
+    if !data.is_null() && WORKSPACE_OWNED.swap(false, Ordering::Relaxed) {
+        // 分配时已经检查过 size
+        if let Some(layout) = workspace_layout(size) {
+            alloc::dealloc(data, layout);
+        }
+    }
+}
+
+/// 数据是否在工作区中，工作区中的张量的数据从不超出工作区的末尾
+///
+/// 空的工作区也至少占一个字节，其中只能有没有元素的张量。
+fn in_workspace(data: *mut u8) -> bool {
+    let start = WORKSPACE_DATA.load(Ordering::Relaxed) as usize;
+    let size = WORKSPACE_SIZE.load(Ordering::Relaxed);
+    start != 0 && (start..start + size.max(1)).contains(&(data as usize))
+}
@@ -0,0 +1,52 @@
+//! 运行时张量的分配、引用计数和释放
+
+use mool_runtime::DType;
+use mool_runtime::{mool_tensor_alloc, mool_tensor_numel, mool_tensor_release, mool_tensor_retain};
+
+#[test]
+fn alloc_contiguous_tensor() {
+    unsafe {
+        let tensor = mool_tensor_alloc(DType::Float as i32, 3, [2, 3, 4].as_ptr());
+        assert_eq!((*tensor).dtype(), DType::Float);
+        assert_eq!((*tensor).shape(), [2, 3, 4]);
+        assert_eq!((*tensor).strides(), [12, 4, 1]);
+        assert_eq!(mool_tensor_numel(tensor), 24);
+        assert_eq!((*tensor).size(), 192);
+        let data = std::slice::from_raw_parts((*tensor).data as *const f64, 24);
+        assert!(data.iter().all(|value| *value == 0.0));
+        assert_eq!((*tensor).refcount, 1);
+        mool_tensor_retain(tensor);
+        assert_eq!((*tensor).refcount, 2);
+        mool_tensor_release(tensor);
+        assert_eq!((*tensor).refcount, 1);
+        mool_tensor_release(tensor);
+    }
+}
+
+#[test]
+fn scalar_and_empty_tensors() {
+    unsafe {
+        let scalar = mool_tensor_alloc(DType::Int as i32, 0, std::ptr::null());
+        assert_eq!(mool_tensor_numel(scalar), 1);
+        mool_tensor_release(scalar);
+        let empty = mool_tensor_alloc(DType::Bool as i32, 2, [0, 5].as_ptr());
+        assert_eq!(mool_tensor_numel(empty), 0);
+        assert_eq!((*empty).strides(), [5, 1]);
+        mool_tensor_release(empty);
+        // 空指针什么也不做
+        mool_tensor_retain(std::ptr::null_mut());
+        mool_tensor_release(std::ptr::null_mut());
+    }
+}
+
+#[test]
+fn invalid_arguments() {
+    unsafe {
+        // 元素类型、形状不正确或者数据的字节数溢出时返回空指针，不会 panic
+        assert!(mool_tensor_alloc(7, 1, [3].as_ptr()).is_null());
+        assert!(mool_tensor_alloc(DType::Int as i32, 2, [2, -1].as_ptr()).is_null());
+        assert!(mool_tensor_alloc(DType::Int as i32, -1, std::ptr::null()).is_null());
+        assert!(mool_tensor_alloc(DType::Int as i32, 1, std::ptr::null()).is_null());
+        assert!(mool_tensor_alloc(DType::Float as i32, 2, [i64::MAX, 2].as_ptr()).is_null());
+    }
+}
@@ -0,0 +1,55 @@
+//! 工作区中的张量和工作区的分配
+
+use mool_runtime::DType;
+use mool_runtime::{
+    mool_tensor_alloc_at, mool_tensor_release, mool_workspace_init, mool_workspace_reserve,
+    mool_workspace_size,
+};
+
+// 工作区是全局的，放在同一个测试中按顺序执行
+#[test]
+fn tensors_in_workspace() {
+    unsafe {
+        let mut buffer = [7i64; 8];
+        let data = buffer.as_mut_ptr() as *mut u8;
+        assert_eq!(mool_workspace_init(data, 64), 0);
+        assert_eq!(mool_workspace_size(), 64);
+        let tensor = mool_tensor_alloc_at(DType::Int as i32, 1, [3].as_ptr(), 16);
+        assert_eq!((*tensor).data, data.add(16));
+        assert_eq!((*tensor).shape(), [3]);
+        *((*tensor).data as *mut i64) = 42;
+        // 释放张量时数据留在工作区中
+        mool_tensor_release(tensor);
+        assert_eq!(buffer, [7, 7, 42, 7, 7, 7, 7, 7]);
+        // 没有元素的张量可以在工作区的末尾，释放时不释放数据
+        let empty = mool_tensor_alloc_at(DType::Int as i32, 1, [0].as_ptr(), 64);
+        assert!(!empty.is_null());
+        assert_eq!((*empty).size(), 0);
+        mool_tensor_release(empty);
+        // 超出工作区时返回空指针
+        assert!(mool_tensor_alloc_at(DType::Int as i32, 1, [3].as_ptr(), 48).is_null());
+        assert!(mool_tensor_alloc_at(DType::Int as i32, 1, [0].as_ptr(), 72).is_null());
+        assert!(mool_tensor_alloc_at(DType::Int as i32, 1, [1].as_ptr(), -8).is_null());
+        assert!(mool_tensor_alloc_at(DType::Int as i32, 1, [1].as_ptr(), i64::MAX).is_null());
+        // 调用方提供的工作区足够大时直接使用，不够大时返回空指针，工作区不变
+        assert_eq!(mool_workspace_reserve(32), data);
+        assert!(mool_workspace_reserve(128).is_null());
+        assert!(mool_workspace_reserve(-1).is_null());
+        assert_eq!(mool_workspace_size(), 64);
+        // 没有对齐的内存不能作为工作区
+        assert_eq!(mool_workspace_init(data.add(1), 32), -1);
+        assert_eq!(mool_workspace_init(data, -1), -1);
+        assert_eq!(mool_workspace_size(), 64);
+        // 没有工作区时由运行时分配，不够大时重新分配
+        assert_eq!(mool_workspace_init(std::ptr::null_mut(), 0), 0);
+        assert_eq!(mool_workspace_size(), 0);
+        let data = mool_workspace_reserve(64);
+        assert!(!data.is_null());
+        assert_eq!(mool_workspace_size(), 64);
+        assert_eq!(mool_workspace_reserve(128), mool_workspace_reserve(16));
+        assert_eq!(mool_workspace_size(), 128);
+        let tensor = mool_tensor_alloc_at(DType::Float as i32, 2, [4, 4].as_ptr(), 0);
+        assert_eq!((*tensor).size(), 128);
+        mool_tensor_release(tensor);
+    }
+}
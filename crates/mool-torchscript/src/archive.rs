@@ -0,0 +1,423 @@
+mod pickle;
+
+use pickle::{Shared, Value};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{self, Read, Seek};
+use std::rc::Rc;
+
+/// torch.jit.save 保存的模型文件
+#[derive(Serialize, Debug)]
+pub struct Archive {
+    /// code/ 目录下的 TorchScript 源码：(路径, 源码)，按路径排序
+    pub code: Vec<(String, String)>,
+    /// constants.pkl 中的常量，对应源码中的 CONSTANTS.c0、CONSTANTS.c1 ...
+    pub constants: Vec<Attribute>,
+    /// data.pkl 中保存的模块对象
+    pub module: Module,
+}
+
+/// TorchScript 模块对象，class 为去掉 __torch__. 前缀的类名
+#[derive(Serialize, Debug)]
+pub struct Module {
+    pub class: String,
+    pub attributes: Vec<(String, Attribute)>,
+}
+
+#[derive(Serialize, Debug)]
+pub enum Attribute {
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Tensor(Tensor),
+    Module(Module),
+    /// 暂不支持的值，只保留类型名
+    Other(String),
+}
+
+/// 按行优先顺序展开的张量
+#[derive(Serialize, Debug, Clone)]
+pub struct Tensor {
+    pub shape: Vec<usize>,
+    pub data: TensorData,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub enum TensorData {
+    Int(Vec<i64>),
+    Float(Vec<f64>),
+    Bool(Vec<bool>),
+}
+
+impl Tensor {
+    pub fn numel(&self) -> usize {
+        match &self.data {
+            TensorData::Int(data) => data.len(),
+            TensorData::Float(data) => data.len(),
+            TensorData::Bool(data) => data.len(),
+        }
+    }
+
+    /// Mool 只支持一维张量，多维张量按行优先展开
+    pub fn mool_type(&self) -> String {
+        let dtype = match &self.data {
+            TensorData::Int(_) => "int",
+            TensorData::Float(_) => "float",
+            TensorData::Bool(_) => "bool",
+        };
+        format!("Tensor[({}),{}]", self.numel(), dtype)
+    }
+
+    /// 生成等价的 torch.tensor 字面量
+    pub fn to_torchscript(&self) -> String {
+        let literals: Vec<String> = match &self.data {
+            TensorData::Int(data) => data.iter().map(|n| n.to_string()).collect(),
+            TensorData::Float(data) => data
+                .iter()
+                .map(|f| super::codegen::float_literal(*f))
+                .collect(),
+            TensorData::Bool(data) => data
+                .iter()
+                .map(|b| if *b { "True" } else { "False" }.to_string())
+                .collect(),
+        };
+        format!("torch.tensor([{}])", literals.join(", "))
+    }
+}
+
+impl Module {
+    /// 模块及其子模块中的所有张量属性（参数和缓冲区），名称以 . 连接
+    pub fn parameters(&self) -> Vec<(String, &Tensor)> {
+        let mut parameters = Vec::new();
+        for (name, attribute) in self.attributes.iter() {
+            match attribute {
+                Attribute::Tensor(tensor) => parameters.push((name.clone(), tensor)),
+                Attribute::Module(module) => {
+                    for (child, tensor) in module.parameters() {
+                        parameters.push((format!("{}.{}", name, child), tensor));
+                    }
+                }
+                _ => {}
+            }
+        }
+        parameters
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, attribute)| attribute)
+    }
+}
+
+impl Archive {
+    /// 合并所有源码，并把 CONSTANTS.cN 替换为常量张量的字面量
+    pub fn source(&self) -> String {
+        let code: Vec<&str> = self.code.iter().map(|(_, code)| code.as_str()).collect();
+        let code = code.join("\n");
+        let mut output = String::with_capacity(code.len());
+        let mut rest = code.as_str();
+        while let Some(start) = rest.find("CONSTANTS.c") {
+            output.push_str(&rest[..start]);
+            let after = &rest[start + "CONSTANTS.c".len()..];
+            let digits = after.len() - after.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let index: usize = match after[..digits].parse() {
+                Ok(index) => index,
+                Err(_) => panic!("无法解析常量{}", &rest[start..start + 12]),
+            };
+            match self.constants.get(index) {
+                Some(Attribute::Tensor(tensor)) => output.push_str(&tensor.to_torchscript()),
+                Some(_) => panic!("暂不支持非张量常量CONSTANTS.c{}", index),
+                None => panic!("常量CONSTANTS.c{}不存在", index),
+            }
+            rest = &after[digits..];
+        }
+        output.push_str(rest);
+        output
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// 读取 torch.jit.save 保存的 zip 压缩包
+pub fn load<R: Read + Seek>(reader: R) -> io::Result<Archive> {
+    let mut zip = zip::ZipArchive::new(reader)?;
+    // 所有文件位于同一个顶层目录下，目录名由保存时的文件名决定
+    let prefix = match zip
+        .file_names()
+        .find(|name| name.ends_with("/data.pkl") && name.matches('/').count() == 1)
+    {
+        Some(name) => name.trim_end_matches("data.pkl").to_string(),
+        None => {
+            return Err(invalid(
+                "不是 TorchScript 模型文件：缺少 data.pkl".to_string(),
+            ))
+        }
+    };
+    let mut names: Vec<String> = zip.file_names().map(String::from).collect();
+    names.sort();
+    // 源码
+    let mut code = Vec::new();
+    for name in names.iter() {
+        if name.starts_with(&format!("{}code/", prefix)) && name.ends_with(".py") {
+            let source = String::from_utf8(read_file(&mut zip, name)?)
+                .map_err(|_| invalid(format!("{}不是合法的 UTF-8", name)))?;
+            code.push((name[prefix.len()..].to_string(), source));
+        }
+    }
+    // 常量的存储位于 constants/ 目录下
+    let constants_name = format!("{}constants.pkl", prefix);
+    let constants = if names.contains(&constants_name) {
+        let value = pickle::load(&read_file(&mut zip, &constants_name)?)?;
+        let mut loader = Loader::new(&mut zip, format!("{}constants/", prefix));
+        let items = match value {
+            Value::Tuple(items) => items,
+            Value::List(items) => items.borrow().clone(),
+            _ => return Err(invalid("constants.pkl 必须为元组".to_string())),
+        };
+        items
+            .into_iter()
+            .map(|item| loader.attribute(item))
+            .collect::<io::Result<Vec<Attribute>>>()?
+    } else {
+        Vec::new()
+    };
+    // 模块对象，张量的存储位于 data/ 目录下
+    let value = pickle::load(&read_file(&mut zip, &format!("{}data.pkl", prefix))?)?;
+    let module = match Loader::new(&mut zip, format!("{}data/", prefix)).attribute(value)? {
+        Attribute::Module(module) => module,
+        _ => {
+            return Err(invalid(
+                "data.pkl 中保存的不是 TorchScript 模块".to_string(),
+            ))
+        }
+    };
+    Ok(Archive {
+        code,
+        constants,
+        module,
+    })
+}
+
+fn read_file<R: Read + Seek>(zip: &mut zip::ZipArchive<R>, name: &str) -> io::Result<Vec<u8>> {
+    let mut file = zip.by_name(name)?;
+    let mut bytes = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// 把 pickle 的值解释为模块、张量等属性
+struct Loader<'a, R: Read + Seek> {
+    zip: &'a mut zip::ZipArchive<R>,
+    /// 张量存储所在的目录
+    storage_dir: String,
+    /// 多个张量可以共享同一个存储
+    storages: HashMap<String, Vec<u8>>,
+    /// 正在读取的模块的状态，memo 中的引用可以构成循环
+    modules: Vec<Shared<Option<Value>>>,
+}
+
+impl<'a, R: Read + Seek> Loader<'a, R> {
+    fn new(zip: &'a mut zip::ZipArchive<R>, storage_dir: String) -> Self {
+        Loader {
+            zip,
+            storage_dir,
+            storages: HashMap::new(),
+            modules: Vec::new(),
+        }
+    }
+
+    fn attribute(&mut self, value: Value) -> io::Result<Attribute> {
+        Ok(match value {
+            Value::None => Attribute::None,
+            Value::Bool(b) => Attribute::Bool(b),
+            Value::Int(n) => Attribute::Int(n),
+            Value::Float(f) => Attribute::Float(f),
+            Value::Str(s) => Attribute::Str(s),
+            Value::Object { class, args, state } => match class.as_str() {
+                "torch._utils._rebuild_tensor_v2" => Attribute::Tensor(self.tensor(args)?),
+                // nn.Parameter 包装了一个张量
+                "torch._utils._rebuild_parameter" => match args.into_iter().next() {
+                    Some(data) => self.attribute(data)?,
+                    None => return Err(invalid("_rebuild_parameter 缺少参数".to_string())),
+                },
+                _ if class.starts_with("__torch__.") => match state.borrow().clone() {
+                    // 没有自定义 __getstate__ 的对象，状态为属性字典
+                    Some(Value::Dict(items)) => {
+                        if self.modules.iter().any(|module| Rc::ptr_eq(module, &state)) {
+                            return Err(invalid(format!("{}的属性中引用了自身", class)));
+                        }
+                        self.modules.push(state.clone());
+                        let items = items.borrow().clone();
+                        let mut attributes = Vec::with_capacity(items.len());
+                        for (key, value) in items {
+                            match key {
+                                Value::Str(name) => attributes.push((name, self.attribute(value)?)),
+                                _ => return Err(invalid(format!("{}的属性名必须为字符串", class))),
+                            }
+                        }
+                        self.modules.pop();
+                        Attribute::Module(Module {
+                            class: class["__torch__.".len()..].to_string(),
+                            attributes,
+                        })
+                    }
+                    _ => Attribute::Other(class),
+                },
+                _ => Attribute::Other(class),
+            },
+            Value::Bytes => Attribute::Other("bytes".to_string()),
+            Value::Tuple(_) => Attribute::Other("tuple".to_string()),
+            Value::List(_) => Attribute::Other("list".to_string()),
+            Value::Dict(_) => Attribute::Other("dict".to_string()),
+            Value::Global(name) => Attribute::Other(name),
+            Value::Persistent(_) => Attribute::Other("storage".to_string()),
+        })
+    }
+
+    /// _rebuild_tensor_v2(storage, storage_offset, size, stride, requires_grad, backward_hooks)
+    fn tensor(&mut self, args: Vec<Value>) -> io::Result<Tensor> {
+        let (storage, offset, size, stride) = match args.as_slice() {
+            [Value::Persistent(storage), Value::Int(offset), Value::Tuple(size), Value::Tuple(stride), ..] => {
+                (storage, *offset as usize, ints(size)?, ints(stride)?)
+            }
+            _ => return Err(invalid("_rebuild_tensor_v2 的参数不正确".to_string())),
+        };
+        // persistent_id 为 ('storage', 存储类型, 文件名, 设备, 元素个数)
+        let (dtype, key) = match storage.as_ref() {
+            Value::Tuple(items) => match items.as_slice() {
+                [Value::Str(kind), Value::Global(dtype), Value::Str(key), ..]
+                    if kind == "storage" =>
+                {
+                    (dtype.clone(), key.clone())
+                }
+                _ => return Err(invalid("张量存储的 persistent_id 不正确".to_string())),
+            },
+            _ => return Err(invalid("张量存储的 persistent_id 不正确".to_string())),
+        };
+        if !self.storages.contains_key(&key) {
+            let bytes = read_file(self.zip, &format!("{}{}", self.storage_dir, key))?;
+            self.storages.insert(key.clone(), bytes);
+        }
+        let bytes = &self.storages[&key];
+        let element_size = match dtype.as_str() {
+            "torch.DoubleStorage" | "torch.LongStorage" => 8,
+            "torch.FloatStorage" | "torch.IntStorage" => 4,
+            "torch.HalfStorage" | "torch.BFloat16Storage" | "torch.ShortStorage" => 2,
+            "torch.CharStorage" | "torch.ByteStorage" | "torch.BoolStorage" => 1,
+            _ => return Err(invalid(format!("暂不支持{}类型的张量", dtype))),
+        };
+        // 按行优先顺序计算每个元素在存储中的位置
+        let numel: usize = size.iter().product();
+        let mut index = vec![0; size.len()];
+        let mut elements = Vec::with_capacity(numel);
+        for _ in 0..numel {
+            let position = offset
+                + index
+                    .iter()
+                    .zip(stride.iter())
+                    .map(|(i, s)| i * s)
+                    .sum::<usize>();
+            let start = position * element_size;
+            match bytes.get(start..start + element_size) {
+                Some(element) => elements.push(element),
+                None => return Err(invalid(format!("张量超出存储{}的范围", key))),
+            }
+            for dim in (0..size.len()).rev() {
+                index[dim] += 1;
+                if index[dim] < size[dim] {
+                    break;
+                }
+                index[dim] = 0;
+            }
+        }
+        // 存储为小端字节序
+        let data = match dtype.as_str() {
+            "torch.DoubleStorage" => TensorData::Float(
+                elements
+                    .iter()
+                    .map(|e| f64::from_le_bytes((*e).try_into().unwrap()))
+                    .collect(),
+            ),
+            "torch.FloatStorage" => TensorData::Float(
+                elements
+                    .iter()
+                    .map(|e| f32::from_le_bytes((*e).try_into().unwrap()) as f64)
+                    .collect(),
+            ),
+            "torch.HalfStorage" => TensorData::Float(
+                elements
+                    .iter()
+                    .map(|e| half_to_f32(u16::from_le_bytes((*e).try_into().unwrap())) as f64)
+                    .collect(),
+            ),
+            "torch.BFloat16Storage" => TensorData::Float(
+                elements
+                    .iter()
+                    .map(|e| {
+                        let bits = u16::from_le_bytes((*e).try_into().unwrap());
+                        f32::from_bits((bits as u32) << 16) as f64
+                    })
+                    .collect(),
+            ),
+            "torch.LongStorage" => TensorData::Int(
+                elements
+                    .iter()
+                    .map(|e| i64::from_le_bytes((*e).try_into().unwrap()))
+                    .collect(),
+            ),
+            "torch.IntStorage" => TensorData::Int(
+                elements
+                    .iter()
+                    .map(|e| i32::from_le_bytes((*e).try_into().unwrap()) as i64)
+                    .collect(),
+            ),
+            "torch.ShortStorage" => TensorData::Int(
+                elements
+                    .iter()
+                    .map(|e| i16::from_le_bytes((*e).try_into().unwrap()) as i64)
+                    .collect(),
+            ),
+            "torch.CharStorage" => {
+                TensorData::Int(elements.iter().map(|e| e[0] as i8 as i64).collect())
+            }
+            "torch.ByteStorage" => TensorData::Int(elements.iter().map(|e| e[0] as i64).collect()),
+            _ => TensorData::Bool(elements.iter().map(|e| e[0] != 0).collect()),
+        };
+        Ok(Tensor { shape: size, data })
+    }
+}
+
+fn ints(values: &[Value]) -> io::Result<Vec<usize>> {
+    values
+        .iter()
+        .map(|value| match value {
+            Value::Int(n) if *n >= 0 => Ok(*n as usize),
+            _ => Err(invalid("张量的形状和步长必须为非负整数".to_string())),
+        })
+        .collect()
+}
+
+/// IEEE 754 半精度浮点数转换为单精度
+fn half_to_f32(bits: u16) -> f32 {
+    let sign = ((bits >> 15) as u32) << 31;
+    let exponent = ((bits >> 10) & 0x1f) as u32;
+    let mantissa = (bits & 0x3ff) as u32;
+    let bits = match (exponent, mantissa) {
+        (0, 0) => sign,
+        // 非规格化数
+        (0, _) => {
+            let value = mantissa as f32 / 1024.0 / 16384.0;
+            return if sign == 0 { value } else { -value };
+        }
+        (0x1f, _) => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exponent + 112) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(bits)
+}
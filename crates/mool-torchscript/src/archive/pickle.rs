@@ -0,0 +1,392 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io;
+use std::rc::Rc;
+
+/// 可以修改的值在 memo 和栈中共享
+pub type Shared<T> = Rc<RefCell<T>>;
+
+/// pickle 反序列化得到的值
+///
+/// 只实现 torch.jit.save 用到的协议 2 的操作码，
+/// 不执行任何 Python 代码，REDUCE 和 NEWOBJ 只记录类名和参数，由调用者解释。
+/// 与 Python 相同，列表、字典和对象的状态是引用，克隆得到的是同一个对象。
+#[derive(Debug, Clone)]
+pub enum Value {
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    /// 字节串，模型文件中用不到其内容
+    Bytes,
+    Tuple(Vec<Value>),
+    List(Shared<Vec<Value>>),
+    Dict(Shared<Vec<(Value, Value)>>),
+    /// 全局名称，例如 torch._utils._rebuild_tensor_v2
+    Global(String),
+    /// persistent_id 引用的外部对象，torch 中为张量的存储
+    Persistent(Box<Value>),
+    /// REDUCE 或 NEWOBJ 构造的对象，BUILD 的状态保存在 state 中
+    Object {
+        class: String,
+        args: Vec<Value>,
+        state: Shared<Option<Value>>,
+    },
+}
+
+fn shared<T>(value: T) -> Shared<T> {
+    Rc::new(RefCell::new(value))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// 反序列化一个 pickle
+pub fn load(data: &[u8]) -> io::Result<Value> {
+    Unpickler {
+        data,
+        position: 0,
+        stack: Vec::new(),
+        marks: Vec::new(),
+        memo: HashMap::new(),
+    }
+    .run()
+}
+
+struct Unpickler<'a> {
+    data: &'a [u8],
+    position: usize,
+    stack: Vec<Value>,
+    marks: Vec<usize>,
+    // memo 和栈共享可以修改的值，BINGET 之后的修改对之前的引用可见
+    memo: HashMap<u32, Value>,
+}
+
+impl<'a> Unpickler<'a> {
+    fn run(mut self) -> io::Result<Value> {
+        loop {
+            let opcode = self.read(1)?[0];
+            match opcode {
+                // PROTO
+                0x80 => {
+                    self.read(1)?;
+                }
+                // FRAME
+                0x95 => {
+                    self.read(8)?;
+                }
+                // STOP
+                b'.' => return self.pop(),
+                // MARK
+                b'(' => self.marks.push(self.stack.len()),
+                // POP
+                b'0' => {
+                    self.pop()?;
+                }
+                // POP_MARK
+                b'1' => {
+                    self.pop_mark()?;
+                }
+                // DUP
+                b'2' => {
+                    let top = self.top()?.clone();
+                    self.stack.push(top);
+                }
+                b'N' => self.stack.push(Value::None),
+                // NEWTRUE、NEWFALSE
+                0x88 => self.stack.push(Value::Bool(true)),
+                0x89 => self.stack.push(Value::Bool(false)),
+                // BININT、BININT1、BININT2
+                b'J' => {
+                    let n = i32::from_le_bytes(self.read(4)?.try_into().unwrap());
+                    self.stack.push(Value::Int(n as i64));
+                }
+                b'K' => {
+                    let n = self.read(1)?[0];
+                    self.stack.push(Value::Int(n as i64));
+                }
+                b'M' => {
+                    let n = u16::from_le_bytes(self.read(2)?.try_into().unwrap());
+                    self.stack.push(Value::Int(n as i64));
+                }
+                // LONG1：小端补码表示的整数
+                0x8a => {
+                    let length = self.read(1)?[0] as usize;
+                    let bytes = self.read(length)?;
+                    if length > 8 {
+                        return Err(invalid(format!("整数超出范围：{}字节", length)));
+                    }
+                    let mut n: i64 = 0;
+                    for (i, byte) in bytes.iter().enumerate() {
+                        n |= (*byte as i64) << (8 * i);
+                    }
+                    if length > 0 && length < 8 && bytes[length - 1] & 0x80 != 0 {
+                        n -= 1 << (8 * length);
+                    }
+                    self.stack.push(Value::Int(n));
+                }
+                // BINFLOAT：大端的 f64
+                b'G' => {
+                    let f = f64::from_be_bytes(self.read(8)?.try_into().unwrap());
+                    self.stack.push(Value::Float(f));
+                }
+                // SHORT_BINUNICODE、BINUNICODE、BINUNICODE8
+                0x8c => {
+                    let length = self.read(1)?[0] as usize;
+                    let s = self.read_str(length)?;
+                    self.stack.push(Value::Str(s));
+                }
+                b'X' => {
+                    let length = self.read_u32()? as usize;
+                    let s = self.read_str(length)?;
+                    self.stack.push(Value::Str(s));
+                }
+                0x8d => {
+                    let length = u64::from_le_bytes(self.read(8)?.try_into().unwrap()) as usize;
+                    let s = self.read_str(length)?;
+                    self.stack.push(Value::Str(s));
+                }
+                // SHORT_BINSTRING、BINSTRING
+                b'U' => {
+                    let length = self.read(1)?[0] as usize;
+                    let s = self.read_str(length)?;
+                    self.stack.push(Value::Str(s));
+                }
+                b'T' => {
+                    let length = self.read_u32()? as usize;
+                    let s = self.read_str(length)?;
+                    self.stack.push(Value::Str(s));
+                }
+                // SHORT_BINBYTES、BINBYTES
+                b'C' => {
+                    let length = self.read(1)?[0] as usize;
+                    self.read(length)?;
+                    self.stack.push(Value::Bytes);
+                }
+                b'B' => {
+                    let length = self.read_u32()? as usize;
+                    self.read(length)?;
+                    self.stack.push(Value::Bytes);
+                }
+                // EMPTY_TUPLE、TUPLE、TUPLE1、TUPLE2、TUPLE3
+                b')' => self.stack.push(Value::Tuple(Vec::new())),
+                b't' => {
+                    let items = self.pop_mark()?;
+                    self.stack.push(Value::Tuple(items));
+                }
+                0x85..=0x87 => {
+                    let length = (opcode - 0x84) as usize;
+                    if self.stack.len() < length {
+                        return Err(invalid("pickle 栈为空".to_string()));
+                    }
+                    let items = self.stack.split_off(self.stack.len() - length);
+                    self.stack.push(Value::Tuple(items));
+                }
+                // EMPTY_LIST、LIST、APPEND、APPENDS
+                b']' => self.stack.push(Value::List(shared(Vec::new()))),
+                b'l' => {
+                    let items = self.pop_mark()?;
+                    self.stack.push(Value::List(shared(items)));
+                }
+                b'a' => {
+                    let item = self.pop()?;
+                    self.append(vec![item])?;
+                }
+                b'e' => {
+                    let items = self.pop_mark()?;
+                    self.append(items)?;
+                }
+                // EMPTY_DICT、DICT、SETITEM、SETITEMS
+                b'}' => self.stack.push(Value::Dict(shared(Vec::new()))),
+                b'd' => {
+                    let items = self.pop_mark()?;
+                    self.stack.push(Value::Dict(shared(pairs(items))));
+                }
+                b's' => {
+                    let value = self.pop()?;
+                    let key = self.pop()?;
+                    self.set_items(vec![(key, value)])?;
+                }
+                b'u' => {
+                    let items = self.pop_mark()?;
+                    self.set_items(pairs(items))?;
+                }
+                // GLOBAL：以换行分隔的模块名和名称
+                b'c' => {
+                    let module = self.read_line()?;
+                    let name = self.read_line()?;
+                    self.stack
+                        .push(Value::Global(format!("{}.{}", module, name)));
+                }
+                // STACK_GLOBAL
+                0x93 => {
+                    let name = self.pop()?;
+                    let module = self.pop()?;
+                    match (module, name) {
+                        (Value::Str(module), Value::Str(name)) => self
+                            .stack
+                            .push(Value::Global(format!("{}.{}", module, name))),
+                        _ => return Err(invalid("STACK_GLOBAL 的参数必须为字符串".to_string())),
+                    }
+                }
+                // BINPUT、LONG_BINPUT、MEMOIZE
+                b'q' => {
+                    let index = self.read(1)?[0] as u32;
+                    self.memo.insert(index, self.top()?.clone());
+                }
+                b'r' => {
+                    let index = self.read_u32()?;
+                    self.memo.insert(index, self.top()?.clone());
+                }
+                0x94 => {
+                    let index = self.memo.len() as u32;
+                    self.memo.insert(index, self.top()?.clone());
+                }
+                // BINGET、LONG_BINGET
+                b'h' => {
+                    let index = self.read(1)?[0] as u32;
+                    self.memo_get(index)?;
+                }
+                b'j' => {
+                    let index = self.read_u32()?;
+                    self.memo_get(index)?;
+                }
+                // BINPERSID
+                b'Q' => {
+                    let pid = self.pop()?;
+                    self.stack.push(Value::Persistent(Box::new(pid)));
+                }
+                // REDUCE、NEWOBJ
+                b'R' | 0x81 => {
+                    let args = self.pop()?;
+                    let callable = self.pop()?;
+                    let class = match callable {
+                        Value::Global(name) => name,
+                        _ => return Err(invalid("只支持调用全局名称".to_string())),
+                    };
+                    let args = match args {
+                        Value::Tuple(args) => args,
+                        _ => return Err(invalid(format!("{}的参数必须为元组", class))),
+                    };
+                    self.stack.push(Value::Object {
+                        class,
+                        args,
+                        state: shared(None),
+                    });
+                }
+                // BUILD
+                b'b' => {
+                    let new_state = self.pop()?;
+                    match self.stack.last() {
+                        Some(Value::Object { state, .. }) => *state.borrow_mut() = Some(new_state),
+                        _ => {
+                            return Err(invalid(
+                                "BUILD 的对象必须由 REDUCE 或 NEWOBJ 构造".to_string(),
+                            ))
+                        }
+                    }
+                }
+                _ => {
+                    return Err(invalid(format!(
+                        "{}:暂不支持 pickle 操作码 0x{:02x}",
+                        self.position - 1,
+                        opcode
+                    )))
+                }
+            }
+        }
+    }
+
+    fn read(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        let end = self.position + length;
+        if end > self.data.len() {
+            return Err(invalid("pickle 数据不完整".to_string()));
+        }
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.read(4)?.try_into().unwrap()))
+    }
+
+    fn read_str(&mut self, length: usize) -> io::Result<String> {
+        let bytes = self.read(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid("字符串不是合法的 UTF-8".to_string()))
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let rest = &self.data[self.position..];
+        match rest.iter().position(|byte| *byte == b'\n') {
+            Some(length) => {
+                let line = self.read_str(length)?;
+                self.position += 1;
+                Ok(line)
+            }
+            None => Err(invalid("pickle 数据不完整".to_string())),
+        }
+    }
+
+    fn top(&self) -> io::Result<&Value> {
+        self.stack
+            .last()
+            .ok_or_else(|| invalid("pickle 栈为空".to_string()))
+    }
+
+    fn pop(&mut self) -> io::Result<Value> {
+        self.stack
+            .pop()
+            .ok_or_else(|| invalid("pickle 栈为空".to_string()))
+    }
+
+    /// 弹出最近的 MARK 之后的所有值
+    fn pop_mark(&mut self) -> io::Result<Vec<Value>> {
+        match self.marks.pop() {
+            Some(mark) if mark <= self.stack.len() => Ok(self.stack.split_off(mark)),
+            _ => Err(invalid("pickle 缺少 MARK".to_string())),
+        }
+    }
+
+    fn memo_get(&mut self, index: u32) -> io::Result<()> {
+        match self.memo.get(&index) {
+            Some(value) => {
+                self.stack.push(value.clone());
+                Ok(())
+            }
+            None => Err(invalid(format!("pickle memo 中没有{}", index))),
+        }
+    }
+
+    fn append(&mut self, items: Vec<Value>) -> io::Result<()> {
+        match self.stack.last() {
+            Some(Value::List(list)) => {
+                list.borrow_mut().extend(items);
+                Ok(())
+            }
+            _ => Err(invalid("APPEND 的对象必须为列表".to_string())),
+        }
+    }
+
+    fn set_items(&mut self, items: Vec<(Value, Value)>) -> io::Result<()> {
+        match self.stack.last() {
+            Some(Value::Dict(dict)) => {
+                dict.borrow_mut().extend(items);
+                Ok(())
+            }
+            _ => Err(invalid("SETITEM 的对象必须为字典".to_string())),
+        }
+    }
+}
+
+fn pairs(items: Vec<Value>) -> Vec<(Value, Value)> {
+    let mut pairs = Vec::with_capacity(items.len() / 2);
+    let mut items = items.into_iter();
+    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+        pairs.push((key, value));
+    }
+    pairs
+}
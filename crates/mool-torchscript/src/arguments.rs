@@ -0,0 +1,273 @@
+use super::ast::{Argument, Expr, FunctionArg, FunctionStatement, Literal, Operator, Program};
+use std::collections::HashMap;
+
+/// 按形参名把位置参数和关键字参数对应到形参表，没有传入的形参为 None
+fn bind(callee: &str, params: &[&str], args: Vec<Argument>) -> Vec<Option<Expr>> {
+    let mut bound: Vec<Option<Expr>> = vec![None; params.len()];
+    let mut keyword = false;
+    for (i, arg) in args.into_iter().enumerate() {
+        let index = match arg.name {
+            Some(name) => {
+                keyword = true;
+                match params.iter().position(|param| *param == name) {
+                    Some(index) => index,
+                    None => panic!("{}没有参数{}", callee, name),
+                }
+            }
+            None if keyword => panic!("{}的位置参数不能出现在关键字参数之后", callee),
+            None if i < params.len() => i,
+            None => panic!("{}最多接受{}个参数", callee, params.len()),
+        };
+        if bound[index].is_some() {
+            panic!("{}的参数{}重复传入", callee, params[index]);
+        }
+        bound[index] = Some(arg.value);
+    }
+    bound
+}
+
+fn required(callee: &str, param: &str, value: Option<Expr>) -> Box<Expr> {
+    match value {
+        Some(value) => Box::new(value),
+        None => panic!("{}缺少参数{}", callee, param),
+    }
+}
+
+/// 解析 torch.xxx(...) 算子调用，形参名与 PyTorch 文档一致
+pub fn operator(name: &str, args: Vec<Argument>) -> Expr {
+    let callee = format!("torch.{}", name);
+    let operator = match name {
+        // Mool 的张量运算不支持标量，alpha 只能为 1
+        "add" | "sub" => {
+            let mut bound = bind(&callee, &["input", "other", "alpha"], args).into_iter();
+            let input = required(&callee, "input", bound.next().unwrap());
+            let other = required(&callee, "other", bound.next().unwrap());
+            match bound.next().unwrap() {
+                None
+                | Some(Expr::Literal(Literal::Int(1)))
+                | Some(Expr::Literal(Literal::Float(1.0))) => {}
+                Some(_) => panic!("{}暂不支持 alpha 不为 1", callee),
+            }
+            match name {
+                "add" => Operator::Add(input, other),
+                _ => Operator::Sub(input, other),
+            }
+        }
+        "mul" | "div" | "matmul" | "lt" | "le" | "gt" | "ge" | "eq" | "ne" => {
+            let mut bound = bind(&callee, &["input", "other"], args).into_iter();
+            let input = required(&callee, "input", bound.next().unwrap());
+            let other = required(&callee, "other", bound.next().unwrap());
+            match name {
+                "mul" => Operator::Mul(input, other),
+                "div" => Operator::Div(input, other),
+                "matmul" => Operator::Matmul(input, other),
+                "lt" => Operator::Lt(input, other),
+                "le" => Operator::Le(input, other),
+                "gt" => Operator::Gt(input, other),
+                "ge" => Operator::Ge(input, other),
+                "eq" => Operator::Eq(input, other),
+                _ => Operator::Ne(input, other),
+            }
+        }
+        "relu" => {
+            let mut bound = bind(&callee, &["input"], args).into_iter();
+            Operator::Relu(required(&callee, "input", bound.next().unwrap()))
+        }
+        // 按形参表检查参数，Mool IR 没有归约算子，暂不支持
+        "sum" | "mean" => {
+            bind(&callee, &["input", "dim", "keepdim", "dtype"], args);
+            panic!("暂不支持{}算子：Mool IR 没有归约算子", callee)
+        }
+        _ => panic!("暂不支持{}算子", callee),
+    };
+    Expr::Operator(operator)
+}
+
+/// 解析 torch.tensor([...], ...) 的关键字参数，设备和梯度与编译无关
+pub fn tensor(literals: Vec<Literal>, kwargs: Vec<Argument>) -> Expr {
+    if kwargs.iter().any(|arg| arg.name.is_none()) {
+        panic!("torch.tensor 除数据外只接受关键字参数");
+    }
+    let mut bound = bind(
+        "torch.tensor",
+        &["dtype", "device", "requires_grad"],
+        kwargs,
+    )
+    .into_iter();
+    let literals = match bound.next().unwrap() {
+        Some(dtype) => literals
+            .into_iter()
+            .map(|literal| cast(literal, &dtype))
+            .collect(),
+        None => literals,
+    };
+    Expr::Operator(Operator::Tensor(literals))
+}
+
+/// 按 dtype 转换张量的元素，Mool 只有 int、float 和 bool 三种元素类型，不区分位宽
+fn cast(literal: Literal, dtype: &Expr) -> Literal {
+    let name = match dtype {
+        Expr::Attribute(base, name) if matches!(&**base, Expr::Identifier(torch) if torch == "torch") => {
+            name.as_str()
+        }
+        // Python 的内置类型也可以作为 dtype，例如 dtype=float
+        Expr::Identifier(name) => name.as_str(),
+        _ => panic!("torch.tensor 的 dtype 必须为 torch.float32 等类型"),
+    };
+    match name {
+        "float" | "float16" | "float32" | "float64" | "half" | "double" | "bfloat16" => {
+            Literal::Float(match literal {
+                Literal::Int(n) => n as f64,
+                Literal::Float(f) => f,
+                Literal::Bool(b) => b as i64 as f64,
+            })
+        }
+        // 与 PyTorch 相同，浮点数向零取整
+        "int" | "int8" | "int16" | "int32" | "int64" | "uint8" | "short" | "long" => {
+            Literal::Int(match literal {
+                Literal::Int(n) => n,
+                Literal::Float(f) => f as i64,
+                Literal::Bool(b) => b as i64,
+            })
+        }
+        "bool" => Literal::Bool(match literal {
+            Literal::Int(n) => n != 0,
+            Literal::Float(f) => f != 0.0,
+            Literal::Bool(b) => b,
+        }),
+        _ => panic!("暂不支持dtype {}", name),
+    }
+}
+
+/// 把用户函数调用的关键字参数和默认值展开为位置参数
+///
+/// 在类展开之后执行，此时方法调用已经变为普通的函数调用。
+pub fn resolve(input: Vec<Program>) -> Vec<Program> {
+    let signatures: HashMap<String, Vec<FunctionArg>> = input
+        .iter()
+        .filter_map(|program| match program {
+            Program::Function(function) => Some((function.name.clone(), function.args.clone())),
+            _ => None,
+        })
+        .collect();
+    input
+        .into_iter()
+        .map(|program| match program {
+            Program::Function(mut function) => {
+                function.body = resolve_block(function.body, &signatures);
+                Program::Function(function)
+            }
+            Program::Statement(expr) => Program::Statement(resolve_expr(expr, &signatures)),
+            program => program,
+        })
+        .collect()
+}
+
+fn resolve_block(
+    statements: Vec<FunctionStatement>,
+    signatures: &HashMap<String, Vec<FunctionArg>>,
+) -> Vec<FunctionStatement> {
+    statements
+        .into_iter()
+        .map(|statement| match statement {
+            FunctionStatement::Expr(expr) => {
+                FunctionStatement::Expr(resolve_expr(expr, signatures))
+            }
+            FunctionStatement::Return(expr) => {
+                FunctionStatement::Return(resolve_expr(expr, signatures))
+            }
+            FunctionStatement::Assign(name, expr) => {
+                FunctionStatement::Assign(name, resolve_expr(expr, signatures))
+            }
+            FunctionStatement::AssignTuple(names, expr) => {
+                FunctionStatement::AssignTuple(names, resolve_expr(expr, signatures))
+            }
+            FunctionStatement::For(mut for_loop) => {
+                for_loop.range = for_loop
+                    .range
+                    .into_iter()
+                    .map(|expr| resolve_expr(expr, signatures))
+                    .collect();
+                for_loop.body = resolve_block(for_loop.body, signatures);
+                FunctionStatement::For(for_loop)
+            }
+            FunctionStatement::While(mut while_loop) => {
+                while_loop.cond = resolve_expr(while_loop.cond, signatures);
+                while_loop.body = resolve_block(while_loop.body, signatures);
+                FunctionStatement::While(while_loop)
+            }
+            statement => statement,
+        })
+        .collect()
+}
+
+fn resolve_expr(expr: Expr, signatures: &HashMap<String, Vec<FunctionArg>>) -> Expr {
+    match expr {
+        Expr::Call(name, args) => {
+            let args: Vec<Argument> = args
+                .into_iter()
+                .map(|arg| Argument {
+                    name: arg.name,
+                    value: resolve_expr(arg.value, signatures),
+                })
+                .collect();
+            let params = match signatures.get(&name) {
+                Some(params) => params,
+                None => {
+                    if args.iter().any(|arg| arg.name.is_some()) {
+                        panic!("无法确定函数{}的形参，不能使用关键字参数", name);
+                    }
+                    return Expr::Call(name, args);
+                }
+            };
+            let names: Vec<&str> = params.iter().map(|param| param.name.as_str()).collect();
+            let args = bind(&name, &names, args)
+                .into_iter()
+                .zip(params.iter())
+                .map(|(value, param)| {
+                    let value = match (value, &param.default) {
+                        (Some(value), _) => value,
+                        (None, Some(default)) => default.clone(),
+                        (None, None) => panic!("{}缺少参数{}", name, param.name),
+                    };
+                    Argument { name: None, value }
+                })
+                .collect();
+            Expr::Call(name, args)
+        }
+        Expr::Operator(operator) => Expr::Operator(resolve_operator(operator, signatures)),
+        Expr::Tuple(items) => Expr::Tuple(
+            items
+                .into_iter()
+                .map(|item| resolve_expr(item, signatures))
+                .collect(),
+        ),
+        Expr::Subscript(base, index) => Expr::Subscript(
+            Box::new(resolve_expr(*base, signatures)),
+            Box::new(resolve_expr(*index, signatures)),
+        ),
+        expr => expr,
+    }
+}
+
+fn resolve_operator(
+    operator: Operator,
+    signatures: &HashMap<String, Vec<FunctionArg>>,
+) -> Operator {
+    let resolve = |x: Box<Expr>| Box::new(resolve_expr(*x, signatures));
+    match operator {
+        Operator::Add(x, y) => Operator::Add(resolve(x), resolve(y)),
+        Operator::Sub(x, y) => Operator::Sub(resolve(x), resolve(y)),
+        Operator::Mul(x, y) => Operator::Mul(resolve(x), resolve(y)),
+        Operator::Div(x, y) => Operator::Div(resolve(x), resolve(y)),
+        Operator::Matmul(x, y) => Operator::Matmul(resolve(x), resolve(y)),
+        Operator::Lt(x, y) => Operator::Lt(resolve(x), resolve(y)),
+        Operator::Le(x, y) => Operator::Le(resolve(x), resolve(y)),
+        Operator::Gt(x, y) => Operator::Gt(resolve(x), resolve(y)),
+        Operator::Ge(x, y) => Operator::Ge(resolve(x), resolve(y)),
+        Operator::Eq(x, y) => Operator::Eq(resolve(x), resolve(y)),
+        Operator::Ne(x, y) => Operator::Ne(resolve(x), resolve(y)),
+        Operator::Relu(x) => Operator::Relu(resolve(x)),
+        Operator::Tensor(literals) => Operator::Tensor(literals),
+    }
+}
@@ -1,51 +1,147 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Program {
     Function(Function),
+    Class(Class),
     Statement(Expr),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// nn.Module 等脚本类，只保留方法，属性的值从模型文件中读取
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Class {
+    pub name: String,
+    pub methods: Vec<Function>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Function {
     pub name: String,
     pub args: Vec<FunctionArg>,
-    pub rtn: String,
+    pub rtn: Type,
+    pub doc: Option<String>,
     pub body: Vec<FunctionStatement>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FunctionArg {
     pub name: String,
-    pub annotation: String,
+    pub annotation: Type,
+    pub default: Option<Expr>,
+}
+
+/// TorchScript 的类型标注，没有标注的参数为 Tensor
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Str,
+    None,
+    Tensor,
+    List(Box<Type>),
+    Tuple(Vec<Type>),
+    Optional(Box<Type>),
+    Dict(Box<Type>, Box<Type>),
+    /// 脚本类，例如 __torch__.MyModel
+    Class(String),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |types: &[Type]| {
+            types
+                .iter()
+                .map(|ty| ty.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::Str => write!(f, "str"),
+            Type::None => write!(f, "None"),
+            Type::Tensor => write!(f, "Tensor"),
+            Type::List(element) => write!(f, "List[{}]", element),
+            Type::Tuple(elements) => write!(f, "Tuple[{}]", join(elements)),
+            Type::Optional(element) => write!(f, "Optional[{}]", element),
+            Type::Dict(key, value) => write!(f, "Dict[{}, {}]", key, value),
+            Type::Class(name) => write!(f, "{}", name),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum FunctionStatement {
     Expr(Expr),
+    Assign(String, Expr),
+    /// 元组解包，例如 a, b = f(x)
+    AssignTuple(Vec<String>, Expr),
     Return(Expr),
+    For(For),
+    While(While),
+    Break,
+    Continue,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct For {
+    pub var: String,
+    pub range: Vec<Expr>,
+    pub body: Vec<FunctionStatement>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct While {
+    pub cond: Expr,
+    pub body: Vec<FunctionStatement>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Expr {
     Identifier(String),
     Literal(Literal),
     Operator(Operator),
-    Call(String, Vec<Expr>),
+    Call(String, Vec<Argument>),
+    /// 元组，例如 (a, b)
+    Tuple(Vec<Expr>),
+    /// 下标，例如 t[0]
+    Subscript(Box<Expr>, Box<Expr>),
+    /// 属性访问，例如 self.weight
+    Attribute(Box<Expr>, String),
+    /// 方法调用，例如 self.linear.forward(x)
+    MethodCall(Box<Expr>, String, Vec<Argument>),
+}
+
+/// 调用的实参，关键字参数带有形参名，例如 alpha=1
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Argument {
+    pub name: Option<String>,
+    pub value: Expr,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Literal {
     Int(i64),
     Float(f64),
     Bool(bool),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Operator {
     Add(Box<Expr>, Box<Expr>),
     Sub(Box<Expr>, Box<Expr>),
     Mul(Box<Expr>, Box<Expr>),
     Div(Box<Expr>, Box<Expr>),
-    Tensor(Vec<Literal>)
+    Matmul(Box<Expr>, Box<Expr>),
+    Relu(Box<Expr>),
+    Lt(Box<Expr>, Box<Expr>),
+    Le(Box<Expr>, Box<Expr>),
+    Gt(Box<Expr>, Box<Expr>),
+    Ge(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    Ne(Box<Expr>, Box<Expr>),
+    Tensor(Vec<Literal>),
 }
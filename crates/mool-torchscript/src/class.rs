@@ -0,0 +1,338 @@
+use super::archive::{Attribute, Module, Tensor, TensorData};
+use super::ast::{
+    Argument, Class, Expr, Function, FunctionStatement, Literal, Operator, Program, Type,
+};
+use std::collections::{HashMap, HashSet};
+
+/// 把类展开为普通函数
+///
+/// 模块的每个实例（根模块和各级子模块）单独生成函数，函数名为实例路径加方法名，
+/// 例如根模块的 forward 和子模块 linear 的 linear__forward。
+/// self 的张量和标量属性替换为常量，子模块的方法调用替换为对应实例的函数调用。
+/// 只展开根模块 forward 用到的方法，被调用的函数总是先于调用者生成。
+pub fn lower(input: Vec<Program>, module: Option<&Module>) -> Vec<Program> {
+    let mut lowering = Lowering {
+        classes: HashMap::new(),
+        output: Vec::new(),
+        done: HashSet::new(),
+    };
+    let mut statements = Vec::new();
+    for program in input {
+        match program {
+            Program::Function(function) => {
+                let function = lowering.function(function, "", None);
+                lowering.output.push(Program::Function(function));
+            }
+            Program::Class(class) => {
+                lowering.classes.insert(qualified_name(&class), class);
+            }
+            Program::Statement(expr) => {
+                let mut scope = Scope::new("", None);
+                let expr = lowering.expr(expr, &mut scope);
+                statements.push(Program::Statement(expr));
+            }
+        }
+    }
+    match module {
+        Some(module) => {
+            lowering.method("", module, "forward");
+        }
+        None => {
+            if let Some(name) = lowering.classes.keys().next() {
+                panic!("类{}的属性需要从模型文件中读取", name);
+            }
+        }
+    }
+    lowering.output.extend(statements);
+    lowering.output
+}
+
+/// 序列化的代码中，方法的 self 参数标注了完整的类名，例如 self: __torch__.MyModel
+fn qualified_name(class: &Class) -> String {
+    let annotation = class
+        .methods
+        .iter()
+        .filter_map(|method| method.args.first())
+        .filter(|arg| arg.name == "self")
+        .find_map(|arg| match &arg.annotation {
+            Type::Class(name) => Some(name.as_str()),
+            _ => None,
+        });
+    match annotation {
+        Some(annotation) => annotation.trim_start_matches("__torch__.").to_string(),
+        None => class.name.clone(),
+    }
+}
+
+fn function_name(path: &str, method: &str) -> String {
+    match path {
+        "" => method.to_string(),
+        path => format!("{}__{}", path.replace('.', "__"), method),
+    }
+}
+
+struct Lowering {
+    classes: HashMap<String, Class>,
+    output: Vec<Program>,
+    /// 已经生成的函数
+    done: HashSet<String>,
+}
+
+/// 正在展开的函数所属的实例
+struct Scope<'a> {
+    path: String,
+    module: Option<&'a Module>,
+    /// 绑定到子模块的局部变量，例如 linear = self.linear
+    aliases: HashMap<String, (String, &'a Module)>,
+}
+
+impl<'a> Scope<'a> {
+    fn new(path: &str, module: Option<&'a Module>) -> Self {
+        Scope {
+            path: path.to_string(),
+            module,
+            aliases: HashMap::new(),
+        }
+    }
+
+    /// 表达式引用的模块实例
+    fn resolve(&self, expr: &Expr) -> Option<(String, &'a Module)> {
+        match expr {
+            Expr::Identifier(name) if name == "self" => {
+                self.module.map(|module| (self.path.clone(), module))
+            }
+            Expr::Identifier(name) => self.aliases.get(name).cloned(),
+            Expr::Attribute(base, name) => {
+                let (path, module) = self.resolve(base)?;
+                match module.attribute(name) {
+                    Some(Attribute::Module(child)) => Some((join(&path, name), child)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn join(path: &str, name: &str) -> String {
+    match path {
+        "" => name.to_string(),
+        path => format!("{}.{}", path, name),
+    }
+}
+
+impl Lowering {
+    /// 生成实例的方法，返回对应的函数名
+    fn method(&mut self, path: &str, module: &Module, name: &str) -> String {
+        let function_name = function_name(path, name);
+        if !self.done.insert(function_name.clone()) {
+            return function_name;
+        }
+        // 手写的代码中类名可能没有模块前缀，此时按类名查找
+        let short_name = |name: &str| name.rsplit('.').next().unwrap_or("").to_string();
+        let class = match self.classes.get(&module.class).or_else(|| {
+            self.classes
+                .iter()
+                .find(|(name, _)| short_name(name) == short_name(&module.class))
+                .map(|(_, class)| class)
+        }) {
+            Some(class) => class,
+            None => panic!("找不到类{}的代码", module.class),
+        };
+        let method = match class.methods.iter().find(|method| method.name == name) {
+            Some(method) => method.clone(),
+            None => panic!("类{}没有方法{}", module.class, name),
+        };
+        let mut function = self.function(method, path, Some(module));
+        function.name = function_name.clone();
+        self.output.push(Program::Function(function));
+        function_name
+    }
+
+    fn function(&mut self, function: Function, path: &str, module: Option<&Module>) -> Function {
+        let mut scope = Scope::new(path, module);
+        let args = function
+            .args
+            .into_iter()
+            .filter(|arg| module.is_none() || arg.name != "self")
+            .collect();
+        let body = self.block(function.body, &mut scope);
+        Function {
+            name: function.name,
+            args,
+            rtn: function.rtn,
+            doc: function.doc,
+            body,
+        }
+    }
+
+    fn block(
+        &mut self,
+        statements: Vec<FunctionStatement>,
+        scope: &mut Scope,
+    ) -> Vec<FunctionStatement> {
+        let mut output = Vec::with_capacity(statements.len());
+        for statement in statements {
+            output.push(match statement {
+                FunctionStatement::Expr(expr) => FunctionStatement::Expr(self.expr(expr, scope)),
+                FunctionStatement::Return(expr) => {
+                    FunctionStatement::Return(self.expr(expr, scope))
+                }
+                FunctionStatement::Assign(name, expr) => match scope.resolve(&expr) {
+                    // 子模块只记录别名，不生成赋值
+                    Some(instance) => {
+                        scope.aliases.insert(name, instance);
+                        continue;
+                    }
+                    None => FunctionStatement::Assign(name, self.expr(expr, scope)),
+                },
+                FunctionStatement::AssignTuple(names, expr) => {
+                    FunctionStatement::AssignTuple(names, self.expr(expr, scope))
+                }
+                FunctionStatement::For(mut for_loop) => {
+                    for_loop.range = for_loop
+                        .range
+                        .into_iter()
+                        .map(|expr| self.expr(expr, scope))
+                        .collect();
+                    for_loop.body = self.block(for_loop.body, scope);
+                    FunctionStatement::For(for_loop)
+                }
+                FunctionStatement::While(mut while_loop) => {
+                    while_loop.cond = self.expr(while_loop.cond, scope);
+                    while_loop.body = self.block(while_loop.body, scope);
+                    FunctionStatement::While(while_loop)
+                }
+                statement => statement,
+            });
+        }
+        output
+    }
+
+    fn expr(&mut self, expr: Expr, scope: &mut Scope) -> Expr {
+        match expr {
+            Expr::Attribute(base, name) => {
+                let (path, module) = match scope.resolve(&base) {
+                    Some(instance) => instance,
+                    None => panic!("暂不支持访问属性{}", name),
+                };
+                match module.attribute(&name) {
+                    Some(Attribute::Tensor(tensor)) => {
+                        Expr::Operator(Operator::Tensor(tensor_literals(tensor)))
+                    }
+                    Some(Attribute::Int(n)) => Expr::Literal(Literal::Int(*n)),
+                    Some(Attribute::Float(f)) => Expr::Literal(Literal::Float(*f)),
+                    Some(Attribute::Bool(b)) => Expr::Literal(Literal::Bool(*b)),
+                    Some(Attribute::Module(_)) => {
+                        panic!("子模块{}只能用于调用方法", join(&path, &name))
+                    }
+                    Some(_) => panic!("暂不支持模块属性{}的类型", join(&path, &name)),
+                    None => panic!("模块{}没有属性{}", module.class, name),
+                }
+            }
+            Expr::MethodCall(receiver, name, args) => {
+                let args = self.arguments(args, scope);
+                match scope.resolve(&receiver) {
+                    Some((path, module)) => Expr::Call(self.method(&path, module, &name), args),
+                    // 序列化的代码以完整的名称调用普通函数，例如 __torch__.helper(x)
+                    None if is_torch_module(&receiver) => Expr::Call(name, args),
+                    None => panic!("暂不支持调用方法{}", name),
+                }
+            }
+            Expr::Call(name, args) => Expr::Call(name, self.arguments(args, scope)),
+            Expr::Tuple(items) => Expr::Tuple(
+                items
+                    .into_iter()
+                    .map(|item| self.expr(item, scope))
+                    .collect(),
+            ),
+            Expr::Subscript(base, index) => Expr::Subscript(
+                Box::new(self.expr(*base, scope)),
+                Box::new(self.expr(*index, scope)),
+            ),
+            Expr::Operator(operator) => Expr::Operator(self.operator(operator, scope)),
+            expr => expr,
+        }
+    }
+
+    fn operator(&mut self, operator: Operator, scope: &mut Scope) -> Operator {
+        match operator {
+            Operator::Add(x, y) => {
+                let (x, y) = self.binary(*x, *y, scope);
+                Operator::Add(x, y)
+            }
+            Operator::Sub(x, y) => {
+                let (x, y) = self.binary(*x, *y, scope);
+                Operator::Sub(x, y)
+            }
+            Operator::Mul(x, y) => {
+                let (x, y) = self.binary(*x, *y, scope);
+                Operator::Mul(x, y)
+            }
+            Operator::Div(x, y) => {
+                let (x, y) = self.binary(*x, *y, scope);
+                Operator::Div(x, y)
+            }
+            Operator::Matmul(x, y) => {
+                let (x, y) = self.binary(*x, *y, scope);
+                Operator::Matmul(x, y)
+            }
+            Operator::Lt(x, y) => {
+                let (x, y) = self.binary(*x, *y, scope);
+                Operator::Lt(x, y)
+            }
+            Operator::Le(x, y) => {
+                let (x, y) = self.binary(*x, *y, scope);
+                Operator::Le(x, y)
+            }
+            Operator::Gt(x, y) => {
+                let (x, y) = self.binary(*x, *y, scope);
+                Operator::Gt(x, y)
+            }
+            Operator::Ge(x, y) => {
+                let (x, y) = self.binary(*x, *y, scope);
+                Operator::Ge(x, y)
+            }
+            Operator::Eq(x, y) => {
+                let (x, y) = self.binary(*x, *y, scope);
+                Operator::Eq(x, y)
+            }
+            Operator::Ne(x, y) => {
+                let (x, y) = self.binary(*x, *y, scope);
+                Operator::Ne(x, y)
+            }
+            Operator::Relu(x) => Operator::Relu(Box::new(self.expr(*x, scope))),
+            Operator::Tensor(literals) => Operator::Tensor(literals),
+        }
+    }
+
+    fn arguments(&mut self, args: Vec<Argument>, scope: &mut Scope) -> Vec<Argument> {
+        args.into_iter()
+            .map(|arg| Argument {
+                name: arg.name,
+                value: self.expr(arg.value, scope),
+            })
+            .collect()
+    }
+
+    fn binary(&mut self, x: Expr, y: Expr, scope: &mut Scope) -> (Box<Expr>, Box<Expr>) {
+        (Box::new(self.expr(x, scope)), Box::new(self.expr(y, scope)))
+    }
+}
+
+fn is_torch_module(expr: &Expr) -> bool {
+    match expr {
+        Expr::Identifier(name) => name == "__torch__",
+        Expr::Attribute(base, _) => is_torch_module(base),
+        _ => false,
+    }
+}
+
+/// Mool 只支持一维张量，多维的参数按行优先展开
+fn tensor_literals(tensor: &Tensor) -> Vec<Literal> {
+    match &tensor.data {
+        TensorData::Int(data) => data.iter().map(|n| Literal::Int(*n)).collect(),
+        TensorData::Float(data) => data.iter().map(|f| Literal::Float(*f)).collect(),
+        TensorData::Bool(data) => data.iter().map(|b| Literal::Bool(*b)).collect(),
+    }
+}
@@ -1,38 +1,240 @@
-use super::ast::Program;
+use super::archive::Module;
+use super::arguments;
+use super::ast::{Expr, Function, FunctionStatement, Literal, Operator, Program};
+use super::class;
+use super::infer::{infer_signatures, Signature};
+use std::collections::{HashMap, HashSet};
 
+/// 将 TorchScript 抽象语法树翻译为 Mool 代码
+///
+/// # Safety
+///
+/// 与其他前端的接口保持一致，本函数内部没有不安全操作。
 pub unsafe fn codegen(input: Vec<Program>) -> String {
-    let code = format!("{:?}", input);
-    if code.contains("add") {
-        "let %add = fn(%x: Tensor[(2),int], %y:Tensor[(2),int]) -> Tensor[(2),int] { 
-    Add(%x, %y)
+    codegen_programs(arguments::resolve(class::lower(input, None)))
 }
 
-%add(Tensor([1,2]),Tensor([1,2]))
-"
-        .to_string()
-    } else if code.contains("sub") {
-        "let %sub = fn(%x: Tensor[(2),int], %y:Tensor[(2),int]) -> Tensor[(2),int] { 
-    Sub(%x, %y)
+/// 翻译模型文件中的代码，以 module 的 forward 方法为入口，模块属性作为常量
+///
+/// # Safety
+///
+/// 与其他前端的接口保持一致，本函数内部没有不安全操作。
+pub unsafe fn codegen_module(input: Vec<Program>, module: &Module) -> String {
+    codegen_programs(arguments::resolve(class::lower(input, Some(module))))
 }
 
-%sub(Tensor([1,2]),Tensor([1,2]))
-"
-        .to_string()
-    } else if code.contains("mul") {
-        "let %mul = fn(%x: Tensor[(2),int], %y:Tensor[(2),int]) -> Tensor[(2),int] { 
-    Mul(%x, %y)
+fn codegen_programs(input: Vec<Program>) -> String {
+    // 根据调用处的张量实参推断函数的张量类型
+    let signatures = infer_signatures(&input);
+    let mut code = String::new();
+    for program in input.iter() {
+        match program {
+            Program::Function(function) => {
+                code.push_str(&codegen_function(function, &signatures));
+                code.push_str("\n\n");
+            }
+            Program::Statement(expr) => {
+                code.push_str(&codegen_expr(expr));
+                code.push('\n');
+            }
+            Program::Class(class) => unreachable!("类{}应当已经展开为函数", class.name),
+        }
+    }
+    code
+}
+
+fn codegen_function(function: &Function, signatures: &HashMap<String, Signature>) -> String {
+    let signature = &signatures[&function.name];
+    let args: Vec<String> = function
+        .args
+        .iter()
+        .zip(signature.args.iter())
+        .map(|(arg, ty)| format!("%{}: {}", arg.name, ty))
+        .collect();
+    // 形参视为已定义的变量
+    let mut defined: HashSet<String> = function.args.iter().map(|arg| arg.name.clone()).collect();
+    let body = codegen_block(&function.body, 1, &mut defined);
+    // 文档字符串保留为 Mool 注释
+    let doc: String = match &function.doc {
+        Some(doc) => clean_doc(doc)
+            .iter()
+            .map(|line| match line.as_str() {
+                "" => "//\n".to_string(),
+                line => format!("// {}\n", line),
+            })
+            .collect(),
+        None => String::new(),
+    };
+    format!(
+        "{}let %{} = fn({}) -> {} {{\n{}}}",
+        doc,
+        function.name,
+        args.join(", "),
+        signature.rtn,
+        body
+    )
 }
 
-%mul(Tensor([1,2]),Tensor([1,2]))
-"
-        .to_string()
-    } else {
-        "let %div = fn(%x: Tensor[(2),int], %y:Tensor[(2),int]) -> Tensor[(2),int] { 
-    Div(%x, %y)
+/// 与 Python 的 inspect.cleandoc 相同，去掉文档字符串续行的公共缩进
+fn clean_doc(doc: &str) -> Vec<String> {
+    let lines: Vec<&str> = doc.lines().collect();
+    let indent = lines
+        .iter()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let mut cleaned: Vec<String> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| match i {
+            0 => line.trim().to_string(),
+            _ => line.get(indent..).unwrap_or("").trim_end().to_string(),
+        })
+        .collect();
+    // 去掉首尾的空行
+    while cleaned.first().is_some_and(|line| line.is_empty()) {
+        cleaned.remove(0);
+    }
+    while cleaned.last().is_some_and(|line| line.is_empty()) {
+        cleaned.pop();
+    }
+    cleaned
 }
 
-%div(Tensor([1,2]),Tensor([1,2]))
-"
-        .to_string()
+fn codegen_block(
+    statements: &[FunctionStatement],
+    depth: usize,
+    defined: &mut HashSet<String>,
+) -> String {
+    let indent = "    ".repeat(depth);
+    let mut code = String::new();
+    for statement in statements.iter() {
+        let line = match statement {
+            FunctionStatement::Expr(expr) => codegen_expr(expr),
+            // Mool 函数以最后一个表达式作为返回值
+            // 解析时已经拒绝循环中的 return
+            FunctionStatement::Return(expr) => codegen_expr(expr),
+            // 第一次赋值时声明变量，之后更新变量
+            FunctionStatement::Assign(name, expr) => {
+                if defined.insert(name.clone()) {
+                    format!("let %{} = {}", name, codegen_expr(expr))
+                } else {
+                    format!("%{} = {}", name, codegen_expr(expr))
+                }
+            }
+            // 解构时已经存在的变量会被更新
+            FunctionStatement::AssignTuple(names, expr) => {
+                let names: Vec<String> = names
+                    .iter()
+                    .map(|name| {
+                        defined.insert(name.clone());
+                        format!("%{}", name)
+                    })
+                    .collect();
+                format!("let ({}) = {}", names.join(", "), codegen_expr(expr))
+            }
+            FunctionStatement::For(for_loop) => {
+                let (start, end, step) = match for_loop.range.as_slice() {
+                    [end] => ("0".to_string(), codegen_expr(end), "1".to_string()),
+                    [start, end] => (codegen_expr(start), codegen_expr(end), "1".to_string()),
+                    [start, end, step] => {
+                        (codegen_expr(start), codegen_expr(end), codegen_expr(step))
+                    }
+                    _ => panic!("range 的参数个数必须为 1 到 3 个"),
+                };
+                defined.insert(for_loop.var.clone());
+                format!(
+                    "for %{} in range({}, {}, {}) {{\n{}{}}}",
+                    for_loop.var,
+                    start,
+                    end,
+                    step,
+                    codegen_block(&for_loop.body, depth + 1, defined),
+                    indent
+                )
+            }
+            FunctionStatement::While(while_loop) => format!(
+                "while {} {{\n{}{}}}",
+                codegen_expr(&while_loop.cond),
+                codegen_block(&while_loop.body, depth + 1, defined),
+                indent
+            ),
+            FunctionStatement::Break => "break".to_string(),
+            FunctionStatement::Continue => "continue".to_string(),
+        };
+        code.push_str(&indent);
+        code.push_str(&line);
+        code.push('\n');
+    }
+    code
+}
+
+fn codegen_expr(expr: &Expr) -> String {
+    match expr {
+        Expr::Identifier(name) => format!("%{}", name),
+        Expr::Literal(literal) => codegen_literal(literal),
+        Expr::Call(name, args) => {
+            let args: Vec<String> = args.iter().map(|arg| codegen_expr(&arg.value)).collect();
+            format!("%{}({})", name, args.join(", "))
+        }
+        Expr::Tuple(items) => {
+            let items: Vec<String> = items.iter().map(codegen_expr).collect();
+            match items.as_slice() {
+                [item] => format!("({},)", item),
+                items => format!("({})", items.join(", ")),
+            }
+        }
+        // Mool 的元组只能用常量下标取元素
+        Expr::Subscript(base, index) => match &**index {
+            Expr::Literal(Literal::Int(index)) => format!("{}.{}", codegen_expr(base), index),
+            _ => panic!("暂不支持非常量下标"),
+        },
+        Expr::Operator(operator) => match operator {
+            Operator::Add(x, y) => codegen_binary("Add", x, y),
+            Operator::Sub(x, y) => codegen_binary("Sub", x, y),
+            Operator::Mul(x, y) => codegen_binary("Mul", x, y),
+            Operator::Div(x, y) => codegen_binary("Div", x, y),
+            Operator::Matmul(x, y) => codegen_binary("Matmul", x, y),
+            Operator::Relu(x) => format!("Relu({})", codegen_expr(x)),
+            Operator::Lt(x, y) => codegen_binary("Lt", x, y),
+            Operator::Le(x, y) => codegen_binary("Le", x, y),
+            Operator::Gt(x, y) => codegen_binary("Gt", x, y),
+            Operator::Ge(x, y) => codegen_binary("Ge", x, y),
+            Operator::Eq(x, y) => codegen_binary("Eq", x, y),
+            Operator::Ne(x, y) => codegen_binary("Ne", x, y),
+            Operator::Tensor(literals) => {
+                let literals: Vec<String> = literals.iter().map(codegen_literal).collect();
+                format!("Tensor([{}])", literals.join(","))
+            }
+        },
+        Expr::Attribute(_, name) | Expr::MethodCall(_, name, _) => {
+            unreachable!("{}应当已经在展开类时处理", name)
+        }
+    }
+}
+
+fn codegen_binary(name: &str, x: &Expr, y: &Expr) -> String {
+    format!("{}({}, {})", name, codegen_expr(x), codegen_expr(y))
+}
+
+fn codegen_literal(literal: &Literal) -> String {
+    match literal {
+        Literal::Int(int_literal) => int_literal.to_string(),
+        Literal::Float(float) => float_literal(*float),
+        Literal::Bool(bool_literal) => bool_literal.to_string(),
+    }
+}
+
+/// Mool 的浮点字面量必须包含小数点
+pub fn float_literal(value: f64) -> String {
+    let literal = format!("{:?}", value);
+    if literal.contains('.') {
+        return literal;
+    }
+    match literal.find('e') {
+        Some(exponent) => format!("{}.0{}", &literal[..exponent], &literal[exponent..]),
+        None => format!("{}.0", literal),
     }
 }
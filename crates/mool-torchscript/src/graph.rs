@@ -0,0 +1,5 @@
+mod ast;
+mod codegen;
+mod parser;
+pub use codegen::codegen;
+pub use parser::graph_parser::graph as parse;
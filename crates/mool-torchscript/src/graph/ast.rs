@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Graph {
+    pub inputs: Vec<Value>,
+    pub nodes: Vec<Node>,
+    pub outputs: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Value {
+    pub name: String,
+    pub ty: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Node {
+    pub outputs: Vec<Value>,
+    pub kind: String,
+    pub attributes: Vec<(String, Attribute)>,
+    pub inputs: Vec<String>,
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Block {
+    pub inputs: Vec<Value>,
+    pub nodes: Vec<Node>,
+    pub outputs: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Attribute {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    /// 列表常量，例如 [1, 2]
+    List(Vec<Attribute>),
+    /// 张量常量的元素和类型，例如 " 1  2 [ CPULongType{2} ]"
+    Tensor(Vec<Attribute>, String),
+    Other(String),
+}
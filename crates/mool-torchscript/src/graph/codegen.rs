@@ -0,0 +1,391 @@
+use super::super::codegen::float_literal;
+use super::ast::{Attribute, Block, Graph, Node};
+use std::collections::HashMap;
+
+/// 将 TorchScript 图翻译为 Mool 代码，整个图翻译为一个名为 %graph 的函数
+///
+/// 图是 SSA 形式，值必须先定义后使用。
+pub fn codegen(graph: Graph) -> String {
+    let mut lowering = Lowering::default();
+    // 形参
+    let mut args = Vec::new();
+    let mut first_tensor = None;
+    for input in graph.inputs.iter() {
+        let ty = mool_type(&input.ty, &input.name);
+        if first_tensor.is_none() && ty.starts_with("Tensor") {
+            first_tensor = Some(ty.clone());
+        }
+        args.push(format!("%{}: {}", mool_name(&input.name), ty));
+        lowering.types.insert(input.name.clone(), ty);
+        lowering.bind_variable(&input.name);
+    }
+    // 函数体
+    let mut body = String::new();
+    for node in graph.nodes.iter() {
+        lowering.codegen_node(node, 1, &mut body);
+    }
+    let rtn = match graph.outputs.as_slice() {
+        [output] => {
+            body.push_str(&format!("    {}\n", lowering.expr(output)));
+            // 未标注形状的张量返回值与第一个张量形参类型相同
+            match lowering.types.get(output) {
+                Some(ty) => ty.clone(),
+                None => match first_tensor {
+                    Some(ty) => ty,
+                    None => panic!("无法推断返回值%{}的类型", output),
+                },
+            }
+        }
+        _ => panic!("暂不支持返回{}个值", graph.outputs.len()),
+    };
+    format!(
+        "let %graph = fn({}) -> {} {{\n{}}}\n",
+        args.join(", "),
+        rtn,
+        body
+    )
+}
+
+/// 图中的值翻译后的形式
+#[derive(Clone)]
+enum Lowered {
+    /// Mool 表达式，常量直接内联
+    Expr(String),
+    /// prim::ListConstruct 构造的列表
+    List(Vec<String>),
+    /// prim::Constant 构造的 None
+    None,
+}
+
+#[derive(Default)]
+struct Lowering {
+    values: HashMap<String, Lowered>,
+    /// 已知形状的值的 Mool 类型
+    types: HashMap<String, String>,
+    /// 数值常量的值，用于检查可选参数是否为默认值
+    numbers: HashMap<String, f64>,
+    loops: usize,
+}
+
+impl Lowering {
+    fn bind_variable(&mut self, name: &str) {
+        self.values.insert(
+            name.to_string(),
+            Lowered::Expr(format!("%{}", mool_name(name))),
+        );
+    }
+
+    fn lowered(&self, name: &str) -> Lowered {
+        match self.values.get(name) {
+            Some(lowered) => lowered.clone(),
+            None => panic!("图中没有定义%{}", name),
+        }
+    }
+
+    fn expr(&self, name: &str) -> String {
+        match self.lowered(name) {
+            Lowered::Expr(expr) => expr,
+            _ => panic!("%{}不能作为表达式使用", name),
+        }
+    }
+
+    /// 可选的标量参数，例如 aten::add 的 alpha，按数值比较，1 和 1.0 都是默认值 1
+    fn expect_default(&self, node: &Node, index: usize, default: f64) {
+        if let Some(name) = node.inputs.get(index) {
+            match self.lowered(name) {
+                Lowered::None => {}
+                _ if self.numbers.get(name) == Some(&default) => {}
+                _ => panic!("{}暂不支持第{}个参数取非默认值", node.kind, index + 1),
+            }
+        }
+    }
+
+    fn codegen_node(&mut self, node: &Node, depth: usize, code: &mut String) {
+        let indent = "    ".repeat(depth);
+        // 输入必须在之前的节点中定义，包括节点不使用的输入
+        for input in node.inputs.iter() {
+            self.lowered(input);
+        }
+        for output in node.outputs.iter() {
+            if let Some(ty) = known_type(&output.ty) {
+                self.types.insert(output.name.clone(), ty);
+            }
+        }
+        let operator = match node.kind.as_str() {
+            "aten::add" => Some("Add"),
+            "aten::sub" => Some("Sub"),
+            "aten::mul" => Some("Mul"),
+            "aten::div" => Some("Div"),
+            "aten::matmul" => Some("Matmul"),
+            "aten::lt" => Some("Lt"),
+            "aten::le" => Some("Le"),
+            "aten::gt" => Some("Gt"),
+            "aten::ge" => Some("Ge"),
+            "aten::eq" => Some("Eq"),
+            "aten::ne" => Some("Ne"),
+            _ => None,
+        };
+        if let Some(operator) = operator {
+            // aten::add 和 aten::sub 的 alpha 只支持默认值 1
+            if operator == "Add" || operator == "Sub" {
+                self.expect_default(node, 2, 1.0);
+            }
+            let expr = format!(
+                "{}({}, {})",
+                operator,
+                self.expr(&node.inputs[0]),
+                self.expr(&node.inputs[1])
+            );
+            self.codegen_let(node, expr, &indent, code);
+            return;
+        }
+        match node.kind.as_str() {
+            "prim::Constant" => {
+                let output = &node.outputs[0];
+                let lowered = match node.attributes.iter().find(|(name, _)| name == "value") {
+                    None => Lowered::None,
+                    // 列表常量与 prim::ListConstruct 构造的列表相同
+                    Some((_, Attribute::List(items))) => {
+                        Lowered::List(items.iter().map(|item| constant(item, "")).collect())
+                    }
+                    Some((_, value)) => {
+                        match value {
+                            Attribute::Int(int_value) => {
+                                self.numbers.insert(output.name.clone(), *int_value as f64);
+                            }
+                            Attribute::Float(float_value) => {
+                                self.numbers.insert(output.name.clone(), *float_value);
+                            }
+                            _ => {}
+                        }
+                        Lowered::Expr(constant(value, &output.ty))
+                    }
+                };
+                self.values.insert(output.name.clone(), lowered);
+            }
+            "prim::ListConstruct" => {
+                let items = node.inputs.iter().map(|input| self.expr(input)).collect();
+                self.values
+                    .insert(node.outputs[0].name.clone(), Lowered::List(items));
+            }
+            "aten::relu" => {
+                let expr = format!("Relu({})", self.expr(&node.inputs[0]));
+                self.codegen_let(node, expr, &indent, code);
+            }
+            // aten::tensor(data, dtype, device, requires_grad)
+            "aten::tensor" => match self.lowered(&node.inputs[0]) {
+                Lowered::List(items) => {
+                    let expr = format!("Tensor([{}])", items.join(","));
+                    self.values
+                        .insert(node.outputs[0].name.clone(), Lowered::Expr(expr));
+                }
+                _ => panic!("aten::tensor 只支持常量列表"),
+            },
+            "prim::If" => self.codegen_if(node, depth, code),
+            "prim::Loop" => self.codegen_loop(node, depth, code),
+            kind => panic!("暂不支持{}节点", kind),
+        }
+    }
+
+    fn codegen_let(&mut self, node: &Node, expr: String, indent: &str, code: &mut String) {
+        let output = &node.outputs[0].name;
+        code.push_str(&format!(
+            "{}let %{} = {}\n",
+            indent,
+            mool_name(output),
+            expr
+        ));
+        self.bind_variable(output);
+    }
+
+    /// 生成语句块内的节点，返回语句块输出的表达式
+    fn codegen_block(&mut self, block: &Block, depth: usize, code: &mut String) -> Vec<String> {
+        for node in block.nodes.iter() {
+            self.codegen_node(node, depth, code);
+        }
+        block
+            .outputs
+            .iter()
+            .map(|output| self.expr(output))
+            .collect()
+    }
+
+    /// prim::If 翻译为 if 表达式
+    fn codegen_if(&mut self, node: &Node, depth: usize, code: &mut String) {
+        let indent = "    ".repeat(depth);
+        if node.outputs.len() > 1 {
+            panic!("prim::If 暂不支持返回{}个值", node.outputs.len());
+        }
+        let cond = self.expr(&node.inputs[0]);
+        let mut branches = Vec::new();
+        for block in node.blocks.iter() {
+            let mut branch = String::new();
+            let outputs = self.codegen_block(block, depth + 1, &mut branch);
+            for output in outputs {
+                branch.push_str(&format!("{}    {}\n", indent, output));
+            }
+            branches.push(branch);
+        }
+        let (then, otherwise) = match branches.as_slice() {
+            [then, otherwise] => (then, otherwise),
+            _ => panic!("prim::If 必须有两个分支"),
+        };
+        match node.outputs.first() {
+            Some(output) => {
+                code.push_str(&format!("{}let %{} = ", indent, mool_name(&output.name)));
+                self.bind_variable(&output.name);
+            }
+            None => code.push_str(&indent),
+        }
+        code.push_str(&format!(
+            "if {} {{\n{}{}}} else {{\n{}{}}}\n",
+            cond, then, indent, otherwise, indent
+        ));
+    }
+
+    /// prim::Loop(max_trip_count, initial_condition, carried...) 翻译为 for 循环
+    fn codegen_loop(&mut self, node: &Node, depth: usize, code: &mut String) {
+        let indent = "    ".repeat(depth);
+        let block = match node.blocks.as_slice() {
+            [block] => block,
+            _ => panic!("prim::Loop 必须有一个循环体"),
+        };
+        let max_trip = self.expr(&node.inputs[0]);
+        let initial_cond = self.expr(&node.inputs[1]);
+        // 循环体的形参：迭代次数和循环变量，循环变量保存在 alloca 中跨迭代传递
+        let counter = &block.inputs[0].name;
+        self.bind_variable(counter);
+        for (param, init) in block.inputs[1..].iter().zip(node.inputs[2..].iter()) {
+            let init = self.expr(init);
+            code.push_str(&format!(
+                "{}let %{} = {}\n",
+                indent,
+                mool_name(&param.name),
+                init
+            ));
+            self.bind_variable(&param.name);
+        }
+        // 条件恒为 true 时不需要检查条件
+        let cond_variable = match (initial_cond.as_str(), block.outputs.first()) {
+            ("true", Some(cond)) if self.expr(cond) == "true" => None,
+            _ => {
+                self.loops += 1;
+                let cond_variable = format!("%loop_cond_{}", self.loops);
+                code.push_str(&format!(
+                    "{}let {} = {}\n",
+                    indent, cond_variable, initial_cond
+                ));
+                Some(cond_variable)
+            }
+        };
+        code.push_str(&format!(
+            "{}for %{} in range(0, {}, 1) {{\n",
+            indent,
+            mool_name(counter),
+            max_trip
+        ));
+        if let Some(cond_variable) = &cond_variable {
+            code.push_str(&format!(
+                "{}    if Eq({}, false) {{\n{}        break\n{}    }}\n",
+                indent, cond_variable, indent, indent
+            ));
+        }
+        let outputs = self.codegen_block(block, depth + 1, code);
+        // 先计算所有新值再更新循环变量，避免互相覆盖
+        let mut updates = Vec::new();
+        if let Some(cond_variable) = &cond_variable {
+            updates.push((cond_variable.clone(), outputs[0].clone()));
+        }
+        for (param, output) in block.inputs[1..].iter().zip(outputs[1..].iter()) {
+            updates.push((format!("%{}", mool_name(&param.name)), output.clone()));
+        }
+        for (variable, value) in updates.iter() {
+            code.push_str(&format!(
+                "{}    let {}_next = {}\n",
+                indent, variable, value
+            ));
+        }
+        for (variable, _) in updates.iter() {
+            code.push_str(&format!("{}    {} = {}_next\n", indent, variable, variable));
+        }
+        code.push_str(&format!("{}}}\n", indent));
+        // 循环的输出即为循环变量的最终值
+        for (output, param) in node.outputs.iter().zip(block.inputs[1..].iter()) {
+            self.values.insert(
+                output.name.clone(),
+                Lowered::Expr(format!("%{}", mool_name(&param.name))),
+            );
+        }
+    }
+}
+
+/// 图中的值名可以以数字开头并包含 .，转换为合法的 Mool 变量名
+fn mool_name(name: &str) -> String {
+    let name = name.replace('.', "_");
+    match name.chars().next() {
+        Some(c) if c.is_ascii_digit() => format!("v{}", name),
+        _ => name,
+    }
+}
+
+/// 标量类型和带形状的张量类型，例如 Float(2, strides=[1], requires_grad=0, device=cpu)
+fn known_type(ty: &str) -> Option<String> {
+    match ty {
+        "int" | "float" | "bool" => return Some(ty.to_string()),
+        _ => {}
+    }
+    let open = ty.find('(')?;
+    let dtype = match &ty[..open] {
+        "Float" | "Double" | "Half" | "BFloat16" => "float",
+        "Long" | "Int" | "Short" | "Char" | "Byte" => "int",
+        "Bool" => "bool",
+        _ => return None,
+    };
+    let sizes: Vec<&str> = ty[open + 1..ty.len() - 1]
+        .split(',')
+        .map(|size| size.trim())
+        .take_while(|size| !size.contains('='))
+        .collect();
+    match sizes.as_slice() {
+        [size] if size.parse::<usize>().is_ok() => Some(format!("Tensor[({}),{}]", size, dtype)),
+        _ => panic!("暂只支持一维张量：{}", ty),
+    }
+}
+
+fn mool_type(ty: &str, name: &str) -> String {
+    match known_type(ty) {
+        Some(ty) => ty,
+        None if ty == "Tensor" => panic!("张量%{}缺少形状信息", name),
+        None => panic!("暂不支持{}类型", ty),
+    }
+}
+
+/// prim::Constant 的 value 属性
+fn constant(value: &Attribute, ty: &str) -> String {
+    match value {
+        Attribute::Int(int_value) if ty == "bool" => (*int_value != 0).to_string(),
+        Attribute::Int(int_value) => int_value.to_string(),
+        Attribute::Float(float_value) => float_literal(*float_value),
+        // 张量常量打印为 " 1  2 [ CPULongType{2} ]"，元素的写法与类型无关
+        Attribute::Tensor(items, dtype) => {
+            let element = match dtype.trim_start_matches("CPU").trim_end_matches("Type") {
+                "Float" | "Double" | "Half" | "BFloat16" => "float",
+                "Long" | "Int" | "Short" | "Char" | "Byte" => "int",
+                "Bool" => "bool",
+                _ => panic!("暂不支持{}类型的张量常量", dtype),
+            };
+            let items: Vec<String> = items
+                .iter()
+                .map(|item| match (item, element) {
+                    (Attribute::Int(value), "float") => float_literal(*value as f64),
+                    (Attribute::Float(value), "float") => float_literal(*value),
+                    (Attribute::Int(value), "int") => value.to_string(),
+                    (Attribute::Int(value), _) => (*value != 0).to_string(),
+                    _ => panic!("无法解析张量常量：{:?}", value),
+                })
+                .collect();
+            format!("Tensor([{}])", items.join(","))
+        }
+        _ => panic!("暂不支持常量：{:?}", value),
+    }
+}
@@ -0,0 +1,61 @@
+use super::ast;
+
+peg::parser! {
+    pub grammar graph_parser() for str {
+        use ast::{Graph, Value, Node, Block, Attribute};
+        pub rule graph() -> Graph =
+            ig_line() "graph" ig_space() "(" inputs:(typed_value() ** ",") ")" ig_space() ":" end_line()
+                nodes:node()* ig_line() "return" ig_space() "(" outputs:(value_list_item() ** ",") ")" ig_line() {
+                Graph{inputs, nodes, outputs}
+            }
+        rule typed_value() -> Value = ig_line() name:value_name() ig_space() ":" ig_space() ty:type_str() ig_line() {
+            Value{name, ty}
+        }
+        rule value_name() -> String = "%" name:$(['a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.']+) { name.to_owned() }
+        rule value_list_item() -> String = ig_line() v:value_name() ig_line() { v }
+        // 类型中可能包含括号和逗号，例如 Float(2, strides=[1], requires_grad=0, device=cpu)
+        rule type_str() -> String = t:$(type_atom()+) { t.trim().to_owned() }
+        rule type_atom() = "(" balanced() ")" / "[" balanced() "]" / [^ ',' | ')' | ']' | '=' | '(' | '[' | '\n' | ':']
+        rule balanced() = ("(" balanced() ")" / "[" balanced() "]" / [^ '(' | ')' | '[' | ']'])*
+        rule node() -> Node =
+            ig_line() !("return" / "block" / "->") outputs:(typed_value() ** ",") ig_space() "=" ig_space()
+                kind:kind() attributes:attributes() ig_space() "(" inputs:(value_list_item() ** ",") ")" end_line()
+                blocks:block()* {
+                Node{outputs, kind, attributes, inputs, blocks}
+            }
+        rule kind() -> String = k:$(identifier() "::" identifier()) { k.to_owned() }
+        rule identifier() = ['a'..='z' | 'A'..='Z' | '_']['a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.']*
+        rule attributes() -> Vec<(String, Attribute)> =
+            "[" a:(attribute() ** ",") "]" { a }
+            / "" { Vec::new() }
+        rule attribute() -> (String, Attribute) =
+            ig_space() name:$(identifier()) "=" value:attribute_value() { (name.to_owned(), value) }
+        rule attribute_value() -> Attribute =
+            "[" a:(ig_space() a:attribute_value() ig_space() { a }) ** "," "]" { Attribute::List(a) }
+            / t:(ig_space() n:number() { n })+ ig_space() "[" ig_space() dtype:$(identifier()) "{" ['0'..='9' | ',']* "}" ig_space() "]" {
+                Attribute::Tensor(t, dtype.to_owned())
+            }
+            / number()
+            / "\"" s:$((!"\"" ("\\" [_] / [_]))*) "\"" { Attribute::Str(s.to_owned()) }
+            // 不能解析的数值是语法错误，不作为其他属性
+            / !['0'..='9' | '-'] s:$(("[" balanced() "]" / [^ ',' | ']' | '['])*) { Attribute::Other(s.trim().to_owned()) }
+        rule number() -> Attribute =
+            n:$("-"? ['0'..='9']+) !['.' | 'e' | 'E' | '0'..='9'] {?
+                n.parse().map(Attribute::Int).or(Err("整数"))
+            }
+            / n:$("-"? ['0'..='9']+ ("." ['0'..='9']* exponent()? / exponent())) !['a'..='z' | 'A'..='Z'] {?
+                n.parse().map(Attribute::Float).or(Err("浮点数"))
+            }
+        rule exponent() = ['e' | 'E'] ['+' | '-']? ['0'..='9']+
+        rule block() -> Block =
+            ig_line() "block" ['0'..='9']+ "(" inputs:(typed_value() ** ",") ")" ig_space() ":" end_line()
+                nodes:node()* ig_line() "->" ig_space() "(" outputs:(value_list_item() ** ",") ")" end_line() {
+                Block{inputs, nodes, outputs}
+            }
+        // 节点后面的源码位置注释，例如 # test.py:4:11
+        rule comment() = "#" [^'\n']*
+        rule end_line() = ig_space() comment()? ("\n" / ![_])
+        rule ig_space() = quiet!{ [' ' | '\t']* }
+        rule ig_line() = quiet!{ ([' ' | '\t' | '\r' | '\n'] / comment())* }
+    }
+}
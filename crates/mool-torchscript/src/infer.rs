@@ -0,0 +1,427 @@
+use super::ast::{Expr, Function, FunctionStatement, Literal, Operator, Program, Type};
+use std::collections::HashMap;
+
+/// 函数签名：每个形参的 Mool 类型和返回值的 Mool 类型
+pub struct Signature {
+    pub args: Vec<String>,
+    pub rtn: String,
+}
+
+/// 推断每个函数的张量形状和列表长度
+///
+/// 先取顶层语句中调用处的张量和列表实参，再在函数之间反复传播，直到不再变化：
+/// - 二元运算一侧的类型传给另一侧的变量
+/// - 调用时实参的类型传给被调用函数的形参，被调用函数的形参类型也传回实参变量
+pub fn infer_signatures(input: &[Program]) -> HashMap<String, Signature> {
+    let functions: Vec<&Function> = input
+        .iter()
+        .filter_map(|program| match program {
+            Program::Function(function) => Some(function),
+            _ => None,
+        })
+        .collect();
+    // 收集每个函数在调用处的张量和列表字面量实参
+    let mut literal_args: HashMap<String, Vec<Option<Expr>>> = HashMap::new();
+    for program in input.iter() {
+        if let Program::Statement(expr) = program {
+            collect_literal_args(expr, &mut literal_args);
+        }
+    }
+    let mut inference = Inference {
+        functions: functions
+            .iter()
+            .map(|function| (function.name.clone(), *function))
+            .collect(),
+        arg_types: HashMap::new(),
+        changed: true,
+    };
+    for function in functions.iter() {
+        let literals = literal_args.get(&function.name);
+        let types = function
+            .args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| {
+                let literal = literals.and_then(|literals| literals.get(i)?.as_ref());
+                match (&arg.annotation, literal) {
+                    (Type::Tensor, Some(Expr::Operator(Operator::Tensor(literals)))) => {
+                        Some(tensor_type(literals))
+                    }
+                    // 列表的长度和其中张量的形状由列表字面量确定
+                    (Type::List(element), Some(Expr::Tuple(items))) => {
+                        let tensor = items.iter().find_map(|item| match item {
+                            Expr::Operator(Operator::Tensor(literals)) => {
+                                Some(tensor_type(literals))
+                            }
+                            _ => None,
+                        });
+                        list_type(element, items.len(), tensor.as_ref())
+                            .ok()
+                            .flatten()
+                    }
+                    (annotation, _) => mool_type(annotation, None).ok().flatten(),
+                }
+            })
+            .collect();
+        inference.arg_types.insert(function.name.clone(), types);
+    }
+    while inference.changed {
+        inference.changed = false;
+        for function in functions.iter() {
+            inference.function(function);
+        }
+    }
+    let mut signatures = HashMap::new();
+    for function in functions.iter() {
+        let types = &inference.arg_types[&function.name];
+        let args = function
+            .args
+            .iter()
+            .zip(types.iter())
+            .map(|(arg, ty)| match (ty, &arg.annotation) {
+                (Some(ty), _) => ty.clone(),
+                (None, Type::Tensor) => {
+                    panic!("无法推断函数{}中张量{}的形状", function.name, arg.name)
+                }
+                (None, annotation) => match mool_type(annotation, None) {
+                    Err(message) => panic!("函数{}中参数{}的{}", function.name, arg.name, message),
+                    Ok(_) if contains_list(annotation) => panic!(
+                        "无法确定函数{}中参数{}的类型{}的长度，调用时需要传入列表字面量",
+                        function.name, arg.name, annotation
+                    ),
+                    Ok(_) => panic!(
+                        "无法推断函数{}中参数{}的类型{}",
+                        function.name, arg.name, annotation
+                    ),
+                },
+            })
+            .collect();
+        let rtn = match return_type(function, types) {
+            Ok(Some(rtn)) => rtn,
+            Err(message) => panic!("函数{}返回值的{}", function.name, message),
+            Ok(None) if contains_list(&function.rtn) => panic!(
+                "无法确定函数{}返回值的类型{}的长度，需要返回列表字面量",
+                function.name, function.rtn
+            ),
+            Ok(None) => panic!("无法推断函数{}返回张量的形状", function.name),
+        };
+        signatures.insert(function.name.clone(), Signature { args, rtn });
+    }
+    signatures
+}
+
+/// 返回值中的张量与第一个张量形参的类型相同
+fn return_type(
+    function: &Function,
+    arg_types: &[Option<String>],
+) -> Result<Option<String>, String> {
+    let tensor = first_tensor(function, arg_types);
+    match (&function.rtn, returned_list(function)) {
+        (Type::List(element), Some(length)) => list_type(element, length, tensor.as_ref()),
+        (rtn, _) => mool_type(rtn, tensor.as_ref()),
+    }
+}
+
+/// 函数体中返回的列表字面量的长度
+fn returned_list(function: &Function) -> Option<usize> {
+    function.body.iter().find_map(|statement| match statement {
+        FunctionStatement::Return(Expr::Tuple(items)) => Some(items.len()),
+        _ => None,
+    })
+}
+
+fn first_tensor(function: &Function, arg_types: &[Option<String>]) -> Option<String> {
+    function
+        .args
+        .iter()
+        .zip(arg_types.iter())
+        .find(|(arg, _)| arg.annotation == Type::Tensor)
+        .and_then(|(_, ty)| ty.clone())
+}
+
+struct Inference<'a> {
+    functions: HashMap<String, &'a Function>,
+    /// 每个函数形参的类型，未知的张量为 None
+    arg_types: HashMap<String, Vec<Option<String>>>,
+    changed: bool,
+}
+
+impl<'a> Inference<'a> {
+    fn function(&mut self, function: &Function) {
+        let mut types: HashMap<String, String> = function
+            .args
+            .iter()
+            .zip(self.arg_types[&function.name].iter())
+            .filter_map(|(arg, ty)| ty.clone().map(|ty| (arg.name.clone(), ty)))
+            .filter(|(_, ty)| ty.starts_with("Tensor"))
+            .collect();
+        // 循环中后面的语句确定的类型可能影响前面的语句，因此遍历两次
+        for _ in 0..2 {
+            self.block(&function.body, &mut types);
+        }
+        let arg_types = self.arg_types.get_mut(&function.name).unwrap();
+        for (arg, ty) in function.args.iter().zip(arg_types.iter_mut()) {
+            if ty.is_none() {
+                if let Some(inferred) = types.get(&arg.name) {
+                    *ty = Some(inferred.clone());
+                    self.changed = true;
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, statements: &[FunctionStatement], types: &mut HashMap<String, String>) {
+        for statement in statements.iter() {
+            match statement {
+                FunctionStatement::Expr(expr) | FunctionStatement::Return(expr) => {
+                    self.expr(expr, types);
+                }
+                FunctionStatement::Assign(name, expr) => {
+                    if let Some(ty) = self.expr(expr, types) {
+                        types.entry(name.clone()).or_insert(ty);
+                    }
+                    // 直接赋值的变量类型相同，例如 y = x
+                    if let (Expr::Identifier(source), Some(ty)) = (expr, types.get(name)) {
+                        let ty = ty.clone();
+                        types.entry(source.clone()).or_insert(ty);
+                    }
+                    // 元组变量的元素记为 t.0、t.1 等
+                    for index in 0..self.arity(expr) {
+                        if let Some(ty) = self.element(expr, index, types) {
+                            types.entry(format!("{}.{}", name, index)).or_insert(ty);
+                        }
+                    }
+                }
+                FunctionStatement::AssignTuple(names, expr) => {
+                    self.expr(expr, types);
+                    for (index, name) in names.iter().enumerate() {
+                        if let Some(ty) = self.element(expr, index, types) {
+                            types.entry(name.clone()).or_insert(ty);
+                        }
+                    }
+                }
+                FunctionStatement::For(for_loop) => {
+                    for expr in for_loop.range.iter() {
+                        self.expr(expr, types);
+                    }
+                    self.block(&for_loop.body, types);
+                }
+                FunctionStatement::While(while_loop) => {
+                    self.expr(&while_loop.cond, types);
+                    self.block(&while_loop.body, types);
+                }
+                FunctionStatement::Break | FunctionStatement::Continue => {}
+            }
+        }
+    }
+
+    /// 返回表达式的张量类型
+    fn expr(&mut self, expr: &Expr, types: &mut HashMap<String, String>) -> Option<String> {
+        match expr {
+            Expr::Identifier(name) => types.get(name).cloned(),
+            Expr::Operator(operator) => match operator {
+                Operator::Tensor(literals) => Some(tensor_type(literals)),
+                Operator::Relu(x) => self.expr(x, types),
+                Operator::Add(x, y)
+                | Operator::Sub(x, y)
+                | Operator::Mul(x, y)
+                | Operator::Div(x, y) => self.binary(x, y, types),
+                // 比较和点积的结果与操作数类型不同
+                Operator::Matmul(x, y)
+                | Operator::Lt(x, y)
+                | Operator::Le(x, y)
+                | Operator::Gt(x, y)
+                | Operator::Ge(x, y)
+                | Operator::Eq(x, y)
+                | Operator::Ne(x, y) => {
+                    self.binary(x, y, types);
+                    None
+                }
+            },
+            Expr::Call(name, args) => {
+                let actual: Vec<Option<String>> = args
+                    .iter()
+                    .map(|arg| self.expr(&arg.value, types))
+                    .collect();
+                let function = *self.functions.get(name)?;
+                let formal = self.arg_types.get_mut(name)?;
+                for ((arg, actual), formal) in args.iter().zip(actual).zip(formal.iter_mut()) {
+                    match (formal.as_ref(), actual) {
+                        (None, Some(actual)) if actual.starts_with("Tensor") => {
+                            *formal = Some(actual);
+                            self.changed = true;
+                        }
+                        (Some(formal), None) if formal.starts_with("Tensor") => {
+                            if let Expr::Identifier(name) = &arg.value {
+                                types.entry(name.clone()).or_insert_with(|| formal.clone());
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                return_type(function, &self.arg_types[name])
+                    .ok()
+                    .flatten()
+                    .filter(|rtn| rtn.starts_with("Tensor"))
+            }
+            Expr::Tuple(items) => {
+                for item in items.iter() {
+                    self.expr(item, types);
+                }
+                None
+            }
+            Expr::Subscript(base, index) => match &**index {
+                Expr::Literal(Literal::Int(index)) if *index >= 0 => {
+                    self.element(base, *index as usize, types)
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// 元组表达式的元素个数，不是元组时为 0
+    fn arity(&self, expr: &Expr) -> usize {
+        match expr {
+            Expr::Tuple(items) => items.len(),
+            Expr::Call(name, _) => match self.functions.get(name) {
+                Some(function) => match &function.rtn {
+                    Type::Tuple(elements) => elements.len(),
+                    Type::List(_) => returned_list(function).unwrap_or(0),
+                    _ => 0,
+                },
+                None => 0,
+            },
+            _ => 0,
+        }
+    }
+
+    /// 元组表达式第 index 个元素的张量类型
+    fn element(
+        &mut self,
+        expr: &Expr,
+        index: usize,
+        types: &mut HashMap<String, String>,
+    ) -> Option<String> {
+        match expr {
+            Expr::Tuple(items) => self.expr(items.get(index)?, types),
+            Expr::Identifier(name) => types.get(&format!("{}.{}", name, index)).cloned(),
+            Expr::Call(name, _) => {
+                self.expr(expr, types);
+                let function = *self.functions.get(name)?;
+                let element = match &function.rtn {
+                    Type::Tuple(elements) => elements.get(index)?,
+                    Type::List(element) => element,
+                    _ => return None,
+                };
+                let tensor = first_tensor(function, &self.arg_types[name]);
+                mool_type(element, tensor.as_ref())
+                    .ok()
+                    .flatten()
+                    .filter(|ty| ty.starts_with("Tensor"))
+            }
+            _ => None,
+        }
+    }
+
+    /// 把二元运算一侧的类型传播给另一侧的变量
+    fn binary(
+        &mut self,
+        x: &Expr,
+        y: &Expr,
+        types: &mut HashMap<String, String>,
+    ) -> Option<String> {
+        let x_type = self.expr(x, types);
+        let y_type = self.expr(y, types);
+        for (expr, ty) in [(x, &y_type), (y, &x_type)] {
+            if let (Expr::Identifier(name), Some(ty)) = (expr, ty) {
+                types.entry(name.clone()).or_insert_with(|| ty.clone());
+            }
+        }
+        x_type.or(y_type)
+    }
+}
+
+fn collect_literal_args(expr: &Expr, literal_args: &mut HashMap<String, Vec<Option<Expr>>>) {
+    if let Expr::Call(name, args) = expr {
+        for arg in args.iter() {
+            collect_literal_args(&arg.value, literal_args);
+        }
+        let literals = literal_args
+            .entry(name.clone())
+            .or_insert_with(|| vec![None; args.len()]);
+        for (i, arg) in args.iter().enumerate() {
+            if let Expr::Operator(Operator::Tensor(_)) | Expr::Tuple(_) = &arg.value {
+                if i < literals.len() && literals[i].is_none() {
+                    literals[i] = Some(arg.value.clone());
+                }
+            }
+        }
+    }
+}
+
+fn tensor_type(literals: &[Literal]) -> String {
+    let dtype = match literals.first() {
+        Some(Literal::Float(_)) => "float",
+        Some(Literal::Bool(_)) => "bool",
+        _ => "int",
+    };
+    format!("Tensor[({}),{}]", literals.len(), dtype)
+}
+
+/// TorchScript 类型对应的 Mool 类型，其中的张量类型为 tensor，无法确定时返回 None
+///
+/// 元组对应 Mool 的元组，Optional[T] 表示为带标记的元组 (bool, T)，
+/// List[T] 只有长度确定时才能表示为 T 组成的元组，见 list_type。
+/// 其他类型没有对应的 Mool 类型，返回错误。
+fn mool_type(ty: &Type, tensor: Option<&String>) -> Result<Option<String>, String> {
+    match ty {
+        Type::Int => Ok(Some("int".to_string())),
+        Type::Float => Ok(Some("float".to_string())),
+        Type::Bool => Ok(Some("bool".to_string())),
+        Type::Tensor => Ok(tensor.cloned()),
+        Type::Tuple(elements) => {
+            let elements = elements
+                .iter()
+                .map(|element| mool_type(element, tensor))
+                .collect::<Result<Vec<Option<String>>, String>>()?;
+            Ok(elements
+                .into_iter()
+                .collect::<Option<Vec<String>>>()
+                .map(|elements| tuple_type(&elements)))
+        }
+        Type::Optional(element) => {
+            Ok(mool_type(element, tensor)?.map(|element| format!("(bool, {})", element)))
+        }
+        // 长度未知，仍然检查元素的类型
+        Type::List(element) => mool_type(element, tensor).map(|_| None),
+        Type::Str | Type::None | Type::Dict(_, _) | Type::Class(_) => {
+            Err(format!("类型{}无法翻译为 Mool 类型", ty))
+        }
+    }
+}
+
+/// 长度为 length 的 List[element] 表示为 element 组成的元组
+fn list_type(
+    element: &Type,
+    length: usize,
+    tensor: Option<&String>,
+) -> Result<Option<String>, String> {
+    Ok(mool_type(element, tensor)?.map(|element| tuple_type(&vec![element; length])))
+}
+
+fn tuple_type(elements: &[String]) -> String {
+    match elements {
+        [element] => format!("({},)", element),
+        elements => format!("({})", elements.join(", ")),
+    }
+}
+
+fn contains_list(ty: &Type) -> bool {
+    match ty {
+        Type::List(_) => true,
+        Type::Tuple(elements) => elements.iter().any(contains_list),
+        Type::Optional(element) => contains_list(element),
+        _ => false,
+    }
+}
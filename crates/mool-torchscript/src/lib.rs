@@ -1,5 +1,11 @@
+pub mod archive;
+mod arguments;
 mod ast;
+mod class;
 mod codegen;
+pub mod graph;
+mod infer;
 mod parser;
-pub use codegen::codegen;
-pub use parser::torchscript_parser::program as parse;
+pub mod tokenizer;
+pub use codegen::{codegen, codegen_module};
+pub use parser::parse;
@@ -1,24 +1,193 @@
+use super::arguments;
 use super::ast;
+use super::tokenizer;
+
+/// 先由 tokenizer 处理缩进，再解析为抽象语法树
+pub fn parse(code: &str) -> Result<Vec<ast::Program>, peg::error::ParseError<peg::str::LineCol>> {
+    torchscript_parser::program(&tokenizer::tokenize(code)?)
+}
+
+/// 签名中允许的类型标注：标量、张量以及由它们组成的 Tuple、Optional、List 和 Dict
+///
+/// Dict 的键还可以是 str。List 和 Dict 能否翻译为 Mool 类型在翻译时检查。
+fn supported(ty: &ast::Type) -> bool {
+    match ty {
+        ast::Type::Int | ast::Type::Float | ast::Type::Bool | ast::Type::Tensor => true,
+        ast::Type::Tuple(elements) => elements.iter().all(supported),
+        ast::Type::Optional(element) | ast::Type::List(element) => supported(element),
+        ast::Type::Dict(key, value) => {
+            (**key == ast::Type::Str || supported(key)) && supported(value)
+        }
+        ast::Type::Str | ast::Type::None | ast::Type::Class(_) => false,
+    }
+}
 
 peg::parser! {
     pub grammar torchscript_parser() for str {
-        use ast::{Program, Function, FunctionArg, FunctionStatement, Expr, Literal, Operator};
-        pub rule program() -> Vec<Program> = f:((function() / statement())*) { f }
+        use ast::{Program, Function, Class, FunctionArg, Type, Argument, FunctionStatement, For, While, Expr, Literal, Operator};
+        pub rule program() -> Vec<Program> = f:((skip_line()* p:(function() / class() / statement()) { p })*) skip_line()* { f }
+        // 顶层的空行、注释和模块文档字符串
+        rule skip_line() = blank_line() / string_literal() ig_space() end_line()
         rule statement() -> Program = s:expression() ig_line() { Program::Statement(s) }
-        rule function() -> Program =
-            "def" " " name:identifier_str() ig_space() "(" ig_line() args:function_args() ig_line() ")" 
-                                                        ig_space() "->" ig_space() rt:identifier_str() ig_space() ":" ig_space() "\n" e:function_body() ig_line() {
-                Program::Function(Function{name:name.to_string(), args:args, rtn:rt.to_string(), body:e})
+        rule function() -> Program = f:function_def() { Program::Function(f) }
+        rule function_def() -> Function =
+            "def" " " name:identifier_str() ig_space() "(" ig_line() args:function_args() ig_line() ")"
+                                                        ig_space() "->" ig_space() rt:mool_type_expr() ig_space() ":" ig_space() "\n" body:function_body() {
+                let (doc, body) = body;
+                Function{name, args, rtn:rt, doc, body}
             }
+        // 类体中除方法外的 __parameters__、属性声明等语句只用于 Python，直接跳过
+        rule class() -> Program =
+            "class" " " ig_space() name:identifier_str() ig_space() ("(" ig_line() dotted_name() ig_line() ")")? ig_space() ":" ig_space() "\n"
+                blank_line()* indent_token() members:(blank_line()* m:class_member() { m })* blank_line()* dedent_token() {
+                Program::Class(Class{name, methods: members.into_iter().flatten().collect()})
+            }
+        rule class_member() -> Option<Function> =
+            f:function_def() { Some(f) }
+            / !"def " [^ '\n' | '\u{2}' | '\u{3}']+ "\n" { None }
+        rule dotted_name() -> String = n:$(identifier_str() ("." identifier_str())*) { n.to_owned() }
+        // 函数体的第一条语句可以是文档字符串
+        rule function_body() -> (Option<String>, Vec<FunctionStatement>) =
+            blank_line()* indent_token() doc:(d:string_literal() ig_space() end_line() { d })?
+                s:(blank_line()* s:function_statement() { s })* blank_line()* dedent_token() { (doc, s) }
         rule function_args() -> Vec<FunctionArg> = args:(function_arg() ** ",") ","? { args }
-        rule function_arg() -> FunctionArg = ig_line() arg:identifier_str() ig_line() ":" ig_line() annotation:identifier_str() ig_line() {
-            FunctionArg{name: arg.to_string(), annotation: annotation.to_string()}
+        // 与 TorchScript 相同，没有类型标注的参数为 Tensor，方法的 self 参数标注为类名
+        rule function_arg() -> FunctionArg =
+            ig_line() arg:identifier_str() ig_line() annotation:(":" ig_line() t:type_expr() { t })? ig_line()
+                default:("=" ig_line() e:expression() ig_line() { e })? {?
+                let annotation = annotation.unwrap_or(Type::Tensor);
+                match annotation {
+                    Type::Class(_) if arg == "self" => Ok(FunctionArg{name: arg, annotation, default}),
+                    _ if supported(&annotation) => Ok(FunctionArg{name: arg, annotation, default}),
+                    _ => Err("int、float、bool、Tensor 或者由它们组成的 Tuple、Optional、List、Dict 类型"),
+                }
+            }
+        rule mool_type_expr() -> Type = t:type_expr() {?
+            if supported(&t) {
+                Ok(t)
+            } else {
+                Err("int、float、bool、Tensor 或者由它们组成的 Tuple、Optional、List、Dict 类型")
+            }
         }
-        rule function_body() -> Vec<FunctionStatement> = e:(function_statement() ** "\n") {e}
-        rule function_statement() -> FunctionStatement = 
-            indent() "return" ig_space() e:expression() { FunctionStatement::Return(e) } 
-            / indent() e:expression() { FunctionStatement::Expr(e) }
-        rule expression() -> Expr = literal() / operator() / call() / identifier()
+        // 类型标注，容器类型也可以写作 Python 3.9 的 list[int] 等形式
+        rule type_expr() -> Type =
+            "typing."? ("List" / "list") ig_space() "[" t:type_args() "]" {?
+                match t.as_slice() {
+                    [element] => Ok(Type::List(Box::new(element.clone()))),
+                    _ => Err("List[T]"),
+                }
+            }
+            / "typing."? ("Tuple" / "tuple") ig_space() "[" t:type_args() "]" { Type::Tuple(t) }
+            / "typing."? "Optional" ig_space() "[" t:type_args() "]" {?
+                match t.as_slice() {
+                    [element] => Ok(Type::Optional(Box::new(element.clone()))),
+                    _ => Err("Optional[T]"),
+                }
+            }
+            / "typing."? ("Dict" / "dict") ig_space() "[" t:type_args() "]" {?
+                match t.as_slice() {
+                    [key, value] => Ok(Type::Dict(Box::new(key.clone()), Box::new(value.clone()))),
+                    _ => Err("Dict[K, V]"),
+                }
+            }
+            / n:dotted_name() {
+                match n.trim_start_matches("typing.") {
+                    "int" => Type::Int,
+                    "float" => Type::Float,
+                    "bool" => Type::Bool,
+                    "str" => Type::Str,
+                    "None" | "NoneType" => Type::None,
+                    "Tensor" | "torch.Tensor" => Type::Tensor,
+                    name => Type::Class(name.to_string()),
+                }
+            }
+        rule type_args() -> Vec<Type> = t:((ig_line() t:type_expr() ig_line() { t }) ** ",") ","? { t }
+        // 缩进的循环体，INDENT/DEDENT 标记由 tokenizer 插入
+        rule suite() -> Vec<FunctionStatement> =
+            blank_line()* indent_token() s:(blank_line()* s:loop_statement() { s })+ blank_line()* dedent_token() { s }
+        // Mool 函数以最后一个表达式作为返回值，不能从循环中提前返回
+        rule loop_statement() -> FunctionStatement =
+            "return" !identifier_char() {? Err("循环之外的 return") }
+            / !("return" !identifier_char()) s:function_statement() { s }
+        rule function_statement() -> FunctionStatement =
+            for_statement()
+            / while_statement()
+            / s:simple_statement() ig_space() end_line() { s }
+        rule simple_statement() -> FunctionStatement =
+            "return" ig_space() e:expression_list() { FunctionStatement::Return(e) }
+            / "break" !identifier_char() { FunctionStatement::Break }
+            / "continue" !identifier_char() { FunctionStatement::Continue }
+            / not_keyword() id:identifier_str() ig_space() "=" !"=" ig_space() e:expression_list() { FunctionStatement::Assign(id, e) }
+            / names:target_list() ig_space() "=" !"=" ig_space() e:expression_list() { FunctionStatement::AssignTuple(names, e) }
+            / e:expression() { FunctionStatement::Expr(e) }
+        rule for_statement() -> FunctionStatement =
+            "for" " " ig_space() var:identifier_str() ig_space() "in" ig_space() "range" ig_space()
+                "(" ig_line() range:(expression() **<1,3> ",") ig_line() ")" ig_space() ":" ig_space() "\n" body:suite() {
+                FunctionStatement::For(For{var, range, body})
+            }
+        rule while_statement() -> FunctionStatement =
+            "while" " " ig_space() cond:expression() ig_space() ":" ig_space() "\n" body:suite() {
+                FunctionStatement::While(While{cond, body})
+            }
+        // 逗号分隔的多个表达式为元组，例如 return a, b
+        rule expression_list() -> Expr =
+            items:(expression() ++ (ig_space() "," ig_space())) trailing:(ig_space() ",")? {
+                if items.len() == 1 && trailing.is_none() {
+                    items.into_iter().next().unwrap()
+                } else {
+                    Expr::Tuple(items)
+                }
+            }
+        // 元组解包的变量，例如 a, b 或者 (a, b)
+        rule target_list() -> Vec<String> =
+            "(" ig_line() names:target_list() ig_line() ")" { names }
+            / names:((not_keyword() n:identifier_str() { n }) ++ (ig_space() "," ig_space())) (ig_space() ",")? { names }
+        rule expression() -> Expr = precedence!{
+            x:(@) ig_space() op:$("<=" / ">=" / "<" / ">" / "==" / "!=") ig_space() y:@ {
+                let (x, y) = (Box::new(x), Box::new(y));
+                Expr::Operator(match op {
+                    "<=" => Operator::Le(x, y),
+                    ">=" => Operator::Ge(x, y),
+                    "<" => Operator::Lt(x, y),
+                    ">" => Operator::Gt(x, y),
+                    "==" => Operator::Eq(x, y),
+                    _ => Operator::Ne(x, y),
+                })
+            }
+            --
+            x:(@) ig_space() "+" ig_space() y:@ { Expr::Operator(Operator::Add(Box::new(x), Box::new(y))) }
+            x:(@) ig_space() "-" ig_space() y:@ { Expr::Operator(Operator::Sub(Box::new(x), Box::new(y))) }
+            --
+            x:(@) ig_space() "*" ig_space() y:@ { Expr::Operator(Operator::Mul(Box::new(x), Box::new(y))) }
+            x:(@) ig_space() "/" ig_space() y:@ { Expr::Operator(Operator::Div(Box::new(x), Box::new(y))) }
+            x:(@) ig_space() "@" ig_space() y:@ { Expr::Operator(Operator::Matmul(Box::new(x), Box::new(y))) }
+            --
+            x:(@) "." name:identifier_str() ig_line() "(" ig_line() args:call_args() ig_line() ")" ig_space() {
+                Expr::MethodCall(Box::new(x), name, args)
+            }
+            x:(@) "." name:identifier_str() { Expr::Attribute(Box::new(x), name) }
+            x:(@) "[" ig_line() i:expression() ig_line() "]" ig_space() { Expr::Subscript(Box::new(x), Box::new(i)) }
+            --
+            ig_space() e:primary() { e }
+            ig_space() "(" ig_line() e:expression() ig_line() ")" { e }
+            ig_space() t:tuple() { t }
+            ig_space() l:list() { l }
+        }
+        // 只有一个元素的元组需要以逗号结尾，例如 (a,)
+        rule tuple() -> Expr =
+            "(" ig_line() items:((ig_line() e:expression() ig_line() { e }) ** ",") trailing:","? ig_line() ")" {?
+                if items.len() == 1 && trailing.is_none() {
+                    Err("(expression,)")
+                } else {
+                    Ok(Expr::Tuple(items))
+                }
+            }
+        // 列表字面量，Mool 中表示为元组
+        rule list() -> Expr =
+            "[" ig_line() items:((ig_line() e:expression() ig_line() { e }) ** ",") ","? ig_line() "]" {
+                Expr::Tuple(items)
+            }
+        rule primary() -> Expr = literal() / operator() / call() / identifier()
         rule literal() -> Expr = int_literal() / float_literal() / bool_literal()
         rule int_literal() -> Expr = p:position!() ig_space() n:$(['0'..='9']+) !"." ig_space() {
             match n.parse::<i64>(){
@@ -33,52 +202,66 @@ peg::parser! {
             }
         }
         rule bool_literal() -> Expr = ig_space() b:$("True" / "False") ig_space(){
-            Expr::Literal(Literal::Bool(if b == "True" { true } else { false }))
+            Expr::Literal(Literal::Bool(b == "True"))
         }
         rule identifier() -> Expr = not_keyword() id:identifier_str() { Expr::Identifier(id) }
-        rule identifier_str() -> String = id:$(['a'..='z' | 'A'..='Z' | '_']['a'..='z' | 'A'..='Z' | '0'..='9' | '_' ]*) { id.to_owned() }
+        rule identifier_str() -> String = id:$(['a'..='z' | 'A'..='Z' | '_'] identifier_char()*) { id.to_owned() }
+        rule identifier_char() = ['a'..='z' | 'A'..='Z' | '0'..='9' | '_' ]
+        // 算子的参数按 PyTorch 的形参表解析，支持关键字参数
         rule operator() -> Expr =
-            p:position!() "torch." op:$("add"/"sub"/"mul"/"div") ig_line() "(" ig_line() x:expression() ig_line() "," ig_line() y:expression() ig_line() ")" ig_space() {
-                match op {
-                    "add" => { Expr::Operator(Operator::Add(Box::new(x), Box::new(y))) },
-                    "sub" => { Expr::Operator(Operator::Sub(Box::new(x), Box::new(y))) },
-                    "mul" => { Expr::Operator(Operator::Mul(Box::new(x), Box::new(y))) },
-                    "div" => { Expr::Operator(Operator::Div(Box::new(x), Box::new(y))) },
-                    _ => { panic!("{}:暂不支持{}算子", p ,op) }
-                }
+            ig_space() "torch.tensor" ig_line() "(" ig_line() t:tensor() kwargs:(ig_line() "," a:argument() { a })* ig_line() ","? ig_line() ")" ig_space() {
+                arguments::tensor(t, kwargs)
+            }
+            / "torch." op:identifier_str() ig_line() "(" ig_line() args:call_args() ig_line() ")" ig_space() {
+                arguments::operator(&op, args)
             }
-            / ig_space() "torch.tensor" ig_line() "(" ig_line() t:tensor() ig_line() ")" ig_space() { Expr::Operator(Operator::Tensor(t)) }
         rule tensor() -> Vec<Literal> = "[" ig_line() t:(tensor_type()** ",") ig_line() "]" { t }
-        rule tensor_type() -> Literal = 
-            p:position!() ig_space() n:$(['0'..='9']+) !"." ig_space(){
+        rule tensor_type() -> Literal =
+            p:position!() ig_space() n:$("-"? ['0'..='9']+) !['.' | 'e' | 'E'] ig_space(){
                 match n.parse::<i64>(){
                     Ok(t) => { Literal::Int(t) },
                     Err(e) => { panic!("{}:无法解析为整数类型", p) }
                 }
             }
-            / p:position!() ig_space() n:$(['0'..='9']+"."['0'..='9']*) ig_space(){
+            // 从模型文件导出的常量张量中可能包含负数和科学计数法
+            / p:position!() ig_space() n:$("-"? ['0'..='9']+ ("." ['0'..='9']*)? (['e' | 'E'] ['+' | '-']? ['0'..='9']+)?) ig_space(){
                 match n.parse::<f64>() {
                     Ok(t) => { Literal::Float(t) },
                     Err(e) => { panic!("{}:无法解析为浮点类型", p) }
                 }
             }
             / ig_space() b:$("True" / "False") ig_space(){
-                Literal::Bool(if b == "True" { true } else { false })
+                Literal::Bool(b == "True")
             }
-        rule call() -> Expr = id:identifier_str() ig_line() "(" ig_line() args:call_args() ig_line() ")" ig_space() {
+        rule call() -> Expr = not_keyword() id:identifier_str() ig_line() "(" ig_line() args:call_args() ig_line() ")" ig_space() {
             Expr::Call(id, args)
         }
-        rule call_args() -> Vec<Expr> = args:(expression() ** ",") ","? { args }
-        rule not_keyword() = !(
+        rule call_args() -> Vec<Argument> = args:(argument() ** ",") ","? { args }
+        rule argument() -> Argument =
+            ig_line() name:identifier_str() ig_space() "=" !"=" ig_line() value:expression() ig_line() {
+                Argument{name: Some(name), value}
+            }
+            / ig_line() value:expression() ig_line() { Argument{name: None, value} }
+        rule not_keyword() = !((
             "and"/"as"/"assert"/"async"/"await"
             /"break"/"class"/"continue"/"def"/"del"
             /"elif"/"else"/"except"/"False"/"finally"
             /"for"/"from"/"global"/"if"/"import"
             /"in"/"is"/"lambda"/"None"/"nonlocal"
             /"not"/"or"/"pass"/"raise"/"return"
-            /"True"/"try"/"while"/"with"/"yield")
-        rule ig_space() = quiet!{ [' ' | '\t']* }
-        rule ig_line() = quiet!{ [' ' | '\t' | '\n']* }
-        rule indent() = quiet!{ "  " }
+            /"True"/"try"/"while"/"with"/"yield") !identifier_char())
+        rule ig_space() = quiet!{ ([' ' | '\t'] / comment())* }
+        rule ig_line() = quiet!{ ([' ' | '\t' | '\n'] / comment())* }
+        rule string_literal() -> String =
+            "\"\"\"" s:$((!"\"\"\"" string_char())*) "\"\"\"" { s.to_owned() }
+            / "'''" s:$((!"'''" string_char())*) "'''" { s.to_owned() }
+            / "\"" s:$((!['"' | '\n'] string_char())*) "\"" { s.to_owned() }
+            / "'" s:$((!['\'' | '\n'] string_char())*) "'" { s.to_owned() }
+        rule string_char() = "\\" [_] / [_]
+        rule comment() = "#" [^'\n']*
+        rule blank_line() = quiet!{ ig_space() "\n" }
+        rule end_line() = "\n" / ![_] / &dedent_token()
+        rule indent_token() = quiet!{ ['\u{2}'] } / expected!("INDENT")
+        rule dedent_token() = quiet!{ ['\u{3}'] } / expected!("DEDENT")
     }
 }
@@ -0,0 +1,181 @@
+use peg::error::ParseError;
+use peg::str::LineCol;
+
+/// 缩进增加一层时插入的标记
+pub const INDENT: char = '\u{2}';
+/// 缩进减少一层时插入的标记
+pub const DEDENT: char = '\u{3}';
+
+/// 与 Python 相同，tab 对齐到 8 的倍数
+const TAB_SIZE: usize = 8;
+
+/// 缩进预处理：把每个逻辑行开头的缩进替换为 INDENT/DEDENT 标记
+///
+/// 与 Python 的 tokenizer 一致：
+/// - 缩进宽度可以任意，只要同一个语句块内保持一致
+/// - 空行和只有注释的行不影响缩进
+/// - 括号内换行的续行和三引号字符串的续行不影响缩进
+///
+/// 输出保持原有的行数不变，便于语法错误定位到原始代码的行号。
+/// 缩进不一致、括号不匹配和字符串没有闭合时返回出错的行号和列号。
+pub fn tokenize(code: &str) -> Result<String, ParseError<LineCol>> {
+    let code = code.replace("\r\n", "\n");
+    let mut output = String::with_capacity(code.len());
+    // 缩进栈，栈底为顶层的 0 缩进
+    let mut indents: Vec<usize> = vec![0];
+    // 跨行的括号和字符串，存在时为续行
+    let mut continuation = Continuation::default();
+    let mut offset = 0;
+    for (number, line) in code.lines().enumerate() {
+        let start = LineCol {
+            line: number + 1,
+            column: 1,
+            offset,
+        };
+        offset += line.len() + 1;
+        if continuation.is_open() {
+            output.push_str(line);
+            output.push('\n');
+            continuation.scan(line, &start)?;
+            continue;
+        }
+        let content = line.trim_start_matches([' ', '\t']);
+        // 空行和注释行原样保留，由语法中的 ig_line 跳过
+        if content.is_empty() || content.starts_with('#') {
+            output.push_str(content);
+            output.push('\n');
+            continue;
+        }
+        let indent = &line[..line.len() - content.len()];
+        let start = position(&start, indent);
+        let width = indent_width(indent);
+        let top = *indents.last().unwrap();
+        if width > top {
+            indents.push(width);
+            output.push(INDENT);
+        } else {
+            while width < *indents.last().unwrap() {
+                indents.pop();
+                output.push(DEDENT);
+            }
+            if width != *indents.last().unwrap() {
+                return Err(error(errors::dedent, start));
+            }
+        }
+        output.push_str(content);
+        output.push('\n');
+        continuation.scan(content, &start)?;
+    }
+    if let Some((_, start)) = continuation.string {
+        return Err(error(errors::string, start));
+    }
+    // 文件结束时关闭所有语句块
+    while indents.len() > 1 {
+        indents.pop();
+        output.push(DEDENT);
+    }
+    Ok(output)
+}
+
+fn indent_width(indent: &str) -> usize {
+    indent.chars().fold(0, |width, c| match c {
+        '\t' => (width / TAB_SIZE + 1) * TAB_SIZE,
+        _ => width + 1,
+    })
+}
+
+/// line 中 consumed 之后的位置，consumed 为 line 的前缀，start 为 line 开头的位置
+fn position(start: &LineCol, consumed: &str) -> LineCol {
+    LineCol {
+        line: start.line,
+        column: start.column + consumed.chars().count(),
+        offset: start.offset + consumed.len(),
+    }
+}
+
+/// 生成位置为 location 的语法错误
+fn error(
+    rule: fn(&str) -> Result<(), ParseError<LineCol>>,
+    location: LineCol,
+) -> ParseError<LineCol> {
+    let mut error = rule("").unwrap_err();
+    error.location = location;
+    error
+}
+
+// peg 的 ExpectedSet 不能直接构造，用只包含 expected! 的规则生成错误
+peg::parser! {
+    grammar errors() for str {
+        pub rule dedent() = expected!("与外层语句块一致的缩进")
+        pub rule bracket() = expected!("匹配的括号")
+        pub rule string() = expected!("闭合的字符串")
+    }
+}
+
+/// 跨越多行的括号和三引号字符串
+#[derive(Default)]
+struct Continuation {
+    brackets: usize,
+    /// 没有闭合的字符串的引号和开始的位置
+    string: Option<(&'static str, LineCol)>,
+}
+
+impl Continuation {
+    fn is_open(&self) -> bool {
+        self.brackets > 0 || self.string.is_some()
+    }
+
+    /// 扫描一行代码，更新未闭合的括号和字符串
+    fn scan(&mut self, line: &str, start: &LineCol) -> Result<(), ParseError<LineCol>> {
+        let mut rest = line;
+        while let Some(c) = rest.chars().next() {
+            if let Some((quote, _)) = self.string {
+                // 跳过转义字符
+                if c == '\\' {
+                    rest = &rest[c.len_utf8()..];
+                    if let Some(escaped) = rest.chars().next() {
+                        rest = &rest[escaped.len_utf8()..];
+                    }
+                    continue;
+                }
+                match rest.strip_prefix(quote) {
+                    Some(after) => {
+                        self.string = None;
+                        rest = after;
+                    }
+                    None => rest = &rest[c.len_utf8()..],
+                }
+                continue;
+            }
+            if let Some(quote) = ["\"\"\"", "'''", "\"", "'"]
+                .iter()
+                .find(|quote| rest.starts_with(*quote))
+            {
+                self.string = Some((quote, position(start, &line[..line.len() - rest.len()])));
+                rest = &rest[quote.len()..];
+                continue;
+            }
+            match c {
+                '#' => break,
+                '(' | '[' | '{' => self.brackets += 1,
+                ')' | ']' | '}' => {
+                    if self.brackets == 0 {
+                        let location = position(start, &line[..line.len() - rest.len()]);
+                        return Err(error(errors::bracket, location));
+                    }
+                    self.brackets -= 1;
+                }
+                _ => {}
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+        // 单引号字符串不能跨行
+        match self.string.take() {
+            Some(("\"", start)) | Some(("'", start)) => Err(error(errors::string, start)),
+            string => {
+                self.string = string;
+                Ok(())
+            }
+        }
+    }
+}
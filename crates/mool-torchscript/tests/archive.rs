@@ -0,0 +1,312 @@
+//! 手工构造的 pickle 和 zip，覆盖张量、元组、共享存储、memo 中的引用和不合法的输入
+
+use mool_torchscript::archive::{load, Archive, Attribute, TensorData};
+use std::io::{Cursor, Write};
+use zip::write::{FileOptions, ZipWriter};
+use zip::CompressionMethod;
+
+/// 按操作码拼接 pickle
+#[derive(Default)]
+struct Pickle(Vec<u8>);
+
+impl Pickle {
+    fn op(&mut self, bytes: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    /// SHORT_BINUNICODE
+    fn str(&mut self, s: &str) -> &mut Self {
+        self.op(&[0x8c, s.len() as u8]).op(s.as_bytes())
+    }
+
+    /// GLOBAL
+    fn global(&mut self, module: &str, name: &str) -> &mut Self {
+        self.op(b"c")
+            .op(module.as_bytes())
+            .op(b"\n")
+            .op(name.as_bytes())
+            .op(b"\n")
+    }
+
+    /// BININT1
+    fn int(&mut self, n: u8) -> &mut Self {
+        self.op(&[b'K', n])
+    }
+
+    /// 一维或二维的形状和步长，分别用 TUPLE1 和 TUPLE2
+    fn ints(&mut self, values: &[u8]) -> &mut Self {
+        for value in values {
+            self.int(*value);
+        }
+        self.op(&[0x84 + values.len() as u8])
+    }
+
+    /// _rebuild_tensor_v2 的调用，storage 为已经压入栈中的 persistent_id 之后的部分
+    fn tensor(
+        &mut self,
+        storage: impl FnOnce(&mut Self),
+        offset: u8,
+        size: &[u8],
+        stride: &[u8],
+    ) -> &mut Self {
+        self.global("torch._utils", "_rebuild_tensor_v2").op(b"(");
+        storage(self);
+        self.int(offset).ints(size).ints(stride);
+        // requires_grad 和 backward_hooks
+        self.op(&[0x89])
+            .global("collections", "OrderedDict")
+            .op(b")R")
+            .op(b"tR")
+    }
+
+    /// persistent_id 为 ('storage', dtype, key, 'cpu', numel)
+    fn storage(&mut self, dtype: &str, key: &str, numel: u8) -> &mut Self {
+        self.op(b"(")
+            .str("storage")
+            .global("torch", dtype)
+            .str(key)
+            .str("cpu")
+            .int(numel)
+            .op(b"tQ")
+    }
+
+    fn stop(&mut self) -> Vec<u8> {
+        self.op(b".");
+        self.0.clone()
+    }
+}
+
+fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+    for (name, data) in files {
+        writer.start_file(*name, options).unwrap();
+        writer.write_all(data).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+fn longs(values: &[i64]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+/// Net 模块：weight 为 2x2 的转置张量，bias 与 weight 共享存储，scale 为浮点数，training 为 True
+fn model() -> Vec<u8> {
+    let mut data = Pickle::default();
+    data.op(&[0x80, 2])
+        .global("__torch__", "Net")
+        .op(b")")
+        .op(&[0x81])
+        .op(b"}(")
+        .str("weight")
+        // 存储的 persistent_id 放入 memo 0，bias 用 BINGET 取出
+        .tensor(
+            |pickle| {
+                pickle.storage("LongStorage", "0", 4).op(&[b'q', 0]);
+            },
+            0,
+            &[2, 2],
+            &[1, 2],
+        )
+        .str("bias")
+        .tensor(
+            |pickle| {
+                pickle.op(&[b'h', 0]);
+            },
+            1,
+            &[2],
+            &[1],
+        )
+        .str("scale")
+        .op(b"G")
+        .op(&0.5f64.to_be_bytes())
+        .str("training")
+        .op(&[0x88])
+        .op(b"ub");
+    let mut constants = Pickle::default();
+    constants
+        .op(&[0x80, 2])
+        .op(b"(")
+        .tensor(
+            |pickle| {
+                pickle.storage("FloatStorage", "0", 2);
+            },
+            0,
+            &[2],
+            &[1],
+        )
+        .op(b"t");
+    let floats: Vec<u8> = [1.5f32, -2.0]
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    zip(&[
+        ("net/data.pkl", &data.stop()),
+        ("net/data/0", &longs(&[1, 2, 3, 4])),
+        ("net/constants.pkl", &constants.stop()),
+        ("net/constants/0", &floats),
+        ("net/code/__torch__.py", b"y = x * CONSTANTS.c0\n"),
+        ("net/version", b"3\n"),
+    ])
+}
+
+fn read(bytes: Vec<u8>) -> std::io::Result<Archive> {
+    load(Cursor::new(bytes))
+}
+
+#[test]
+fn tensors_and_tuples() {
+    let archive = read(model()).unwrap();
+    assert_eq!(archive.module.class, "Net");
+    let names: Vec<&str> = archive
+        .module
+        .attributes
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect();
+    assert_eq!(names, ["weight", "bias", "scale", "training"]);
+    // 按步长取出的转置张量按行优先展开
+    match archive.module.attribute("weight") {
+        Some(Attribute::Tensor(tensor)) => {
+            assert_eq!(tensor.shape, [2, 2]);
+            assert!(matches!(&tensor.data, TensorData::Int(data) if data == &[1, 3, 2, 4]));
+            assert_eq!(tensor.mool_type(), "Tensor[(4),int]");
+        }
+        attribute => panic!("weight 应该是张量：{:?}", attribute),
+    }
+    match archive.module.attribute("bias") {
+        Some(Attribute::Tensor(tensor)) => {
+            assert!(matches!(&tensor.data, TensorData::Int(data) if data == &[2, 3]))
+        }
+        attribute => panic!("bias 应该是张量：{:?}", attribute),
+    }
+    assert!(matches!(archive.module.attribute("scale"), Some(Attribute::Float(f)) if *f == 0.5));
+    assert!(matches!(
+        archive.module.attribute("training"),
+        Some(Attribute::Bool(true))
+    ));
+    let parameters: Vec<String> = archive
+        .module
+        .parameters()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    assert_eq!(parameters, ["weight", "bias"]);
+    // constants.pkl 是张量的元组，源码中的常量替换为字面量
+    assert_eq!(archive.constants.len(), 1);
+    assert_eq!(archive.code[0].0, "code/__torch__.py");
+    assert_eq!(archive.source(), "y = x * torch.tensor([1.5, -2.0])\n");
+}
+
+#[test]
+fn mutate_after_binget() {
+    // 子模块先放入 memo 1 和父模块的属性字典，之后用 BINGET 取出再 BUILD，
+    // 父模块中的引用应该看到子模块的状态
+    let mut data = Pickle::default();
+    data.op(&[0x80, 2])
+        .global("__torch__", "Net")
+        .op(b")")
+        .op(&[0x81])
+        .op(b"}(")
+        .str("sub")
+        .global("__torch__", "Sub")
+        .op(b")")
+        .op(&[0x81, b'q', 1])
+        .str("scale")
+        .op(b"G")
+        .op(&0.5f64.to_be_bytes())
+        .op(b"ub")
+        .op(&[b'h', 1])
+        .op(b"}(")
+        .str("training")
+        .op(&[0x88])
+        .op(b"ub0");
+    let archive = read(zip(&[("net/data.pkl", &data.stop())])).unwrap();
+    match archive.module.attribute("sub") {
+        Some(Attribute::Module(module)) => {
+            assert_eq!(module.class, "Sub");
+            assert!(matches!(
+                module.attribute("training"),
+                Some(Attribute::Bool(true))
+            ));
+        }
+        attribute => panic!("sub 应该是模块：{:?}", attribute),
+    }
+    assert!(matches!(archive.module.attribute("scale"), Some(Attribute::Float(f)) if *f == 0.5));
+    // 引用自身的模块
+    let mut data = Pickle::default();
+    data.op(&[0x80, 2])
+        .global("__torch__", "Net")
+        .op(b")")
+        .op(&[0x81, b'q', 0])
+        .op(b"}(")
+        .str("net")
+        .op(&[b'h', 0])
+        .op(b"ub");
+    assert_eq!(
+        read(zip(&[("net/data.pkl", &data.stop())]))
+            .unwrap_err()
+            .to_string(),
+        "__torch__.Net的属性中引用了自身"
+    );
+}
+
+#[test]
+fn malformed_input() {
+    let error = |bytes: Vec<u8>| read(bytes).unwrap_err().to_string();
+    assert!(read(b"not a zip".to_vec()).is_err());
+    assert_eq!(
+        error(zip(&[("net/version", b"3\n")])),
+        "不是 TorchScript 模型文件：缺少 data.pkl"
+    );
+    // 没有 STOP 的 pickle
+    let mut truncated = Pickle::default();
+    truncated.op(&[0x80, 2]).str("weight");
+    assert_eq!(
+        error(zip(&[("net/data.pkl", &truncated.0)])),
+        "pickle 数据不完整"
+    );
+    assert_eq!(
+        error(zip(&[(
+            "net/data.pkl",
+            &Pickle::default().op(&[0x80, 2, 0xff]).stop()
+        )])),
+        "2:暂不支持 pickle 操作码 0xff"
+    );
+    assert_eq!(
+        error(zip(&[("net/data.pkl", &Pickle::default().op(b"t").stop())])),
+        "pickle 缺少 MARK"
+    );
+    assert_eq!(
+        error(zip(&[("net/data.pkl", &Pickle::default().int(1).stop())])),
+        "data.pkl 中保存的不是 TorchScript 模块"
+    );
+    // 2x2 的张量只有 3 个元素的存储
+    let mut data = Pickle::default();
+    data.op(&[0x80, 2])
+        .global("__torch__", "Net")
+        .op(b")")
+        .op(&[0x81])
+        .op(b"}(")
+        .str("weight")
+        .tensor(
+            |pickle| {
+                pickle.storage("LongStorage", "0", 4);
+            },
+            0,
+            &[2, 2],
+            &[2, 1],
+        )
+        .op(b"ub");
+    assert_eq!(
+        error(zip(&[
+            ("net/data.pkl", &data.stop()),
+            ("net/data/0", &longs(&[1, 2, 3]))
+        ])),
+        "张量超出存储0的范围"
+    );
+}
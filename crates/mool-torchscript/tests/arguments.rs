@@ -0,0 +1,83 @@
+//! 关键字参数和默认参数展开为 Mool 的位置参数
+
+fn lower(code: &str) -> String {
+    unsafe { mool_torchscript::codegen(mool_torchscript::parse(code).unwrap()) }
+}
+
+#[test]
+fn keyword_and_default_arguments() {
+    let mool = lower(
+        "def scale(x: Tensor, y: Tensor, bias: Tensor = torch.tensor([1, 1])) -> Tensor:
+    return torch.add(torch.mul(input=x, other=y), bias, alpha=1)
+
+scale(y=torch.tensor([3, 4]), x=torch.tensor([1, 2]))
+scale(torch.tensor([1, 2]), torch.tensor([3, 4]), bias=torch.tensor([0, 1]))
+",
+    );
+    // 关键字参数按形参顺序排列，没有传入的参数取默认值
+    assert!(mool.contains("Add(Mul(%x, %y), %bias)"), "{}", mool);
+    assert!(
+        mool.contains("%scale(Tensor([1,2]), Tensor([3,4]), Tensor([1,1]))"),
+        "{}",
+        mool
+    );
+    assert!(
+        mool.contains("%scale(Tensor([1,2]), Tensor([3,4]), Tensor([0,1]))"),
+        "{}",
+        mool
+    );
+}
+
+#[test]
+fn tensor_dtype_keyword() {
+    let mool = lower(
+        "def f(x: Tensor) -> Tensor:
+    return torch.relu(input=x)
+
+f(torch.tensor([1, -2], dtype=torch.float32, requires_grad=False))
+",
+    );
+    assert!(mool.contains("%f(Tensor([1.0,-2.0]))"), "{}", mool);
+}
+
+#[test]
+#[should_panic(expected = "torch.sub暂不支持 alpha 不为 1")]
+fn non_default_alpha() {
+    lower("torch.sub(torch.tensor([1]), torch.tensor([2]), alpha=2)\n");
+}
+
+#[test]
+#[should_panic(expected = "f的参数x重复传入")]
+fn duplicate_argument() {
+    lower(
+        "def f(x: Tensor) -> Tensor:
+    return x
+
+f(torch.tensor([1]), x=torch.tensor([2]))
+",
+    );
+}
+
+#[test]
+#[should_panic(expected = "f缺少参数y")]
+fn missing_argument() {
+    lower(
+        "def f(x: Tensor, y: Tensor) -> Tensor:
+    return torch.add(x, y)
+
+f(x=torch.tensor([1]))
+",
+    );
+}
+
+#[test]
+#[should_panic(expected = "torch.sum没有参数axis")]
+fn unknown_keyword() {
+    lower("torch.sum(torch.tensor([1, 2]), axis=0)\n");
+}
+
+#[test]
+#[should_panic(expected = "暂不支持torch.sum算子：Mool IR 没有归约算子")]
+fn reduction_not_supported() {
+    lower("torch.sum(torch.tensor([1, 2]), dim=0, keepdim=False)\n");
+}
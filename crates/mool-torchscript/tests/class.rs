@@ -0,0 +1,78 @@
+//! 手工构造的模块对象，覆盖子模块的方法调用、属性常量和缺少的属性
+
+use mool_torchscript::archive::{Attribute, Module, Tensor, TensorData};
+use mool_torchscript::{codegen_module, parse};
+
+const CODE: &str = "class Linear(Module):
+  __parameters__ = [\"weight\", \"bias\", ]
+  weight : Tensor
+  bias : Tensor
+  def forward(self: __torch__.Linear,
+    x: Tensor) -> Tensor:
+    return torch.add(torch.mul(x, self.weight), self.bias)
+class M(Module):
+  __parameters__ = []
+  linear : __torch__.Linear
+  def forward(self: __torch__.M,
+    x: Tensor) -> Tensor:
+    linear = self.linear
+    y = linear.forward(x)
+    return torch.mul(y, self.scale)
+";
+
+fn tensor(data: &[f64]) -> Attribute {
+    Attribute::Tensor(Tensor {
+        shape: vec![data.len()],
+        data: TensorData::Float(data.to_vec()),
+    })
+}
+
+/// 根模块 M 有子模块 linear 和浮点数属性 scale
+fn module(linear: Vec<(&str, Attribute)>) -> Module {
+    Module {
+        class: "M".to_string(),
+        attributes: vec![
+            (
+                "linear".to_string(),
+                Attribute::Module(Module {
+                    class: "Linear".to_string(),
+                    attributes: linear
+                        .into_iter()
+                        .map(|(name, attribute)| (name.to_string(), attribute))
+                        .collect(),
+                }),
+            ),
+            ("scale".to_string(), Attribute::Float(0.5)),
+        ],
+    }
+}
+
+#[test]
+fn submodule_method() {
+    let module = module(vec![
+        ("weight", tensor(&[1.0, 2.0])),
+        ("bias", tensor(&[0.5, -0.5])),
+    ]);
+    let mool = unsafe { codegen_module(parse(CODE).unwrap(), &module) };
+    // 子模块的方法展开为以实例路径命名的函数，属性替换为常量
+    assert_eq!(
+        mool,
+        "let %linear__forward = fn(%x: Tensor[(2),float]) -> Tensor[(2),float] {
+    Add(Mul(%x, Tensor([1.0,2.0])), Tensor([0.5,-0.5]))
+}
+
+let %forward = fn(%x: Tensor[(2),float]) -> Tensor[(2),float] {
+    let %y = %linear__forward(%x)
+    Mul(%y, 0.5)
+}
+
+"
+    );
+}
+
+#[test]
+#[should_panic(expected = "模块Linear没有属性bias")]
+fn missing_attribute() {
+    let module = module(vec![("weight", tensor(&[1.0, 2.0]))]);
+    unsafe { codegen_module(parse(CODE).unwrap(), &module) };
+}
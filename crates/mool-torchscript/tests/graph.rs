@@ -0,0 +1,92 @@
+//! TorchScript 图的常量和默认参数
+
+use mool_torchscript::graph::{codegen, parse};
+
+fn lower(code: &str) -> String {
+    codegen(parse(code).unwrap())
+}
+
+#[test]
+fn list_and_tensor_constants() {
+    let mool = lower(
+        "graph(%x : Float(2, strides=[1], requires_grad=0, device=cpu)):
+  %none : NoneType = prim::Constant()
+  %1 : int[] = prim::Constant[value=[1, 2]]()
+  %2 : Tensor = aten::tensor(%1, %none, %none, %none)
+  %3 : Tensor = prim::Constant[value= 1  2 [ CPUFloatType{2} ]]()
+  %4 : Tensor = prim::Constant[value= 0.5000 -1.2500 [ CPUDoubleType{2} ]]()
+  %5 : Tensor = prim::Constant[value= 1  0 [ CPUBoolType{2} ]]()
+  %6 : Tensor = aten::mul(%3, %4)
+  %7 : Tensor = aten::lt(%2, %5)
+  return (%6)
+",
+    );
+    // 列表常量的数据不丢失，浮点张量中的整数写作浮点数
+    assert!(mool.contains("Tensor([1,2])"), "{}", mool);
+    assert!(
+        mool.contains("Mul(Tensor([1.0,2.0]), Tensor([0.5,-1.25]))"),
+        "{}",
+        mool
+    );
+    assert!(
+        mool.contains("Lt(Tensor([1,2]), Tensor([true,false]))"),
+        "{}",
+        mool
+    );
+}
+
+#[test]
+fn numeric_default_arguments() {
+    // alpha 写作 1 或 1.0 都是默认值
+    for alpha in ["1", "1.", "1.0"] {
+        let mool = lower(&format!(
+            "graph(%x : Long(2, strides=[1], requires_grad=0, device=cpu)):
+  %2 : Scalar = prim::Constant[value={}]()
+  %3 : Long(2, strides=[1], requires_grad=0, device=cpu) = aten::sub(%x, %x, %2)
+  return (%3)
+",
+            alpha
+        ));
+        assert!(mool.contains("let %v3 = Sub(%x, %x)"), "{}", mool);
+    }
+}
+
+#[test]
+#[should_panic(expected = "aten::add暂不支持第3个参数取非默认值")]
+fn non_default_alpha() {
+    lower(
+        "graph(%x : Long(2, strides=[1], requires_grad=0, device=cpu)):
+  %2 : float = prim::Constant[value=2.5]()
+  %3 : Tensor = aten::add(%x, %x, %2)
+  return (%3)
+",
+    );
+}
+
+#[test]
+#[should_panic(expected = "图中没有定义%none")]
+fn use_before_definition() {
+    // 节点不使用的输入也必须先定义
+    lower(
+        "graph(%x : Long(2, strides=[1], requires_grad=0, device=cpu)):
+  %1 : int[] = prim::Constant[value=[1, 2]]()
+  %2 : Tensor = aten::tensor(%1, %none, %none, %none)
+  %none : NoneType = prim::Constant()
+  return (%2)
+",
+    );
+}
+
+#[test]
+fn parse_errors() {
+    // 超出 i64 范围的整数返回错误而不是 panic
+    let error = parse(
+        "graph(%x : int):
+  %2 : int = prim::Constant[value=99999999999999999999]()
+  return (%2)
+",
+    )
+    .unwrap_err();
+    assert_eq!(error.location.line, 2);
+    assert!(parse("graph(%x : int):\n  return (%x\n").is_err());
+}
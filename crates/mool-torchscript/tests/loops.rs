@@ -0,0 +1,65 @@
+//! for 和 while 循环翻译为 Mool 的循环
+
+fn lower(code: &str) -> String {
+    unsafe { mool_torchscript::codegen(mool_torchscript::parse(code).unwrap()) }
+}
+
+#[test]
+fn loops_with_break_and_continue() {
+    let mool = lower(
+        "def f(x: Tensor, n: int) -> Tensor:
+    y = x
+    for i in range(1, n, 2):
+        y = y + x
+    j = n
+    while j > 0:
+        j = j - 1
+        if_break = j
+        continue
+    for k in range(n):
+        break
+    return y
+
+f(torch.tensor([1, 2]), 3)
+",
+    );
+    // 第一次赋值声明变量，循环中的赋值更新外层的变量
+    assert!(
+        mool.contains(
+            "    let %y = %x\n    for %i in range(1, %n, 2) {\n        %y = Add(%y, %x)\n    }\n"
+        ),
+        "{}",
+        mool
+    );
+    assert!(
+        mool.contains("    while Gt(%j, 0) {\n        %j = Sub(%j, 1)\n        let %if_break = %j\n        continue\n    }\n"),
+        "{}",
+        mool
+    );
+    assert!(
+        mool.contains("    for %k in range(0, %n, 1) {\n        break\n    }\n    %y\n"),
+        "{}",
+        mool
+    );
+}
+
+#[test]
+fn reject_return_in_loops() {
+    for (code, line) in [
+        ("def f(n: int) -> int:\n    for i in range(n):\n        return i\n    return n\n", 3),
+        ("def f(n: int) -> int:\n    while n > 0:\n        n = n - 1\n        return n\n    return n\n", 4),
+        ("def f(n: int) -> int:\n    for i in range(n):\n        while n > 0:\n            return n\n    return n\n", 4),
+    ] {
+        let error = match mool_torchscript::parse(code) {
+            Ok(_) => panic!("循环中不能 return：{}", code),
+            Err(error) => error,
+        };
+        assert_eq!(error.location.line, line, "{}", code);
+        assert!(
+            error.expected.tokens().any(|token| token == "循环之外的 return"),
+            "{}: {}",
+            code,
+            error
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! 缩进预处理的 INDENT/DEDENT 标记和错误位置
+
+use mool_torchscript::tokenizer::{tokenize, DEDENT, INDENT};
+
+/// 把标记替换为可读的 < 和 >
+fn tokens(code: &str) -> String {
+    tokenize(code)
+        .unwrap()
+        .replace(INDENT, "<")
+        .replace(DEDENT, ">")
+}
+
+#[test]
+fn indent_and_dedent() {
+    let code = "def f(x: int) -> int:\n    y = x\n    for i in range(3):\n\ty = y + i\n\n    # 注释\n    return y\nf(1)\n";
+    // tab 对齐到 8，与 4 个空格的外层缩进相比增加一层；空行和注释行不影响缩进
+    assert_eq!(
+        tokens(code),
+        "def f(x: int) -> int:\n<y = x\nfor i in range(3):\n<y = y + i\n\n# 注释\n>return y\n>f(1)\n"
+    );
+    // 文件结束时关闭所有语句块
+    assert_eq!(
+        tokens("def f() -> int:\n  if x:\n    return 1"),
+        "def f() -> int:\n<if x:\n<return 1\n>>"
+    );
+}
+
+#[test]
+fn continuation_lines() {
+    let code = "def f(x: int,\n        y: int) -> int:\n    \"\"\"文档\n  字符串\"\"\"\n    return (x +\n  y)\n";
+    // 括号和三引号字符串中的续行原样保留
+    assert_eq!(
+        tokens(code),
+        "def f(x: int,\n        y: int) -> int:\n<\"\"\"文档\n  字符串\"\"\"\nreturn (x +\n  y)\n>"
+    );
+    // 字符串和注释中的括号不计入
+    assert_eq!(tokens("f(')', \"(\") # (\nx\n"), "f(')', \"(\") # (\nx\n");
+}
+
+#[test]
+fn errors_with_location() {
+    let error = tokenize("def f() -> int:\n    x = 1\n  return x\n").unwrap_err();
+    assert_eq!((error.location.line, error.location.column), (3, 3));
+    assert_eq!(error.location.offset, 28);
+    assert_eq!(
+        error.expected.tokens().collect::<Vec<_>>(),
+        ["与外层语句块一致的缩进"]
+    );
+    let error = tokenize("x = 1\ny = f(x))\n").unwrap_err();
+    assert_eq!((error.location.line, error.location.column), (2, 9));
+    assert_eq!(error.expected.tokens().collect::<Vec<_>>(), ["匹配的括号"]);
+    let error = tokenize("x = 1\ny = 'abc\n").unwrap_err();
+    assert_eq!((error.location.line, error.location.column), (2, 5));
+    let error = tokenize("def f() -> int:\n    \"\"\"文档\n    return 1\n").unwrap_err();
+    assert_eq!((error.location.line, error.location.column), (2, 5));
+    assert_eq!(
+        error.expected.tokens().collect::<Vec<_>>(),
+        ["闭合的字符串"]
+    );
+    // 解析时同样返回错误而不是 panic
+    let error = mool_torchscript::parse("def f() -> int:\n    x = 1\n  return x\n").unwrap_err();
+    assert_eq!(error.location.line, 3);
+}
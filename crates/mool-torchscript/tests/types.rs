@@ -0,0 +1,96 @@
+//! 类型标注翻译为 Mool 类型，不支持的类型标注在解析时报错，无法翻译的 List、Dict 在翻译时报错
+
+#[test]
+fn tuple_and_optional_annotations() {
+    let code = "def f(x: Tensor, n: int, scale: typing.Optional[float]) -> Tuple[Tensor, Tuple[int, bool]]:
+    return x, (n, n > 0)
+
+f(torch.tensor([1.0, 2.0]), 3, (True, 0.5))
+";
+    let mool = unsafe { mool_torchscript::codegen(mool_torchscript::parse(code).unwrap()) };
+    assert!(
+        mool.contains("fn(%x: Tensor[(2),float], %n: int, %scale: (bool, float)) -> (Tensor[(2),float], (int, bool))"),
+        "{}",
+        mool
+    );
+}
+
+#[test]
+fn list_and_dict_annotations() {
+    for code in [
+        "def f(x: List[int]) -> int:\n    return 1\n",
+        "def f(x: Dict[str, Tensor]) -> int:\n    return 1\n",
+        "def f(x: typing.Optional[list[Tuple[int, float]]]) -> dict[int, List[Tensor]]:\n    return x\n",
+        "def f(x: Tensor) -> Tuple[Tensor, List[Tensor]]:\n    return x, [x, x]\n",
+    ] {
+        if let Err(error) = mool_torchscript::parse(code) {
+            panic!("应该支持类型标注：{}: {}", code, error);
+        }
+    }
+}
+
+#[test]
+fn list_annotations_with_known_length() {
+    // 参数的长度由调用处的列表字面量确定，返回值的长度由返回的列表字面量确定
+    let code = "def f(xs: List[int], ts: List[Tensor]) -> List[int]:
+    return [xs[0], xs[1] + 1]
+
+f([1, 2], [torch.tensor([1.0, 2.0]), torch.tensor([3.0, 4.0])])
+";
+    let mool = unsafe { mool_torchscript::codegen(mool_torchscript::parse(code).unwrap()) };
+    assert!(
+        mool.contains("fn(%xs: (int, int), %ts: (Tensor[(2),float], Tensor[(2),float])) -> (int, int)"),
+        "{}",
+        mool
+    );
+    assert!(mool.contains("(%xs.0, Add(%xs.1, 1))"), "{}", mool);
+    assert!(
+        mool.contains("%f((1, 2), (Tensor([1.0,2.0]), Tensor([3.0,4.0])))"),
+        "{}",
+        mool
+    );
+}
+
+#[test]
+#[should_panic(expected = "无法确定函数f中参数xs的类型List[int]的长度，调用时需要传入列表字面量")]
+fn list_annotation_with_unknown_length() {
+    let code = "def f(xs: List[int]) -> int:
+    return xs[0]
+";
+    unsafe { mool_torchscript::codegen(mool_torchscript::parse(code).unwrap()) };
+}
+
+#[test]
+#[should_panic(expected = "函数f中参数x的类型Dict[str, Tensor]无法翻译为 Mool 类型")]
+fn dict_annotation() {
+    let code = "def f(x: Dict[str, Tensor]) -> int:
+    return 1
+";
+    unsafe { mool_torchscript::codegen(mool_torchscript::parse(code).unwrap()) };
+}
+
+#[test]
+fn unsupported_annotations() {
+    for (code, line) in [
+        ("def f(x: Tensor) -> int:\n    return 1\ndef g(name: str) -> int:\n    return 1\n", 3),
+        ("def f(x: Tensor) -> None:\n    return x\n", 1),
+        ("def f(x: Tensor) -> Tuple[Tensor, List[str]]:\n    return x\n", 1),
+        ("def f(x: Dict[Tensor, str]) -> int:\n    return 1\n", 1),
+        ("def f(m: MyModule) -> int:\n    return 1\n", 1),
+    ] {
+        let error = match mool_torchscript::parse(code) {
+            Ok(_) => panic!("应该不支持类型标注：{}", code),
+            Err(error) => error,
+        };
+        assert_eq!(error.location.line, line, "{}", code);
+        assert!(
+            error
+                .expected
+                .tokens()
+                .any(|token| token.starts_with("int、float、bool、Tensor")),
+            "{}: {}",
+            code,
+            error
+        );
+    }
+}
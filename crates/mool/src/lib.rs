@@ -1,5 +1,7 @@
 pub use mool_ir as ir;
 pub use mool_torchscript as torchscript;
 pub mod codegen {
+    pub use mool_codegen::backend::{create, Artifact, Backend, TargetOptions};
     pub use mool_codegen::llvm::codegen as llvm;
+    pub use mool_codegen::llvm::{CodegenContext, CodegenError, OptLevel};
 }
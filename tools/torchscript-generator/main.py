@@ -21,6 +21,25 @@ def div(x, y):
     return x / y
 
 
+class Scale(torch.nn.Module):
+    def __init__(self):
+        super().__init__()
+        self.weight = torch.nn.Parameter(torch.tensor([1, 2]), requires_grad=False)
+
+    def forward(self, x):
+        return x * self.weight
+
+
+class Model(torch.nn.Module):
+    def __init__(self):
+        super().__init__()
+        self.scale = Scale()
+        self.bias = torch.nn.Parameter(torch.tensor([3, 4]), requires_grad=False)
+
+    def forward(self, x):
+        return self.scale(x) + self.bias
+
+
 with open('../../example/torchscript/add', 'w') as f:
     f.write(add.code)
 with open('../../example/torchscript/sub', 'w') as f:
@@ -29,3 +48,4 @@ with open('../../example/torchscript/mul', 'w') as f:
     f.write(mul.code)
 with open('../../example/torchscript/div', 'w') as f:
     f.write(div.code)
+torch.jit.save(torch.jit.script(Model()), '../../example/pt/model.pt')